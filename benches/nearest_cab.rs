@@ -0,0 +1,75 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use fuber::metric::Euclidean;
+use fuber::models::cab_model::Cab;
+use fuber::models::person_model::Person;
+use fuber::models::point_model::Point;
+use fuber::spatial::{GridIndex, IndexedCab};
+use mongodb::bson::oid::ObjectId;
+use rand::Rng;
+
+// cabs spread over a city sized grid instead of the tiny one the random
+// points of `Point` cover
+fn random_point(rng: &mut impl Rng) -> Point {
+    Point::new(rng.gen_range(-5_000..5_000), rng.gen_range(-5_000..5_000))
+}
+
+fn fleet(size: usize) -> Vec<Cab> {
+    let mut rng = rand::thread_rng();
+    (0..size)
+        .map(|_| {
+            let mut cab = Cab::new(random_point(&mut rng));
+            cab.id = Some(ObjectId::new());
+            cab
+        })
+        .collect()
+}
+
+// nearest free cab the way `request_cab` used to find it versus the
+// grid index, for fleets of growing size
+fn nearest_cab(c: &mut Criterion) {
+    let mut group = c.benchmark_group("nearest_free_cab");
+    for size in [1_000, 10_000, 100_000] {
+        let fleet = fleet(size);
+        let mut index = GridIndex::new(64);
+        for cab in fleet.iter() {
+            index.insert(IndexedCab {
+                id: cab.id.expect("cab has no id"),
+                location: cab.location.clone(),
+                category: cab.category,
+            });
+        }
+        let person = Person::new(
+            None,
+            "bench".to_string(),
+            random_point(&mut rand::thread_rng()),
+            Point::new(0, 0),
+        );
+
+        group.bench_with_input(BenchmarkId::new("linear_scan", size), &fleet, |b, fleet| {
+            b.iter(|| {
+                fleet
+                    .iter()
+                    .filter(|x| x.person_id.is_none())
+                    .cloned()
+                    .reduce(|c1, c2| person.nearest_cab(&c1, &c2, &Euclidean))
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("grid_index", size), &index, |b, index| {
+            b.iter(|| index.nearest(black_box(&person.location), 1, None, |_| true))
+        });
+        group.bench_with_input(
+            BenchmarkId::new("grid_index_k8", size),
+            &index,
+            |b, index| b.iter(|| index.nearest(black_box(&person.location), 8, None, |_| true)),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("grid_index_radius", size),
+            &index,
+            |b, index| b.iter(|| index.within_radius(black_box(&person.location), 200.0)),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, nearest_cab);
+criterion_main!(benches);
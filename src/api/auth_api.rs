@@ -0,0 +1,66 @@
+use crate::{
+    auth::{self, hash_key, Admin},
+    error::{ErrorCode, FuberError},
+    models::{
+        auth_model::{check_subject, ApiKey, CreatedApiKey, NewApiKey, Role},
+        ride_model::now_millis,
+    },
+    repository::fuber_repo::BoxedRepo,
+};
+
+use rocket::{delete, post, serde::json::Json, State};
+
+// A key for a rider, a driver or another admin. The key is in the answer
+// and nowhere else, only its hash is stored.
+#[post("/api_keys", data = "<new_api_key>")]
+pub fn create_api_key(
+    _admin: Admin,
+    db: &State<BoxedRepo>,
+    new_api_key: Json<NewApiKey>,
+) -> Result<Json<CreatedApiKey>, FuberError> {
+    let NewApiKey { role, subject } = new_api_key.into_inner();
+    check_subject(role, subject)?;
+    // the person or the cab has to be there
+    match (role, subject) {
+        (Role::Rider, Some(person_id)) => {
+            db.get_person(&person_id.to_hex())?;
+        }
+        (Role::Driver, Some(cab_id)) => {
+            db.get_cab(&cab_id.to_hex())?;
+        }
+        _ => {}
+    }
+    let key = auth::new_api_key();
+    let api_key = db.create_api_key(ApiKey {
+        id: None,
+        key_hash: hash_key(&key),
+        role,
+        subject,
+        created_at: now_millis(),
+    })?;
+    Ok(Json(CreatedApiKey {
+        id: api_key.inserted_id,
+        key,
+        role,
+        subject,
+    }))
+}
+
+#[delete("/api_keys/<api_key_id>")]
+pub fn delete_api_key(
+    _admin: Admin,
+    db: &State<BoxedRepo>,
+    api_key_id: String,
+) -> Result<Json<String>, FuberError> {
+    if api_key_id.is_empty() {
+        return Err(FuberError::InvalidId(
+            ErrorCode::InvalidObjectId,
+            "api key id cannot be empty".into(),
+        ));
+    }
+    if db.delete_api_key(&api_key_id)?.deleted_count == 1 {
+        Ok(Json("Api key successfully deleted!".into()))
+    } else {
+        Err(FuberError::api_key_not_found(&api_key_id))
+    }
+}
@@ -0,0 +1,219 @@
+use crate::{
+    api::{
+        person_api::{assign_nearest_cab, check_trip, nearest_free_cabs, serve_request},
+        ride_api,
+    },
+    auth::{Admin, Caller},
+    error::{ErrorCode, FuberError},
+    metric::Travel,
+    models::booking_model::{Booking, BookingState, NewBooking},
+    models::person_model::Person,
+    models::ride_model::{now_millis, Ride, RideState},
+    repository::fuber_repo::BoxedRepo,
+    scheduler::Schedule,
+    tracking::Tracker,
+};
+
+use mongodb::bson::oid::ObjectId;
+
+use rocket::{get, post, put, serde::json::Json, State};
+
+fn empty_booking_id() -> FuberError {
+    FuberError::InvalidId(
+        ErrorCode::InvalidObjectId,
+        "booking id cannot be empty".into(),
+    )
+}
+
+fn empty_person_id() -> FuberError {
+    FuberError::InvalidId(
+        ErrorCode::InvalidObjectId,
+        "person id cannot be empty".into(),
+    )
+}
+
+// the person as dispatch sees them for the booking, waiting at its pickup
+// and going to its drop
+fn rider(person: Person, booking: &Booking) -> Person {
+    Person {
+        location: booking.pickup.clone(),
+        destination: booking.drop.clone(),
+        ..person
+    }
+}
+
+#[post("/<person_id>/schedule_ride", data = "<booking>")]
+pub fn schedule_ride(
+    caller: Caller,
+    db: &State<BoxedRepo>,
+    person_id: String,
+    booking: Json<NewBooking>,
+) -> Result<Json<Booking>, FuberError> {
+    caller.may_ride_as(&person_id)?;
+    if person_id.is_empty() {
+        return Err(empty_person_id());
+    }
+    let person = db.get_person(&person_id)?;
+    let mut booking = Booking::new(person.id.unwrap_or_default(), booking.into_inner());
+    if booking.pickup_at <= now_millis() {
+        return Err(FuberError::validation("the pickup has to be in the future"));
+    }
+    check_trip(&rider(person, &booking))?;
+    booking.id = Some(db.create_booking(booking.clone())?.inserted_id);
+    Ok(Json(booking))
+}
+
+// the bookings of the person that are still ahead of them
+#[get("/person/<person_id>")]
+pub fn get_bookings_of_person(
+    caller: Caller,
+    db: &State<BoxedRepo>,
+    person_id: String,
+) -> Result<Json<Vec<Booking>>, FuberError> {
+    caller.may_ride_as(&person_id)?;
+    if person_id.is_empty() {
+        return Err(empty_person_id());
+    }
+    db.get_person(&person_id)?;
+    let now = now_millis();
+    Ok(Json(
+        db.get_bookings_of_person(&person_id)?
+            .into_iter()
+            .filter(|x| x.is_upcoming(now))
+            .collect(),
+    ))
+}
+
+// every booking that is still ahead, for the admins
+#[get("/upcoming")]
+pub fn get_upcoming_bookings(
+    _admin: Admin,
+    db: &State<BoxedRepo>,
+) -> Result<Json<Vec<Booking>>, FuberError> {
+    Ok(Json(db.get_upcoming_bookings(now_millis())?))
+}
+
+#[get("/<booking_id>")]
+pub fn get_booking(
+    caller: Caller,
+    db: &State<BoxedRepo>,
+    booking_id: String,
+) -> Result<Json<Booking>, FuberError> {
+    if booking_id.is_empty() {
+        return Err(empty_booking_id());
+    }
+    let booking = db.get_booking(&booking_id)?;
+    caller.may_ride_as(&booking.person_id.to_hex())?;
+    Ok(Json(booking))
+}
+
+// A scheduled booking is simply cancelled, a dispatched one cancels its
+// ride too, which frees the cab that is on its way. The scheduler can
+// dispatch the booking while we are at it, then we go again with the ride.
+#[put("/<booking_id>/cancel")]
+pub fn cancel_booking(
+    caller: Caller,
+    db: &State<BoxedRepo>,
+    travel: &State<Travel>,
+    tracker: &State<Tracker>,
+    booking_id: String,
+) -> Result<Json<Booking>, FuberError> {
+    if booking_id.is_empty() {
+        return Err(empty_booking_id());
+    }
+    caller.may_ride_as(&db.get_booking(&booking_id)?.person_id.to_hex())?;
+    loop {
+        let mut booking = db.get_booking(&booking_id)?;
+        let expected = booking.state;
+        match (booking.state, booking.ride_id) {
+            (BookingState::Cancelled, _) => {
+                return Err(FuberError::Conflict(
+                    ErrorCode::BookingAlreadyCancelled,
+                    "the booking is already cancelled".into(),
+                ))
+            }
+            (BookingState::Dispatched, Some(ride_id)) => {
+                let ride = db.get_ride(&ride_id.to_hex())?;
+                // the person may have cancelled the ride already
+                if ride.state != RideState::Cancelled {
+                    ride_api::cancel_tracked(db, travel, tracker, &ride_id.to_hex())?;
+                }
+            }
+            (BookingState::Scheduled, _) | (BookingState::Dispatched, None) => {}
+        }
+        booking.state = BookingState::Cancelled;
+        if db.update_booking(booking.clone(), expected)?.matched_count == 1 {
+            return Ok(Json(booking));
+        }
+    }
+}
+
+// Gives the bookings that are due a cab, the scheduler calls this every
+// tick with the current time. A booking is due `lead` before its pickup,
+// from then on every tick looks for a free cab within the radius for the
+// attempts it made so far. The booking is claimed before its ride starts,
+// so a booking that gets cancelled meanwhile isn't dispatched anymore.
+// Bookings still without a cab at their pickup time are served like any
+// ride request and queued when there is no free cab at all.
+pub fn dispatch_due_bookings(
+    db: &BoxedRepo,
+    travel: &Travel,
+    schedule: &Schedule,
+    now: i64,
+) -> Result<(), FuberError> {
+    let lead = schedule.lead().as_millis() as i64;
+    for mut booking in db.get_scheduled_bookings(now.saturating_add(lead))? {
+        let person = match db.get_person(&booking.person_id.to_hex()) {
+            Ok(person) => rider(person, &booking),
+            // nobody to pick up anymore
+            Err(FuberError::NotFound(..)) => {
+                booking.state = BookingState::Cancelled;
+                db.update_booking(booking, BookingState::Scheduled)?;
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        let overdue = now >= booking.pickup_at;
+        let within = (!overdue).then(|| schedule.radius(booking.attempts));
+        if within.is_some()
+            && nearest_free_cabs(db, travel, &person.location, booking.category, within)?.is_empty()
+        {
+            booking.attempts += 1;
+            db.update_booking(booking, BookingState::Scheduled)?;
+            continue;
+        }
+
+        let mut ride = Ride::new(
+            booking.person_id,
+            booking.pickup.clone(),
+            booking.drop.clone(),
+        );
+        ride.id = Some(ObjectId::new());
+        booking.state = BookingState::Dispatched;
+        booking.ride_id = ride.id;
+        if db
+            .update_booking(booking.clone(), BookingState::Scheduled)?
+            .matched_count
+            != 1
+        {
+            continue;
+        }
+        db.create_ride(ride.clone())?;
+        if overdue {
+            serve_request(db, travel, person, ride, booking.category)?;
+        } else if assign_nearest_cab(db, travel, &person, &mut ride, booking.category, within)?
+            .is_none()
+        {
+            // the cabs nearby got taken meanwhile, the ride goes and the
+            // booking tries again next tick
+            let expected = ride.state;
+            ride.move_to(RideState::Cancelled)?;
+            db.update_ride(ride, expected)?;
+            booking.state = BookingState::Scheduled;
+            booking.ride_id = None;
+            booking.attempts += 1;
+            db.update_booking(booking, BookingState::Dispatched)?;
+        }
+    }
+    Ok(())
+}
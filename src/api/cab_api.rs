@@ -1,219 +1,243 @@
 use crate::{
-    models::cab_model::Cab, models::point_model::Point, repository::mongodb_repos::MongoRepo,
+    api::person_api::match_waiting,
+    auth::{Admin, Caller},
+    error::{ErrorCode, FuberError},
+    metric::Travel,
+    models::cab_model::Cab,
+    models::point_model::Point,
+    repository::fuber_repo::BoxedRepo,
+    tracking::Tracker,
 };
 
-use mongodb::{
-    bson::oid::ObjectId,
-    results::{InsertManyResult, InsertOneResult},
-};
+use mongodb::bson::oid::ObjectId;
 
-use rocket::{delete, get, http::Status, post, put, serde::json::Json, State};
+use rocket::{delete, get, post, put, serde::json::Json, State};
 
-#[post("/create", data = "<new_cab>")]
-pub fn create_cab(db: &State<MongoRepo>, new_cab: Json<Cab>) -> Result<Json<String>, Status> {
-    let data = Cab::new(new_cab.location.clone());
-
-    let cab_detail = db.create_cab(data);
-    match cab_detail {
-        Ok(cab) => match cab.inserted_id.as_object_id() {
-            Some(obj_id) => Ok(Json(obj_id.to_hex())),
-            None => Err(Status::ExpectationFailed),
-        },
-        Err(_) => Err(Status::InternalServerError),
+// a cab without seats could never be dispatched
+fn check_seats<'a>(mut cabs: impl Iterator<Item = &'a Cab>) -> Result<(), FuberError> {
+    if cabs.any(|x| x.seats == 0) {
+        Err(FuberError::validation("a cab needs at least one seat"))
+    } else {
+        Ok(())
     }
 }
 
+// new cabs go to the queued ride requests first
+#[post("/create", data = "<new_cab>")]
+pub fn create_cab(
+    _admin: Admin,
+    db: &State<BoxedRepo>,
+    travel: &State<Travel>,
+    new_cab: Json<Cab>,
+) -> Result<Json<String>, FuberError> {
+    check_seats(std::iter::once(&*new_cab))?;
+    let data =
+        Cab::with_category(new_cab.location.clone(), new_cab.category).with_seats(new_cab.seats);
+
+    let cab = db.create_cab(data)?;
+    match_waiting(db, travel)?;
+    Ok(Json(cab.inserted_id.to_hex()))
+}
+
 #[post("/create/fleet", data = "<fleet>")]
 pub fn create_fleet(
-    db: &State<MongoRepo>,
+    _admin: Admin,
+    db: &State<BoxedRepo>,
+    travel: &State<Travel>,
     fleet: Json<Vec<Cab>>,
-) -> Result<Json<Vec<Option<String>>>, Status> {
+) -> Result<Json<Vec<Option<String>>>, FuberError> {
     let data = fleet.into_inner();
-    match db.create_fleet(data) {
-        Ok(fleet) => {
-            let bson = fleet.inserted_ids.values().clone();
-            let vec_obj_id = bson
-                .into_iter()
-                .map(|x| match x.as_object_id() {
-                    Some(s) => Some(s.to_hex()),
-                    None => None,
-                })
-                .collect::<Vec<Option<String>>>();
-            Ok(Json(vec_obj_id))
-        }
-        Err(_) => Err(Status::InternalServerError),
-    }
+    check_seats(data.iter())?;
+    let fleet = db.create_fleet(data)?;
+    match_waiting(db, travel)?;
+    let vec_obj_id = fleet
+        .inserted_ids
+        .into_iter()
+        .map(|x| Some(x.to_hex()))
+        .collect::<Vec<Option<String>>>();
+    Ok(Json(vec_obj_id))
 }
 
 pub fn simulate_fleet(n: usize) -> Json<Vec<Cab>> {
     let points: Vec<Point> = Point::create_random_points(n);
 
-    Json(points.into_iter().map(|x| Cab::new(x)).collect())
+    Json(points.into_iter().map(Cab::new).collect())
 }
 
 #[get("/fleet/<size>")]
-pub fn generate_fleet(_db: &State<MongoRepo>, size: usize) -> Json<Vec<Cab>> {
+pub fn generate_fleet(_admin: Admin, _db: &State<BoxedRepo>, size: usize) -> Json<Vec<Cab>> {
     simulate_fleet(size)
 }
 
 #[get("/<cab_id>")]
-pub fn get_cab(db: &State<MongoRepo>, cab_id: String) -> Result<Json<Cab>, Status> {
+pub fn get_cab(db: &State<BoxedRepo>, cab_id: String) -> Result<Json<Cab>, FuberError> {
     if cab_id.is_empty() {
-        Err(Status::BadRequest)
+        Err(FuberError::InvalidId(
+            ErrorCode::InvalidObjectId,
+            "cab id cannot be empty".into(),
+        ))
     } else {
-        match db.get_cab(&cab_id) {
-            Ok(cab) => Ok(Json(cab)),
-            Err(_) => Err(Status::InternalServerError),
-        }
+        Ok(Json(db.get_cab(&cab_id)?))
     }
 }
 
 #[get("/fleet")]
-pub fn get_fleet(db: &State<MongoRepo>) -> Result<Json<Vec<Cab>>, Status> {
-    let cabs = db.get_fleet();
-    match cabs {
-        Ok(cabs) => Ok(Json(cabs)),
-        Err(_) => Err(Status::InternalServerError),
-    }
+pub fn get_fleet(db: &State<BoxedRepo>) -> Result<Json<Vec<Cab>>, FuberError> {
+    Ok(Json(db.get_fleet()?))
 }
 
-fn is_free(cab: Json<Cab>) -> Result<(), Status> {
+fn is_free(cab: Json<Cab>) -> Result<(), FuberError> {
     match cab.into_inner().person_id {
         None => Ok(()),
-        Some(_) => Err(Status::Forbidden),
+        Some(_) => Err(FuberError::Conflict(
+            ErrorCode::CabAlreadyAssigned,
+            "cab is already assigned".into(),
+        )),
     }
 }
 
 #[put("/assign_person/<person_id>", data = "<cab>")]
 pub fn assign_person(
-    db: &State<MongoRepo>,
+    _admin: Admin,
+    db: &State<BoxedRepo>,
     person_id: String,
     cab: Json<Cab>,
-) -> Result<Json<Cab>, Status> {
+) -> Result<Json<Cab>, FuberError> {
     if person_id.is_empty() {
-        Err(Status::BadRequest)
+        Err(FuberError::InvalidId(
+            ErrorCode::InvalidObjectId,
+            "person id cannot be empty".into(),
+        ))
     } else {
-        match is_free(cab.clone()) {
-            Ok(_) => {
-                if person_id.is_empty() {
-                    Err(Status::BadRequest)
-                } else {
-                    let mut cab = cab.into_inner().clone();
-                    let person = db
-                        .get_person(&person_id)
-                        .expect("Cannot find the person in the db");
-                    cab.update_destination(Some(person.destination));
-                    cab.update_person_id(ObjectId::parse_str(&person_id).ok());
-                    let cab_id = cab.id.and_then(|x| Some(x.to_hex())).unwrap();
-                    let update_result = db.assign_person(&cab_id, cab.clone());
-                    match update_result {
-                        Ok(update) => {
-                            if update.matched_count == 1 {
-                                let updated_cab_info = db.get_cab(&cab_id);
-                                match updated_cab_info {
-                                    Ok(cab) => Ok(Json(cab)),
-                                    Err(_) => Err(Status::InternalServerError),
-                                }
-                            } else {
-                                Err(Status::NotFound)
-                            }
-                        }
-                        Err(_) => Err(Status::InternalServerError),
-                    }
-                }
+        is_free(cab.clone())?;
+        let mut cab = cab.into_inner();
+        let person = db.get_person(&person_id)?;
+        cab.update_destination(Some(person.destination));
+        cab.update_person_id(person.id);
+        let cab_id = match cab.id {
+            Some(obj_id) => obj_id.to_hex(),
+            None => {
+                return Err(FuberError::InvalidId(
+                    ErrorCode::InvalidObjectId,
+                    "the cab has no id".into(),
+                ))
             }
-            Err(e) => Err(e),
+        };
+        let update = db.assign_person(&cab_id, cab)?;
+        if update.matched_count == 1 {
+            Ok(Json(db.get_cab(&cab_id)?))
+        } else {
+            // either the cab doesn't exist or somebody took it first
+            db.get_cab(&cab_id)?;
+            Err(FuberError::Conflict(
+                ErrorCode::CabAlreadyAssigned,
+                "cab is already assigned".into(),
+            ))
         }
     }
 }
 
+// the cab as it is stored after a move goes out to everyone tracking it
+fn publish_move(db: &BoxedRepo, tracker: &Tracker, cab_id: &str) -> Result<(), FuberError> {
+    tracker.publish(&db.get_cab(cab_id)?);
+    Ok(())
+}
+
 #[put("/update_location/<cab_id>", data = "<point>")]
 pub fn update_location(
-    db: &State<MongoRepo>,
+    caller: Caller,
+    db: &State<BoxedRepo>,
+    tracker: &State<Tracker>,
     cab_id: String,
     point: Json<Option<Point>>,
-) -> Result<Json<Cab>, Status> {
-    if cab_id.is_empty() {
-        Err(Status::BadRequest)
-    } else {
-        match get_cab(db, cab_id) {
-            Ok(Json(mut cab)) => match point.into_inner() {
-                Some(p) => {
-                    cab.update_location(p);
-                    match db.update_cab(cab.clone()) {
-                        Ok(update) => {
-                            if update.matched_count == 1 {
-                                Ok(Json(cab))
-                            } else {
-                                Err(Status::NotFound)
-                            }
-                        }
-                        Err(_) => Err(Status::InternalServerError),
-                    }
-                }
-                None => Err(Status::BadRequest),
-            },
-            Err(_) => Err(Status::BadRequest),
+) -> Result<Json<Cab>, FuberError> {
+    caller.may_drive(&cab_id)?;
+    get_cab(db, cab_id.clone())?;
+    match point.into_inner() {
+        Some(p) => {
+            let update = db.update_location(&cab_id, p)?;
+            if update.matched_count == 1 {
+                let cab = db.get_cab(&cab_id)?;
+                tracker.publish(&cab);
+                Ok(Json(cab))
+            } else {
+                Err(FuberError::NotFound(
+                    ErrorCode::CabNotFound,
+                    "the cab was deleted".into(),
+                ))
+            }
         }
+        None => Err(FuberError::validation("a location is required")),
     }
 }
 
 #[put("/update_cab/<cab_id>", data = "<new_cab_info>")]
 pub fn update_cab(
-    db: &State<MongoRepo>,
+    _admin: Admin,
+    db: &State<BoxedRepo>,
+    tracker: &State<Tracker>,
     cab_id: String,
     new_cab_info: Json<Cab>,
-) -> Result<Json<Cab>, Status> {
+) -> Result<Json<Cab>, FuberError> {
     if cab_id.is_empty() {
-        Err(Status::BadRequest)
+        Err(FuberError::InvalidId(
+            ErrorCode::InvalidObjectId,
+            "cab id cannot be empty".into(),
+        ))
     } else {
+        let obj_id = ObjectId::parse_str(&cab_id).map_err(|_| FuberError::invalid_id(&cab_id))?;
+        check_seats(std::iter::once(&*new_cab_info))?;
         let new_cab = Cab {
-            id: ObjectId::parse_str(cab_id).ok(),
+            id: Some(obj_id),
             location: new_cab_info.location.clone(),
             destination: new_cab_info.destination.clone(),
             person_id: new_cab_info.person_id,
+            category: new_cab_info.category,
+            seats: new_cab_info.seats,
+            // riders come and go through dispatch only
+            riders: Vec::new(),
+            stops: Vec::new(),
         };
-        match db.update_cab(new_cab.clone()) {
-            Ok(update) => {
-                if update.matched_count == 1 {
-                    Ok(Json(new_cab))
-                } else {
-                    Err(Status::NotFound)
-                }
-            }
-            Err(_) => Err(Status::InternalServerError),
+        let update = db.update_cab(new_cab.clone())?;
+        if update.matched_count == 1 {
+            publish_move(db, tracker, &cab_id)?;
+            Ok(Json(new_cab))
+        } else {
+            Err(FuberError::cab_not_found(&cab_id))
         }
     }
 }
 
 #[delete("/delete_cab/<cab_id>")]
-pub fn delete_cab(db: &State<MongoRepo>, cab_id: String) -> Result<Json<String>, Status> {
+pub fn delete_cab(
+    _admin: Admin,
+    db: &State<BoxedRepo>,
+    cab_id: String,
+) -> Result<Json<String>, FuberError> {
     if cab_id.is_empty() {
-        Err(Status::BadRequest)
+        Err(FuberError::InvalidId(
+            ErrorCode::InvalidObjectId,
+            "cab id cannot be empty".into(),
+        ))
     } else {
-        match db.delete_cab(&cab_id) {
-            Ok(res) => {
-                if res.deleted_count == 1 {
-                    Ok(Json("Cab successfully deleted!".into()))
-                } else {
-                    Err(Status::NotFound)
-                }
-            }
-            Err(_) => Err(Status::InternalServerError),
+        let res = db.delete_cab(&cab_id)?;
+        if res.deleted_count == 1 {
+            Ok(Json("Cab successfully deleted!".into()))
+        } else {
+            Err(FuberError::cab_not_found(&cab_id))
         }
     }
 }
 
 #[delete("/delete_fleet")]
-pub fn delete_fleet(db: &State<MongoRepo>) -> Result<Json<String>, Status> {
-    match db.delete_fleet() {
-        Ok(res) => {
-            if res.deleted_count >= 1 {
-                Ok(Json("Fleet successfully deleted!".into()))
-            } else {
-                Err(Status::NotFound)
-            }
-        }
-        Err(_) => Err(Status::InternalServerError),
+pub fn delete_fleet(_admin: Admin, db: &State<BoxedRepo>) -> Result<Json<String>, FuberError> {
+    let res = db.delete_fleet()?;
+    if res.deleted_count >= 1 {
+        Ok(Json("Fleet successfully deleted!".into()))
+    } else {
+        Err(FuberError::NotFound(
+            ErrorCode::CabNotFound,
+            "there is no fleet to delete".into(),
+        ))
     }
 }
@@ -0,0 +1,56 @@
+use crate::{
+    auth::Authenticated,
+    error::{ErrorBody, ErrorCode},
+};
+
+use rocket::{catch, serde::json::Json, Request};
+
+// Catchers only run when no handler produced a response, e.g. an unknown
+// route, a body that doesn't deserialize or a handler that panicked.
+// They answer with the same `ErrorBody` the handlers use for their errors.
+
+// the guards tell why they turned the caller away
+#[catch(401)]
+pub fn unauthorized(req: &Request) -> Json<ErrorBody> {
+    let message = match &Authenticated::of(req).0 {
+        Err(e) => e.message().to_string(),
+        Ok(_) => "the request needs a bearer token".to_string(),
+    };
+    Json(ErrorBody::new(ErrorCode::Unauthorized, message, req))
+}
+
+#[catch(403)]
+pub fn forbidden(req: &Request) -> Json<ErrorBody> {
+    let message = match &Authenticated::of(req).0 {
+        Ok(caller) => format!("a {} cannot do that", caller.role.as_str()),
+        Err(e) => e.message().to_string(),
+    };
+    Json(ErrorBody::new(ErrorCode::Forbidden, message, req))
+}
+
+#[catch(404)]
+pub fn not_found(req: &Request) -> Json<ErrorBody> {
+    Json(ErrorBody::new(
+        ErrorCode::RouteNotFound,
+        format!("no route matches {} {}", req.method(), req.uri()),
+        req,
+    ))
+}
+
+#[catch(422)]
+pub fn unprocessable_entity(req: &Request) -> Json<ErrorBody> {
+    Json(ErrorBody::new(
+        ErrorCode::InvalidRequestBody,
+        "the request body couldn't be understood",
+        req,
+    ))
+}
+
+#[catch(500)]
+pub fn internal_error(req: &Request) -> Json<ErrorBody> {
+    Json(ErrorBody::new(
+        ErrorCode::InternalError,
+        "something went wrong on our side",
+        req,
+    ))
+}
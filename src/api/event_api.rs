@@ -0,0 +1,35 @@
+use crate::{
+    auth::Admin, error::FuberError, models::event_model::EventPage,
+    repository::fuber_repo::BoxedRepo,
+};
+
+use rocket::{get, serde::json::Json, State};
+
+pub const DEFAULT_PAGE_SIZE: usize = 100;
+pub const MAX_PAGE_SIZE: usize = 1000;
+
+// The change feed of cabs and persons, oldest first. Consumers start from
+// a `since` of 0 and pass the `next` of every page on as the `since` of
+// the following one, an empty page means they are caught up.
+#[get("/?<since>&<limit>")]
+pub fn get_events(
+    _admin: Admin,
+    db: &State<BoxedRepo>,
+    since: Option<i64>,
+    limit: Option<usize>,
+) -> Result<Json<EventPage>, FuberError> {
+    let since = since.unwrap_or(0);
+    if since < 0 {
+        return Err(FuberError::validation("since cannot be negative"));
+    }
+    let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if limit == 0 || limit > MAX_PAGE_SIZE {
+        return Err(FuberError::validation(format!(
+            "limit has to be between 1 and {}",
+            MAX_PAGE_SIZE
+        )));
+    }
+    let events = db.get_events(since, limit)?;
+    let next = events.last().map_or(since, |x| x.seq);
+    Ok(Json(EventPage { events, next }))
+}
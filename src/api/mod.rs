@@ -1,2 +1,53 @@
+pub mod auth_api;
+pub mod booking_api;
 pub mod cab_api;
+pub mod catcher_api;
+pub mod event_api;
 pub mod person_api;
+pub mod pricing_api;
+pub mod queue_api;
+pub mod ride_api;
+pub mod tracking_api;
+pub mod webhook_api;
+
+use rocket::{Build, Rocket};
+
+// The helpers that wipe the fleet or push a person into a cab. They are
+// only built with the `test-routes` feature and even then only mounted
+// with `FUBER_TEST_ROUTES=true`, so a production binary never has them.
+#[cfg(feature = "test-routes")]
+pub fn mount_test_routes(rocket: Rocket<Build>) -> Rocket<Build> {
+    use rocket::{fairing::AdHoc, routes};
+    use std::env;
+
+    dotenv::dotenv().ok();
+    let enabled = match env::var("FUBER_TEST_ROUTES") {
+        Ok(value) => value
+            .parse::<bool>()
+            .unwrap_or_else(|_| panic!("FUBER_TEST_ROUTES cannot be {}", value)),
+        Err(_) => false,
+    };
+    if !enabled {
+        return rocket;
+    }
+    rocket
+        .mount("/person/test", routes![person_api::delete_all_people])
+        .mount(
+            "/cab/test",
+            routes![
+                cab_api::assign_person,
+                cab_api::generate_fleet,
+                cab_api::delete_fleet
+            ],
+        )
+        .attach(AdHoc::on_liftoff("Test routes", |_| {
+            Box::pin(async {
+                rocket::warn!("the test routes under /cab/test and /person/test are mounted");
+            })
+        }))
+}
+
+#[cfg(not(feature = "test-routes"))]
+pub fn mount_test_routes(rocket: Rocket<Build>) -> Rocket<Build> {
+    rocket
+}
@@ -1,225 +1,857 @@
 use crate::{
-    models::cab_model::Cab, models::person_model::Person, repository::mongodb_repos::MongoRepo,
+    api::{queue_api, ride_api::ride_moved_on},
+    auth::{Admin, Caller},
+    dispatch::{min_cost_assignment, CabRequest, Dispatch, PendingRequest},
+    error::{ErrorCode, FuberError},
+    metric::Travel,
+    models::cab_model::{Cab, CabCategory, Rider},
+    models::fare_model::Fare,
+    models::person_model::Person,
+    models::point_model::Point,
+    models::ride_model::{now_millis, Ride, RideState},
+    models::ticket_model::{Ticket, TicketState},
+    pooling::{Insertion, Pooling},
+    pricing::Tariff,
+    repository::{fuber_repo::BoxedRepo, shared_repo::SharedRepo},
+    surge::Surge,
+    tracking::Tracker,
 };
 
-use mongodb::{
-    bson::from_bson,
-    bson::{self, oid::ObjectId, DeserializerOptions},
-    results::{InsertManyResult, InsertOneResult},
-};
+use mongodb::bson::oid::ObjectId;
 
-use rocket::{delete, get, http::Status, post, put, serde::json::Json, State};
+use rocket::tokio::{sync::oneshot, task, time};
+use rocket::{delete, get, post, put, serde::json::Json, State};
 
 #[get("/")]
-pub fn hello() -> Result<Json<String>, Status> {
+pub fn hello() -> Result<Json<String>, FuberError> {
     Ok(Json(String::from("Hello from Fuber")))
 }
 
+// a trip from a grid point to a gps position has no length
+pub(crate) fn check_trip(person: &Person) -> Result<(), FuberError> {
+    if person.location.same_kind(&person.destination) {
+        Ok(())
+    } else {
+        Err(FuberError::validation(
+            "location and destination have to be both grid points or both gps positions",
+        ))
+    }
+}
+
 #[post("/create", data = "<new_person>")]
 pub fn create_person(
-    db: &State<MongoRepo>,
+    db: &State<BoxedRepo>,
     new_person: Json<Person>,
-) -> Result<Json<String>, Status> {
+) -> Result<Json<String>, FuberError> {
     let data = Person::new(
         None,
         new_person.name.clone(),
         new_person.location.clone(),
         new_person.destination.clone(),
     );
-    let person_detail = db.create_person(data);
-    match person_detail {
-        Ok(person) => match person.inserted_id.as_object_id() {
-            Some(obj_id) => Ok(Json(obj_id.to_hex())),
-            None => Err(Status::ExpectationFailed),
-        },
-        Err(_) => Err(Status::InternalServerError),
-    }
+    check_trip(&data)?;
+    let person = db.create_person(data)?;
+    Ok(Json(person.inserted_id.to_hex()))
 }
 
 #[get("/<person_id>")]
-pub fn get_person(db: &State<MongoRepo>, person_id: String) -> Result<Json<Person>, Status> {
+pub fn get_person(db: &State<BoxedRepo>, person_id: String) -> Result<Json<Person>, FuberError> {
     if person_id.is_empty() {
-        Err(Status::BadRequest)
+        Err(FuberError::InvalidId(
+            ErrorCode::InvalidObjectId,
+            "person id cannot be empty".into(),
+        ))
     } else {
-        match db.get_person(&person_id) {
-            Ok(person) => Ok(Json(person)),
-            Err(_) => Err(Status::InternalServerError),
-        }
+        Ok(Json(db.get_person(&person_id)?))
     }
 }
 
-// to check if cab is free
-fn is_free(cab: Cab) -> Result<(), Status> {
-    match cab.person_id {
+// how many of the nearest free cabs `request_cab` fetches at once
+const DISPATCH_BATCH: usize = 8;
+
+// the ride of the person that hasn't been completed or cancelled yet
+pub fn active_ride(db: &BoxedRepo, person_id: &str) -> Result<Option<Ride>, FuberError> {
+    Ok(db
+        .get_rides_of_person(person_id)?
+        .into_iter()
+        .rev()
+        .find(|x| x.state.is_active()))
+}
+
+// a request that couldn't be served still leaves its ride behind, cancelled
+fn cancel_ride(db: &BoxedRepo, mut ride: Ride, err: FuberError) -> FuberError {
+    let expected = ride.state;
+    match ride.move_to(RideState::Cancelled) {
+        Ok(()) => match db.update_ride(ride, expected) {
+            Ok(_) => err,
+            Err(e) => e,
+        },
+        Err(e) => e,
+    }
+}
+
+// The free cabs dispatch should try first, cheapest first by
+// `Travel::dispatch_costs`. The repo only knows `Point::dist`, so after the
+// nearest batch by that it is asked again for every cab that could still be
+// as cheap as that batch. Cabs that can't get to the point at all are left
+// out, and so are the ones farther than `within` by `Point::dist`.
+pub(crate) fn nearest_free_cabs(
+    db: &BoxedRepo,
+    travel: &Travel,
+    point: &Point,
+    category: Option<CabCategory>,
+    within: Option<f64>,
+) -> Result<Vec<Cab>, FuberError> {
+    let costs = |cabs: &[Cab]| {
+        let locations = cabs.iter().map(|x| &x.location).collect::<Vec<&Point>>();
+        travel.dispatch_costs(&locations, point)
+    };
+    let nearest = db.find_nearest_free_cabs(point, DISPATCH_BATCH, within, category)?;
+    // a short batch is every free cab there is
+    let cabs = if nearest.len() < DISPATCH_BATCH {
+        nearest
+    } else {
+        // when none of the batch can make it every cab is worth a look
+        let reach = costs(&nearest)
+            .into_iter()
+            .filter(|x| x.is_finite())
+            .reduce(f64::max)
+            .map(|x| travel.dispatch_reach(x));
+        let reach = match (reach, within) {
+            (Some(reach), Some(within)) => Some(reach.min(within)),
+            (reach, within) => reach.or(within),
+        };
+        db.find_nearest_free_cabs(point, usize::MAX, reach, category)?
+    };
+    let mut ranked = costs(&cabs)
+        .into_iter()
+        .zip(cabs)
+        .filter(|(cost, _)| cost.is_finite())
+        .collect::<Vec<(f64, Cab)>>();
+    // stable, so cabs that cost the same stay nearest first by
+    // `Point::dist`
+    ranked.sort_by(|a, b| a.0.total_cmp(&b.0));
+    Ok(ranked
+        .into_iter()
+        .take(DISPATCH_BATCH)
+        .map(|(_, cab)| cab)
+        .collect())
+}
+
+// A request for a category the fleet has no cab of at all fails right
+// away instead of waiting in the queue, only requests whose cabs are all
+// busy are queued.
+fn check_category(db: &BoxedRepo, category: Option<CabCategory>) -> Result<(), FuberError> {
+    match category {
+        Some(category) if !db.has_cab_of_category(category)? => Err(FuberError::Conflict(
+            ErrorCode::NoCabsInCategory,
+            format!("the fleet has no {} cabs", category.as_str()),
+        )),
+        _ => Ok(()),
+    }
+}
+
+// One ride at a time, a person whose ride is still going or queued can't
+// ask for another one. A queued request is a ride that is still requested.
+fn check_no_active_ride(db: &BoxedRepo, person_id: &str) -> Result<(), FuberError> {
+    match active_ride(db, person_id)? {
+        Some(ride) => Err(FuberError::Conflict(
+            ErrorCode::RideAlreadyActive,
+            format!(
+                "the person already has the {} ride {}",
+                ride.state.as_str(),
+                ride.id.unwrap_or_default().to_hex()
+            ),
+        )),
         None => Ok(()),
-        Some(_) => Err(Status::Forbidden),
     }
 }
 
-#[get("/request_cab/<person_id>")]
+// every request starts a new ride, priced with the surge where it starts
+pub(crate) fn start_ride(
+    db: &BoxedRepo,
+    surge: &Surge,
+    person: &Person,
+) -> Result<Ride, FuberError> {
+    let mut ride = Ride::new(
+        person.id.unwrap_or_default(),
+        person.location.clone(),
+        person.destination.clone(),
+    );
+    let reading = surge.record_request(db, &person.location, now_millis())?;
+    ride.surge_multiplier = Some(reading.multiplier);
+    ride.id = Some(db.create_ride(ride.clone())?.inserted_id);
+    Ok(ride)
+}
+
+// Sends `cab` to pick up the person and starts the ride on it. `None` when
+// another request got the cab first, `assign_person` only takes it if it is
+// still free.
+fn try_assign(
+    db: &BoxedRepo,
+    travel: &Travel,
+    person: &Person,
+    ride: &mut Ride,
+    mut cab: Cab,
+) -> Result<Option<Cab>, FuberError> {
+    // update cab destination and person_id
+    cab.update_destination(Some(person.location.clone()));
+    cab.update_person_id(person.id);
+    let cab_id = match cab.id {
+        Some(obj_id) => obj_id.to_hex(),
+        None => return Err(FuberError::storage("cannot get the cab id")),
+    };
+    let update = db.assign_person(&cab_id, cab)?;
+    if update.matched_count != 1 {
+        return Ok(None);
+    }
+    let cab = db.get_cab(&cab_id)?;
+    let expected = ride.state;
+    ride.eta_minutes = Some(travel.eta_minutes(&cab.location, &person.location));
+    let assigned = match ride.assign(cab.id.unwrap_or_default()) {
+        Ok(()) => db.update_ride(ride.clone(), expected)?.matched_count == 1,
+        Err(_) => false,
+    };
+    if !assigned {
+        // the ride got cancelled while we took the cab, which goes back to
+        // the fleet
+        let mut free = cab;
+        free.update_destination(None);
+        free.update_person_id(None);
+        db.unassign_person(&cab_id, free)?;
+        return Err(ride_moved_on(db, &ride.id.unwrap_or_default().to_hex()));
+    }
+    Ok(Some(cab))
+}
+
+// Gives the person the cheapest free cab there is, `None` when there isn't
+// any within `within`. The nearest free cabs are fetched a batch at a time, the batch can be
+// stale by the time we get to a cab, so on a conflict we move on to the
+// next one and ask again once the batch is used up.
+pub(crate) fn assign_nearest_cab(
+    db: &BoxedRepo,
+    travel: &Travel,
+    person: &Person,
+    ride: &mut Ride,
+    category: Option<CabCategory>,
+    within: Option<f64>,
+) -> Result<Option<Cab>, FuberError> {
+    loop {
+        let free_cabs = nearest_free_cabs(db, travel, &person.location, category, within)?;
+        if free_cabs.is_empty() {
+            return Ok(None);
+        }
+        for cab in free_cabs {
+            if let Some(cab) = try_assign(db, travel, person, ride, cab)? {
+                return Ok(Some(cab));
+            }
+        }
+    }
+}
+
+// Serves the request with the cheapest free cab, or queues it when there is
+// none. A cab can free up between our search and the ticket being stored,
+// with nobody in the queue yet to give it to, so the queue is matched once
+// more right after and the request gets that cab if it was first in line.
+pub(crate) fn serve_request(
+    db: &BoxedRepo,
+    travel: &Travel,
+    person: Person,
+    mut ride: Ride,
+    category: Option<CabCategory>,
+) -> Result<CabRequest, FuberError> {
+    if let Some(cab) = assign_nearest_cab(db, travel, &person, &mut ride, category, None)? {
+        return Ok(CabRequest::Assigned(Json((person, cab))));
+    }
+    let ticket = Ticket::new(
+        person.id.unwrap_or_default(),
+        ride.id.unwrap_or_default(),
+        category,
+    );
+    let ticket_id = db.create_ticket(ticket)?.inserted_id.to_hex();
+    match_waiting(db, travel)?;
+    let ticket = db.get_ticket(&ticket_id)?;
+    match ticket.cab_id {
+        Some(cab_id) => Ok(CabRequest::Assigned(Json((
+            person,
+            db.get_cab(&cab_id.to_hex())?,
+        )))),
+        None => Ok(CabRequest::Queued(Json(queue_api::ticket_status(
+            db, ticket,
+        )?))),
+    }
+}
+
+// the queued ride request goes away along with its ride, the person is
+// gone or the ride got cancelled
+fn drop_ticket(db: &BoxedRepo, mut ticket: Ticket, ride: Option<Ride>) -> Result<(), FuberError> {
+    ticket.state = TicketState::Cancelled;
+    if db
+        .update_ticket(ticket, TicketState::Waiting)?
+        .matched_count
+        == 1
+    {
+        if let Some(mut ride) = ride {
+            let expected = ride.state;
+            if ride.move_to(RideState::Cancelled).is_ok() {
+                db.update_ride(ride, expected)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Gives the free cabs to the queued requests, first come first served.
+// Every handler that frees up a cab or adds one to the fleet calls this, a
+// request only gets skipped while there is no free cab for it, e.g. of the
+// category it asked for. A ticket is claimed before any cab is tried, so
+// two of these running at once never serve the same request twice.
+pub fn match_waiting(db: &BoxedRepo, travel: &Travel) -> Result<(), FuberError> {
+    for mut ticket in db.get_waiting_tickets()? {
+        let mut ride = db.get_ride(&ticket.ride_id.to_hex())?;
+        if ride.state != RideState::Requested {
+            drop_ticket(db, ticket, None)?;
+            continue;
+        }
+        let person = match db.get_person(&ticket.person_id.to_hex()) {
+            Ok(person) => person,
+            Err(FuberError::NotFound(..)) => {
+                drop_ticket(db, ticket, Some(ride))?;
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        let free_cabs = nearest_free_cabs(db, travel, &person.location, ticket.category, None)?;
+        if free_cabs.is_empty() {
+            continue;
+        }
+
+        ticket.state = TicketState::Matched;
+        if db
+            .update_ticket(ticket.clone(), TicketState::Waiting)?
+            .matched_count
+            != 1
+        {
+            continue;
+        }
+        match assign_nearest_cab(db, travel, &person, &mut ride, ticket.category, None) {
+            Ok(Some(cab)) => {
+                ticket.cab_id = cab.id;
+                db.update_ticket(ticket, TicketState::Matched)?;
+            }
+            // every free cab got taken meanwhile, back in line it goes
+            Ok(None) => {
+                ticket.state = TicketState::Waiting;
+                db.update_ticket(ticket, TicketState::Matched)?;
+            }
+            // the ride got cancelled meanwhile, and so does the ticket
+            Err(FuberError::Conflict(ErrorCode::InvalidRideTransition, _)) => {
+                ticket.state = TicketState::Cancelled;
+                db.update_ticket(ticket, TicketState::Matched)?;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+// `category` restricts the search to free cabs of that category, without it
+// any free cab will do. Without a free cab the request is queued and
+// answered with a ticket instead.
+#[get("/request_cab/<person_id>?<category>")]
 pub fn request_cab(
-    db: &State<MongoRepo>,
+    caller: Caller,
+    db: &State<BoxedRepo>,
+    travel: &State<Travel>,
+    surge: &State<Surge>,
     person_id: String,
-) -> Result<Json<(Person, Cab)>, Status> {
+    category: Option<String>,
+) -> Result<CabRequest, FuberError> {
+    caller.may_ride_as(&person_id)?;
     if person_id.is_empty() {
-        Err(Status::BadRequest)
+        Err(FuberError::InvalidId(
+            ErrorCode::InvalidObjectId,
+            "person id cannot be empty".into(),
+        ))
     } else {
+        let category = category.map(|x| x.parse::<CabCategory>()).transpose()?;
         // get person using person_id
-        let person = db
-            .get_person(&person_id)
-            .expect("Cannot get a person's detail");
-        // get fleet
-        let fleet = db.get_fleet().expect("Unable to get the fleet");
-        // find the nearest cab in the fleet from the person
-        let mut nearest_cab = fleet
+        let person = db.get_person(&person_id)?;
+        check_no_active_ride(db, &person_id)?;
+        check_category(db, category)?;
+        let ride = start_ride(db, surge, &person)?;
+        serve_request(db, travel, person, ride, category)
+    }
+}
+
+// Seats the rider of the ride in the cab that drives the least longer for
+// them, one that is shared already or a free one. `None` when no cab takes
+// them without a longer detour than `pooling` allows. A cab is claimed by
+// its riders and stops as we saw them, when somebody else got it first we
+// ask again.
+pub(crate) fn pool_ride(
+    db: &BoxedRepo,
+    travel: &Travel,
+    pooling: &Pooling,
+    person: &Person,
+    ride: &mut Ride,
+    category: Option<CabCategory>,
+) -> Result<Option<Cab>, FuberError> {
+    let ride_id = ride.id.unwrap_or_default();
+    loop {
+        let mut cabs = db.get_pooled_cabs(category)?;
+        cabs.extend(nearest_free_cabs(db, travel, &ride.pickup, category, None)?);
+        let mut options = cabs
             .into_iter()
-            .filter(|x| is_free((*x).clone()).is_ok())
-            .reduce(|c1, c2| person.nearest_cab(&c1, &c2))
-            .and_then(|x| Some(x.clone()))
-            .expect("Unable to get the nearest cab");
-        // check if the cab is assigned or not
-
-        // update cab destination and person_id
-        nearest_cab.update_destination(Some(person.location.clone()));
-        nearest_cab.update_person_id(ObjectId::parse_str(person_id).ok());
-        // update cab by using `assign_person`
-        let cab_id = match nearest_cab.id {
-            Some(obj_id) => obj_id.to_hex(),
-            None => panic!("cannot get the cab id"),
-        };
-        let update_result = db.assign_person(&cab_id, nearest_cab.clone());
-        // return result as person and cab tuple
-        match update_result {
-            Ok(update) => {
-                if update.matched_count == 1 {
-                    let updated_cab_info = db.get_cab(&cab_id);
-                    match updated_cab_info {
-                        Ok(cab) => Ok(Json((person, cab))),
-                        Err(_) => Err(Status::InternalServerError),
+            .filter_map(|cab| {
+                pooling
+                    .insert(travel, &cab, ride_id, &ride.pickup, &ride.drop)
+                    .map(|x| (cab, x))
+            })
+            .collect::<Vec<(Cab, Insertion)>>();
+        if options.is_empty() {
+            return Ok(None);
+        }
+        options.sort_by(|a, b| a.1.added_minutes.total_cmp(&b.1.added_minutes));
+        for (expected, insertion) in options {
+            let mut cab = expected.clone();
+            cab.riders.push(Rider {
+                person_id: person.id.unwrap_or_default(),
+                ride_id,
+                onboard: false,
+            });
+            cab.stops = insertion.stops;
+            cab.update_destination(cab.stops.first().map(|x| x.location.clone()));
+            if db.update_pool(cab.clone(), &expected)?.matched_count != 1 {
+                continue;
+            }
+            let state = ride.state;
+            ride.assign(cab.id.unwrap_or_default())?;
+            ride.eta_minutes = Some(insertion.pickup_minutes);
+            if db.update_ride(ride.clone(), state)?.matched_count != 1 {
+                // the ride got cancelled while we took the seat
+                update_pooled(db, cab.id.unwrap_or_default(), ride_id, |cab| {
+                    cab.leave(ride_id)
+                })?;
+                return Err(ride_moved_on(db, &ride_id.to_hex()));
+            }
+            return Ok(Some(cab));
+        }
+    }
+}
+
+// Changes the shared cab of the ride by `change`, as long as the rider of
+// the ride is still in it. The cab changes under us while other riders get
+// in and out, so we go again until our change is the one that's stored.
+pub(crate) fn update_pooled(
+    db: &BoxedRepo,
+    cab_id: ObjectId,
+    ride_id: ObjectId,
+    change: impl Fn(&mut Cab),
+) -> Result<Option<Cab>, FuberError> {
+    loop {
+        let expected = db.get_cab(&cab_id.to_hex())?;
+        if expected.rider(ride_id).is_none() {
+            return Ok(None);
+        }
+        let mut cab = expected.clone();
+        change(&mut cab);
+        if db.update_pool(cab.clone(), &expected)?.matched_count == 1 {
+            return Ok(Some(cab));
+        }
+    }
+}
+
+// `request_cab` for people who are fine sharing their cab. They join a
+// shared cab going their way or start sharing a free one, and when that
+// would take everyone on board too far out of their way the request is
+// served like any other, with a cab of its own or the queue.
+#[get("/request_pool/<person_id>?<category>")]
+pub fn request_pool(
+    caller: Caller,
+    db: &State<BoxedRepo>,
+    travel: &State<Travel>,
+    pooling: &State<Pooling>,
+    surge: &State<Surge>,
+    person_id: String,
+    category: Option<String>,
+) -> Result<CabRequest, FuberError> {
+    caller.may_ride_as(&person_id)?;
+    if person_id.is_empty() {
+        return Err(FuberError::InvalidId(
+            ErrorCode::InvalidObjectId,
+            "person id cannot be empty".into(),
+        ));
+    }
+    let category = category.map(|x| x.parse::<CabCategory>()).transpose()?;
+    let person = db.get_person(&person_id)?;
+    check_no_active_ride(db, &person_id)?;
+    check_category(db, category)?;
+    let mut ride = start_ride(db, surge, &person)?;
+    match pool_ride(db, travel, pooling, &person, &mut ride, category)? {
+        Some(cab) => Ok(CabRequest::Assigned(Json((person, cab)))),
+        None => serve_request(db, travel, person, ride, category),
+    }
+}
+
+// The pooled rider of the ride got dropped off. They leave the cab, which
+// is now at their drop, and pay for their own trip.
+fn drop_off_pooled(
+    db: &BoxedRepo,
+    tariff: &Tariff,
+    travel: &Travel,
+    person: Person,
+    mut ride: Ride,
+) -> Result<Json<(Person, Cab, Fare)>, FuberError> {
+    let ride_id = ride.id.unwrap_or_default();
+    let cab_id = ride.cab_id.unwrap_or_default();
+    let no_cab = || {
+        FuberError::Conflict(
+            ErrorCode::NoCabAssigned,
+            "the person doesn't have a cab assigned".into(),
+        )
+    };
+    let cab = db.get_cab(&cab_id.to_hex())?;
+    if cab.rider(ride_id).is_none() {
+        return Err(no_cab());
+    }
+    // the category decides the surcharge on the fare
+    let category = cab.category;
+    // the ride is done before the seat is given up, so nobody else gets it
+    // while the ride could still be moved on by somebody else
+    let expected = ride.state;
+    ride.complete()?;
+    let fare = tariff.fare_for_ride(&ride, Some(category.as_str()), travel.metric());
+    ride.fare = Some(fare.clone());
+    if db.update_ride(ride.clone(), expected)?.matched_count != 1 {
+        return Err(ride_moved_on(db, &ride_id.to_hex()));
+    }
+    let drop = ride.drop.clone();
+    let cab = update_pooled(db, cab_id, ride_id, |cab| {
+        cab.leave(ride_id);
+        cab.update_location(drop.clone());
+    })?;
+    let cab = cab.ok_or_else(no_cab)?;
+    // the last rider out frees the cab
+    if cab.is_free() {
+        match_waiting(db, travel)?;
+    }
+    Ok(Json((person, cab, fare)))
+}
+
+// Hands out the cabs to a batch of requests so that they cost the least
+// together. Every request can get any of the cheapest free cabs for itself
+// or for anybody else in the batch, of its category if it asked for one.
+// Requests that are left without a cab, or whose cab got taken by a greedy
+// request meanwhile, get the cheapest cab that is left or are queued.
+pub fn dispatch_batch(db: &BoxedRepo, travel: &Travel, batch: Vec<PendingRequest>) {
+    let mut requests = Vec::with_capacity(batch.len());
+    let mut cabs: Vec<Cab> = Vec::new();
+    for request in batch {
+        // nobody is waiting for a cab anymore
+        if request.reply.is_closed() {
+            let mut ride = request.ride;
+            if ride.move_to(RideState::Cancelled).is_ok() {
+                let _ = db.update_ride(ride, RideState::Requested);
+            }
+            continue;
+        }
+        match nearest_free_cabs(db, travel, &request.person.location, request.category, None) {
+            Ok(nearest) => {
+                for cab in nearest {
+                    if !cabs.iter().any(|x| x.id == cab.id) {
+                        cabs.push(cab);
                     }
-                } else {
-                    Err(Status::NotFound)
                 }
+                requests.push(request);
+            }
+            Err(e) => {
+                let err = cancel_ride(db, request.ride, e);
+                let _ = request.reply.send(Err(err));
+            }
+        }
+    }
+
+    let locations = cabs.iter().map(|x| &x.location).collect::<Vec<&Point>>();
+    let costs = requests
+        .iter()
+        .map(|request| {
+            let mut costs = travel.dispatch_costs(&locations, &request.person.location);
+            for (cost, cab) in costs.iter_mut().zip(cabs.iter()) {
+                if request.category.is_some_and(|c| cab.category != c) {
+                    *cost = f64::INFINITY;
+                }
+            }
+            costs
+        })
+        .collect::<Vec<Vec<f64>>>();
+    let assigned = min_cost_assignment(&costs);
+
+    let mut left = Vec::new();
+    for (mut request, cab) in requests.into_iter().zip(assigned) {
+        let result = match cab {
+            Some(cab) => try_assign(
+                db,
+                travel,
+                &request.person,
+                &mut request.ride,
+                cabs[cab].clone(),
+            ),
+            None => Ok(None),
+        };
+        match result {
+            Ok(Some(cab)) => {
+                let reply = CabRequest::Assigned(Json((request.person, cab)));
+                let _ = request.reply.send(Ok(reply));
+            }
+            Ok(None) => left.push(request),
+            Err(e) => {
+                let _ = request.reply.send(Err(e));
             }
-            Err(_) => Err(Status::InternalServerError),
         }
     }
+    for request in left {
+        let result = serve_request(db, travel, request.person, request.ride, request.category);
+        let _ = request.reply.send(result);
+    }
+}
+
+// `request_cab` for deployments with batched dispatch, mounted instead of
+// it. The request waits for the rest of its batch and whichever request of
+// the batch is first to notice it is due dispatches all of them. The repos
+// block, so it works with them off the async workers like the booking
+// scheduler, through the shared handle rocket manages next to the repo.
+#[get("/request_cab/<person_id>?<category>")]
+pub async fn request_cab_batched(
+    caller: Caller,
+    db: &State<SharedRepo>,
+    travel: &State<Travel>,
+    dispatch: &State<Dispatch>,
+    surge: &State<Surge>,
+    person_id: String,
+    category: Option<String>,
+) -> Result<CabRequest, FuberError> {
+    caller.may_ride_as(&person_id)?;
+    if person_id.is_empty() {
+        return Err(FuberError::InvalidId(
+            ErrorCode::InvalidObjectId,
+            "person id cannot be empty".into(),
+        ));
+    }
+    let category = category.map(|x| x.parse::<CabCategory>()).transpose()?;
+    let (shared, surge) = (db.inner().clone(), surge.inner().clone());
+    let (person, ride) = blocking(move || {
+        let db = shared.repo();
+        let person = db.get_person(&person_id)?;
+        check_no_active_ride(db, &person_id)?;
+        check_category(db, category)?;
+        let ride = start_ride(db, &surge, &person)?;
+        Ok((person, ride))
+    })
+    .await?;
+    let (reply, cab) = oneshot::channel();
+    let due = dispatch.join(PendingRequest {
+        person,
+        ride,
+        category,
+        reply,
+    });
+    time::sleep_until(due.into()).await;
+    if let Some(batch) = dispatch.take_due() {
+        let (shared, travel) = (db.inner().clone(), travel.inner().clone());
+        blocking(move || {
+            dispatch_batch(shared.repo(), &travel, batch);
+            Ok(())
+        })
+        .await?;
+    }
+    match cab.await {
+        Ok(result) => result,
+        Err(_) => Err(FuberError::storage("the ride request got lost in dispatch")),
+    }
+}
+
+// runs `f` on the threads for blocking work and waits for it
+async fn blocking<T, F>(f: F) -> Result<T, FuberError>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, FuberError> + Send + 'static,
+{
+    match task::spawn_blocking(f).await {
+        Ok(result) => result,
+        Err(e) => Err(FuberError::storage(format!("the request failed: {}", e))),
+    }
 }
 
+// The person is dropped off and pays for the ride. The cab goes out to the
+// tracker as it is afterwards, which ends the stream of the person.
 #[get("/unassign_cab/<person_id>")]
 pub fn unassign_cab(
-    db: &State<MongoRepo>,
+    caller: Caller,
+    db: &State<BoxedRepo>,
+    tariff: &State<Tariff>,
+    travel: &State<Travel>,
+    tracker: &State<Tracker>,
     person_id: String,
-) -> Result<Json<(Person, Cab)>, Status> {
+) -> Result<Json<(Person, Cab, Fare)>, FuberError> {
+    caller.may_ride_as(&person_id)?;
     if person_id.is_empty() {
-        Err(Status::BadRequest)
+        Err(FuberError::InvalidId(
+            ErrorCode::InvalidObjectId,
+            "person id cannot be empty".into(),
+        ))
     } else {
         // get person using person_id
-        let person = db
-            .get_person(&person_id)
-            .expect("Cannot get a person's detail");
-        // get fleet
-        let fleet = db.get_fleet().expect("Unable to get the fleet");
-        // find the assigned cab for the person in the fleet
-        let assigned_cab = fleet.into_iter().fold(None, |acc, x| match x.person_id {
-            Some(obj_id) => {
-                if obj_id.to_hex() == person_id {
-                    Some(x)
+        let person = db.get_person(&person_id)?;
+        let no_cab = || {
+            FuberError::Conflict(
+                ErrorCode::NoCabAssigned,
+                "the person doesn't have a cab assigned".into(),
+            )
+        };
+        let released = match active_ride(db, &person_id)? {
+            // the ride knows its cab, whether the person has it to
+            // themselves or shares it
+            Some(ride) => {
+                let cab_id = ride.cab_id.ok_or_else(no_cab)?;
+                let cab = db.get_cab(&cab_id.to_hex())?;
+                if cab.person_id.is_some() && cab.person_id == person.id {
+                    release_cab(db, tariff, travel, person, cab, Some(ride))
                 } else {
-                    None
+                    drop_off_pooled(db, tariff, travel, person, ride)
                 }
             }
-            None => None,
-        });
-        match assigned_cab {
-            None => Err(Status::Forbidden),
-            Some(mut assigned_cab) => {
-                // nullify cab destination and person_id
-                assigned_cab.update_destination(None);
-                assigned_cab.update_person_id(None);
-                assigned_cab.update_location(person.destination.clone());
-                // update cab by using `assign_person`
-                let cab_id = match assigned_cab.id {
-                    Some(obj_id) => obj_id.to_hex(),
-                    None => panic!("cannot get the cab id"),
-                };
-                let update_result = db.unassign_person(&cab_id, assigned_cab.clone());
-                // return result as person and cab tuple
-                match update_result {
-                    Ok(update) => {
-                        if update.matched_count == 1 {
-                            let updated_cab_info = db.get_cab(&cab_id);
-                            match updated_cab_info {
-                                Ok(cab) => Ok(Json((person, cab))),
-                                Err(_) => Err(Status::InternalServerError),
-                            }
-                        } else {
-                            Err(Status::NotFound)
-                        }
-                    }
-                    Err(_) => Err(Status::InternalServerError),
-                }
+            // cabs assigned by hand through the test routes have no ride,
+            // only the fleet knows them
+            None => {
+                let assigned_cab = db
+                    .get_fleet()?
+                    .into_iter()
+                    .find(|x| x.person_id.is_some() && x.person_id == person.id)
+                    .ok_or_else(no_cab)?;
+                release_cab(db, tariff, travel, person, assigned_cab, None)
             }
+        }?;
+        let Json((_, cab, _)) = &released;
+        tracker.publish(cab);
+        Ok(released)
+    }
+}
+
+// Completes and charges the ride of the person, then frees their cab. The
+// ride is stored before the cab is free again, so a cancel that comes in
+// meanwhile can't leave the cab with somebody else and the ride still
+// going. Without a ride the person pays for the straight line.
+fn release_cab(
+    db: &BoxedRepo,
+    tariff: &Tariff,
+    travel: &Travel,
+    person: Person,
+    mut assigned_cab: Cab,
+    ride: Option<Ride>,
+) -> Result<Json<(Person, Cab, Fare)>, FuberError> {
+    let cab_id = match assigned_cab.id {
+        Some(obj_id) => obj_id.to_hex(),
+        None => return Err(FuberError::storage("cannot get the cab id")),
+    };
+    // the category decides the surcharge on the fare
+    let category = Some(assigned_cab.category.as_str());
+    let fare = match ride {
+        Some(mut ride) => {
+            let expected = ride.state;
+            ride.complete()?;
+            let fare = tariff.fare_for_ride(&ride, category, travel.metric());
+            ride.fare = Some(fare.clone());
+            if db.update_ride(ride.clone(), expected)?.matched_count != 1 {
+                return Err(ride_moved_on(db, &ride.id.unwrap_or_default().to_hex()));
+            }
+            fare
         }
+        None => {
+            let distance = travel.dist(&person.location, &person.destination);
+            tariff.fare(distance, 0.0, category)
+        }
+    };
+    // nullify cab destination and person_id
+    assigned_cab.update_destination(None);
+    assigned_cab.update_person_id(None);
+    assigned_cab.update_location(person.destination.clone());
+    // update cab by using `unassign_person`
+    let update = db.unassign_person(&cab_id, assigned_cab)?;
+    // return result as person and cab tuple
+    if update.matched_count == 1 {
+        let cab = db.get_cab(&cab_id)?;
+        // the cab is free again for whoever waits the longest
+        match_waiting(db, travel)?;
+        Ok(Json((person, cab, fare)))
+    } else {
+        Err(FuberError::cab_not_found(&cab_id))
     }
 }
 
 #[put("/update_person/<person_id>", data = "<person_data>")]
 pub fn update_person(
-    db: &State<MongoRepo>,
+    caller: Caller,
+    db: &State<BoxedRepo>,
     person_id: String,
     person_data: Json<Person>,
-) -> Result<Json<Person>, Status> {
+) -> Result<Json<Person>, FuberError> {
+    caller.may_ride_as(&person_id)?;
     if person_id.is_empty() {
-        Err(Status::BadRequest)
+        Err(FuberError::InvalidId(
+            ErrorCode::InvalidObjectId,
+            "person id cannot be empty".into(),
+        ))
     } else {
+        let obj_id =
+            ObjectId::parse_str(&person_id).map_err(|_| FuberError::invalid_id(&person_id))?;
         let new_person = Person {
-            id: ObjectId::parse_str(person_id).ok(),
+            id: Some(obj_id),
             name: person_data.name.clone(),
             location: person_data.location.clone(),
             destination: person_data.destination.clone(),
         };
-        match db.update_person(new_person.clone()) {
-            Ok(update) => {
-                if update.matched_count == 1 {
-                    Ok(Json(new_person))
-                } else {
-                    Err(Status::NotFound)
-                }
-            }
-            Err(_) => Err(Status::InternalServerError),
+        check_trip(&new_person)?;
+        let update = db.update_person(new_person.clone())?;
+        if update.matched_count == 1 {
+            Ok(Json(new_person))
+        } else {
+            Err(FuberError::person_not_found(&person_id))
         }
     }
 }
 
 #[delete("/delete_person/<person_id>")]
-pub fn delete_person(db: &State<MongoRepo>, person_id: String) -> Result<Json<String>, Status> {
+pub fn delete_person(
+    caller: Caller,
+    db: &State<BoxedRepo>,
+    person_id: String,
+) -> Result<Json<String>, FuberError> {
+    caller.may_ride_as(&person_id)?;
     if person_id.is_empty() {
-        Err(Status::BadRequest)
+        Err(FuberError::InvalidId(
+            ErrorCode::InvalidObjectId,
+            "person id cannot be empty".into(),
+        ))
     } else {
-        match db.delete_person(&person_id) {
-            Ok(res) => {
-                if res.deleted_count == 1 {
-                    Ok(Json("Person successfully deleted!".into()))
-                } else {
-                    Err(Status::NotFound)
-                }
-            }
-            Err(_) => Err(Status::InternalServerError),
+        let res = db.delete_person(&person_id)?;
+        if res.deleted_count == 1 {
+            Ok(Json("Person successfully deleted!".into()))
+        } else {
+            Err(FuberError::person_not_found(&person_id))
         }
     }
 }
 
 #[delete("/delete_all_people")]
-pub fn delete_all_people(db: &State<MongoRepo>) -> Result<Json<String>, Status> {
-    match db.delete_all_people() {
-        Ok(res) => {
-            if res.deleted_count >= 1 {
-                Ok(Json("Every person successfully deleted!".into()))
-            } else {
-                Err(Status::NotFound)
-            }
-        }
-        Err(_) => Err(Status::InternalServerError),
+pub fn delete_all_people(_admin: Admin, db: &State<BoxedRepo>) -> Result<Json<String>, FuberError> {
+    let res = db.delete_all_people()?;
+    if res.deleted_count >= 1 {
+        Ok(Json("Every person successfully deleted!".into()))
+    } else {
+        Err(FuberError::NotFound(
+            ErrorCode::PersonNotFound,
+            "there is nobody to delete".into(),
+        ))
     }
 }
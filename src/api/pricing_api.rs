@@ -0,0 +1,33 @@
+use crate::{
+    error::FuberError,
+    models::point_model::Point,
+    models::ride_model::now_millis,
+    repository::fuber_repo::BoxedRepo,
+    surge::{Surge, SurgeReading},
+};
+
+use rocket::{get, serde::json::Json, State};
+
+// The surge a ride requested from a point would be priced with right now,
+// `x` and `y` for a grid point or `lat` and `lon` for a gps position.
+// Looking doesn't count as a request.
+#[get("/surge?<x>&<y>&<lat>&<lon>")]
+pub fn get_surge(
+    db: &State<BoxedRepo>,
+    surge: &State<Surge>,
+    x: Option<i64>,
+    y: Option<i64>,
+    lat: Option<f64>,
+    lon: Option<f64>,
+) -> Result<Json<SurgeReading>, FuberError> {
+    let point = match (x, y, lat, lon) {
+        (Some(x), Some(y), None, None) => Point::grid(x, y)?,
+        (None, None, Some(lat), Some(lon)) => Point::geo(lat, lon)?,
+        _ => {
+            return Err(FuberError::validation(
+                "the surge needs either x and y or lat and lon",
+            ))
+        }
+    };
+    Ok(Json(surge.surge_at(db, &point, now_millis())?))
+}
@@ -0,0 +1,86 @@
+use crate::{
+    auth::Caller,
+    error::{ErrorCode, FuberError},
+    models::ride_model::RideState,
+    models::ticket_model::{Ticket, TicketState, TicketStatus},
+    repository::fuber_repo::BoxedRepo,
+};
+
+use mongodb::bson::oid::ObjectId;
+
+use rocket::{get, put, serde::json::Json, State};
+
+fn empty_ticket_id() -> FuberError {
+    FuberError::InvalidId(
+        ErrorCode::InvalidObjectId,
+        "ticket id cannot be empty".into(),
+    )
+}
+
+// the ticket along with its place in the queue while it is waiting
+pub fn ticket_status(db: &BoxedRepo, ticket: Ticket) -> Result<TicketStatus, FuberError> {
+    let position = match ticket.state {
+        TicketState::Waiting => db
+            .get_waiting_tickets()?
+            .iter()
+            .position(|x| x.id == ticket.id)
+            .map(|x| x + 1),
+        TicketState::Matched | TicketState::Cancelled => None,
+    };
+    Ok(TicketStatus { ticket, position })
+}
+
+// takes the ticket of a ride out of the queue, if it is still waiting
+pub fn cancel_ticket_of_ride(db: &BoxedRepo, ride_id: ObjectId) -> Result<(), FuberError> {
+    let waiting = db.get_waiting_tickets()?;
+    if let Some(mut ticket) = waiting.into_iter().find(|x| x.ride_id == ride_id) {
+        ticket.state = TicketState::Cancelled;
+        db.update_ticket(ticket, TicketState::Waiting)?;
+    }
+    Ok(())
+}
+
+#[get("/<ticket_id>")]
+pub fn get_ticket(
+    caller: Caller,
+    db: &State<BoxedRepo>,
+    ticket_id: String,
+) -> Result<Json<TicketStatus>, FuberError> {
+    if ticket_id.is_empty() {
+        return Err(empty_ticket_id());
+    }
+    let ticket = db.get_ticket(&ticket_id)?;
+    caller.may_ride_as(&ticket.person_id.to_hex())?;
+    Ok(Json(ticket_status(db, ticket)?))
+}
+
+// Takes the request out of the queue and cancels its ride. Only a waiting
+// ticket can be cancelled, a matched one already has its cab and is
+// cancelled through its ride like any other.
+#[put("/<ticket_id>/cancel")]
+pub fn cancel_ticket(
+    caller: Caller,
+    db: &State<BoxedRepo>,
+    ticket_id: String,
+) -> Result<Json<TicketStatus>, FuberError> {
+    if ticket_id.is_empty() {
+        return Err(empty_ticket_id());
+    }
+    let mut ticket = db.get_ticket(&ticket_id)?;
+    caller.may_ride_as(&ticket.person_id.to_hex())?;
+    ticket.state = TicketState::Cancelled;
+    let update = db.update_ticket(ticket.clone(), TicketState::Waiting)?;
+    if update.matched_count != 1 {
+        let ticket = db.get_ticket(&ticket_id)?;
+        return Err(FuberError::Conflict(
+            ErrorCode::TicketNotWaiting,
+            format!("the ticket is already {}", ticket.state.as_str()),
+        ));
+    }
+    let mut ride = db.get_ride(&ticket.ride_id.to_hex())?;
+    if ride.state == RideState::Requested {
+        ride.move_to(RideState::Cancelled)?;
+        db.update_ride(ride, RideState::Requested)?;
+    }
+    Ok(Json(ticket_status(db, ticket)?))
+}
@@ -0,0 +1,165 @@
+use crate::{
+    api::{
+        person_api::{match_waiting, update_pooled},
+        queue_api::cancel_ticket_of_ride,
+    },
+    auth::Caller,
+    error::{ErrorCode, FuberError},
+    metric::Travel,
+    models::ride_model::{Ride, RideState},
+    repository::fuber_repo::BoxedRepo,
+    tracking::Tracker,
+};
+
+use rocket::{get, put, serde::json::Json, State};
+
+fn empty_ride_id() -> FuberError {
+    FuberError::InvalidId(ErrorCode::InvalidObjectId, "ride id cannot be empty".into())
+}
+
+fn load_ride(db: &BoxedRepo, ride_id: &str) -> Result<Ride, FuberError> {
+    if ride_id.is_empty() {
+        return Err(empty_ride_id());
+    }
+    db.get_ride(ride_id)
+}
+
+// somebody else moved the ride on between our read and our write
+pub(crate) fn ride_moved_on(db: &BoxedRepo, ride_id: &str) -> FuberError {
+    match db.get_ride(ride_id) {
+        Ok(ride) => FuberError::Conflict(
+            ErrorCode::InvalidRideTransition,
+            format!("the ride is already {}", ride.state.as_str()),
+        ),
+        Err(e) => e,
+    }
+}
+
+// moves the stored ride to `next`, the state machine in `Ride` decides if
+// that is allowed
+fn advance_ride(db: &BoxedRepo, ride_id: &str, next: RideState) -> Result<Ride, FuberError> {
+    let mut ride = load_ride(db, ride_id)?;
+    let expected = ride.state;
+    ride.move_to(next)?;
+    let update = db.update_ride(ride.clone(), expected)?;
+    if update.matched_count == 1 {
+        Ok(ride)
+    } else {
+        Err(ride_moved_on(db, ride_id))
+    }
+}
+
+// the driver of the cab of the ride, or an admin
+fn may_drive(caller: &Caller, ride: &Ride) -> Result<(), FuberError> {
+    match ride.cab_id {
+        Some(cab_id) => caller.may_drive(&cab_id.to_hex()),
+        None if caller.is_admin() => Ok(()),
+        None => Err(FuberError::forbidden("the ride has no cab to drive yet")),
+    }
+}
+
+#[get("/<ride_id>")]
+pub fn get_ride(
+    caller: Caller,
+    db: &State<BoxedRepo>,
+    ride_id: String,
+) -> Result<Json<Ride>, FuberError> {
+    let ride = load_ride(db, &ride_id)?;
+    caller.may_ride_as(&ride.person_id.to_hex())?;
+    Ok(Json(ride))
+}
+
+#[get("/person/<person_id>")]
+pub fn get_rides_of_person(
+    caller: Caller,
+    db: &State<BoxedRepo>,
+    person_id: String,
+) -> Result<Json<Vec<Ride>>, FuberError> {
+    caller.may_ride_as(&person_id)?;
+    if person_id.is_empty() {
+        Err(FuberError::InvalidId(
+            ErrorCode::InvalidObjectId,
+            "person id cannot be empty".into(),
+        ))
+    } else {
+        Ok(Json(db.get_rides_of_person(&person_id)?))
+    }
+}
+
+#[put("/<ride_id>/driver_arriving")]
+pub fn driver_arriving(
+    caller: Caller,
+    db: &State<BoxedRepo>,
+    ride_id: String,
+) -> Result<Json<Ride>, FuberError> {
+    may_drive(&caller, &load_ride(db, &ride_id)?)?;
+    Ok(Json(advance_ride(db, &ride_id, RideState::DriverArriving)?))
+}
+
+// a shared cab is where the rider got in and heads on to its next stop
+#[put("/<ride_id>/picked_up")]
+pub fn picked_up(
+    caller: Caller,
+    db: &State<BoxedRepo>,
+    ride_id: String,
+) -> Result<Json<Ride>, FuberError> {
+    may_drive(&caller, &load_ride(db, &ride_id)?)?;
+    let ride = advance_ride(db, &ride_id, RideState::PickedUp)?;
+    if let (Some(cab_id), Some(ride_id)) = (ride.cab_id, ride.id) {
+        update_pooled(db, cab_id, ride_id, |cab| cab.board(ride_id))?;
+    }
+    Ok(Json(ride))
+}
+
+// Cancels the ride along with whatever it holds on to, its cab or its place
+// in the queue. Cancelled bookings go through here once they are dispatched.
+pub fn cancel(db: &BoxedRepo, travel: &Travel, ride_id: &str) -> Result<Ride, FuberError> {
+    let ride = advance_ride(db, ride_id, RideState::Cancelled)?;
+    match ride.cab_id {
+        // the cab goes back to the fleet right where it is now and on to
+        // the next request in the queue
+        Some(cab_id) => {
+            let cab = db.get_cab(&cab_id.to_hex())?;
+            if cab.person_id == Some(ride.person_id) {
+                db.unassign_person(&cab_id.to_hex(), cab)?;
+                match_waiting(db, travel)?;
+            } else if let Some(ride_id) = ride.id {
+                // a shared cab just skips the stops of the ride
+                let cab = update_pooled(db, cab_id, ride_id, |cab| cab.leave(ride_id))?;
+                if cab.is_some_and(|x| x.is_free()) {
+                    match_waiting(db, travel)?;
+                }
+            }
+        }
+        // a ride without a cab may still be waiting for one
+        None => cancel_ticket_of_ride(db, ride.id.unwrap_or_default())?,
+    }
+    Ok(ride)
+}
+
+// `cancel` for the handlers, the cab the ride had goes out to the tracker
+// as it is afterwards, which ends the stream of the rider
+pub(crate) fn cancel_tracked(
+    db: &BoxedRepo,
+    travel: &Travel,
+    tracker: &Tracker,
+    ride_id: &str,
+) -> Result<Ride, FuberError> {
+    let ride = cancel(db, travel, ride_id)?;
+    if let Some(cab_id) = ride.cab_id {
+        tracker.publish(&db.get_cab(&cab_id.to_hex())?);
+    }
+    Ok(ride)
+}
+
+#[put("/<ride_id>/cancel")]
+pub fn cancel_ride(
+    caller: Caller,
+    db: &State<BoxedRepo>,
+    travel: &State<Travel>,
+    tracker: &State<Tracker>,
+    ride_id: String,
+) -> Result<Json<Ride>, FuberError> {
+    caller.may_ride_as(&load_ride(db, &ride_id)?.person_id.to_hex())?;
+    Ok(Json(cancel_tracked(db, travel, tracker, &ride_id)?))
+}
@@ -0,0 +1,92 @@
+use crate::{
+    auth::{Admin, Caller},
+    error::{ErrorCode, FuberError},
+    repository::fuber_repo::BoxedRepo,
+    tracking::{CabPosition, Tracker},
+};
+
+use rocket::response::stream::{Event, EventStream};
+use rocket::tokio::{select, sync::broadcast::error::RecvError};
+use rocket::{get, Shutdown, State};
+
+// The cab of the person as it moves, a `location` event with its
+// `CabPosition` right away and on every move after. The stream follows the
+// cab the person has when they subscribe and ends once they aren't in it
+// anymore, or when the server shuts down.
+#[get("/person/<person_id>")]
+pub fn track_cab_of_person(
+    caller: Caller,
+    db: &State<BoxedRepo>,
+    tracker: &State<Tracker>,
+    mut shutdown: Shutdown,
+    person_id: String,
+) -> Result<EventStream![], FuberError> {
+    caller.may_ride_as(&person_id)?;
+    if person_id.is_empty() {
+        return Err(FuberError::InvalidId(
+            ErrorCode::InvalidObjectId,
+            "person id cannot be empty".into(),
+        ));
+    }
+    let person = db.get_person(&person_id)?;
+    let person_id = person.id.unwrap_or_default();
+    // subscribed before we look, so no move gets lost in between
+    let mut updates = tracker.subscribe();
+    let cab = match db.get_fleet()?.into_iter().find(|x| x.carries(person_id)) {
+        Some(cab) => cab,
+        None => {
+            return Err(FuberError::Conflict(
+                ErrorCode::NoCabAssigned,
+                "the person doesn't have a cab assigned".into(),
+            ))
+        }
+    };
+    let cab_id = cab.id;
+    Ok(EventStream! {
+        yield Event::json(&CabPosition::of(&cab)).event("location");
+        loop {
+            let cab = select! {
+                // moves that were published before the shutdown still go out
+                biased;
+                update = updates.recv() => match update {
+                    Ok(cab) => cab,
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                },
+                _ = &mut shutdown => break,
+            };
+            if cab.id != cab_id {
+                continue;
+            }
+            if !cab.carries(person_id) {
+                break;
+            }
+            yield Event::json(&CabPosition::of(&cab)).event("location");
+        }
+    })
+}
+
+// every cab of the fleet as it moves, for the admins, a `cab` event with
+// the whole cab on every move
+#[get("/fleet")]
+pub fn track_fleet(
+    _admin: Admin,
+    tracker: &State<Tracker>,
+    mut shutdown: Shutdown,
+) -> EventStream![] {
+    let mut updates = tracker.subscribe();
+    EventStream! {
+        loop {
+            let cab = select! {
+                biased;
+                update = updates.recv() => match update {
+                    Ok(cab) => cab,
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                },
+                _ = &mut shutdown => break,
+            };
+            yield Event::json(&cab).event("cab");
+        }
+    }
+}
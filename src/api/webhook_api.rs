@@ -0,0 +1,95 @@
+use crate::{
+    auth::Admin,
+    error::{ErrorCode, FuberError},
+    models::{
+        ride_model::now_millis,
+        webhook_model::{Delivery, DeliveryState, NewWebhook, Webhook, WebhookInfo},
+    },
+    repository::fuber_repo::BoxedRepo,
+};
+
+use rocket::{delete, get, post, put, serde::json::Json, State};
+
+fn empty_id(what: &str) -> FuberError {
+    FuberError::InvalidId(
+        ErrorCode::InvalidObjectId,
+        format!("{} id cannot be empty", what),
+    )
+}
+
+// the webhook hears about every assignment from now on, the ones before
+// are in the change feed
+#[post("/create", data = "<new_webhook>")]
+pub fn create_webhook(
+    _admin: Admin,
+    db: &State<BoxedRepo>,
+    new_webhook: Json<NewWebhook>,
+) -> Result<Json<String>, FuberError> {
+    let webhook = db.create_webhook(Webhook::new(new_webhook.into_inner())?)?;
+    Ok(Json(webhook.inserted_id.to_hex()))
+}
+
+#[get("/")]
+pub fn get_webhooks(
+    _admin: Admin,
+    db: &State<BoxedRepo>,
+) -> Result<Json<Vec<WebhookInfo>>, FuberError> {
+    Ok(Json(db.get_webhooks()?.iter().map(Webhook::info).collect()))
+}
+
+// whatever wasn't delivered to the webhook yet is dropped with it
+#[delete("/<webhook_id>")]
+pub fn delete_webhook(
+    _admin: Admin,
+    db: &State<BoxedRepo>,
+    webhook_id: String,
+) -> Result<Json<String>, FuberError> {
+    if webhook_id.is_empty() {
+        return Err(empty_id("webhook"));
+    }
+    if db.delete_webhook(&webhook_id)?.deleted_count == 1 {
+        Ok(Json("Webhook successfully deleted!".into()))
+    } else {
+        Err(FuberError::webhook_not_found(&webhook_id))
+    }
+}
+
+// the deliveries that ran out of attempts, oldest event first
+#[get("/dead_letters")]
+pub fn get_dead_letters(
+    _admin: Admin,
+    db: &State<BoxedRepo>,
+) -> Result<Json<Vec<Delivery>>, FuberError> {
+    Ok(Json(db.get_dead_deliveries()?))
+}
+
+// Sends a dead letter off again, with all of its attempts as if it was new.
+// The dispatcher picks it up on its next tick.
+#[put("/dead_letters/<delivery_id>/retry")]
+pub fn retry_dead_letter(
+    _admin: Admin,
+    db: &State<BoxedRepo>,
+    delivery_id: String,
+) -> Result<Json<Delivery>, FuberError> {
+    if delivery_id.is_empty() {
+        return Err(empty_id("delivery"));
+    }
+    let delivery = Delivery {
+        state: DeliveryState::Pending,
+        attempts: 0,
+        next_attempt_at: now_millis(),
+        ..db.get_delivery(&delivery_id)?
+    };
+    if db
+        .update_delivery(delivery.clone(), DeliveryState::Dead)?
+        .matched_count
+        != 1
+    {
+        let delivery = db.get_delivery(&delivery_id)?;
+        return Err(FuberError::Conflict(
+            ErrorCode::DeliveryNotDead,
+            format!("the delivery is {}", delivery.state.as_str()),
+        ));
+    }
+    Ok(Json(delivery))
+}
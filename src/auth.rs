@@ -0,0 +1,262 @@
+use std::env;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use dotenv::dotenv;
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use mongodb::bson::oid::ObjectId;
+use rand::{distributions::Alphanumeric, Rng};
+use rocket::{
+    http::Status,
+    request::{FromRequest, Outcome},
+    Request,
+};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    error::FuberError,
+    models::auth_model::{check_subject, Claims, Role},
+    repository::fuber_repo::BoxedRepo,
+    scheduler::env_or,
+};
+
+// how long the keys handed out by `new_api_key` are
+const API_KEY_LENGTH: usize = 40;
+// how long the tokens of `fuber token` are good for, a day
+const DEFAULT_TOKEN_TTL_MINUTES: u64 = 24 * 60;
+
+// How callers prove who they are. A bearer token is either a jwt signed
+// with hs256 and `FUBER_JWT_SECRET`, or an api key stored in the repo.
+// Without the secret only api keys work.
+#[derive(Clone, Default)]
+pub struct Auth {
+    jwt_secret: Option<Vec<u8>>,
+}
+
+impl Auth {
+    pub fn new(jwt_secret: Option<&str>) -> Self {
+        Auth {
+            jwt_secret: jwt_secret.map(|x| x.as_bytes().to_vec()),
+        }
+    }
+
+    // reads `FUBER_JWT_SECRET`, an empty one is as good as none
+    pub fn init() -> Self {
+        dotenv().ok();
+        let secret = env::var("FUBER_JWT_SECRET").ok();
+        Auth::new(secret.as_deref().filter(|x| !x.is_empty()))
+    }
+
+    // a token for `role` acting as `subject` that is good for `ttl`
+    pub fn issue(
+        &self,
+        role: Role,
+        subject: Option<ObjectId>,
+        ttl: Duration,
+    ) -> Result<String, FuberError> {
+        check_subject(role, subject)?;
+        let secret = self
+            .jwt_secret
+            .as_ref()
+            .ok_or_else(|| FuberError::validation("FUBER_JWT_SECRET isn't set"))?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let claims = Claims {
+            sub: subject.map(|x| x.to_hex()),
+            role,
+            exp: (now + ttl).as_secs(),
+        };
+        jsonwebtoken::encode(
+            &Header::new(Algorithm::HS256),
+            &claims,
+            &EncodingKey::from_secret(secret),
+        )
+        .map_err(|e| FuberError::validation(format!("cannot sign the token: {}", e)))
+    }
+
+    fn verify(&self, token: &str) -> Result<Caller, FuberError> {
+        let secret = self
+            .jwt_secret
+            .as_ref()
+            .ok_or_else(|| FuberError::unauthorized("tokens aren't accepted here"))?;
+        let claims = jsonwebtoken::decode::<Claims>(
+            token,
+            &DecodingKey::from_secret(secret),
+            &Validation::new(Algorithm::HS256),
+        )
+        .map_err(|e| FuberError::unauthorized(format!("the token isn't valid: {}", e)))?
+        .claims;
+        let subject = match claims.sub {
+            Some(sub) => Some(
+                ObjectId::parse_str(&sub)
+                    .map_err(|_| FuberError::unauthorized("the subject isn't an ObjectId"))?,
+            ),
+            None => None,
+        };
+        check_subject(claims.role, subject).map_err(|e| FuberError::unauthorized(e.message()))?;
+        Ok(Caller {
+            role: claims.role,
+            subject,
+        })
+    }
+}
+
+// reads `FUBER_TOKEN_TTL_MINUTES`
+pub fn token_ttl() -> Duration {
+    dotenv().ok();
+    let ttl = env_or("FUBER_TOKEN_TTL_MINUTES", DEFAULT_TOKEN_TTL_MINUTES, |x| {
+        *x > 0
+    });
+    Duration::from_secs(ttl * 60)
+}
+
+// what is stored of an api key
+pub fn hash_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+pub fn new_api_key() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(API_KEY_LENGTH)
+        .map(char::from)
+        .collect()
+}
+
+// Whoever sent the request, take it as a request guard to only let known
+// callers through. The handlers check what the caller may do with the
+// `may_*` methods, riders only act for their own person and drivers only
+// for their own cab.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Caller {
+    pub role: Role,
+    pub subject: Option<ObjectId>,
+}
+
+impl Caller {
+    pub fn admin() -> Self {
+        Caller {
+            role: Role::Admin,
+            subject: None,
+        }
+    }
+
+    pub fn rider(person_id: ObjectId) -> Self {
+        Caller {
+            role: Role::Rider,
+            subject: Some(person_id),
+        }
+    }
+
+    pub fn driver(cab_id: ObjectId) -> Self {
+        Caller {
+            role: Role::Driver,
+            subject: Some(cab_id),
+        }
+    }
+
+    pub fn is_admin(&self) -> bool {
+        self.role == Role::Admin
+    }
+
+    fn may(&self, role: Role, id: &str, what: &str) -> Result<(), FuberError> {
+        let is_subject = self.subject.is_some_and(|x| x.to_hex() == id);
+        if self.is_admin() || (self.role == role && is_subject) {
+            Ok(())
+        } else {
+            Err(FuberError::forbidden(format!(
+                "a {} cannot act for the {} {}",
+                self.role.as_str(),
+                what,
+                id
+            )))
+        }
+    }
+
+    // the rider of the person, or an admin
+    pub fn may_ride_as(&self, person_id: &str) -> Result<(), FuberError> {
+        self.may(Role::Rider, person_id, "person")
+    }
+
+    // the driver of the cab, or an admin
+    pub fn may_drive(&self, cab_id: &str) -> Result<(), FuberError> {
+        self.may(Role::Driver, cab_id, "cab")
+    }
+}
+
+fn bearer_token<'r>(req: &'r Request<'_>) -> Result<&'r str, FuberError> {
+    match req.headers().get_one("Authorization") {
+        Some(header) => header
+            .strip_prefix("Bearer ")
+            .map(str::trim)
+            .filter(|x| !x.is_empty())
+            .ok_or_else(|| FuberError::unauthorized("the authorization isn't a bearer token")),
+        None => Err(FuberError::unauthorized(
+            "the request needs a bearer token in the Authorization header",
+        )),
+    }
+}
+
+fn authenticate(req: &Request<'_>) -> Result<Caller, FuberError> {
+    let token = bearer_token(req)?;
+    // jwts are three base64url parts, api keys have no dots
+    if token.contains('.') {
+        let auth = req.rocket().state::<Auth>().cloned().unwrap_or_default();
+        return auth.verify(token);
+    }
+    let db = req
+        .rocket()
+        .state::<BoxedRepo>()
+        .ok_or_else(|| FuberError::storage("there is no repo to look the api key up in"))?;
+    match db.get_api_key_by_hash(&hash_key(token)) {
+        Ok(api_key) => Ok(Caller {
+            role: api_key.role,
+            subject: api_key.subject,
+        }),
+        Err(FuberError::NotFound(..)) => Err(FuberError::unauthorized("the api key isn't known")),
+        Err(e) => Err(e),
+    }
+}
+
+// The outcome of authenticating, cached on the request so it only happens
+// once however many guards ask. The catchers read it to tell why.
+pub struct Authenticated(pub Result<Caller, FuberError>);
+
+impl Authenticated {
+    pub fn of<'r>(req: &'r Request<'_>) -> &'r Authenticated {
+        req.local_cache(|| Authenticated(authenticate(req)))
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Caller {
+    type Error = FuberError;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match &Authenticated::of(req).0 {
+            Ok(caller) => Outcome::Success(caller.clone()),
+            Err(e) => Outcome::Failure((e.status(), e.clone())),
+        }
+    }
+}
+
+// A request guard for the routes only admins can use, anybody else known
+// gets a 403.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Admin;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Admin {
+    type Error = FuberError;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match &Authenticated::of(req).0 {
+            Ok(caller) if caller.is_admin() => Outcome::Success(Admin),
+            Ok(caller) => Outcome::Failure((
+                Status::Forbidden,
+                FuberError::forbidden(format!("a {} cannot do that", caller.role.as_str())),
+            )),
+            Err(e) => Outcome::Failure((e.status(), e.clone())),
+        }
+    }
+}
@@ -0,0 +1,220 @@
+use std::env;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use dotenv::dotenv;
+use rocket::{serde::json::Json, tokio::sync::oneshot, Responder};
+
+use crate::{
+    error::FuberError,
+    models::{
+        cab_model::{Cab, CabCategory},
+        person_model::Person,
+        ride_model::Ride,
+        ticket_model::TicketStatus,
+    },
+};
+
+pub const DEFAULT_WINDOW_MS: u64 = 2000;
+
+// How `person/request_cab` hands out cabs, managed by rocket next to the
+// travel config. Greedy dispatch gives every request the cheapest free cab
+// right away. Batched dispatch holds requests back for `window` and then
+// hands out the cabs so that all the requests together cost the least,
+// riders can wait a little longer but nobody takes the only cab close to
+// somebody else when another one would do.
+pub struct Dispatch {
+    window: Option<Duration>,
+    // the batch requests are waiting in and when it is due
+    batch: Mutex<Option<(Instant, Vec<PendingRequest>)>>,
+}
+
+// a ride request waiting for its batch, the cab it gets is sent to `reply`
+pub struct PendingRequest {
+    pub person: Person,
+    pub ride: Ride,
+    pub category: Option<CabCategory>,
+    pub reply: oneshot::Sender<Result<CabRequest, FuberError>>,
+}
+
+// What a ride request ends in, the cab that is on its way or, when there
+// was no free cab, a ticket in the queue that gets a cab as soon as one
+// frees up. Queued requests are answered with 202 Accepted.
+#[derive(Debug, Responder)]
+pub enum CabRequest {
+    Assigned(Json<(Person, Cab)>),
+    #[response(status = 202)]
+    Queued(Json<TicketStatus>),
+}
+
+impl CabRequest {
+    pub fn assigned(self) -> Option<(Person, Cab)> {
+        match self {
+            CabRequest::Assigned(Json(x)) => Some(x),
+            CabRequest::Queued(_) => None,
+        }
+    }
+
+    pub fn queued(self) -> Option<TicketStatus> {
+        match self {
+            CabRequest::Queued(Json(x)) => Some(x),
+            CabRequest::Assigned(_) => None,
+        }
+    }
+}
+
+impl Dispatch {
+    pub fn greedy() -> Self {
+        Dispatch {
+            window: None,
+            batch: Mutex::new(None),
+        }
+    }
+
+    pub fn batched(window: Duration) -> Self {
+        Dispatch {
+            window: Some(window),
+            batch: Mutex::new(None),
+        }
+    }
+
+    // reads `FUBER_DISPATCH_MODE`, `greedy` or `batch`, and the window of
+    // batched dispatch from `FUBER_DISPATCH_WINDOW_MS`, greedy without them
+    pub fn init() -> Self {
+        dotenv().ok();
+        match env::var("FUBER_DISPATCH_MODE").as_deref() {
+            Ok("greedy") | Err(_) => Dispatch::greedy(),
+            Ok("batch") => {
+                let window_ms = match env::var("FUBER_DISPATCH_WINDOW_MS") {
+                    Ok(ms) => match ms.parse::<u64>() {
+                        Ok(ms) => ms,
+                        Err(_) => panic!("FUBER_DISPATCH_WINDOW_MS cannot be {}", ms),
+                    },
+                    Err(_) => DEFAULT_WINDOW_MS,
+                };
+                Dispatch::batched(Duration::from_millis(window_ms))
+            }
+            Ok(other) => panic!("unknown FUBER_DISPATCH_MODE: {}", other),
+        }
+    }
+
+    pub fn window(&self) -> Option<Duration> {
+        self.window
+    }
+
+    pub fn is_batched(&self) -> bool {
+        self.window.is_some()
+    }
+
+    // Adds a request to the batch that is being collected, or starts a new
+    // one, and tells when that batch is due.
+    pub fn join(&self, request: PendingRequest) -> Instant {
+        let window = self.window.unwrap_or_default();
+        let mut batch = self.batch.lock().unwrap_or_else(|e| e.into_inner());
+        let (due, requests) = batch.get_or_insert_with(|| (Instant::now() + window, Vec::new()));
+        requests.push(request);
+        *due
+    }
+
+    // Takes the batch once it is due. Every request in it asks after the
+    // batch is due and the first one gets to dispatch all of them, that way
+    // no batch is left behind when a request goes away while waiting.
+    pub fn take_due(&self) -> Option<Vec<PendingRequest>> {
+        let mut batch = self.batch.lock().unwrap_or_else(|e| e.into_inner());
+        match batch.as_ref() {
+            Some((due, _)) if *due <= Instant::now() => batch.take().map(|(_, x)| x),
+            _ => None,
+        }
+    }
+}
+
+// The cheapest way to give every row its own column, e.g. every rider their
+// own cab, by the hungarian algorithm in O(n^3) for n the larger of the rows
+// and columns. Costs that aren't finite can't be assigned, so rows can be
+// left without a column, but never while the rows that can get one could
+// all get one. Rows don't have to be the same length, missing costs aren't
+// finite either.
+pub fn min_cost_assignment(costs: &[Vec<f64>]) -> Vec<Option<usize>> {
+    let rows = costs.len();
+    let cols = costs.iter().map(Vec::len).max().unwrap_or(0);
+    let n = rows.max(cols);
+    // every way to assign costs less than a single pair that can't be
+    // assigned, so as many rows as possible get a column first and the
+    // cost only decides which ones
+    let largest = costs
+        .iter()
+        .flatten()
+        .filter(|x| x.is_finite())
+        .fold(0.0, |max: f64, x| max.max(x.abs()));
+    let forbidden = (largest + 1.0) * 2.0 * (n as f64 + 1.0);
+    // padded to a square with free dummy rows or columns
+    let cost = |row: usize, col: usize| {
+        if row >= rows || col >= cols {
+            return 0.0;
+        }
+        match costs[row].get(col) {
+            Some(x) if x.is_finite() => *x,
+            _ => forbidden,
+        }
+    };
+
+    // potentials of the rows and columns, which row every column goes to
+    // and the column before it on the path that is being grown, all
+    // counted from 1 with column 0 as the start of the path
+    let mut row_potential = vec![0.0; n + 1];
+    let mut col_potential = vec![0.0; n + 1];
+    let mut row_of = vec![0; n + 1];
+    let mut previous = vec![0; n + 1];
+    for row in 1..=n {
+        row_of[0] = row;
+        let mut col = 0;
+        let mut slack = vec![f64::INFINITY; n + 1];
+        let mut used = vec![false; n + 1];
+        loop {
+            used[col] = true;
+            let current = row_of[col];
+            let mut delta = f64::INFINITY;
+            let mut next = 0;
+            for j in 1..=n {
+                if used[j] {
+                    continue;
+                }
+                let reduced = cost(current - 1, j - 1) - row_potential[current] - col_potential[j];
+                if reduced < slack[j] {
+                    slack[j] = reduced;
+                    previous[j] = col;
+                }
+                if slack[j] < delta {
+                    delta = slack[j];
+                    next = j;
+                }
+            }
+            for j in 0..=n {
+                if used[j] {
+                    row_potential[row_of[j]] += delta;
+                    col_potential[j] -= delta;
+                } else {
+                    slack[j] -= delta;
+                }
+            }
+            col = next;
+            if row_of[col] == 0 {
+                break;
+            }
+        }
+        // flip the path that ends in the free column
+        while col != 0 {
+            let before = previous[col];
+            row_of[col] = row_of[before];
+            col = before;
+        }
+    }
+
+    let mut assigned = vec![None; rows];
+    for (col, row) in row_of.into_iter().enumerate().skip(1) {
+        if row <= rows && col <= cols && cost(row - 1, col - 1) < forbidden {
+            assigned[row - 1] = Some(col - 1);
+        }
+    }
+    assigned
+}
@@ -0,0 +1,215 @@
+use std::fmt;
+
+use rocket::{
+    http::Status,
+    response::{self, Responder},
+    serde::json::Json,
+    Request,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::request_id::RequestId;
+
+// Machine readable reason for a failure, this is what clients should match
+// on since the message is only meant for humans and can change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    PersonNotFound,
+    CabNotFound,
+    RideNotFound,
+    TicketNotFound,
+    BookingNotFound,
+    WebhookNotFound,
+    DeliveryNotFound,
+    ApiKeyNotFound,
+    RouteNotFound,
+    InvalidObjectId,
+    CabAlreadyAssigned,
+    NoCabsInCategory,
+    NoCabAssigned,
+    RideAlreadyActive,
+    TicketNotWaiting,
+    BookingAlreadyCancelled,
+    DeliveryNotDead,
+    InvalidRideTransition,
+    InvalidRequestBody,
+    Unauthorized,
+    Forbidden,
+    StorageError,
+    InternalError,
+}
+
+// Every failure the repositories and the api can run into.
+// The variant decides which http status the client sees, the ErrorCode
+// tells it why and the String is a human readable message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FuberError {
+    // the id is well formed but nothing is stored under it
+    NotFound(ErrorCode, String),
+    // the id isn't a valid ObjectId
+    InvalidId(ErrorCode, String),
+    // the request clashes with the current state, e.g. the cab is taken
+    Conflict(ErrorCode, String),
+    // the storage backend itself failed
+    Storage(ErrorCode, String),
+    // the data sent by the client doesn't make sense
+    Validation(ErrorCode, String),
+    // nobody we know sent the request
+    Unauthorized(ErrorCode, String),
+    // the caller is known but mustn't do this
+    Forbidden(ErrorCode, String),
+}
+
+impl FuberError {
+    pub fn person_not_found(id: &str) -> Self {
+        FuberError::NotFound(
+            ErrorCode::PersonNotFound,
+            format!("Cannot find the person {}", id),
+        )
+    }
+
+    pub fn cab_not_found(id: &str) -> Self {
+        FuberError::NotFound(
+            ErrorCode::CabNotFound,
+            format!("Cannot find the cab {}", id),
+        )
+    }
+
+    pub fn ride_not_found(id: &str) -> Self {
+        FuberError::NotFound(
+            ErrorCode::RideNotFound,
+            format!("Cannot find the ride {}", id),
+        )
+    }
+
+    pub fn ticket_not_found(id: &str) -> Self {
+        FuberError::NotFound(
+            ErrorCode::TicketNotFound,
+            format!("Cannot find the ticket {}", id),
+        )
+    }
+
+    pub fn booking_not_found(id: &str) -> Self {
+        FuberError::NotFound(
+            ErrorCode::BookingNotFound,
+            format!("Cannot find the booking {}", id),
+        )
+    }
+
+    pub fn webhook_not_found(id: &str) -> Self {
+        FuberError::NotFound(
+            ErrorCode::WebhookNotFound,
+            format!("Cannot find the webhook {}", id),
+        )
+    }
+
+    pub fn delivery_not_found(id: &str) -> Self {
+        FuberError::NotFound(
+            ErrorCode::DeliveryNotFound,
+            format!("Cannot find the delivery {}", id),
+        )
+    }
+
+    pub fn api_key_not_found(id: &str) -> Self {
+        FuberError::NotFound(
+            ErrorCode::ApiKeyNotFound,
+            format!("Cannot find the api key {}", id),
+        )
+    }
+
+    pub fn invalid_id(id: &str) -> Self {
+        FuberError::InvalidId(
+            ErrorCode::InvalidObjectId,
+            format!("{} is not a valid ObjectId", id),
+        )
+    }
+
+    pub fn storage(message: impl Into<String>) -> Self {
+        FuberError::Storage(ErrorCode::StorageError, message.into())
+    }
+
+    pub fn validation(message: impl Into<String>) -> Self {
+        FuberError::Validation(ErrorCode::InvalidRequestBody, message.into())
+    }
+
+    pub fn unauthorized(message: impl Into<String>) -> Self {
+        FuberError::Unauthorized(ErrorCode::Unauthorized, message.into())
+    }
+
+    pub fn forbidden(message: impl Into<String>) -> Self {
+        FuberError::Forbidden(ErrorCode::Forbidden, message.into())
+    }
+
+    pub fn status(&self) -> Status {
+        match self {
+            FuberError::NotFound(..) => Status::NotFound,
+            FuberError::InvalidId(..) => Status::BadRequest,
+            FuberError::Conflict(..) => Status::Conflict,
+            FuberError::Storage(..) => Status::InternalServerError,
+            FuberError::Validation(..) => Status::UnprocessableEntity,
+            FuberError::Unauthorized(..) => Status::Unauthorized,
+            FuberError::Forbidden(..) => Status::Forbidden,
+        }
+    }
+
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            FuberError::NotFound(code, _)
+            | FuberError::InvalidId(code, _)
+            | FuberError::Conflict(code, _)
+            | FuberError::Storage(code, _)
+            | FuberError::Validation(code, _)
+            | FuberError::Unauthorized(code, _)
+            | FuberError::Forbidden(code, _) => *code,
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            FuberError::NotFound(_, m)
+            | FuberError::InvalidId(_, m)
+            | FuberError::Conflict(_, m)
+            | FuberError::Storage(_, m)
+            | FuberError::Validation(_, m)
+            | FuberError::Unauthorized(_, m)
+            | FuberError::Forbidden(_, m) => m,
+        }
+    }
+}
+
+impl fmt::Display for FuberError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
+
+impl std::error::Error for FuberError {}
+
+// The json every failed request gets back, from the handlers as well as
+// from the catchers registered in main.rs
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErrorBody {
+    pub code: ErrorCode,
+    pub message: String,
+    pub request_id: String,
+}
+
+impl ErrorBody {
+    pub fn new(code: ErrorCode, message: impl Into<String>, req: &Request<'_>) -> Self {
+        ErrorBody {
+            code,
+            message: message.into(),
+            request_id: RequestId::of(req).to_string(),
+        }
+    }
+}
+
+// lets the handlers return `Result<_, FuberError>` and rocket turns the
+// error into the matching status with an `ErrorBody` as the body
+impl<'r> Responder<'r, 'static> for FuberError {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let body = ErrorBody::new(self.code(), self.message(), req);
+        (self.status(), Json(body)).respond_to(req)
+    }
+}
@@ -1,10 +1,21 @@
 pub mod api;
+pub mod auth;
+pub mod dispatch;
+pub mod error;
+pub mod metric;
 pub mod models;
+pub mod pooling;
+pub mod pricing;
 pub mod repository;
+pub mod request_id;
+pub mod routing;
+pub mod scheduler;
+pub mod spatial;
+pub mod surge;
+pub mod tracking;
+pub mod webhooks;
 
-use rand;
 use rand::{distributions::Alphanumeric, Rng};
-use rocket::UriDisplayPath;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
@@ -172,7 +183,7 @@ impl Person {
     }
 
     pub fn get_id(&self) -> usize {
-        self.id.clone()
+        self.id
     }
 
     pub fn get_location(&self) -> Point {
@@ -210,7 +221,6 @@ impl Fleet {
 
         cabs.into_iter().for_each(|x| {
             let _ = hmap.entry(x).or_default();
-            ()
         });
 
         Fleet(hmap)
@@ -235,11 +245,7 @@ impl Fleet {
             }
         }
 
-        return Err(format!("Cannot find person {} in the fleet", p.get_id()));
-    }
-
-    fn get_tuple_by_cab(&self, c: &Cab) -> Result<(Cab, Option<Person>), String> {
-        unimplemented!()
+        Err(format!("Cannot find person {} in the fleet", p.get_id()))
     }
 
     // deallocate a person instance from a cab instance in the fleet and set
@@ -316,9 +322,9 @@ impl Fleet {
             )),
 
             Ok((c, p)) => match p {
-                None => Err(format!(
-                    "Expected Some(Person) found None\nThis error happened inside [Fleet::remove_person(..)] -> field pattern match -> Ok (..) arm\n"
-                )),
+                None => Err(
+                    "Expected Some(Person) found None\nThis error happened inside [Fleet::remove_person(..)] -> field pattern match -> Ok (..) arm\n".to_string()
+                ),
                 Some(p) => Ok(self.cab_to_none(c, p.get_destination())),
             },
         }
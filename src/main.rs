@@ -1,42 +1,174 @@
-pub mod api;
-mod models;
-mod repository;
-
 #[macro_use]
 extern crate rocket;
-use repository::mongodb_repos::MongoRepo;
+use std::env;
+use std::path::PathBuf;
+use std::process;
+
+use mongodb::bson::oid::ObjectId;
+use rocket::{Build, Rocket};
+
+use fuber::auth::{token_ttl, Auth};
+use fuber::dispatch::Dispatch;
+use fuber::metric::Travel;
+use fuber::models::auth_model::Role;
+use fuber::pooling::Pooling;
+use fuber::pricing::Tariff;
+use fuber::repository::fuber_repo::{init_repo, BoxedRepo};
+use fuber::repository::shared_repo::SharedRepo;
+use fuber::request_id::RequestIdFairing;
+use fuber::routing::cache;
+use fuber::scheduler::{BookingScheduler, Schedule};
+use fuber::surge::Surge;
+use fuber::tracking::Tracker;
+use fuber::webhooks::{WebhookDispatcher, WebhookPolicy};
+
+use fuber::api::auth_api::{create_api_key, delete_api_key};
+use fuber::api::booking_api::{
+    cancel_booking, get_booking, get_bookings_of_person, get_upcoming_bookings, schedule_ride,
+};
+use fuber::api::mount_test_routes;
 
-use api::cab_api::{
-    assign_person, create_cab, create_fleet, delete_cab, delete_fleet, generate_fleet, get_cab,
-    get_fleet, update_cab, update_location,
+use fuber::api::cab_api::{
+    create_cab, create_fleet, delete_cab, get_cab, get_fleet, update_cab, update_location,
+};
+use fuber::api::catcher_api::{
+    forbidden, internal_error, not_found, unauthorized, unprocessable_entity,
+};
+use fuber::api::event_api::get_events;
+use fuber::api::person_api::{
+    create_person, delete_person, get_person, hello, request_cab, request_cab_batched,
+    request_pool, unassign_cab, update_person,
+};
+use fuber::api::pricing_api::get_surge;
+use fuber::api::queue_api::{cancel_ticket, get_ticket};
+use fuber::api::ride_api::{
+    cancel_ride, driver_arriving, get_ride, get_rides_of_person, picked_up,
 };
-use api::person_api::{
-    create_person, delete_all_people, delete_person, get_person, hello, request_cab, unassign_cab,
-    update_person,
+use fuber::api::tracking_api::{track_cab_of_person, track_fleet};
+use fuber::api::webhook_api::{
+    create_webhook, delete_webhook, get_dead_letters, get_webhooks, retry_dead_letter,
 };
 
-#[launch]
-fn rocket() -> _ {
-    let db = MongoRepo::init();
-    rocket::build()
-        .manage(db)
-        .mount("/", routes![hello])
-        .mount("/person/test", routes![delete_all_people])
-        .mount(
-            "/cab/test",
-            routes![assign_person, generate_fleet, delete_fleet],
+const USAGE: &str =
+    "usage: fuber [preprocess <road graph> [<cache>] | token <role> [<person or cab id>]]";
+
+// `fuber preprocess` reads a road graph, usually an osm extract, and caches
+// it in the binary format for `FUBER_ROAD_GRAPH`, next to the binary unless
+// it is told where
+fn preprocess(args: &[String]) -> Result<(), String> {
+    let (input, output) = match args {
+        [input] => (input, cache::default_cache_path(input)?),
+        [input, output] => (input, PathBuf::from(output)),
+        _ => return Err(USAGE.to_string()),
+    };
+    let graph = cache::preprocess(input, &output)
+        .map_err(|e| format!("unable to preprocess {}: {}", input, e))?;
+    println!(
+        "wrote {} nodes and {} edges to {}",
+        graph.nodes.len(),
+        graph.edges.len(),
+        output.display()
+    );
+    Ok(())
+}
+
+// `fuber token` prints a token signed with `FUBER_JWT_SECRET`, riders
+// need the id of their person and drivers the id of their cab
+fn token(args: &[String]) -> Result<(), String> {
+    let (role, subject) = match args {
+        [role] => (role, None),
+        [role, subject] => (
+            role,
+            Some(ObjectId::parse_str(subject).map_err(|_| format!("{} isn't an id", subject))?),
+        ),
+        _ => return Err(USAGE.to_string()),
+    };
+    let role = role.parse::<Role>().map_err(|e| e.message().to_string())?;
+    let token = Auth::init()
+        .issue(role, subject, token_ttl())
+        .map_err(|e| e.message().to_string())?;
+    println!("{}", token);
+    Ok(())
+}
+
+#[rocket::main]
+async fn main() {
+    let args = env::args().skip(1).collect::<Vec<String>>();
+    match args.first().map(String::as_str) {
+        None => {}
+        Some("preprocess") => {
+            if let Err(e) = preprocess(&args[1..]) {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+            return;
+        }
+        Some("token") => {
+            if let Err(e) = token(&args[1..]) {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+            return;
+        }
+        Some(_) => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    }
+    // an error that isn't handled logs why rocket didn't launch when it's
+    // dropped, the same as with #[launch]
+    let _ = rocket().launch().await;
+}
+
+fn rocket() -> Rocket<Build> {
+    // the booking scheduler, the webhook dispatcher and batched requests
+    // work on the same repo as the handlers
+    let db = SharedRepo::new(init_repo());
+    let travel = Travel::init();
+    let dispatch = Dispatch::init();
+    // the same route, one that waits for the batch with batched dispatch
+    let request_cab = if dispatch.is_batched() {
+        routes![request_cab_batched]
+    } else {
+        routes![request_cab]
+    };
+    let rocket = rocket::build()
+        .manage(Box::new(db.clone()) as BoxedRepo)
+        .manage(db.clone())
+        .manage(Tariff::init())
+        .manage(travel.clone())
+        .manage(dispatch)
+        .manage(Pooling::init())
+        .manage(Surge::init())
+        .manage(Tracker::default())
+        .manage(Auth::init())
+        .attach(RequestIdFairing)
+        .attach(BookingScheduler::new(db.clone(), travel, Schedule::init()))
+        .attach(WebhookDispatcher::new(db, WebhookPolicy::init()))
+        .register(
+            "/",
+            catchers![
+                unauthorized,
+                forbidden,
+                not_found,
+                unprocessable_entity,
+                internal_error
+            ],
         )
+        .mount("/", routes![hello])
         .mount(
             "/person",
             routes![
                 create_person,
                 get_person,
-                request_cab,
                 unassign_cab,
                 update_person,
-                delete_person
+                delete_person,
+                request_pool,
+                schedule_ride
             ],
         )
+        .mount("/person", request_cab)
         .mount(
             "/cab",
             routes![
@@ -49,4 +181,39 @@ fn rocket() -> _ {
                 delete_cab,
             ],
         )
+        .mount(
+            "/ride",
+            routes![
+                get_ride,
+                get_rides_of_person,
+                driver_arriving,
+                picked_up,
+                cancel_ride
+            ],
+        )
+        .mount("/queue", routes![get_ticket, cancel_ticket])
+        .mount("/pricing", routes![get_surge])
+        .mount("/track", routes![track_cab_of_person, track_fleet])
+        .mount("/events", routes![get_events])
+        .mount("/auth", routes![create_api_key, delete_api_key])
+        .mount(
+            "/webhooks",
+            routes![
+                create_webhook,
+                get_webhooks,
+                delete_webhook,
+                get_dead_letters,
+                retry_dead_letter
+            ],
+        )
+        .mount(
+            "/booking",
+            routes![
+                get_upcoming_bookings,
+                get_bookings_of_person,
+                get_booking,
+                cancel_booking
+            ],
+        );
+    mount_test_routes(rocket)
 }
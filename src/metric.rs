@@ -0,0 +1,307 @@
+use std::env;
+use std::sync::Arc;
+
+use dotenv::dotenv;
+
+use crate::{
+    models::point_model::{haversine_km, Point, EARTH_RADIUS_KM},
+    routing::graph::RoadGraph,
+};
+
+// How far apart two points are for dispatch, fares and arrival times.
+// A grid point and a gps position are infinitely far apart whatever the
+// metric is.
+pub trait DistanceMetric: Send + Sync {
+    // the name it is configured with
+    fn name(&self) -> &'static str;
+
+    fn dist(&self, a: &Point, b: &Point) -> f64;
+
+    // The longest `Point::dist` between two points this metric puts `dist`
+    // apart. Storage backends only look up cabs by `Point::dist`, dispatch
+    // asks them for everything within this to be sure it sees every cab the
+    // metric could put that close.
+    fn reach(&self, dist: f64) -> f64;
+}
+
+// The legs of the trip from `a` to `b` along the two axes. Gps positions get
+// projected on a flat plane around their mean latitude, which is good to a
+// fraction of a percent over the size of a city.
+fn legs(a: &Point, b: &Point) -> Option<(f64, f64)> {
+    match (a, b) {
+        (Point::Grid { x: x1, y: y1 }, Point::Grid { x: x2, y: y2 }) => {
+            Some(((x1 - x2).abs() as f64, (y1 - y2).abs() as f64))
+        }
+        (
+            Point::Geo {
+                lat: lat1,
+                lon: lon1,
+            },
+            Point::Geo {
+                lat: lat2,
+                lon: lon2,
+            },
+        ) => {
+            // the short way round, across the antimeridian if need be
+            let d_lon = (lon1 - lon2).abs();
+            let d_lon = d_lon.min(360.0 - d_lon);
+            let mean_lat = ((lat1 + lat2) / 2.0).to_radians();
+            Some((
+                EARTH_RADIUS_KM * d_lon.to_radians() * mean_lat.cos(),
+                EARTH_RADIUS_KM * (lat1 - lat2).abs().to_radians(),
+            ))
+        }
+        _ => None,
+    }
+}
+
+// the flat projection of gps positions is a little off from great circles
+const PROJECTION_SLACK: f64 = 1.01;
+
+// the straight line
+pub struct Euclidean;
+
+impl DistanceMetric for Euclidean {
+    fn name(&self) -> &'static str {
+        "euclidean"
+    }
+
+    fn dist(&self, a: &Point, b: &Point) -> f64 {
+        match legs(a, b) {
+            Some((dx, dy)) => dx.hypot(dy),
+            None => f64::INFINITY,
+        }
+    }
+
+    fn reach(&self, dist: f64) -> f64 {
+        dist * PROJECTION_SLACK
+    }
+}
+
+// along the streets of a city laid out in blocks
+pub struct Manhattan;
+
+impl DistanceMetric for Manhattan {
+    fn name(&self) -> &'static str {
+        "manhattan"
+    }
+
+    fn dist(&self, a: &Point, b: &Point) -> f64 {
+        match legs(a, b) {
+            Some((dx, dy)) => dx + dy,
+            None => f64::INFINITY,
+        }
+    }
+
+    // never shorter than the straight line
+    fn reach(&self, dist: f64) -> f64 {
+        dist * PROJECTION_SLACK
+    }
+}
+
+// the longer of the two legs, as if diagonal moves were free
+pub struct Chebyshev;
+
+impl DistanceMetric for Chebyshev {
+    fn name(&self) -> &'static str {
+        "chebyshev"
+    }
+
+    fn dist(&self, a: &Point, b: &Point) -> f64 {
+        match legs(a, b) {
+            Some((dx, dy)) => dx.max(dy),
+            None => f64::INFINITY,
+        }
+    }
+
+    // the straight line can be up to the diagonal of the square
+    fn reach(&self, dist: f64) -> f64 {
+        dist * std::f64::consts::SQRT_2 * PROJECTION_SLACK
+    }
+}
+
+// Great circles between gps positions. The grid is flat, so grid points
+// are measured in a straight line, the same as `Point::dist`.
+pub struct Haversine;
+
+impl DistanceMetric for Haversine {
+    fn name(&self) -> &'static str {
+        "haversine"
+    }
+
+    fn dist(&self, a: &Point, b: &Point) -> f64 {
+        match (a, b) {
+            (
+                Point::Geo {
+                    lat: lat1,
+                    lon: lon1,
+                },
+                Point::Geo {
+                    lat: lat2,
+                    lon: lon2,
+                },
+            ) => haversine_km(*lat1, *lon1, *lat2, *lon2),
+            _ => a.dist(b),
+        }
+    }
+
+    fn reach(&self, dist: f64) -> f64 {
+        dist
+    }
+}
+
+pub fn metric_by_name(name: &str) -> Result<Box<dyn DistanceMetric>, String> {
+    match name {
+        "euclidean" => Ok(Box::new(Euclidean)),
+        "manhattan" => Ok(Box::new(Manhattan)),
+        "chebyshev" => Ok(Box::new(Chebyshev)),
+        "haversine" => Ok(Box::new(Haversine)),
+        _ => Err(format!(
+            "{} is not one of euclidean, manhattan, chebyshev or haversine",
+            name
+        )),
+    }
+}
+
+pub const DEFAULT_SPEED_KMH: f64 = 30.0;
+
+// How the server measures trips, managed by rocket next to the tariff.
+// Cabs are assumed to drive at `speed_kmh` on average when estimating how
+// long they take to get somewhere. With a road graph they follow the roads
+// instead and only drive the straight line onto the nearest node of the
+// graph and off it again at that speed. Clones share the metric and the
+// road graph, the booking scheduler keeps one of its own.
+#[derive(Clone)]
+pub struct Travel {
+    metric: Arc<dyn DistanceMetric>,
+    speed_kmh: f64,
+    roads: Option<Arc<RoadGraph>>,
+}
+
+impl Default for Travel {
+    fn default() -> Self {
+        Travel::new(Box::new(Euclidean), DEFAULT_SPEED_KMH)
+    }
+}
+
+impl Travel {
+    pub fn new(metric: Box<dyn DistanceMetric>, speed_kmh: f64) -> Self {
+        Travel {
+            metric: Arc::from(metric),
+            speed_kmh,
+            roads: None,
+        }
+    }
+
+    pub fn with_roads(mut self, roads: RoadGraph) -> Self {
+        self.roads = Some(Arc::new(roads));
+        self
+    }
+
+    // reads the metric from `FUBER_DISTANCE_METRIC`, the speed from
+    // `FUBER_AVERAGE_SPEED_KMH`, euclidean at 30 km/h without them, and the
+    // road graph from the file in `FUBER_ROAD_GRAPH` if there is one
+    pub fn init() -> Self {
+        dotenv().ok();
+        let metric = match env::var("FUBER_DISTANCE_METRIC") {
+            Ok(name) => match metric_by_name(&name) {
+                Ok(metric) => metric,
+                Err(e) => panic!("unknown FUBER_DISTANCE_METRIC: {}", e),
+            },
+            Err(_) => Box::new(Euclidean),
+        };
+        let speed_kmh = match env::var("FUBER_AVERAGE_SPEED_KMH") {
+            Ok(speed) => match speed.parse::<f64>() {
+                Ok(speed) if speed.is_finite() && speed > 0.0 => speed,
+                _ => panic!("FUBER_AVERAGE_SPEED_KMH cannot be {}", speed),
+            },
+            Err(_) => DEFAULT_SPEED_KMH,
+        };
+        let travel = Travel::new(metric, speed_kmh);
+        match env::var("FUBER_ROAD_GRAPH") {
+            Ok(path) => match RoadGraph::load(&path) {
+                Ok(roads) => travel.with_roads(roads),
+                Err(e) => panic!("unable to load the road graph {}: {}", path, e),
+            },
+            Err(_) => travel,
+        }
+    }
+
+    pub fn metric(&self) -> &dyn DistanceMetric {
+        self.metric.as_ref()
+    }
+
+    pub fn roads(&self) -> Option<&RoadGraph> {
+        self.roads.as_deref()
+    }
+
+    // minutes to drive the straight line from `a` to `b`
+    fn straight_minutes(&self, a: &Point, b: &Point) -> f64 {
+        a.dist(b) / self.speed_kmh * 60.0
+    }
+
+    pub fn dist(&self, a: &Point, b: &Point) -> f64 {
+        self.metric.dist(a, b)
+    }
+
+    // how many minutes a cab takes from `a` to `b`, infinitely long when
+    // the roads don't go there
+    pub fn eta_minutes(&self, a: &Point, b: &Point) -> f64 {
+        match &self.roads {
+            Some(roads) => match roads.route(a, b) {
+                Some(route) => {
+                    let (first, last) = (&route.path[0], &route.path[route.path.len() - 1]);
+                    self.straight_minutes(a, first) + route.minutes + self.straight_minutes(last, b)
+                }
+                None => f64::INFINITY,
+            },
+            None => self.dist(a, b) / self.speed_kmh * 60.0,
+        }
+    }
+
+    // What dispatch ranks cabs at `from` by for a pickup at `to`, the
+    // `eta_minutes` of every cab with a road graph and the distance of the
+    // metric without one. The roads are searched once for all the cabs.
+    pub fn dispatch_costs(&self, from: &[&Point], to: &Point) -> Vec<f64> {
+        let roads = match &self.roads {
+            Some(roads) => roads,
+            None => return from.iter().map(|x| self.dist(x, to)).collect(),
+        };
+        let (target, target_location) = match roads.snap(to) {
+            Some(snapped) => snapped,
+            None => return vec![f64::INFINITY; from.len()],
+        };
+        let snapped = from.iter().map(|x| roads.snap(x)).collect::<Vec<_>>();
+        let sources = snapped
+            .iter()
+            .flatten()
+            .map(|(node, _)| *node)
+            .collect::<Vec<usize>>();
+        let mut minutes = roads.minutes_to(&sources, target).into_iter();
+        let off_roads = self.straight_minutes(&target_location, to);
+        from.iter()
+            .zip(snapped)
+            .map(|(point, snapped)| match snapped {
+                Some((_, location)) => {
+                    let along = minutes.next().unwrap_or(f64::INFINITY);
+                    self.straight_minutes(point, &location) + along + off_roads
+                }
+                None => f64::INFINITY,
+            })
+            .collect()
+    }
+
+    // The furthest `Point::dist` a cab can be from the pickup and still cost
+    // no more than `cost` to dispatch. Along the roads nothing is faster
+    // than the fastest road, or the average speed off them, over the
+    // straight line shrunk by how much shorter roads can be than that.
+    pub fn dispatch_reach(&self, cost: f64) -> f64 {
+        match &self.roads {
+            Some(roads) => {
+                let speed_kmh = roads.max_speed_kmh().max(self.speed_kmh);
+                cost / 60.0 * speed_kmh / roads.stretch()
+            }
+            None => self.metric.reach(cost),
+        }
+    }
+}
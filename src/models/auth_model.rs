@@ -0,0 +1,95 @@
+use std::str::FromStr;
+
+use crate::error::FuberError;
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+
+// what a caller is allowed to do, admins can do everything
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    // acts for one person
+    Rider,
+    // drives one cab
+    Driver,
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Rider => "rider",
+            Role::Driver => "driver",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = FuberError;
+
+    fn from_str(role: &str) -> Result<Self, Self::Err> {
+        match role {
+            "rider" => Ok(Role::Rider),
+            "driver" => Ok(Role::Driver),
+            "admin" => Ok(Role::Admin),
+            _ => Err(FuberError::validation(format!("{} is not a role", role))),
+        }
+    }
+}
+
+// What a signed token says about its bearer. `sub` is the id of the
+// person of a rider or the cab of a driver, admins don't need one. `exp`
+// is in seconds since the unix epoch like every jwt has it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Claims {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+    pub role: Role,
+    pub exp: u64,
+}
+
+// A key callers send instead of a token. Only the sha-256 of the key is
+// stored, the key itself is handed out once when it is created.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApiKey {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub key_hash: String,
+    pub role: Role,
+    // the person of a rider or the cab of a driver
+    pub subject: Option<ObjectId>,
+    // milliseconds since the unix epoch
+    pub created_at: i64,
+}
+
+// what admins send to create a key
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NewApiKey {
+    pub role: Role,
+    #[serde(default)]
+    pub subject: Option<ObjectId>,
+}
+
+// what admins get back, the only time anybody sees `key`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CreatedApiKey {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub key: String,
+    pub role: Role,
+    pub subject: Option<ObjectId>,
+}
+
+// riders and drivers always act for somebody
+pub fn check_subject(role: Role, subject: Option<ObjectId>) -> Result<(), FuberError> {
+    match (role, subject) {
+        (Role::Rider, None) => Err(FuberError::validation(
+            "a rider needs the id of their person as subject",
+        )),
+        (Role::Driver, None) => Err(FuberError::validation(
+            "a driver needs the id of their cab as subject",
+        )),
+        _ => Ok(()),
+    }
+}
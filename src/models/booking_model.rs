@@ -0,0 +1,96 @@
+use std::str::FromStr;
+
+use super::{cab_model::CabCategory, point_model::Point, ride_model::now_millis};
+use crate::error::FuberError;
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+
+// A booking is scheduled until the scheduler dispatches it, from then on
+// its ride tells where the trip is. Dispatched and cancelled are final.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BookingState {
+    Scheduled,
+    Dispatched,
+    Cancelled,
+}
+
+impl BookingState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BookingState::Scheduled => "scheduled",
+            BookingState::Dispatched => "dispatched",
+            BookingState::Cancelled => "cancelled",
+        }
+    }
+}
+
+impl FromStr for BookingState {
+    type Err = FuberError;
+
+    fn from_str(state: &str) -> Result<Self, Self::Err> {
+        match state {
+            "scheduled" => Ok(BookingState::Scheduled),
+            "dispatched" => Ok(BookingState::Dispatched),
+            "cancelled" => Ok(BookingState::Cancelled),
+            _ => Err(FuberError::storage(format!(
+                "{} is not a booking state",
+                state
+            ))),
+        }
+    }
+}
+
+// what a person sends to book a ride ahead, `pickup_at` is in milliseconds
+// since the unix epoch like every other timestamp
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NewBooking {
+    pub pickup_at: i64,
+    pub origin: Point,
+    pub destination: Point,
+    #[serde(default)]
+    pub category: Option<CabCategory>,
+}
+
+// A ride booked ahead of time. The scheduler starts looking for a cab
+// near `pickup` some lead time before `pickup_at` and only takes one
+// within a radius that grows with every `attempts` it made, so the cab
+// doesn't come from across town hours early. Once it has a cab, or the
+// pickup time is there, the booking is dispatched as a ride.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Booking {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub person_id: ObjectId,
+    pub pickup: Point,
+    pub drop: Point,
+    pub pickup_at: i64,
+    pub category: Option<CabCategory>,
+    pub state: BookingState,
+    pub attempts: u32,
+    // only known once the booking is dispatched
+    pub ride_id: Option<ObjectId>,
+    pub created_at: i64,
+}
+
+impl Booking {
+    pub fn new(person_id: ObjectId, booking: NewBooking) -> Self {
+        Booking {
+            id: None,
+            person_id,
+            pickup: booking.origin,
+            drop: booking.destination,
+            pickup_at: booking.pickup_at,
+            category: booking.category,
+            state: BookingState::Scheduled,
+            attempts: 0,
+            ride_id: None,
+            created_at: now_millis(),
+        }
+    }
+
+    // still ahead of the person, dispatched ones too until the pickup
+    pub fn is_upcoming(&self, now: i64) -> bool {
+        self.state != BookingState::Cancelled && self.pickup_at > now
+    }
+}
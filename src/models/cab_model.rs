@@ -1,9 +1,87 @@
+use std::str::FromStr;
+
 use super::point_model::Point;
+use crate::error::FuberError;
 use mongodb::bson::oid::ObjectId;
 use serde::{Deserialize, Serialize};
 
-// Struct Cab to encapsulate what info a cab should be have
+// The kind of vehicle, riders can ask for one and the tariff can charge a
+// surcharge per category. Cabs stored before categories existed are
+// standard ones.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CabCategory {
+    #[default]
+    Standard,
+    Pink,
+    Xl,
+    Accessible,
+}
+
+impl CabCategory {
+    // same names serde uses, also the keys of the tariff surcharges
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CabCategory::Standard => "standard",
+            CabCategory::Pink => "pink",
+            CabCategory::Xl => "xl",
+            CabCategory::Accessible => "accessible",
+        }
+    }
+}
+
+impl FromStr for CabCategory {
+    type Err = FuberError;
+
+    fn from_str(category: &str) -> Result<Self, Self::Err> {
+        match category {
+            "standard" => Ok(CabCategory::Standard),
+            "pink" => Ok(CabCategory::Pink),
+            "xl" => Ok(CabCategory::Xl),
+            "accessible" => Ok(CabCategory::Accessible),
+            _ => Err(FuberError::validation(format!(
+                "{} is not a cab category, use standard, pink, xl or accessible",
+                category
+            ))),
+        }
+    }
+}
+
+// seats of cabs stored before cabs had a number of seats
+pub const DEFAULT_SEATS: u32 = 4;
+
+fn default_seats() -> u32 {
+    DEFAULT_SEATS
+}
+
+// somebody sharing a pooled cab, waiting for it until they are `onboard`
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rider {
+    pub person_id: ObjectId,
+    pub ride_id: ObjectId,
+    pub onboard: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StopKind {
+    Pickup,
+    Drop,
+}
+
+// a place a pooled cab has to go to, to pick up or drop off the rider of
+// the ride
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Stop {
+    pub ride_id: ObjectId,
+    pub kind: StopKind,
+    pub location: Point,
+}
+
+// Struct Cab to encapsulate what info a cab should be have
+// A cab either drives one person, `person_id`, or is shared by its
+// `riders`, going to its `stops` in order. It is free while it has neither.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Cab {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
     pub id: Option<ObjectId>,
@@ -11,6 +89,14 @@ pub struct Cab {
     pub destination: Option<Point>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub person_id: Option<ObjectId>,
+    #[serde(default)]
+    pub category: CabCategory,
+    #[serde(default = "default_seats")]
+    pub seats: u32,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub riders: Vec<Rider>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stops: Vec<Stop>,
 }
 
 // helper functions picking up things that can be accessed outside of the
@@ -18,14 +104,67 @@ pub struct Cab {
 // because we don't want the Cab instance outside library to change it.
 impl Cab {
     pub fn new(location: Point) -> Self {
+        Cab::with_category(location, CabCategory::Standard)
+    }
+
+    pub fn with_category(location: Point, category: CabCategory) -> Self {
         Cab {
             id: None,
             location,
             destination: None,
             person_id: None,
+            category,
+            seats: DEFAULT_SEATS,
+            riders: Vec::new(),
+            stops: Vec::new(),
         }
     }
 
+    pub fn with_seats(self, seats: u32) -> Self {
+        Cab { seats, ..self }
+    }
+
+    pub fn is_free(&self) -> bool {
+        self.person_id.is_none() && self.riders.is_empty()
+    }
+
+    // shared by riders, maybe with seats left for another one
+    pub fn is_pooled(&self) -> bool {
+        self.person_id.is_none() && !self.riders.is_empty()
+    }
+
+    // the person has the cab, for themselves or shared
+    pub fn carries(&self, person_id: ObjectId) -> bool {
+        self.person_id == Some(person_id) || self.riders.iter().any(|x| x.person_id == person_id)
+    }
+
+    pub fn rider(&self, ride_id: ObjectId) -> Option<&Rider> {
+        self.riders.iter().find(|x| x.ride_id == ride_id)
+    }
+
+    // the rider of the ride got in, the cab is where they were picked up
+    pub fn board(&mut self, ride_id: ObjectId) {
+        if let Some(stop) = self
+            .stops
+            .iter()
+            .position(|x| x.ride_id == ride_id && x.kind == StopKind::Pickup)
+        {
+            self.location = self.stops.remove(stop).location;
+        }
+        if let Some(rider) = self.riders.iter_mut().find(|x| x.ride_id == ride_id) {
+            rider.onboard = true;
+        }
+        self.destination = self.stops.first().map(|x| x.location.clone());
+    }
+
+    // the rider of the ride leaves the cab along with their stops, it heads
+    // on to the next stop or is free again
+    pub fn leave(&mut self, ride_id: ObjectId) {
+        self.riders.retain(|x| x.ride_id != ride_id);
+        self.stops.retain(|x| x.ride_id != ride_id);
+        self.destination = self.stops.first().map(|x| x.location.clone());
+    }
+
     pub fn update_location(&mut self, location: Point) {
         self.location = location;
     }
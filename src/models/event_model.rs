@@ -0,0 +1,191 @@
+use std::str::FromStr;
+
+use super::{
+    cab_model::Cab,
+    person_model::Person,
+    ride_model::{now_millis, Ride, RideState},
+};
+use crate::error::FuberError;
+use mongodb::bson::{self, oid::ObjectId, Document};
+use serde::{Deserialize, Serialize};
+
+// every change to a cab or a person the repos keep a record of, and the
+// rides getting a cab, ending or getting cancelled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    PersonCreated,
+    PersonUpdated,
+    PersonDeleted,
+    CabCreated,
+    // the location, destination, category or seats were set through
+    // `update_cab`
+    CabUpdated,
+    // the cab reported where it is through `update_location`
+    LocationUpdated,
+    PersonAssigned,
+    PersonUnassigned,
+    // riders joined or left a pooled cab
+    CabPooled,
+    CabDeleted,
+    // a ride got its cab, pooled rides too
+    RideAssigned,
+    // the person got dropped off, the payload has the fare
+    RideCompleted,
+    RideCancelled,
+}
+
+impl EventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventKind::PersonCreated => "person_created",
+            EventKind::PersonUpdated => "person_updated",
+            EventKind::PersonDeleted => "person_deleted",
+            EventKind::CabCreated => "cab_created",
+            EventKind::CabUpdated => "cab_updated",
+            EventKind::LocationUpdated => "location_updated",
+            EventKind::PersonAssigned => "person_assigned",
+            EventKind::PersonUnassigned => "person_unassigned",
+            EventKind::CabPooled => "cab_pooled",
+            EventKind::CabDeleted => "cab_deleted",
+            EventKind::RideAssigned => "ride_assigned",
+            EventKind::RideCompleted => "ride_completed",
+            EventKind::RideCancelled => "ride_cancelled",
+        }
+    }
+}
+
+impl FromStr for EventKind {
+    type Err = FuberError;
+
+    fn from_str(kind: &str) -> Result<Self, Self::Err> {
+        match kind {
+            "person_created" => Ok(EventKind::PersonCreated),
+            "person_updated" => Ok(EventKind::PersonUpdated),
+            "person_deleted" => Ok(EventKind::PersonDeleted),
+            "cab_created" => Ok(EventKind::CabCreated),
+            "cab_updated" => Ok(EventKind::CabUpdated),
+            "location_updated" => Ok(EventKind::LocationUpdated),
+            "person_assigned" => Ok(EventKind::PersonAssigned),
+            "person_unassigned" => Ok(EventKind::PersonUnassigned),
+            "cab_pooled" => Ok(EventKind::CabPooled),
+            "cab_deleted" => Ok(EventKind::CabDeleted),
+            "ride_assigned" => Ok(EventKind::RideAssigned),
+            "ride_completed" => Ok(EventKind::RideCompleted),
+            "ride_cancelled" => Ok(EventKind::RideCancelled),
+            _ => Err(FuberError::storage(format!(
+                "{} is not an event kind",
+                kind
+            ))),
+        }
+    }
+}
+
+// An entry of the change feed, events are only ever appended and never
+// change afterwards. `seq` counts up from 1 in the order the events were
+// recorded and is the cursor consumers page through the feed with, the
+// repo hands it out when the event is stored. `payload` is the cab, person
+// or ride as it was written, for deletes as it was right before.
+// `person_id` is the person that got into or out of the cab or whose ride
+// it is, the payload of an unassignment doesn't have it anymore.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Event {
+    pub seq: i64,
+    pub kind: EventKind,
+    pub entity_id: ObjectId,
+    // milliseconds since the unix epoch
+    pub at: i64,
+    pub payload: Document,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub person_id: Option<ObjectId>,
+}
+
+fn payload<T: Serialize>(entity: &T) -> Result<Document, FuberError> {
+    bson::to_document(entity)
+        .map_err(|e| FuberError::storage(format!("Error converting an event payload: {}", e)))
+}
+
+impl Event {
+    pub fn new(kind: EventKind, entity_id: ObjectId, payload: Document) -> Self {
+        Event {
+            seq: 0,
+            kind,
+            entity_id,
+            at: now_millis(),
+            payload,
+            person_id: None,
+        }
+    }
+
+    // the id is taken from `cab_id`, callers don't always fill it in
+    pub fn of_cab(kind: EventKind, cab_id: ObjectId, cab: &Cab) -> Result<Self, FuberError> {
+        let cab = Cab {
+            id: Some(cab_id),
+            ..cab.clone()
+        };
+        Ok(Event::new(kind, cab_id, payload(&cab)?))
+    }
+
+    // the cab `before` and `after` a change, the payload is the one after
+    // and the person is the one that got in or, for an unassignment, out
+    pub fn of_cab_change(
+        kind: EventKind,
+        cab_id: ObjectId,
+        before: &Cab,
+        after: &Cab,
+    ) -> Result<Self, FuberError> {
+        let person_id = match kind {
+            EventKind::PersonAssigned => after.person_id,
+            EventKind::PersonUnassigned => before.person_id,
+            _ => None,
+        };
+        Ok(Event {
+            person_id,
+            ..Event::of_cab(kind, cab_id, after)?
+        })
+    }
+
+    // the event of a ride that moved on from `from`, `None` when the ride
+    // stayed where it was or moved to a state nobody is told about
+    pub fn of_ride(
+        from: RideState,
+        ride_id: ObjectId,
+        ride: &Ride,
+    ) -> Result<Option<Self>, FuberError> {
+        let kind = match ride.state {
+            _ if ride.state == from => return Ok(None),
+            RideState::Assigned => EventKind::RideAssigned,
+            RideState::Completed => EventKind::RideCompleted,
+            RideState::Cancelled => EventKind::RideCancelled,
+            _ => return Ok(None),
+        };
+        let ride = Ride {
+            id: Some(ride_id),
+            ..ride.clone()
+        };
+        Ok(Some(Event {
+            person_id: Some(ride.person_id),
+            ..Event::new(kind, ride_id, payload(&ride)?)
+        }))
+    }
+
+    pub fn of_person(
+        kind: EventKind,
+        person_id: ObjectId,
+        person: &Person,
+    ) -> Result<Self, FuberError> {
+        let person = Person {
+            id: Some(person_id),
+            ..person.clone()
+        };
+        Ok(Event::new(kind, person_id, payload(&person)?))
+    }
+}
+
+// one page of the feed, `next` is the cursor to ask for the page after it
+// and stays the same while nothing new happened
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EventPage {
+    pub events: Vec<Event>,
+    pub next: i64,
+}
@@ -0,0 +1,17 @@
+use serde::{Deserialize, Serialize};
+
+// What a ride cost and how the total came together, every amount is in the
+// currency of the tariff it was computed with and rounded to cents.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Fare {
+    pub distance_km: f64,
+    pub duration_minutes: f64,
+    pub base_fare: f64,
+    pub distance_charge: f64,
+    pub time_charge: f64,
+    pub surcharge: f64,
+    // what the surge added on top, fares from before surges have none
+    #[serde(default)]
+    pub surge: f64,
+    pub total: f64,
+}
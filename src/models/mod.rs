@@ -1,3 +1,10 @@
+pub mod auth_model;
+pub mod booking_model;
 pub mod cab_model;
+pub mod event_model;
+pub mod fare_model;
 pub mod person_model;
 pub mod point_model;
+pub mod ride_model;
+pub mod ticket_model;
+pub mod webhook_model;
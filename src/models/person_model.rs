@@ -1,9 +1,10 @@
 use super::cab_model::Cab;
 use super::point_model::Point;
+use crate::metric::DistanceMetric;
 use mongodb::bson::oid::ObjectId;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Person {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
     pub id: Option<ObjectId>,
@@ -24,11 +25,26 @@ impl Person {
         }
     }
 
-    pub fn nearest_cab(&self, c1: &Cab, c2: &Cab) -> Cab {
-        if c2.location == self.location.nearest_point(&c1.location, &c2.location) {
+    pub fn nearest_cab(&self, c1: &Cab, c2: &Cab, metric: &dyn DistanceMetric) -> Cab {
+        if c2.location
+            == self
+                .location
+                .nearest_point(&c1.location, &c2.location, metric)
+        {
             c2.clone()
         } else {
             c1.clone()
         }
     }
+
+    // the cabs ordered from the nearest to the farthest, cabs at the same
+    // distance keep the order they came in
+    pub fn cabs_by_distance(&self, mut cabs: Vec<Cab>, metric: &dyn DistanceMetric) -> Vec<Cab> {
+        cabs.sort_by(|c1, c2| {
+            metric
+                .dist(&self.location, &c1.location)
+                .total_cmp(&metric.dist(&self.location, &c2.location))
+        });
+        cabs
+    }
 }
@@ -1,25 +1,118 @@
+use std::convert::TryFrom;
+
 use serde::{Deserialize, Serialize};
 
-// Point struct to abstract the nitty gritty stuff for locations
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
-pub struct Point {
-    pub x: i64,
-    pub y: i64,
+use crate::{error::FuberError, metric::DistanceMetric};
+
+// mean radius of the earth, what haversine distances are measured with
+pub const EARTH_RADIUS_KM: f64 = 6371.0088;
+
+// grid coordinates have to stay strictly within this either way, the 2d
+// index MongoDB keeps on grid points takes nothing outside of it
+pub const GRID_BOUND: i64 = 1_000_000;
+
+// Point to abstract the nitty gritty stuff for locations. It is either a
+// point on the simulated grid city, `{"x": 1, "y": 2}` in json, or a real
+// gps position, `{"lat": 52.52, "lon": 13.40}`. Distances on the grid are in
+// grid units (one unit counts as a km) and between gps positions they are
+// great-circle kilometres. The two never mix, a grid point is infinitely
+// far away from any gps position.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged, try_from = "RawPoint")]
+pub enum Point {
+    Grid { x: i64, y: i64 },
+    Geo { lat: f64, lon: f64 },
+}
+
+// what a point looks like before its coordinates are checked
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawPoint {
+    Grid { x: i64, y: i64 },
+    Geo { lat: f64, lon: f64 },
+}
+
+impl TryFrom<RawPoint> for Point {
+    type Error = FuberError;
+
+    fn try_from(raw: RawPoint) -> Result<Self, Self::Error> {
+        match raw {
+            RawPoint::Grid { x, y } => Point::grid(x, y),
+            RawPoint::Geo { lat, lon } => Point::geo(lat, lon),
+        }
+    }
 }
 
 // helper functions to generate new or random points even from tuples
 // more helper functions can be added as and when the domain needs grow
 impl Point {
     pub fn new(x: i64, y: i64) -> Self {
-        Point { x, y }
+        Point::Grid { x, y }
+    }
+
+    // x and y have to be within (-GRID_BOUND, GRID_BOUND)
+    pub fn grid(x: i64, y: i64) -> Result<Self, FuberError> {
+        match [("x", x), ("y", y)]
+            .into_iter()
+            .find(|(_, v)| v.unsigned_abs() >= GRID_BOUND as u64)
+        {
+            Some((name, v)) => Err(FuberError::validation(format!(
+                "{} {} is not within (-{}, {})",
+                name, v, GRID_BOUND, GRID_BOUND
+            ))),
+            None => Ok(Point::Grid { x, y }),
+        }
     }
+
+    // latitude has to be within [-90, 90] and longitude within [-180, 180]
+    pub fn geo(lat: f64, lon: f64) -> Result<Self, FuberError> {
+        if !(lat.is_finite() && (-90.0..=90.0).contains(&lat)) {
+            Err(FuberError::validation(format!(
+                "latitude {} is not within [-90, 90]",
+                lat
+            )))
+        } else if !(lon.is_finite() && (-180.0..=180.0).contains(&lon)) {
+            Err(FuberError::validation(format!(
+                "longitude {} is not within [-180, 180]",
+                lon
+            )))
+        } else {
+            Ok(Point::Geo { lat, lon })
+        }
+    }
+
     pub fn from_tuple(t: (i64, i64)) -> Self {
-        Point { x: t.0, y: t.1 }
+        Point::new(t.0, t.1)
+    }
+
+    pub fn is_geo(&self) -> bool {
+        matches!(self, Point::Geo { .. })
     }
+
+    // both on the grid or both gps positions
+    pub fn same_kind(&self, p: &Point) -> bool {
+        self.is_geo() == p.is_geo()
+    }
+
     pub fn dist(&self, p: &Point) -> f64 {
-        let x_sq = (self.x - p.x) * (self.x - p.x);
-        let y_sq = (self.y - p.y) * (self.y - p.y);
-        ((x_sq + y_sq) as f64).sqrt()
+        match (self, p) {
+            (Point::Grid { x: x1, y: y1 }, Point::Grid { x: x2, y: y2 }) => {
+                let x_sq = (x1 - x2) * (x1 - x2);
+                let y_sq = (y1 - y2) * (y1 - y2);
+                ((x_sq + y_sq) as f64).sqrt()
+            }
+            (
+                Point::Geo {
+                    lat: lat1,
+                    lon: lon1,
+                },
+                Point::Geo {
+                    lat: lat2,
+                    lon: lon2,
+                },
+            ) => haversine_km(*lat1, *lon1, *lat2, *lon2),
+            _ => f64::INFINITY,
+        }
     }
 
     pub fn create_random_point() -> Self {
@@ -32,12 +125,12 @@ impl Point {
         (0..n).map(|_| Point::create_random_point()).collect()
     }
 
-    // access the nearest point from p1 or p2
+    // access the nearest point from p1 or p2 as measured by `metric`
     // for eq. if p = (0, 0) and p1 = (1, 2), p2 = (3, 4)
     // p1 is nearer to p and the return value
-    pub fn nearest_point(&self, p1: &Point, p2: &Point) -> Point {
-        let d1 = self.dist(p1);
-        let d2 = self.dist(p2);
+    pub fn nearest_point(&self, p1: &Point, p2: &Point, metric: &dyn DistanceMetric) -> Point {
+        let d1 = metric.dist(self, p1);
+        let d2 = metric.dist(self, p2);
         if d1 < d2 {
             p1.clone()
         } else if d1 > d2 {
@@ -47,3 +140,12 @@ impl Point {
         }
     }
 }
+
+// great-circle distance between two gps positions in km
+pub fn haversine_km(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let d_lat = (lat2 - lat1).to_radians();
+    let d_lon = (lon2 - lon1).to_radians();
+    let a = (d_lat / 2.0).sin().powi(2)
+        + lat1.to_radians().cos() * lat2.to_radians().cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().min(1.0).asin()
+}
@@ -0,0 +1,181 @@
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{fare_model::Fare, point_model::Point};
+use crate::error::{ErrorCode, FuberError};
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+
+// Where a ride is in its lifecycle, the only moves allowed are
+//
+//   Requested -> Assigned -> DriverArriving -> PickedUp -> Completed
+//
+// and any state before PickedUp can also go to Cancelled.
+// Completed and Cancelled are final.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RideState {
+    Requested,
+    Assigned,
+    DriverArriving,
+    PickedUp,
+    Completed,
+    Cancelled,
+}
+
+impl RideState {
+    pub fn can_move_to(&self, next: RideState) -> bool {
+        use RideState::*;
+        matches!(
+            (self, next),
+            (Requested, Assigned)
+                | (Assigned, DriverArriving)
+                | (DriverArriving, PickedUp)
+                | (PickedUp, Completed)
+                | (Requested, Cancelled)
+                | (Assigned, Cancelled)
+                | (DriverArriving, Cancelled)
+        )
+    }
+
+    // a ride that still holds on to a cab or is waiting for one
+    pub fn is_active(&self) -> bool {
+        !matches!(self, RideState::Completed | RideState::Cancelled)
+    }
+
+    // same names serde uses, handy for storage backends that keep the
+    // state as plain text
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RideState::Requested => "requested",
+            RideState::Assigned => "assigned",
+            RideState::DriverArriving => "driver_arriving",
+            RideState::PickedUp => "picked_up",
+            RideState::Completed => "completed",
+            RideState::Cancelled => "cancelled",
+        }
+    }
+}
+
+impl FromStr for RideState {
+    type Err = FuberError;
+
+    fn from_str(state: &str) -> Result<Self, Self::Err> {
+        match state {
+            "requested" => Ok(RideState::Requested),
+            "assigned" => Ok(RideState::Assigned),
+            "driver_arriving" => Ok(RideState::DriverArriving),
+            "picked_up" => Ok(RideState::PickedUp),
+            "completed" => Ok(RideState::Completed),
+            "cancelled" => Ok(RideState::Cancelled),
+            _ => Err(FuberError::storage(format!(
+                "{} is not a ride state",
+                state
+            ))),
+        }
+    }
+}
+
+// when the ride entered `state`, in milliseconds since the unix epoch
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RideTransition {
+    pub state: RideState,
+    pub timestamp: i64,
+}
+
+pub fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+// A trip of a person from `pickup` to `drop`, the cab is only known once
+// the ride got assigned. `transitions` keeps every state the ride went
+// through in order, the last one is always `state`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Ride {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub person_id: ObjectId,
+    pub cab_id: Option<ObjectId>,
+    pub pickup: Point,
+    pub drop: Point,
+    pub state: RideState,
+    pub transitions: Vec<RideTransition>,
+    // only known once the ride is completed
+    #[serde(default)]
+    pub fare: Option<Fare>,
+    // how many minutes the cab needed to the pickup when it got assigned
+    #[serde(default)]
+    pub eta_minutes: Option<f64>,
+    // the surge where the ride was requested, the fare is multiplied by it
+    #[serde(default)]
+    pub surge_multiplier: Option<f64>,
+}
+
+impl Ride {
+    pub fn new(person_id: ObjectId, pickup: Point, drop: Point) -> Self {
+        Ride {
+            id: None,
+            person_id,
+            cab_id: None,
+            pickup,
+            drop,
+            state: RideState::Requested,
+            transitions: vec![RideTransition {
+                state: RideState::Requested,
+                timestamp: now_millis(),
+            }],
+            fare: None,
+            eta_minutes: None,
+            surge_multiplier: None,
+        }
+    }
+
+    pub fn move_to(&mut self, next: RideState) -> Result<(), FuberError> {
+        if self.state.can_move_to(next) {
+            self.state = next;
+            self.transitions.push(RideTransition {
+                state: next,
+                timestamp: now_millis(),
+            });
+            Ok(())
+        } else {
+            Err(FuberError::Conflict(
+                ErrorCode::InvalidRideTransition,
+                format!(
+                    "a ride cannot go from {} to {}",
+                    self.state.as_str(),
+                    next.as_str()
+                ),
+            ))
+        }
+    }
+
+    pub fn assign(&mut self, cab_id: ObjectId) -> Result<(), FuberError> {
+        self.move_to(RideState::Assigned)?;
+        self.cab_id = Some(cab_id);
+        Ok(())
+    }
+
+    // the person got dropped off, clients that never reported the driver
+    // arriving or the pickup get those steps filled in on the way
+    pub fn complete(&mut self) -> Result<(), FuberError> {
+        if self.state == RideState::Assigned {
+            self.move_to(RideState::DriverArriving)?;
+        }
+        if self.state == RideState::DriverArriving {
+            self.move_to(RideState::PickedUp)?;
+        }
+        self.move_to(RideState::Completed)
+    }
+
+    // when the ride entered `state`, if it ever did
+    pub fn entered_at(&self, state: RideState) -> Option<i64> {
+        self.transitions
+            .iter()
+            .find(|t| t.state == state)
+            .map(|t| t.timestamp)
+    }
+}
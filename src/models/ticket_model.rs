@@ -0,0 +1,83 @@
+use std::str::FromStr;
+
+use super::{cab_model::CabCategory, ride_model::now_millis};
+use crate::error::FuberError;
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+
+// A ticket starts out waiting for a cab and either gets matched to one
+// that frees up or is cancelled, both are final.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TicketState {
+    Waiting,
+    Matched,
+    Cancelled,
+}
+
+impl TicketState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TicketState::Waiting => "waiting",
+            TicketState::Matched => "matched",
+            TicketState::Cancelled => "cancelled",
+        }
+    }
+}
+
+impl FromStr for TicketState {
+    type Err = FuberError;
+
+    fn from_str(state: &str) -> Result<Self, Self::Err> {
+        match state {
+            "waiting" => Ok(TicketState::Waiting),
+            "matched" => Ok(TicketState::Matched),
+            "cancelled" => Ok(TicketState::Cancelled),
+            _ => Err(FuberError::storage(format!(
+                "{} is not a ticket state",
+                state
+            ))),
+        }
+    }
+}
+
+// A ride request that found no free cab and waits in the queue for one.
+// The ride stays requested until the ticket gets matched, the queue is
+// first come first served, by `created_at`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ticket {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub person_id: ObjectId,
+    pub ride_id: ObjectId,
+    // the category the person asked for, any free cab will do without one
+    pub category: Option<CabCategory>,
+    pub state: TicketState,
+    // milliseconds since the unix epoch
+    pub created_at: i64,
+    // only known once the ticket is matched
+    pub cab_id: Option<ObjectId>,
+}
+
+impl Ticket {
+    pub fn new(person_id: ObjectId, ride_id: ObjectId, category: Option<CabCategory>) -> Self {
+        Ticket {
+            id: None,
+            person_id,
+            ride_id,
+            category,
+            state: TicketState::Waiting,
+            created_at: now_millis(),
+            cab_id: None,
+        }
+    }
+}
+
+// what clients get to see of a ticket, `position` counts from 1 for the
+// next ticket to get a cab and is only there while the ticket is waiting
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TicketStatus {
+    #[serde(flatten)]
+    pub ticket: Ticket,
+    pub position: Option<usize>,
+}
@@ -0,0 +1,178 @@
+use std::str::FromStr;
+
+use super::{
+    event_model::{Event, EventKind},
+    ride_model::now_millis,
+};
+use crate::error::FuberError;
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+
+// the events that go out to webhooks, they are written to the outbox along
+// with the change
+pub const WEBHOOK_EVENTS: [EventKind; 5] = [
+    EventKind::PersonAssigned,
+    EventKind::PersonUnassigned,
+    EventKind::RideAssigned,
+    EventKind::RideCompleted,
+    EventKind::RideCancelled,
+];
+
+// Somebody who wants to hear about cabs getting assigned and freed and
+// about rides, whose events carry the fare to bill. Every
+// delivery is signed with `secret` so the receiver can tell it came from
+// us, the secret is never handed out again once the webhook is registered.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Webhook {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub url: String,
+    // the events the webhook gets, all of `WEBHOOK_EVENTS` when empty
+    #[serde(default)]
+    pub event_types: Vec<EventKind>,
+    pub secret: String,
+    // milliseconds since the unix epoch
+    pub created_at: i64,
+}
+
+// what clients send to register a webhook
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NewWebhook {
+    pub url: String,
+    #[serde(default)]
+    pub event_types: Vec<EventKind>,
+    pub secret: String,
+}
+
+// what clients get to see of a webhook, everything but the secret
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WebhookInfo {
+    #[serde(rename = "_id")]
+    pub id: Option<ObjectId>,
+    pub url: String,
+    pub event_types: Vec<EventKind>,
+    pub created_at: i64,
+}
+
+impl Webhook {
+    pub fn new(new_webhook: NewWebhook) -> Result<Self, FuberError> {
+        let NewWebhook {
+            url,
+            event_types,
+            secret,
+        } = new_webhook;
+        if !(url.starts_with("http://") || url.starts_with("https://")) {
+            return Err(FuberError::validation(
+                "the url of a webhook has to be http or https",
+            ));
+        }
+        if secret.is_empty() {
+            return Err(FuberError::validation("a webhook needs a secret"));
+        }
+        if let Some(kind) = event_types.iter().find(|x| !WEBHOOK_EVENTS.contains(x)) {
+            return Err(FuberError::validation(format!(
+                "{} doesn't go out to webhooks",
+                kind.as_str()
+            )));
+        }
+        Ok(Webhook {
+            id: None,
+            url,
+            event_types,
+            secret,
+            created_at: now_millis(),
+        })
+    }
+
+    pub fn wants(&self, kind: EventKind) -> bool {
+        WEBHOOK_EVENTS.contains(&kind)
+            && (self.event_types.is_empty() || self.event_types.contains(&kind))
+    }
+
+    pub fn info(&self) -> WebhookInfo {
+        WebhookInfo {
+            id: self.id,
+            url: self.url.clone(),
+            event_types: self.event_types.clone(),
+            created_at: self.created_at,
+        }
+    }
+}
+
+// A delivery waits in the outbox until its webhook answered with a 2xx,
+// and is dead, a dead letter, once it ran out of attempts. Dead letters
+// stay until somebody sends them off again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryState {
+    Pending,
+    Delivered,
+    Dead,
+}
+
+impl DeliveryState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryState::Pending => "pending",
+            DeliveryState::Delivered => "delivered",
+            DeliveryState::Dead => "dead",
+        }
+    }
+}
+
+impl FromStr for DeliveryState {
+    type Err = FuberError;
+
+    fn from_str(state: &str) -> Result<Self, Self::Err> {
+        match state {
+            "pending" => Ok(DeliveryState::Pending),
+            "delivered" => Ok(DeliveryState::Delivered),
+            "dead" => Ok(DeliveryState::Dead),
+            _ => Err(FuberError::storage(format!(
+                "{} is not a delivery state",
+                state
+            ))),
+        }
+    }
+}
+
+// An event on its way to one webhook, the entries of the outbox. The event
+// is sent as it is in the change feed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Delivery {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub webhook_id: ObjectId,
+    pub event: Event,
+    pub state: DeliveryState,
+    pub attempts: u32,
+    // the delivery isn't tried again before then, in milliseconds since the
+    // unix epoch
+    pub next_attempt_at: i64,
+    // why the last attempt failed
+    pub last_error: Option<String>,
+}
+
+impl Delivery {
+    pub fn new(webhook_id: ObjectId, event: Event) -> Self {
+        Delivery {
+            id: None,
+            webhook_id,
+            next_attempt_at: event.at,
+            event,
+            state: DeliveryState::Pending,
+            attempts: 0,
+            last_error: None,
+        }
+    }
+}
+
+// the deliveries of `event` to every webhook that wants it
+pub fn deliveries_of(webhooks: &[Webhook], event: &Event) -> Vec<Delivery> {
+    webhooks
+        .iter()
+        .filter(|x| x.wants(event.kind))
+        .filter_map(|x| x.id)
+        .map(|x| Delivery::new(x, event.clone()))
+        .collect()
+}
@@ -0,0 +1,166 @@
+use std::env;
+
+use dotenv::dotenv;
+use mongodb::bson::oid::ObjectId;
+
+use crate::{
+    metric::Travel,
+    models::cab_model::{Cab, Stop, StopKind},
+    models::point_model::Point,
+};
+
+pub const DEFAULT_MAX_DETOUR_MINUTES: f64 = 10.0;
+
+// How much longer sharing a cab can make a trip, managed by rocket next to
+// the dispatch config. A rider only joins a cab when none of its riders
+// gets to their drop more than `max_detour_minutes` later for it, and the
+// new rider doesn't ride more than that longer than they would alone.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pooling {
+    max_detour_minutes: f64,
+}
+
+impl Default for Pooling {
+    fn default() -> Self {
+        Pooling::new(DEFAULT_MAX_DETOUR_MINUTES)
+    }
+}
+
+// where a new rider goes in the stops of a cab
+#[derive(Debug, Clone, PartialEq)]
+pub struct Insertion {
+    pub stops: Vec<Stop>,
+    // how many minutes longer the cab drives all its stops
+    pub added_minutes: f64,
+    // how many minutes until the cab gets to the new pickup
+    pub pickup_minutes: f64,
+}
+
+// the minutes from `start` until the cab gets to each of the stops
+fn arrivals(travel: &Travel, start: &Point, stops: &[Stop]) -> Vec<f64> {
+    let mut at = start;
+    let mut minutes = 0.0;
+    let mut arrivals = Vec::with_capacity(stops.len());
+    for stop in stops {
+        minutes += travel.eta_minutes(at, &stop.location);
+        arrivals.push(minutes);
+        at = &stop.location;
+    }
+    arrivals
+}
+
+// never more riders on board than seats, starting with the ones already in
+fn fits(stops: &[Stop], onboard: usize, seats: u32) -> bool {
+    let mut riders = onboard;
+    for stop in stops {
+        match stop.kind {
+            StopKind::Pickup => riders += 1,
+            StopKind::Drop => riders = riders.saturating_sub(1),
+        }
+        if riders > seats as usize {
+            return false;
+        }
+    }
+    true
+}
+
+impl Pooling {
+    pub fn new(max_detour_minutes: f64) -> Self {
+        Pooling { max_detour_minutes }
+    }
+
+    // reads `FUBER_POOL_MAX_DETOUR_MINUTES`, 10 minutes without it
+    pub fn init() -> Self {
+        dotenv().ok();
+        match env::var("FUBER_POOL_MAX_DETOUR_MINUTES") {
+            Ok(minutes) => match minutes.parse::<f64>() {
+                Ok(max) if max.is_finite() && max >= 0.0 => Pooling::new(max),
+                _ => panic!("FUBER_POOL_MAX_DETOUR_MINUTES cannot be {}", minutes),
+            },
+            Err(_) => Pooling::default(),
+        }
+    }
+
+    pub fn max_detour_minutes(&self) -> f64 {
+        self.max_detour_minutes
+    }
+
+    // The cheapest way to fit the trip of the ride from `pickup` to `drop`
+    // into the stops of `cab`, the one that makes the cab drive the least
+    // longer. The stops keep their order and the new pickup and drop go
+    // between them, the pickup before the cab's last stop so the rider
+    // joins the trip under way rather than waiting for it to end. `None`
+    // when every way needs more seats than the cab has or a longer detour
+    // than allowed. A free cab simply gets the pickup and then the drop.
+    pub fn insert(
+        &self,
+        travel: &Travel,
+        cab: &Cab,
+        ride_id: ObjectId,
+        pickup: &Point,
+        drop: &Point,
+    ) -> Option<Insertion> {
+        let before = arrivals(travel, &cab.location, &cab.stops);
+        if before.iter().any(|x| !x.is_finite()) {
+            return None;
+        }
+        let total = before.last().copied().unwrap_or(0.0);
+        let alone = travel.eta_minutes(pickup, drop);
+        let onboard = cab.riders.iter().filter(|x| x.onboard).count();
+        let n = cab.stops.len();
+
+        let mut best: Option<Insertion> = None;
+        for i in 0..=n.saturating_sub(1) {
+            for j in i..=n {
+                let mut stops = cab.stops.clone();
+                stops.insert(
+                    j,
+                    Stop {
+                        ride_id,
+                        kind: StopKind::Drop,
+                        location: drop.clone(),
+                    },
+                );
+                stops.insert(
+                    i,
+                    Stop {
+                        ride_id,
+                        kind: StopKind::Pickup,
+                        location: pickup.clone(),
+                    },
+                );
+                if !fits(&stops, onboard, cab.seats) {
+                    continue;
+                }
+                let after = arrivals(travel, &cab.location, &stops);
+                let (pickup_at, drop_at) = (after[i], after[j + 1]);
+                if !drop_at.is_finite() || drop_at - pickup_at > alone + self.max_detour_minutes {
+                    continue;
+                }
+                // the old stop k moved past the new pickup and drop
+                let delayed = cab.stops.iter().enumerate().any(|(k, stop)| {
+                    let moved = k + usize::from(k >= i) + usize::from(k >= j);
+                    stop.kind == StopKind::Drop
+                        && after[moved] - before[k] > self.max_detour_minutes
+                });
+                if delayed {
+                    continue;
+                }
+                let added_minutes = after.last().copied().unwrap_or(0.0) - total;
+                // the same detour picks the new rider up sooner
+                let better = best.as_ref().is_none_or(|x| {
+                    added_minutes < x.added_minutes
+                        || (added_minutes == x.added_minutes && pickup_at < x.pickup_minutes)
+                });
+                if better {
+                    best = Some(Insertion {
+                        stops,
+                        added_minutes,
+                        pickup_minutes: pickup_at,
+                    });
+                }
+            }
+        }
+        best
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+
+use dotenv::dotenv;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    metric::DistanceMetric,
+    models::{
+        fare_model::Fare,
+        ride_model::{Ride, RideState},
+    },
+};
+
+// How rides are charged. A fare is
+//
+//   (base_fare + per_km * km + per_minute * minutes) * category surcharge * surge
+//
+// and never less than `minimum_fare`. One unit on the grid counts as a km,
+// the km are the ones of the distance metric the server is configured with.
+// Every field can be left out of the config file and falls back to the
+// default below.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Tariff {
+    pub base_fare: f64,
+    pub per_km: f64,
+    pub per_minute: f64,
+    pub minimum_fare: f64,
+    // multiplier per cab category, e.g. `{"pink": 1.5}`, categories that
+    // aren't listed are charged the plain fare
+    pub category_surcharges: HashMap<String, f64>,
+}
+
+impl Default for Tariff {
+    fn default() -> Self {
+        Tariff {
+            base_fare: 2.0,
+            per_km: 1.0,
+            per_minute: 0.25,
+            minimum_fare: 5.0,
+            category_surcharges: HashMap::new(),
+        }
+    }
+}
+
+fn round_cents(amount: f64) -> f64 {
+    (amount * 100.0).round() / 100.0
+}
+
+impl Tariff {
+    // reads the json file in `FUBER_TARIFF_PATH`, without it the default
+    // tariff is used
+    pub fn init() -> Self {
+        dotenv().ok();
+        match env::var("FUBER_TARIFF_PATH") {
+            Ok(path) => match Tariff::load(&path) {
+                Ok(tariff) => tariff,
+                Err(e) => panic!("unable to load the tariff {}: {}", path, e),
+            },
+            Err(_) => Tariff::default(),
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let json = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Tariff::from_json(&json)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        let tariff: Tariff = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        tariff.validate()?;
+        Ok(tariff)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let rates = [
+            ("base_fare", self.base_fare),
+            ("per_km", self.per_km),
+            ("per_minute", self.per_minute),
+            ("minimum_fare", self.minimum_fare),
+        ];
+        let surcharges = self
+            .category_surcharges
+            .iter()
+            .map(|(category, x)| (category.as_str(), *x));
+        match rates
+            .into_iter()
+            .chain(surcharges)
+            .find(|(_, x)| !x.is_finite() || *x < 0.0)
+        {
+            Some((name, x)) => Err(format!("{} cannot be {}", name, x)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn surcharge_for(&self, category: Option<&str>) -> f64 {
+        category
+            .and_then(|x| self.category_surcharges.get(x))
+            .copied()
+            .unwrap_or(1.0)
+    }
+
+    pub fn fare(&self, distance_km: f64, duration_minutes: f64, category: Option<&str>) -> Fare {
+        self.surged_fare(distance_km, duration_minutes, category, 1.0)
+    }
+
+    // the fare with the surge multiplier of where the ride started, the
+    // minimum fare doesn't surge
+    pub fn surged_fare(
+        &self,
+        distance_km: f64,
+        duration_minutes: f64,
+        category: Option<&str>,
+        multiplier: f64,
+    ) -> Fare {
+        let distance_charge = round_cents(self.per_km * distance_km);
+        let time_charge = round_cents(self.per_minute * duration_minutes);
+        let subtotal = self.base_fare + distance_charge + time_charge;
+        let surcharge = round_cents(subtotal * (self.surcharge_for(category) - 1.0));
+        let surge = round_cents((subtotal + surcharge) * (multiplier - 1.0));
+        Fare {
+            distance_km: round_cents(distance_km),
+            duration_minutes: round_cents(duration_minutes),
+            base_fare: round_cents(self.base_fare),
+            distance_charge,
+            time_charge,
+            surcharge,
+            surge,
+            total: round_cents((subtotal + surcharge + surge).max(self.minimum_fare)),
+        }
+    }
+
+    // the distance is the one from pickup to drop, the duration is the time
+    // between the pickup and the end of the ride, the surge the one the ride
+    // was requested with
+    pub fn fare_for_ride(
+        &self,
+        ride: &Ride,
+        category: Option<&str>,
+        metric: &dyn DistanceMetric,
+    ) -> Fare {
+        let distance_km = metric.dist(&ride.pickup, &ride.drop);
+        let duration_ms = match (
+            ride.entered_at(RideState::PickedUp),
+            ride.entered_at(RideState::Completed),
+        ) {
+            (Some(start), Some(end)) => (end - start).max(0),
+            _ => 0,
+        };
+        self.surged_fare(
+            distance_km,
+            duration_ms as f64 / 60_000.0,
+            category,
+            ride.surge_multiplier.unwrap_or(1.0),
+        )
+    }
+}
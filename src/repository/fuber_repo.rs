@@ -0,0 +1,278 @@
+use std::env;
+
+use dotenv::dotenv;
+use mongodb::bson::oid::ObjectId;
+
+use super::{
+    indexed_repo::IndexedRepo, memory_repos::MemoryRepo, mongodb_repos::MongoRepo,
+    sqlite_repos::SqliteRepo,
+};
+use crate::{
+    error::FuberError,
+    models::{
+        auth_model::ApiKey,
+        booking_model::{Booking, BookingState},
+        cab_model::{Cab, CabCategory},
+        event_model::Event,
+        person_model::Person,
+        point_model::Point,
+        ride_model::{Ride, RideState},
+        ticket_model::{Ticket, TicketState},
+        webhook_model::{Delivery, DeliveryState, Webhook},
+    },
+};
+
+// The result types below mirror the ones mongodb hands back, but they are
+// owned by us so that any storage backend can construct them
+// (mongodb marks its own result structs as #[non_exhaustive])
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InsertOneResult {
+    pub inserted_id: ObjectId,
+}
+
+// ids are in the same order as the documents that were inserted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InsertManyResult {
+    pub inserted_ids: Vec<ObjectId>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpdateResult {
+    pub matched_count: u64,
+    pub modified_count: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeleteResult {
+    pub deleted_count: u64,
+}
+
+// Everything the api layer needs from a storage backend.
+// `MongoRepo` is one implementation, the handlers only ever see this trait
+// through a `BoxedRepo` so the backend can be swapped at startup.
+// Send + Sync is needed because rocket shares managed state across workers.
+// Every change a backend makes to cabs and persons also appends an `Event`
+// to its change feed, nothing but `get_events` reads it, and so does every
+// ride that gets a cab, ends or is cancelled. Assigning and unassigning a
+// cab and those rides also put a `Delivery` into the outbox for every
+// webhook that wants the event. sqlite and memory write both with the
+// change. MongoDB keeps the events webhooks get in the changed document
+// until they are in the outbox and writes the rest right after the change,
+// a change is never reported as failed because of them.
+pub trait FuberRepository: Send + Sync {
+    fn create_person(&self, new_person: Person) -> Result<InsertOneResult, FuberError>;
+
+    fn get_person(&self, id: &str) -> Result<Person, FuberError>;
+
+    fn update_person(&self, new_person: Person) -> Result<UpdateResult, FuberError>;
+
+    fn delete_person(&self, person_id: &str) -> Result<DeleteResult, FuberError>;
+
+    fn delete_all_people(&self) -> Result<DeleteResult, FuberError>;
+
+    fn create_cab(&self, new_cab: Cab) -> Result<InsertOneResult, FuberError>;
+
+    fn get_cab(&self, id: &str) -> Result<Cab, FuberError>;
+
+    fn create_fleet(&self, fleet: Vec<Cab>) -> Result<InsertManyResult, FuberError>;
+
+    fn get_fleet(&self) -> Result<Vec<Cab>, FuberError>;
+
+    // Sets the destination and person_id of the cab with `cab_id`, but only
+    // while nobody has the cab or shares it. This is a compare-and-set:
+    // when another request took the cab first `matched_count` is 0 and
+    // nothing changes.
+    fn assign_person(&self, cab_id: &str, new_cab: Cab) -> Result<UpdateResult, FuberError>;
+
+    // moves the cab to `new_cab.location` and clears destination and person_id
+    fn unassign_person(&self, cab_id: &str, new_cab: Cab) -> Result<UpdateResult, FuberError>;
+
+    // leaves the riders and stops of the cab alone, those only change
+    // through `update_pool`
+    fn update_cab(&self, new_cab: Cab) -> Result<UpdateResult, FuberError>;
+
+    // moves the cab to `location` and touches nothing else, so a location
+    // ping can't undo an assignment that got in between
+    fn update_location(&self, cab_id: &str, location: Point) -> Result<UpdateResult, FuberError>;
+
+    // Stores the location, destination, riders and stops of a pooled cab,
+    // but only while nobody has the cab to themselves and its riders and
+    // stops are still the ones of `expected`. A compare-and-set like
+    // `assign_person`, a free cab is pooled with an `expected` that has
+    // neither.
+    fn update_pool(&self, new_cab: Cab, expected: &Cab) -> Result<UpdateResult, FuberError>;
+
+    fn delete_cab(&self, cab_id: &str) -> Result<DeleteResult, FuberError>;
+
+    fn delete_fleet(&self) -> Result<DeleteResult, FuberError>;
+
+    // at most `limit` free cabs nearest to `point` first, none further away
+    // than `max_distance` and only of `category` if one is given.
+    // The default scans the whole fleet, backends that can do better
+    // override it.
+    fn find_nearest_free_cabs(
+        &self,
+        point: &Point,
+        limit: usize,
+        max_distance: Option<f64>,
+        category: Option<CabCategory>,
+    ) -> Result<Vec<Cab>, FuberError> {
+        let mut free_cabs = self
+            .get_fleet()?
+            .into_iter()
+            .filter(|x| x.is_free() && category.is_none_or(|c| x.category == c))
+            .map(|x| (point.dist(&x.location), x))
+            // a grid point is infinitely far from a gps position
+            .filter(|(dist, _)| dist.is_finite() && max_distance.is_none_or(|max| *dist <= max))
+            .collect::<Vec<(f64, Cab)>>();
+        // stable, so cabs at the same distance keep the fleet order
+        free_cabs.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Ok(free_cabs
+            .into_iter()
+            .take(limit)
+            .map(|(_, cab)| cab)
+            .collect())
+    }
+
+    // the cabs shared by riders right now, only of `category` if one is
+    // given. The default scans the whole fleet.
+    fn get_pooled_cabs(&self, category: Option<CabCategory>) -> Result<Vec<Cab>, FuberError> {
+        Ok(self
+            .get_fleet()?
+            .into_iter()
+            .filter(|x| x.is_pooled() && category.is_none_or(|c| x.category == c))
+            .collect())
+    }
+
+    // whether the fleet has any cab of `category`, free or not. The default
+    // scans the whole fleet.
+    fn has_cab_of_category(&self, category: CabCategory) -> Result<bool, FuberError> {
+        Ok(self.get_fleet()?.iter().any(|x| x.category == category))
+    }
+
+    fn create_ride(&self, new_ride: Ride) -> Result<InsertOneResult, FuberError>;
+
+    fn get_ride(&self, id: &str) -> Result<Ride, FuberError>;
+
+    // replaces the stored ride that has the same id as `ride`, but only
+    // while it is still in the `expected` state. A compare-and-set like
+    // `update_ticket`, so two transitions can't both start from the same
+    // state
+    fn update_ride(&self, ride: Ride, expected: RideState) -> Result<UpdateResult, FuberError>;
+
+    // every ride the person ever requested, oldest first
+    fn get_rides_of_person(&self, person_id: &str) -> Result<Vec<Ride>, FuberError>;
+
+    fn create_ticket(&self, new_ticket: Ticket) -> Result<InsertOneResult, FuberError>;
+
+    fn get_ticket(&self, id: &str) -> Result<Ticket, FuberError>;
+
+    // replaces the stored ticket that has the same id as `ticket`, but only
+    // while it is still in the `expected` state. This is a compare-and-set
+    // like `assign_person`: when somebody else moved the ticket first
+    // `matched_count` is 0 and nothing changes
+    fn update_ticket(
+        &self,
+        ticket: Ticket,
+        expected: TicketState,
+    ) -> Result<UpdateResult, FuberError>;
+
+    // the tickets still waiting for a cab, oldest first
+    fn get_waiting_tickets(&self) -> Result<Vec<Ticket>, FuberError>;
+
+    fn create_booking(&self, new_booking: Booking) -> Result<InsertOneResult, FuberError>;
+
+    fn get_booking(&self, id: &str) -> Result<Booking, FuberError>;
+
+    // a compare-and-set on the state like `update_ticket`, the scheduler
+    // and a cancelling rider can't both move the same booking
+    fn update_booking(
+        &self,
+        booking: Booking,
+        expected: BookingState,
+    ) -> Result<UpdateResult, FuberError>;
+
+    // every booking the person made, soonest pickup first
+    fn get_bookings_of_person(&self, person_id: &str) -> Result<Vec<Booking>, FuberError>;
+
+    // the bookings that aren't cancelled with a pickup after `after`,
+    // soonest first
+    fn get_upcoming_bookings(&self, after: i64) -> Result<Vec<Booking>, FuberError>;
+
+    // the scheduled bookings with a pickup until `until`, soonest first
+    fn get_scheduled_bookings(&self, until: i64) -> Result<Vec<Booking>, FuberError>;
+
+    // the events recorded after the one with `since` as seq, oldest first
+    // and at most `limit` of them
+    fn get_events(&self, since: i64, limit: usize) -> Result<Vec<Event>, FuberError>;
+
+    // moves the events a backend kept with their changes into the feed and
+    // the outbox, the webhook dispatcher calls it before every round. Only
+    // MongoDB keeps any, the others write them along with the change.
+    fn flush_pending_events(&self) -> Result<(), FuberError> {
+        Ok(())
+    }
+
+    fn create_webhook(&self, new_webhook: Webhook) -> Result<InsertOneResult, FuberError>;
+
+    fn get_webhooks(&self) -> Result<Vec<Webhook>, FuberError>;
+
+    // the deliveries for the webhook go with it, dead or not
+    fn delete_webhook(&self, webhook_id: &str) -> Result<DeleteResult, FuberError>;
+
+    fn get_delivery(&self, id: &str) -> Result<Delivery, FuberError>;
+
+    // a compare-and-set on the state like `update_ticket`, a delivery that
+    // got sent off again while it was tried isn't written over
+    fn update_delivery(
+        &self,
+        delivery: Delivery,
+        expected: DeliveryState,
+    ) -> Result<UpdateResult, FuberError>;
+
+    // at most `limit` pending deliveries due until `now`, the ones due
+    // first first and in the order of their events
+    fn get_due_deliveries(&self, now: i64, limit: usize) -> Result<Vec<Delivery>, FuberError>;
+
+    // the dead letters, in the order of their events
+    fn get_dead_deliveries(&self) -> Result<Vec<Delivery>, FuberError>;
+
+    fn create_api_key(&self, new_api_key: ApiKey) -> Result<InsertOneResult, FuberError>;
+
+    // keys are only ever looked up by the sha-256 of what callers send
+    fn get_api_key_by_hash(&self, key_hash: &str) -> Result<ApiKey, FuberError>;
+
+    fn delete_api_key(&self, api_key_id: &str) -> Result<DeleteResult, FuberError>;
+}
+
+// what rocket manages as state and what every handler takes
+pub type BoxedRepo = Box<dyn FuberRepository>;
+
+// the backends without a spatial index of their own get one in memory, so
+// dispatch doesn't scan the fleet on every request
+fn indexed(backend: BoxedRepo) -> BoxedRepo {
+    match IndexedRepo::new(backend) {
+        Ok(repo) => Box::new(repo),
+        Err(e) => panic!("unable to build the spatial index: {}", e),
+    }
+}
+
+// Picks the storage backend at startup.
+// `FUBER_STORAGE` can be `memory`, `mongodb` or `sqlite` and without the
+// setting we fall back to memory whenever `MONGOURI` isn't configured so
+// the server still runs offline.
+pub fn init_repo() -> BoxedRepo {
+    dotenv().ok();
+    let storage = env::var("FUBER_STORAGE").ok();
+    let has_mongo_uri = env::var("MONGOURI").is_ok();
+
+    match storage.as_deref() {
+        Some("memory") => indexed(Box::new(MemoryRepo::init())),
+        // mongodb answers nearest cab queries with its own geo index
+        Some("mongodb") => Box::new(MongoRepo::init()),
+        Some("sqlite") => indexed(Box::new(SqliteRepo::init())),
+        Some(other) => panic!("unknown FUBER_STORAGE backend: {}", other),
+        None if has_mongo_uri => Box::new(MongoRepo::init()),
+        None => indexed(Box::new(MemoryRepo::init())),
+    }
+}
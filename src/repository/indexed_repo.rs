@@ -0,0 +1,348 @@
+use std::sync::{RwLock, RwLockWriteGuard};
+
+use mongodb::bson::oid::ObjectId;
+
+use super::fuber_repo::{
+    BoxedRepo, DeleteResult, FuberRepository, InsertManyResult, InsertOneResult, UpdateResult,
+};
+use crate::{
+    error::FuberError,
+    models::{
+        auth_model::ApiKey,
+        booking_model::{Booking, BookingState},
+        cab_model::{Cab, CabCategory},
+        event_model::Event,
+        person_model::Person,
+        point_model::Point,
+        ride_model::{Ride, RideState},
+        ticket_model::{Ticket, TicketState},
+        webhook_model::{Delivery, DeliveryState, Webhook},
+    },
+    spatial::{GridIndex, IndexedCab},
+};
+
+// Wraps any backend and keeps a `GridIndex` of the free cabs next to it, so
+// `find_nearest_free_cabs` doesn't have to load the whole fleet.
+// Every write to a cab goes through the backend first and then updates the
+// index while still holding its write lock, so the two can't drift apart
+// inside one process. Other processes writing to the same database are not
+// seen, `assign_person` stays a compare-and-set on the backend so a stale
+// entry only ever costs a retry.
+pub struct IndexedRepo {
+    inner: BoxedRepo,
+    index: RwLock<GridIndex>,
+}
+
+fn poisoned() -> FuberError {
+    FuberError::storage("spatial index lock is poisoned")
+}
+
+fn to_indexed(cab: &Cab) -> Option<IndexedCab> {
+    match cab.id {
+        Some(id) if cab.is_free() => Some(IndexedCab {
+            id,
+            location: cab.location.clone(),
+            category: cab.category,
+        }),
+        _ => None,
+    }
+}
+
+impl IndexedRepo {
+    // builds the index from the fleet already stored in `inner`
+    pub fn new(inner: BoxedRepo) -> Result<Self, FuberError> {
+        IndexedRepo::with_index(inner, GridIndex::default())
+    }
+
+    pub fn with_index(inner: BoxedRepo, mut index: GridIndex) -> Result<Self, FuberError> {
+        for cab in inner.get_fleet()?.iter().filter_map(to_indexed) {
+            index.insert(cab);
+        }
+        Ok(IndexedRepo {
+            inner,
+            index: RwLock::new(index),
+        })
+    }
+
+    fn index(&self) -> Result<RwLockWriteGuard<'_, GridIndex>, FuberError> {
+        self.index.write().map_err(|_| poisoned())
+    }
+
+    // puts the cab with `cab_id` in the index as it is stored now, a cab
+    // that is taken or gone is dropped from it
+    fn reindex(&self, index: &mut GridIndex, cab_id: &str) -> Result<(), FuberError> {
+        let indexed = match self.inner.get_cab(cab_id) {
+            Ok(cab) => to_indexed(&cab),
+            Err(FuberError::NotFound(..)) => None,
+            Err(e) => return Err(e),
+        };
+        match indexed {
+            Some(indexed) => index.insert(indexed),
+            None => {
+                if let Ok(obj_id) = ObjectId::parse_str(cab_id) {
+                    index.remove(&obj_id);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FuberRepository for IndexedRepo {
+    fn create_person(&self, new_person: Person) -> Result<InsertOneResult, FuberError> {
+        self.inner.create_person(new_person)
+    }
+
+    fn get_person(&self, id: &str) -> Result<Person, FuberError> {
+        self.inner.get_person(id)
+    }
+
+    fn update_person(&self, new_person: Person) -> Result<UpdateResult, FuberError> {
+        self.inner.update_person(new_person)
+    }
+
+    fn delete_person(&self, person_id: &str) -> Result<DeleteResult, FuberError> {
+        self.inner.delete_person(person_id)
+    }
+
+    fn delete_all_people(&self) -> Result<DeleteResult, FuberError> {
+        self.inner.delete_all_people()
+    }
+
+    fn create_cab(&self, new_cab: Cab) -> Result<InsertOneResult, FuberError> {
+        let mut index = self.index()?;
+        let result = self.inner.create_cab(new_cab)?;
+        self.reindex(&mut index, &result.inserted_id.to_hex())?;
+        Ok(result)
+    }
+
+    fn get_cab(&self, id: &str) -> Result<Cab, FuberError> {
+        self.inner.get_cab(id)
+    }
+
+    fn create_fleet(&self, fleet: Vec<Cab>) -> Result<InsertManyResult, FuberError> {
+        let mut index = self.index()?;
+        let result = self.inner.create_fleet(fleet)?;
+        for obj_id in result.inserted_ids.iter() {
+            self.reindex(&mut index, &obj_id.to_hex())?;
+        }
+        Ok(result)
+    }
+
+    fn get_fleet(&self) -> Result<Vec<Cab>, FuberError> {
+        self.inner.get_fleet()
+    }
+
+    fn assign_person(&self, cab_id: &str, new_cab: Cab) -> Result<UpdateResult, FuberError> {
+        let mut index = self.index()?;
+        let result = self.inner.assign_person(cab_id, new_cab)?;
+        // on a conflict the cab is taken as well, so it goes either way
+        self.reindex(&mut index, cab_id)?;
+        Ok(result)
+    }
+
+    fn unassign_person(&self, cab_id: &str, new_cab: Cab) -> Result<UpdateResult, FuberError> {
+        let mut index = self.index()?;
+        let result = self.inner.unassign_person(cab_id, new_cab)?;
+        if result.matched_count > 0 {
+            self.reindex(&mut index, cab_id)?;
+        }
+        Ok(result)
+    }
+
+    fn update_cab(&self, new_cab: Cab) -> Result<UpdateResult, FuberError> {
+        let mut index = self.index()?;
+        let cab_id = new_cab.id.map(|x| x.to_hex());
+        let result = self.inner.update_cab(new_cab)?;
+        if let (Some(cab_id), true) = (cab_id, result.matched_count > 0) {
+            self.reindex(&mut index, &cab_id)?;
+        }
+        Ok(result)
+    }
+
+    fn update_location(&self, cab_id: &str, location: Point) -> Result<UpdateResult, FuberError> {
+        let mut index = self.index()?;
+        let result = self.inner.update_location(cab_id, location)?;
+        if result.matched_count > 0 {
+            self.reindex(&mut index, cab_id)?;
+        }
+        Ok(result)
+    }
+
+    fn update_pool(&self, new_cab: Cab, expected: &Cab) -> Result<UpdateResult, FuberError> {
+        let mut index = self.index()?;
+        let cab_id = new_cab.id.map(|x| x.to_hex());
+        let result = self.inner.update_pool(new_cab, expected)?;
+        // a cab leaves the index once it is pooled and is back once its
+        // last rider left
+        if let (Some(cab_id), true) = (cab_id, result.matched_count > 0) {
+            self.reindex(&mut index, &cab_id)?;
+        }
+        Ok(result)
+    }
+
+    fn delete_cab(&self, cab_id: &str) -> Result<DeleteResult, FuberError> {
+        let mut index = self.index()?;
+        let result = self.inner.delete_cab(cab_id)?;
+        self.reindex(&mut index, cab_id)?;
+        Ok(result)
+    }
+
+    fn delete_fleet(&self) -> Result<DeleteResult, FuberError> {
+        let mut index = self.index()?;
+        let result = self.inner.delete_fleet()?;
+        index.clear();
+        Ok(result)
+    }
+
+    fn find_nearest_free_cabs(
+        &self,
+        point: &Point,
+        limit: usize,
+        max_distance: Option<f64>,
+        category: Option<CabCategory>,
+    ) -> Result<Vec<Cab>, FuberError> {
+        let nearest =
+            self.index
+                .read()
+                .map_err(|_| poisoned())?
+                .nearest(point, limit, max_distance, |x| {
+                    category.is_none_or(|c| x.category == c)
+                });
+        // the backend has the full cab, a cab that got taken since the
+        // lookup is simply left out
+        let mut cabs = Vec::with_capacity(nearest.len());
+        for indexed in nearest {
+            match self.inner.get_cab(&indexed.id.to_hex()) {
+                Ok(cab) if cab.is_free() => cabs.push(cab),
+                Ok(_) | Err(FuberError::NotFound(..)) => (),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(cabs)
+    }
+
+    fn get_pooled_cabs(&self, category: Option<CabCategory>) -> Result<Vec<Cab>, FuberError> {
+        self.inner.get_pooled_cabs(category)
+    }
+
+    fn has_cab_of_category(&self, category: CabCategory) -> Result<bool, FuberError> {
+        self.inner.has_cab_of_category(category)
+    }
+
+    fn create_ride(&self, new_ride: Ride) -> Result<InsertOneResult, FuberError> {
+        self.inner.create_ride(new_ride)
+    }
+
+    fn get_ride(&self, id: &str) -> Result<Ride, FuberError> {
+        self.inner.get_ride(id)
+    }
+
+    fn update_ride(&self, ride: Ride, expected: RideState) -> Result<UpdateResult, FuberError> {
+        self.inner.update_ride(ride, expected)
+    }
+
+    fn get_rides_of_person(&self, person_id: &str) -> Result<Vec<Ride>, FuberError> {
+        self.inner.get_rides_of_person(person_id)
+    }
+
+    fn create_ticket(&self, new_ticket: Ticket) -> Result<InsertOneResult, FuberError> {
+        self.inner.create_ticket(new_ticket)
+    }
+
+    fn get_ticket(&self, id: &str) -> Result<Ticket, FuberError> {
+        self.inner.get_ticket(id)
+    }
+
+    fn update_ticket(
+        &self,
+        ticket: Ticket,
+        expected: TicketState,
+    ) -> Result<UpdateResult, FuberError> {
+        self.inner.update_ticket(ticket, expected)
+    }
+
+    fn get_waiting_tickets(&self) -> Result<Vec<Ticket>, FuberError> {
+        self.inner.get_waiting_tickets()
+    }
+
+    fn create_booking(&self, new_booking: Booking) -> Result<InsertOneResult, FuberError> {
+        self.inner.create_booking(new_booking)
+    }
+
+    fn get_booking(&self, id: &str) -> Result<Booking, FuberError> {
+        self.inner.get_booking(id)
+    }
+
+    fn update_booking(
+        &self,
+        booking: Booking,
+        expected: BookingState,
+    ) -> Result<UpdateResult, FuberError> {
+        self.inner.update_booking(booking, expected)
+    }
+
+    fn get_bookings_of_person(&self, person_id: &str) -> Result<Vec<Booking>, FuberError> {
+        self.inner.get_bookings_of_person(person_id)
+    }
+
+    fn get_upcoming_bookings(&self, after: i64) -> Result<Vec<Booking>, FuberError> {
+        self.inner.get_upcoming_bookings(after)
+    }
+
+    fn get_scheduled_bookings(&self, until: i64) -> Result<Vec<Booking>, FuberError> {
+        self.inner.get_scheduled_bookings(until)
+    }
+
+    fn get_events(&self, since: i64, limit: usize) -> Result<Vec<Event>, FuberError> {
+        self.inner.get_events(since, limit)
+    }
+
+    fn flush_pending_events(&self) -> Result<(), FuberError> {
+        self.inner.flush_pending_events()
+    }
+
+    fn create_webhook(&self, new_webhook: Webhook) -> Result<InsertOneResult, FuberError> {
+        self.inner.create_webhook(new_webhook)
+    }
+
+    fn get_webhooks(&self) -> Result<Vec<Webhook>, FuberError> {
+        self.inner.get_webhooks()
+    }
+
+    fn delete_webhook(&self, webhook_id: &str) -> Result<DeleteResult, FuberError> {
+        self.inner.delete_webhook(webhook_id)
+    }
+
+    fn get_delivery(&self, id: &str) -> Result<Delivery, FuberError> {
+        self.inner.get_delivery(id)
+    }
+
+    fn update_delivery(
+        &self,
+        delivery: Delivery,
+        expected: DeliveryState,
+    ) -> Result<UpdateResult, FuberError> {
+        self.inner.update_delivery(delivery, expected)
+    }
+
+    fn get_due_deliveries(&self, now: i64, limit: usize) -> Result<Vec<Delivery>, FuberError> {
+        self.inner.get_due_deliveries(now, limit)
+    }
+
+    fn get_dead_deliveries(&self) -> Result<Vec<Delivery>, FuberError> {
+        self.inner.get_dead_deliveries()
+    }
+
+    fn create_api_key(&self, new_api_key: ApiKey) -> Result<InsertOneResult, FuberError> {
+        self.inner.create_api_key(new_api_key)
+    }
+
+    fn get_api_key_by_hash(&self, key_hash: &str) -> Result<ApiKey, FuberError> {
+        self.inner.get_api_key_by_hash(key_hash)
+    }
+
+    fn delete_api_key(&self, api_key_id: &str) -> Result<DeleteResult, FuberError> {
+        self.inner.delete_api_key(api_key_id)
+    }
+}
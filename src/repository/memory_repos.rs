@@ -0,0 +1,707 @@
+use std::sync::RwLock;
+
+use mongodb::bson::oid::ObjectId;
+
+use super::fuber_repo::{
+    DeleteResult, FuberRepository, InsertManyResult, InsertOneResult, UpdateResult,
+};
+use crate::{
+    error::{ErrorCode, FuberError},
+    models::{
+        auth_model::ApiKey,
+        booking_model::{Booking, BookingState},
+        cab_model::{Cab, CabCategory},
+        event_model::{Event, EventKind},
+        person_model::Person,
+        point_model::Point,
+        ride_model::{Ride, RideState},
+        ticket_model::{Ticket, TicketState},
+        webhook_model::{deliveries_of, Delivery, DeliveryState, Webhook},
+    },
+};
+
+// Keeps everything in process memory with the same semantics as `MongoRepo`:
+// ids are generated on insert, updates and deletes report how many
+// documents they matched and every lookup filters on the ObjectId.
+// Used for tests and local development where there is no MongoDB around.
+pub struct MemoryRepo {
+    cabs: RwLock<Vec<Cab>>,
+    persons: RwLock<Vec<Person>>,
+    rides: RwLock<Vec<Ride>>,
+    tickets: RwLock<Vec<Ticket>>,
+    bookings: RwLock<Vec<Booking>>,
+    events: RwLock<Vec<Event>>,
+    webhooks: RwLock<Vec<Webhook>>,
+    outbox: RwLock<Vec<Delivery>>,
+    api_keys: RwLock<Vec<ApiKey>>,
+}
+
+impl MemoryRepo {
+    pub fn init() -> Self {
+        MemoryRepo {
+            cabs: RwLock::new(Vec::new()),
+            persons: RwLock::new(Vec::new()),
+            rides: RwLock::new(Vec::new()),
+            tickets: RwLock::new(Vec::new()),
+            bookings: RwLock::new(Vec::new()),
+            events: RwLock::new(Vec::new()),
+            webhooks: RwLock::new(Vec::new()),
+            outbox: RwLock::new(Vec::new()),
+            api_keys: RwLock::new(Vec::new()),
+        }
+    }
+}
+
+impl MemoryRepo {
+    // stable, so bookings with the same pickup keep the order they were
+    // made in
+    fn bookings_where(
+        &self,
+        is_match: impl Fn(&Booking) -> bool,
+    ) -> Result<Vec<Booking>, FuberError> {
+        let bookings = self.bookings.read().map_err(|_| poisoned())?;
+        let mut found = bookings
+            .iter()
+            .filter(|x| is_match(x))
+            .cloned()
+            .collect::<Vec<Booking>>();
+        found.sort_by_key(|x| x.pickup_at);
+        Ok(found)
+    }
+
+    // seqs are handed out under the write lock, the feed never has gaps.
+    // The deliveries go into the outbox while the lock is still held, so
+    // the dispatcher never sees a change without them.
+    fn record(&self, event: Event) -> Result<(), FuberError> {
+        let mut events = self.events.write().map_err(|_| poisoned())?;
+        let seq = events.len() as i64 + 1;
+        let event = Event { seq, ..event };
+        let webhooks = self.webhooks.read().map_err(|_| poisoned())?;
+        let mut outbox = self.outbox.write().map_err(|_| poisoned())?;
+        outbox.extend(
+            deliveries_of(&webhooks, &event)
+                .into_iter()
+                .map(|x| Delivery {
+                    id: Some(ObjectId::new()),
+                    ..x
+                }),
+        );
+        events.push(event);
+        Ok(())
+    }
+
+    fn deliveries_where(
+        &self,
+        is_match: impl Fn(&Delivery) -> bool,
+    ) -> Result<Vec<Delivery>, FuberError> {
+        let outbox = self.outbox.read().map_err(|_| poisoned())?;
+        Ok(outbox.iter().filter(|x| is_match(x)).cloned().collect())
+    }
+
+    // updates that didn't change anything aren't worth an event
+    fn record_update(
+        &self,
+        result: UpdateResult,
+        event: impl FnOnce() -> Result<Event, FuberError>,
+    ) -> Result<UpdateResult, FuberError> {
+        if result.modified_count > 0 {
+            self.record(event()?)?;
+        }
+        Ok(result)
+    }
+
+    fn delete_persons(
+        &self,
+        is_match: impl Fn(&Person) -> bool,
+    ) -> Result<DeleteResult, FuberError> {
+        let deleted = delete_where(&self.persons, is_match)?;
+        for person in &deleted {
+            let person_id = person.id.unwrap_or_default();
+            self.record(Event::of_person(
+                EventKind::PersonDeleted,
+                person_id,
+                person,
+            )?)?;
+        }
+        Ok(DeleteResult {
+            deleted_count: deleted.len() as u64,
+        })
+    }
+
+    fn delete_cabs(&self, is_match: impl Fn(&Cab) -> bool) -> Result<DeleteResult, FuberError> {
+        let mut cabs = self.cabs.write().map_err(|_| poisoned())?;
+        let (deleted, kept): (Vec<Cab>, Vec<Cab>) = cabs.drain(..).partition(|x| is_match(x));
+        *cabs = kept;
+        for cab in &deleted {
+            let cab_id = cab.id.unwrap_or_default();
+            self.record(Event::of_cab(EventKind::CabDeleted, cab_id, cab)?)?;
+        }
+        Ok(DeleteResult {
+            deleted_count: deleted.len() as u64,
+        })
+    }
+
+    // Changes the cab with `obj_id` if it passes `is_match` and records the
+    // event with the cab as it got stored. The cabs stay locked until the
+    // event and its deliveries are in, so those are in the order of the
+    // changes, the same as the sqlite transaction.
+    fn set_cab(
+        &self,
+        obj_id: ObjectId,
+        kind: EventKind,
+        is_match: impl Fn(&Cab) -> bool,
+        update: impl Fn(&mut Cab),
+    ) -> Result<UpdateResult, FuberError> {
+        let mut cabs = self.cabs.write().map_err(|_| poisoned())?;
+        match cabs
+            .iter_mut()
+            .find(|x| x.id == Some(obj_id) && is_match(x))
+        {
+            Some(cab) => {
+                let mut after = cab.clone();
+                update(&mut after);
+                let modified = after != *cab;
+                if modified {
+                    self.record(Event::of_cab_change(kind, obj_id, cab, &after)?)?;
+                    *cab = after;
+                }
+                Ok(UpdateResult {
+                    matched_count: 1,
+                    modified_count: modified as u64,
+                })
+            }
+            None => Ok(UpdateResult {
+                matched_count: 0,
+                modified_count: 0,
+            }),
+        }
+    }
+}
+
+impl Default for MemoryRepo {
+    fn default() -> Self {
+        MemoryRepo::init()
+    }
+}
+
+fn parse_id(id: &str) -> Result<ObjectId, FuberError> {
+    ObjectId::parse_str(id).map_err(|_| FuberError::invalid_id(id))
+}
+
+fn poisoned() -> FuberError {
+    FuberError::storage("in-memory store lock is poisoned")
+}
+
+// counts follow mongodb: matched is 1 when the id exists and modified is 1
+// only when the stored value actually changed
+fn set_where<T: Clone + PartialEq>(
+    store: &RwLock<Vec<T>>,
+    is_match: impl Fn(&T) -> bool,
+    update: impl Fn(&mut T),
+) -> Result<UpdateResult, FuberError> {
+    let mut store = store.write().map_err(|_| poisoned())?;
+    match store.iter_mut().find(|x| is_match(x)) {
+        Some(doc) => {
+            let before = doc.clone();
+            update(doc);
+            Ok(UpdateResult {
+                matched_count: 1,
+                modified_count: if before == *doc { 0 } else { 1 },
+            })
+        }
+        None => Ok(UpdateResult {
+            matched_count: 0,
+            modified_count: 0,
+        }),
+    }
+}
+
+// hands back what got deleted, for the events
+fn delete_where<T>(
+    store: &RwLock<Vec<T>>,
+    is_match: impl Fn(&T) -> bool,
+) -> Result<Vec<T>, FuberError> {
+    let mut store = store.write().map_err(|_| poisoned())?;
+    let (deleted, kept) = store.drain(..).partition(|x| is_match(x));
+    *store = kept;
+    Ok(deleted)
+}
+
+impl FuberRepository for MemoryRepo {
+    fn create_person(&self, new_person: Person) -> Result<InsertOneResult, FuberError> {
+        let obj_id = new_person.id.unwrap_or_default();
+        let event = Event::of_person(EventKind::PersonCreated, obj_id, &new_person)?;
+        self.persons.write().map_err(|_| poisoned())?.push(Person {
+            id: Some(obj_id),
+            ..new_person
+        });
+        self.record(event)?;
+        Ok(InsertOneResult {
+            inserted_id: obj_id,
+        })
+    }
+
+    fn get_person(&self, id: &str) -> Result<Person, FuberError> {
+        let obj_id = parse_id(id)?;
+        let persons = self.persons.read().map_err(|_| poisoned())?;
+        match persons.iter().find(|x| x.id == Some(obj_id)) {
+            Some(person) => Ok(person.clone()),
+            None => Err(FuberError::person_not_found(id)),
+        }
+    }
+
+    fn update_person(&self, new_person: Person) -> Result<UpdateResult, FuberError> {
+        match new_person.id {
+            Some(obj_id) => {
+                let result = set_where(
+                    &self.persons,
+                    |x| x.id == Some(obj_id),
+                    |x| {
+                        x.name = new_person.name.clone();
+                        x.location = new_person.location.clone();
+                        x.destination = new_person.destination.clone();
+                    },
+                )?;
+                self.record_update(result, || {
+                    Event::of_person(EventKind::PersonUpdated, obj_id, &new_person)
+                })
+            }
+            None => Err(FuberError::InvalidId(
+                ErrorCode::InvalidObjectId,
+                "ObjectId for the person doesn't exist".into(),
+            )),
+        }
+    }
+
+    fn delete_person(&self, person_id: &str) -> Result<DeleteResult, FuberError> {
+        let obj_id = parse_id(person_id)?;
+        self.delete_persons(|x| x.id == Some(obj_id))
+    }
+
+    fn delete_all_people(&self) -> Result<DeleteResult, FuberError> {
+        self.delete_persons(|_| true)
+    }
+
+    fn create_cab(&self, new_cab: Cab) -> Result<InsertOneResult, FuberError> {
+        let obj_id = new_cab.id.unwrap_or_default();
+        let event = Event::of_cab(EventKind::CabCreated, obj_id, &new_cab)?;
+        let mut cabs = self.cabs.write().map_err(|_| poisoned())?;
+        cabs.push(Cab {
+            id: Some(obj_id),
+            ..new_cab
+        });
+        self.record(event)?;
+        Ok(InsertOneResult {
+            inserted_id: obj_id,
+        })
+    }
+
+    fn get_cab(&self, id: &str) -> Result<Cab, FuberError> {
+        let obj_id = parse_id(id)?;
+        let cabs = self.cabs.read().map_err(|_| poisoned())?;
+        match cabs.iter().find(|x| x.id == Some(obj_id)) {
+            Some(cab) => Ok(cab.clone()),
+            None => Err(FuberError::cab_not_found(id)),
+        }
+    }
+
+    fn create_fleet(&self, fleet: Vec<Cab>) -> Result<InsertManyResult, FuberError> {
+        let fleet = fleet
+            .into_iter()
+            .map(|cab| Cab {
+                id: Some(cab.id.unwrap_or_default()),
+                ..cab
+            })
+            .collect::<Vec<Cab>>();
+        let events = fleet
+            .iter()
+            .map(|x| Event::of_cab(EventKind::CabCreated, x.id.unwrap_or_default(), x))
+            .collect::<Result<Vec<Event>, FuberError>>()?;
+        let inserted_ids = fleet.iter().filter_map(|x| x.id).collect::<Vec<ObjectId>>();
+        let mut cabs = self.cabs.write().map_err(|_| poisoned())?;
+        cabs.extend(fleet);
+        for event in events {
+            self.record(event)?;
+        }
+        Ok(InsertManyResult { inserted_ids })
+    }
+
+    fn get_fleet(&self) -> Result<Vec<Cab>, FuberError> {
+        let cabs = self.cabs.read().map_err(|_| poisoned())?;
+        Ok(cabs.clone())
+    }
+
+    fn assign_person(&self, cab_id: &str, new_cab: Cab) -> Result<UpdateResult, FuberError> {
+        let obj_id = parse_id(cab_id)?;
+        // checked under the same write lock as the update, so only one
+        // caller can ever take a free cab
+        self.set_cab(
+            obj_id,
+            EventKind::PersonAssigned,
+            |x| x.is_free(),
+            |x| {
+                x.location = new_cab.location.clone();
+                x.destination = new_cab.destination.clone();
+                x.person_id = new_cab.person_id;
+            },
+        )
+    }
+
+    fn unassign_person(&self, cab_id: &str, new_cab: Cab) -> Result<UpdateResult, FuberError> {
+        let obj_id = parse_id(cab_id)?;
+        self.set_cab(
+            obj_id,
+            EventKind::PersonUnassigned,
+            |_| true,
+            |x| {
+                x.location = new_cab.location.clone();
+                x.destination = None;
+                x.person_id = None;
+            },
+        )
+    }
+
+    fn update_cab(&self, new_cab: Cab) -> Result<UpdateResult, FuberError> {
+        match new_cab.id {
+            Some(obj_id) => self.set_cab(
+                obj_id,
+                EventKind::CabUpdated,
+                |_| true,
+                |x| {
+                    x.location = new_cab.location.clone();
+                    // same as MongoRepo, a free cab never keeps a destination
+                    // and a pooled one heads to its next stop
+                    x.destination = match (new_cab.person_id, x.riders.is_empty()) {
+                        (Some(_), _) => new_cab.destination.clone(),
+                        (None, false) => x.destination.clone(),
+                        (None, true) => None,
+                    };
+                    x.person_id = new_cab.person_id;
+                    x.category = new_cab.category;
+                    x.seats = new_cab.seats;
+                },
+            ),
+            None => Err(FuberError::InvalidId(
+                ErrorCode::InvalidObjectId,
+                "Couldn't find the object id".to_string(),
+            )),
+        }
+    }
+
+    fn update_location(&self, cab_id: &str, location: Point) -> Result<UpdateResult, FuberError> {
+        self.set_cab(
+            parse_id(cab_id)?,
+            EventKind::LocationUpdated,
+            |_| true,
+            |x| x.location = location.clone(),
+        )
+    }
+
+    fn update_pool(&self, new_cab: Cab, expected: &Cab) -> Result<UpdateResult, FuberError> {
+        let obj_id = new_cab
+            .id
+            .ok_or_else(|| FuberError::storage("cannot get the cab id"))?;
+        self.set_cab(
+            obj_id,
+            EventKind::CabPooled,
+            |x| x.person_id.is_none() && x.riders == expected.riders && x.stops == expected.stops,
+            |x| {
+                x.location = new_cab.location.clone();
+                x.destination = new_cab.destination.clone();
+                x.riders = new_cab.riders.clone();
+                x.stops = new_cab.stops.clone();
+            },
+        )
+    }
+
+    fn delete_cab(&self, cab_id: &str) -> Result<DeleteResult, FuberError> {
+        let obj_id = parse_id(cab_id)?;
+        self.delete_cabs(|x| x.id == Some(obj_id))
+    }
+
+    fn delete_fleet(&self) -> Result<DeleteResult, FuberError> {
+        self.delete_cabs(|_| true)
+    }
+
+    // without copying the fleet like the default
+    fn has_cab_of_category(&self, category: CabCategory) -> Result<bool, FuberError> {
+        let cabs = self.cabs.read().map_err(|_| poisoned())?;
+        Ok(cabs.iter().any(|x| x.category == category))
+    }
+
+    fn create_ride(&self, new_ride: Ride) -> Result<InsertOneResult, FuberError> {
+        let obj_id = new_ride.id.unwrap_or_default();
+        let mut rides = self.rides.write().map_err(|_| poisoned())?;
+        rides.push(Ride {
+            id: Some(obj_id),
+            ..new_ride
+        });
+        Ok(InsertOneResult {
+            inserted_id: obj_id,
+        })
+    }
+
+    fn get_ride(&self, id: &str) -> Result<Ride, FuberError> {
+        let obj_id = parse_id(id)?;
+        let rides = self.rides.read().map_err(|_| poisoned())?;
+        match rides.iter().find(|x| x.id == Some(obj_id)) {
+            Some(ride) => Ok(ride.clone()),
+            None => Err(FuberError::ride_not_found(id)),
+        }
+    }
+
+    fn update_ride(&self, ride: Ride, expected: RideState) -> Result<UpdateResult, FuberError> {
+        match ride.id {
+            Some(obj_id) => {
+                // the rides stay locked until the event is in, like the cabs
+                let mut rides = self.rides.write().map_err(|_| poisoned())?;
+                match rides
+                    .iter_mut()
+                    .find(|x| x.id == Some(obj_id) && x.state == expected)
+                {
+                    Some(stored) => {
+                        if let Some(event) = Event::of_ride(expected, obj_id, &ride)? {
+                            self.record(event)?;
+                        }
+                        let modified = *stored != ride;
+                        *stored = ride;
+                        Ok(UpdateResult {
+                            matched_count: 1,
+                            modified_count: modified as u64,
+                        })
+                    }
+                    None => Ok(UpdateResult {
+                        matched_count: 0,
+                        modified_count: 0,
+                    }),
+                }
+            }
+            None => Err(FuberError::InvalidId(
+                ErrorCode::InvalidObjectId,
+                "ObjectId for the ride doesn't exist".into(),
+            )),
+        }
+    }
+
+    fn get_rides_of_person(&self, person_id: &str) -> Result<Vec<Ride>, FuberError> {
+        let obj_id = parse_id(person_id)?;
+        let rides = self.rides.read().map_err(|_| poisoned())?;
+        Ok(rides
+            .iter()
+            .filter(|x| x.person_id == obj_id)
+            .cloned()
+            .collect())
+    }
+
+    fn create_ticket(&self, new_ticket: Ticket) -> Result<InsertOneResult, FuberError> {
+        let obj_id = new_ticket.id.unwrap_or_default();
+        let mut tickets = self.tickets.write().map_err(|_| poisoned())?;
+        tickets.push(Ticket {
+            id: Some(obj_id),
+            ..new_ticket
+        });
+        Ok(InsertOneResult {
+            inserted_id: obj_id,
+        })
+    }
+
+    fn get_ticket(&self, id: &str) -> Result<Ticket, FuberError> {
+        let obj_id = parse_id(id)?;
+        let tickets = self.tickets.read().map_err(|_| poisoned())?;
+        match tickets.iter().find(|x| x.id == Some(obj_id)) {
+            Some(ticket) => Ok(ticket.clone()),
+            None => Err(FuberError::ticket_not_found(id)),
+        }
+    }
+
+    fn update_ticket(
+        &self,
+        ticket: Ticket,
+        expected: TicketState,
+    ) -> Result<UpdateResult, FuberError> {
+        match ticket.id {
+            // checked under the same write lock as the update, the same as
+            // `assign_person`
+            Some(obj_id) => set_where(
+                &self.tickets,
+                |x| x.id == Some(obj_id) && x.state == expected,
+                |x| *x = ticket.clone(),
+            ),
+            None => Err(FuberError::InvalidId(
+                ErrorCode::InvalidObjectId,
+                "ObjectId for the ticket doesn't exist".into(),
+            )),
+        }
+    }
+
+    fn get_waiting_tickets(&self) -> Result<Vec<Ticket>, FuberError> {
+        let tickets = self.tickets.read().map_err(|_| poisoned())?;
+        let mut waiting = tickets
+            .iter()
+            .filter(|x| x.state == TicketState::Waiting)
+            .cloned()
+            .collect::<Vec<Ticket>>();
+        // stable, so tickets of the same millisecond keep the order they
+        // were queued in
+        waiting.sort_by_key(|x| x.created_at);
+        Ok(waiting)
+    }
+
+    fn create_booking(&self, new_booking: Booking) -> Result<InsertOneResult, FuberError> {
+        let obj_id = new_booking.id.unwrap_or_default();
+        let mut bookings = self.bookings.write().map_err(|_| poisoned())?;
+        bookings.push(Booking {
+            id: Some(obj_id),
+            ..new_booking
+        });
+        Ok(InsertOneResult {
+            inserted_id: obj_id,
+        })
+    }
+
+    fn get_booking(&self, id: &str) -> Result<Booking, FuberError> {
+        let obj_id = parse_id(id)?;
+        let bookings = self.bookings.read().map_err(|_| poisoned())?;
+        match bookings.iter().find(|x| x.id == Some(obj_id)) {
+            Some(booking) => Ok(booking.clone()),
+            None => Err(FuberError::booking_not_found(id)),
+        }
+    }
+
+    fn update_booking(
+        &self,
+        booking: Booking,
+        expected: BookingState,
+    ) -> Result<UpdateResult, FuberError> {
+        match booking.id {
+            Some(obj_id) => set_where(
+                &self.bookings,
+                |x| x.id == Some(obj_id) && x.state == expected,
+                |x| *x = booking.clone(),
+            ),
+            None => Err(FuberError::InvalidId(
+                ErrorCode::InvalidObjectId,
+                "ObjectId for the booking doesn't exist".into(),
+            )),
+        }
+    }
+
+    fn get_bookings_of_person(&self, person_id: &str) -> Result<Vec<Booking>, FuberError> {
+        let obj_id = parse_id(person_id)?;
+        self.bookings_where(|x| x.person_id == obj_id)
+    }
+
+    fn get_upcoming_bookings(&self, after: i64) -> Result<Vec<Booking>, FuberError> {
+        self.bookings_where(|x| x.is_upcoming(after))
+    }
+
+    fn get_scheduled_bookings(&self, until: i64) -> Result<Vec<Booking>, FuberError> {
+        self.bookings_where(|x| x.state == BookingState::Scheduled && x.pickup_at <= until)
+    }
+
+    fn get_events(&self, since: i64, limit: usize) -> Result<Vec<Event>, FuberError> {
+        let events = self.events.read().map_err(|_| poisoned())?;
+        // seq n is at index n - 1
+        Ok(events
+            .iter()
+            .skip(since.max(0) as usize)
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    fn create_webhook(&self, new_webhook: Webhook) -> Result<InsertOneResult, FuberError> {
+        let obj_id = new_webhook.id.unwrap_or_default();
+        let mut webhooks = self.webhooks.write().map_err(|_| poisoned())?;
+        webhooks.push(Webhook {
+            id: Some(obj_id),
+            ..new_webhook
+        });
+        Ok(InsertOneResult {
+            inserted_id: obj_id,
+        })
+    }
+
+    fn get_webhooks(&self) -> Result<Vec<Webhook>, FuberError> {
+        let webhooks = self.webhooks.read().map_err(|_| poisoned())?;
+        Ok(webhooks.clone())
+    }
+
+    fn delete_webhook(&self, webhook_id: &str) -> Result<DeleteResult, FuberError> {
+        let obj_id = parse_id(webhook_id)?;
+        let deleted = delete_where(&self.webhooks, |x| x.id == Some(obj_id))?;
+        delete_where(&self.outbox, |x| x.webhook_id == obj_id)?;
+        Ok(DeleteResult {
+            deleted_count: deleted.len() as u64,
+        })
+    }
+
+    fn get_delivery(&self, id: &str) -> Result<Delivery, FuberError> {
+        let obj_id = parse_id(id)?;
+        let outbox = self.outbox.read().map_err(|_| poisoned())?;
+        match outbox.iter().find(|x| x.id == Some(obj_id)) {
+            Some(delivery) => Ok(delivery.clone()),
+            None => Err(FuberError::delivery_not_found(id)),
+        }
+    }
+
+    fn update_delivery(
+        &self,
+        delivery: Delivery,
+        expected: DeliveryState,
+    ) -> Result<UpdateResult, FuberError> {
+        match delivery.id {
+            Some(obj_id) => set_where(
+                &self.outbox,
+                |x| x.id == Some(obj_id) && x.state == expected,
+                |x| *x = delivery.clone(),
+            ),
+            None => Err(FuberError::InvalidId(
+                ErrorCode::InvalidObjectId,
+                "ObjectId for the delivery doesn't exist".into(),
+            )),
+        }
+    }
+
+    fn get_due_deliveries(&self, now: i64, limit: usize) -> Result<Vec<Delivery>, FuberError> {
+        let mut due = self
+            .deliveries_where(|x| x.state == DeliveryState::Pending && x.next_attempt_at <= now)?;
+        due.sort_by_key(|x| (x.next_attempt_at, x.event.seq));
+        due.truncate(limit);
+        Ok(due)
+    }
+
+    fn get_dead_deliveries(&self) -> Result<Vec<Delivery>, FuberError> {
+        let mut dead = self.deliveries_where(|x| x.state == DeliveryState::Dead)?;
+        dead.sort_by_key(|x| x.event.seq);
+        Ok(dead)
+    }
+
+    fn create_api_key(&self, new_api_key: ApiKey) -> Result<InsertOneResult, FuberError> {
+        let obj_id = new_api_key.id.unwrap_or_default();
+        let mut api_keys = self.api_keys.write().map_err(|_| poisoned())?;
+        api_keys.push(ApiKey {
+            id: Some(obj_id),
+            ..new_api_key
+        });
+        Ok(InsertOneResult {
+            inserted_id: obj_id,
+        })
+    }
+
+    fn get_api_key_by_hash(&self, key_hash: &str) -> Result<ApiKey, FuberError> {
+        let api_keys = self.api_keys.read().map_err(|_| poisoned())?;
+        match api_keys.iter().find(|x| x.key_hash == key_hash) {
+            Some(api_key) => Ok(api_key.clone()),
+            None => Err(FuberError::api_key_not_found(key_hash)),
+        }
+    }
+
+    fn delete_api_key(&self, api_key_id: &str) -> Result<DeleteResult, FuberError> {
+        let obj_id = parse_id(api_key_id)?;
+        let deleted = delete_where(&self.api_keys, |x| x.id == Some(obj_id))?;
+        Ok(DeleteResult {
+            deleted_count: deleted.len() as u64,
+        })
+    }
+}
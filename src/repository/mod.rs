@@ -1 +1,6 @@
+pub mod fuber_repo;
+pub mod indexed_repo;
+pub mod memory_repos;
 pub mod mongodb_repos;
+pub mod shared_repo;
+pub mod sqlite_repos;
@@ -1,17 +1,34 @@
-use std::env;
+use std::{env, thread, time::Duration};
 extern crate dotenv;
 use dotenv::dotenv;
 
 use mongodb::{
-    bson::{doc, extjson::de::Error, oid::ObjectId},
-    results::{DeleteResult, InsertManyResult, InsertOneResult, UpdateResult},
+    bson::{self, doc, oid::ObjectId, Bson, Document},
+    options::{
+        FindOneAndUpdateOptions, FindOptions, IndexOptions, ReplaceOptions, ReturnDocument,
+        UpdateModifications, UpdateOptions,
+    },
     sync::{Client, Collection},
+    IndexModel,
 };
-use rocket::serde::json::Json;
+use serde::{Deserialize, Serialize};
 
+use super::fuber_repo::{
+    DeleteResult, FuberRepository, InsertManyResult, InsertOneResult, UpdateResult,
+};
 use crate::{
-    api::cab_api::update_cab,
-    models::{cab_model::Cab, person_model::Person, point_model::Point},
+    error::{ErrorCode, FuberError},
+    models::{
+        auth_model::ApiKey,
+        booking_model::{Booking, BookingState},
+        cab_model::{Cab, CabCategory, Rider},
+        event_model::{Event, EventKind},
+        person_model::Person,
+        point_model::{Point, GRID_BOUND},
+        ride_model::{now_millis, Ride, RideState},
+        ticket_model::{Ticket, TicketState},
+        webhook_model::{deliveries_of, Delivery, DeliveryState, Webhook, WEBHOOK_EVENTS},
+    },
 };
 
 pub fn hello() {
@@ -21,14 +38,52 @@ pub fn hello() {
 pub struct MongoRepo {
     cabs: Collection<Cab>,
     persons: Collection<Person>,
+    rides: Collection<Ride>,
+    tickets: Collection<Ticket>,
+    bookings: Collection<Booking>,
+    events: Collection<Event>,
+    // the last seq handed out to an event, in the document with the id
+    // "events"
+    counters: Collection<Document>,
+    webhooks: Collection<Webhook>,
+    outbox: Collection<Delivery>,
+    api_keys: Collection<ApiKey>,
+}
+
+// how long a missing seq of the feed holds back the events after it, see
+// `get_events`
+const SEQ_GAP_MILLIS: i64 = 5_000;
+
+// An event kept in the cab or ride it is about until it is in the feed and
+// the outbox, see `record`. The id is the one the event is stored under.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PendingEvent {
+    #[serde(rename = "_id")]
+    id: ObjectId,
+    event: Event,
+}
+
+impl PendingEvent {
+    fn new(event: Event) -> Self {
+        PendingEvent {
+            id: ObjectId::new(),
+            event,
+        }
+    }
+
+    // what goes into `$push` next to the change
+    fn to_bson(&self) -> Result<Bson, FuberError> {
+        bson::to_bson(self)
+            .map_err(|e| FuberError::storage(format!("Error converting an event: {}", e)))
+    }
 }
 
 impl MongoRepo {
     pub fn init() -> Self {
         dotenv().ok();
         let uri = match env::var("MONGOURI") {
-            Ok(v) => v.to_string(),
-            Err(_) => format!("Error loading env variable"),
+            Ok(v) => v,
+            Err(_) => "Error loading env variable".to_string(),
         };
 
         let client = match Client::with_uri_str(uri) {
@@ -39,314 +94,1319 @@ impl MongoRepo {
         let db = client.database("fuber");
         let cabs: Collection<Cab> = db.collection("Cab");
         let persons: Collection<Person> = db.collection("Person");
-        MongoRepo { cabs, persons }
+        let rides: Collection<Ride> = db.collection("Ride");
+        let tickets: Collection<Ticket> = db.collection("Ticket");
+        let bookings: Collection<Booking> = db.collection("Booking");
+        let events: Collection<Event> = db.collection("Event");
+        let counters: Collection<Document> = db.collection("Counter");
+        let webhooks: Collection<Webhook> = db.collection("Webhook");
+        let outbox: Collection<Delivery> = db.collection("Outbox");
+        let api_keys: Collection<ApiKey> = db.collection("ApiKey");
+        if let Err(e) = cabs.create_index(location_index(), None) {
+            panic!("unable to create the cab location index: {}", e)
+        }
+        if let Err(e) = cabs.create_index(geo_location_index(), None) {
+            panic!("unable to create the cab geo location index: {}", e)
+        }
+        if let Err(e) = cabs.create_index(category_index(), None) {
+            panic!("unable to create the cab category index: {}", e)
+        }
+        if let Err(e) = events.create_index(seq_index(), None) {
+            panic!("unable to create the event seq index: {}", e)
+        }
+        if let Err(e) = events.create_index(pending_id_index(), None) {
+            panic!("unable to create the event pending id index: {}", e)
+        }
+        if let Err(e) = cabs.create_index(pending_events_index(), None) {
+            panic!("unable to create the cab pending events index: {}", e)
+        }
+        if let Err(e) = rides.create_index(pending_events_index(), None) {
+            panic!("unable to create the ride pending events index: {}", e)
+        }
+        if let Err(e) = outbox.create_index(outbox_index(), None) {
+            panic!("unable to create the outbox index: {}", e)
+        }
+        if let Err(e) = outbox.create_index(delivery_index(), None) {
+            panic!("unable to create the delivery index: {}", e)
+        }
+        if let Err(e) = api_keys.create_index(key_hash_index(), None) {
+            panic!("unable to create the api key index: {}", e)
+        }
+        MongoRepo {
+            cabs,
+            persons,
+            rides,
+            tickets,
+            bookings,
+            events,
+            counters,
+            webhooks,
+            outbox,
+            api_keys,
+        }
     }
+}
 
-    pub fn create_person(&self, new_person: Person) -> Result<InsertOneResult, Error> {
-        let new_entry = new_person.clone();
+impl MongoRepo {
+    // soonest pickup first, bookings with the same pickup in the order they
+    // were made in
+    fn find_bookings(&self, filter: Document) -> Result<Vec<Booking>, FuberError> {
+        let options = FindOptions::builder()
+            .sort(doc! {"pickup_at": 1, "_id": 1})
+            .build();
+        self.bookings
+            .find(filter, options)
+            .map_err(storage("Error getting the bookings"))?
+            .map(|x| x.map_err(storage("Error reading a booking")))
+            .collect::<Result<Vec<Booking>, FuberError>>()
+    }
 
-        let person = self
-            .persons
-            .insert_one(new_entry, None)
-            .ok()
-            .expect("Error creating new person");
+    // `$inc` on the counter is atomic, no two events get the same seq
+    fn next_seq(&self) -> Result<i64, FuberError> {
+        let options = FindOneAndUpdateOptions::builder()
+            .upsert(Some(true))
+            .return_document(Some(ReturnDocument::After))
+            .build();
+        let counter = self
+            .counters
+            .find_one_and_update(
+                doc! { "_id": "events" },
+                doc! { "$inc": { "seq": 1_i64 } },
+                options,
+            )
+            .map_err(storage("Error counting the event"))?;
+        match counter.as_ref().map(|x| x.get_i64("seq")) {
+            Some(Ok(seq)) => Ok(seq),
+            _ => Err(FuberError::storage("the event counter has no seq")),
+        }
+    }
 
-        Ok(person)
+    // Mongo can't write a change and its event in one go without a replica
+    // set. The events webhooks get are pushed into the changed cab or ride
+    // as `pending_events` by the same update as the change, single document
+    // updates are atomic, and `drain` moves them into the feed and the
+    // outbox right after. One that doesn't make it stays with the document
+    // until `flush_pending_events` gets to it. The other events are written
+    // by `record` right after the change, the seq isn't tried again as a
+    // second one would leave a gap in the feed.
+    fn record(&self, event: Event) -> Result<(), FuberError> {
+        let event = Event {
+            seq: self.next_seq()?,
+            ..event
+        };
+        let upsert = ReplaceOptions::builder().upsert(Some(true)).build();
+        retried(|| {
+            self.events
+                .replace_one(doc! { "seq": event.seq }, &event, upsert.clone())
+                .map_err(storage("Error recording the event"))
+        })?;
+        self.enqueue(&event)
     }
 
-    pub fn get_person(&self, id: &String) -> Result<Person, Error> {
-        match ObjectId::parse_str(id) {
-            Ok(obj_id) => {
-                let filter = doc! {"_id": obj_id};
-                Ok(self
-                    .persons
-                    .find_one(filter, None)
-                    .ok()
-                    .expect("Error getting person's detail")
-                    .unwrap())
+    // one delivery for every webhook that wants the event, the upsert on the
+    // webhook and the seq writes each of them once however often it's tried
+    fn enqueue(&self, event: &Event) -> Result<(), FuberError> {
+        if !WEBHOOK_EVENTS.contains(&event.kind) {
+            return Ok(());
+        }
+        let webhooks = retried(|| self.get_webhooks())?;
+        let upsert = UpdateOptions::builder().upsert(Some(true)).build();
+        for delivery in deliveries_of(&webhooks, event) {
+            let filter = doc! { "webhook_id": delivery.webhook_id, "event.seq": event.seq };
+            let delivery = bson::to_document(&Delivery {
+                id: Some(ObjectId::new()),
+                ..delivery
+            })
+            .map_err(|e| FuberError::storage(format!("Error converting a delivery: {}", e)))?;
+            retried(|| {
+                self.outbox
+                    .update_one(
+                        filter.clone(),
+                        doc! { "$setOnInsert": delivery.clone() },
+                        upsert.clone(),
+                    )
+                    .map_err(storage("Error putting the event into the outbox"))
+            })?;
+        }
+        Ok(())
+    }
+
+    // Writes a pending event of the document with `doc_id` into the feed and
+    // the outbox and then takes it off the document. The event is stored
+    // under the id of the pending one, so draining it twice still records
+    // it once.
+    fn drain(
+        &self,
+        source: &Collection<Document>,
+        doc_id: ObjectId,
+        pending: &PendingEvent,
+    ) -> Result<(), FuberError> {
+        let event = bson::to_document(&Event {
+            seq: self.next_seq()?,
+            ..pending.event.clone()
+        })
+        .map_err(|e| FuberError::storage(format!("Error converting an event: {}", e)))?;
+        let options = FindOneAndUpdateOptions::builder()
+            .upsert(Some(true))
+            .return_document(Some(ReturnDocument::After))
+            .build();
+        let stored = retried(|| {
+            self.events
+                .find_one_and_update(
+                    doc! { "pending_id": pending.id },
+                    doc! { "$setOnInsert": event.clone() },
+                    options.clone(),
+                )
+                .map_err(storage("Error recording the event"))
+        })?
+        .ok_or_else(|| FuberError::storage("the recorded event is gone"))?;
+        self.enqueue(&stored)?;
+        retried(|| {
+            source
+                .update_one(
+                    doc! { "_id": doc_id },
+                    doc! { "$pull": { "pending_events": { "_id": pending.id } } },
+                    None,
+                )
+                .map_err(storage("Error taking the event off its document"))
+        })?;
+        Ok(())
+    }
+
+    // every pending event of the documents of `source` that match `filter`
+    fn drain_where(
+        &self,
+        source: &Collection<Document>,
+        filter: Document,
+    ) -> Result<(), FuberError> {
+        let mut filter = filter;
+        filter.insert("pending_events._id", doc! { "$exists": true });
+        let options = FindOptions::builder()
+            .projection(doc! { "pending_events": 1 })
+            .build();
+        let found = source
+            .find(filter, options)
+            .map_err(storage("Error getting the pending events"))?
+            .map(|x| x.map_err(storage("Error reading the pending events")))
+            .collect::<Result<Vec<Document>, FuberError>>()?;
+        for document in found {
+            let doc_id = document
+                .get_object_id("_id")
+                .map_err(|e| FuberError::storage(format!("Error reading the id: {}", e)))?;
+            let pending: Vec<PendingEvent> = bson::from_bson(
+                document
+                    .get("pending_events")
+                    .cloned()
+                    .unwrap_or(Bson::Array(Vec::new())),
+            )
+            .map_err(|e| FuberError::storage(format!("Error reading a pending event: {}", e)))?;
+            for x in pending {
+                self.drain(source, doc_id, &x)?;
             }
-            Err(_) => Err(Error::DeserializationError {
-                message: "Error while parsing person object".to_string(),
-            }),
         }
+        Ok(())
     }
 
-    pub fn get_cab(&self, id: &String) -> Result<Cab, Error> {
-        match ObjectId::parse_str(id) {
-            Ok(obj_id) => {
-                let filter = doc! {"_id": obj_id};
-                Ok(self
-                    .cabs
-                    .find_one(filter, None)
-                    .ok()
-                    .expect("Error getting person's detail")
-                    .unwrap())
+    // right after the change, a pending event that can't be drained now is
+    // left with its document for `flush_pending_events`
+    fn drain_committed(
+        &self,
+        source: &Collection<Document>,
+        doc_id: ObjectId,
+        pending: PendingEvent,
+    ) {
+        if let Err(e) = self.drain(source, doc_id, &pending) {
+            rocket::warn!(
+                "unable to record the event of a change, it is tried again later: {:?}",
+                e
+            );
+        }
+    }
+
+    // The change already happened when this runs, so it isn't reported as
+    // failed when its event or deliveries can't be written. Those are lost
+    // then and only show up in the log.
+    fn record_committed(&self, event: Result<Event, FuberError>) {
+        if let Err(e) = event.and_then(|x| self.record(x)) {
+            rocket::error!("unable to record the event of a change: {:?}", e);
+        }
+    }
+
+    fn find_deliveries(
+        &self,
+        filter: Document,
+        sort: Document,
+        limit: Option<i64>,
+    ) -> Result<Vec<Delivery>, FuberError> {
+        let options = FindOptions::builder().sort(sort).limit(limit).build();
+        self.outbox
+            .find(filter, options)
+            .map_err(storage("Error getting the deliveries"))?
+            .map(|x| x.map_err(storage("Error reading a delivery")))
+            .collect::<Result<Vec<Delivery>, FuberError>>()
+    }
+
+    // updates that didn't change anything aren't worth an event
+    fn record_update(
+        &self,
+        result: UpdateResult,
+        event: impl FnOnce() -> Result<Event, FuberError>,
+    ) -> Result<UpdateResult, FuberError> {
+        if result.modified_count > 0 {
+            self.record_committed(event());
+        }
+        Ok(result)
+    }
+
+    // one by one, so every event has the document that was deleted
+    fn delete_persons(&self, filter: Document) -> Result<DeleteResult, FuberError> {
+        let ids = self
+            .persons
+            .find(filter, None)
+            .map_err(storage("Error getting the persons to delete"))?
+            .map(|x| x.map_err(storage("Error reading a person to delete")))
+            .collect::<Result<Vec<Person>, FuberError>>()?
+            .into_iter()
+            .filter_map(|x| x.id);
+        let mut deleted_count = 0;
+        for obj_id in ids {
+            let deleted = self
+                .persons
+                .find_one_and_delete(doc! {"_id" : obj_id}, None)
+                .map_err(storage("Cannot delete the person"))?;
+            // somebody else might have been quicker
+            if let Some(person) = deleted {
+                self.record_committed(Event::of_person(EventKind::PersonDeleted, obj_id, &person));
+                deleted_count += 1;
+            }
+        }
+        Ok(DeleteResult { deleted_count })
+    }
+
+    fn delete_cabs(&self, filter: Document) -> Result<DeleteResult, FuberError> {
+        let ids = self
+            .cabs
+            .find(filter, None)
+            .map_err(storage("Error getting the cabs to delete"))?
+            .map(|x| x.map_err(storage("Error reading a cab to delete")))
+            .collect::<Result<Vec<Cab>, FuberError>>()?
+            .into_iter()
+            .filter_map(|x| x.id);
+        let mut deleted_count = 0;
+        for obj_id in ids {
+            // the events still kept in the cab would go with it, a cab that
+            // got a new one in the meantime is left alone
+            self.drain_where(&self.cabs.clone_with_type(), doc! {"_id" : obj_id})?;
+            let deleted = self
+                .cabs
+                .find_one_and_delete(
+                    doc! {"_id" : obj_id, "pending_events.0": {"$exists": false}},
+                    None,
+                )
+                .map_err(storage("Cannot delete the cab"))?;
+            if let Some(cab) = deleted {
+                self.record_committed(Event::of_cab(EventKind::CabDeleted, obj_id, &cab));
+                deleted_count += 1;
             }
-            Err(_) => Err(Error::DeserializationError {
-                message: "Error while parsing person object".to_string(),
-            }),
         }
+        Ok(DeleteResult { deleted_count })
     }
+}
 
-    pub fn create_cab(&self, new_cab: Cab) -> Result<InsertOneResult, Error> {
-        let new_entry = new_cab.clone();
+// Grid locations are x/y points on a flat plane, so their index is a planar
+// `2d` one, `$near` on it measures the same straight line distance as
+// `Point::dist`. The default bounds of a 2d index are the ones of longitudes
+// which random points already leave, hence the wider ones. `Point` turns
+// down grid points outside of them.
+const LOCATION_INDEX_BOUND: f64 = GRID_BOUND as f64;
 
-        let cab = self
+fn location_index() -> IndexModel {
+    let options = IndexOptions::builder()
+        .name(Some("location_2d".to_string()))
+        .min(Some(-LOCATION_INDEX_BOUND))
+        .max(Some(LOCATION_INDEX_BOUND))
+        .bits(Some(32))
+        .build();
+    IndexModel::builder()
+        .keys(doc! { "location": "2d" })
+        .options(options)
+        .build()
+}
+
+// Gps locations can't go through the 2d index, it would treat lat/lon as a
+// flat plane. Every cab also gets a `geo_location` GeoJSON field, null for
+// grid points, with a `2dsphere` index that measures great-circle distances
+// in metres.
+fn geo_location_index() -> IndexModel {
+    let options = IndexOptions::builder()
+        .name(Some("geo_location_2dsphere".to_string()))
+        .sparse(Some(true))
+        .build();
+    IndexModel::builder()
+        .keys(doc! { "geo_location": "2dsphere" })
+        .options(options)
+        .build()
+}
+
+// every ride request checks the fleet has its category
+fn category_index() -> IndexModel {
+    let options = IndexOptions::builder()
+        .name(Some("category".to_string()))
+        .build();
+    IndexModel::builder()
+        .keys(doc! { "category": 1 })
+        .options(options)
+        .build()
+}
+
+// a second event with a seq that was already handed out is refused
+fn seq_index() -> IndexModel {
+    let options = IndexOptions::builder()
+        .name(Some("seq_unique".to_string()))
+        .unique(Some(true))
+        .build();
+    IndexModel::builder()
+        .keys(doc! { "seq": 1 })
+        .options(options)
+        .build()
+}
+
+// a pending event is recorded once however often it is drained, only the
+// events that were pending have the field
+fn pending_id_index() -> IndexModel {
+    let options = IndexOptions::builder()
+        .name(Some("pending_id_unique".to_string()))
+        .unique(Some(true))
+        .partial_filter_expression(Some(doc! { "pending_id": { "$exists": true } }))
+        .build();
+    IndexModel::builder()
+        .keys(doc! { "pending_id": 1 })
+        .options(options)
+        .build()
+}
+
+// `flush_pending_events` only looks at the cabs and rides that have any
+fn pending_events_index() -> IndexModel {
+    let options = IndexOptions::builder()
+        .name(Some("pending_events".to_string()))
+        .sparse(Some(true))
+        .build();
+    IndexModel::builder()
+        .keys(doc! { "pending_events._id": 1 })
+        .options(options)
+        .build()
+}
+
+// one delivery of an event to a webhook, however often it is enqueued
+fn delivery_index() -> IndexModel {
+    let options = IndexOptions::builder()
+        .name(Some("webhook_id_event_seq_unique".to_string()))
+        .unique(Some(true))
+        .build();
+    IndexModel::builder()
+        .keys(doc! { "webhook_id": 1, "event.seq": 1 })
+        .options(options)
+        .build()
+}
+
+// the dispatcher asks for the pending deliveries that are due
+fn outbox_index() -> IndexModel {
+    let options = IndexOptions::builder()
+        .name(Some("state_next_attempt_at".to_string()))
+        .build();
+    IndexModel::builder()
+        .keys(doc! { "state": 1, "next_attempt_at": 1 })
+        .options(options)
+        .build()
+}
+
+// callers are looked up by the hash of their key on every request
+fn key_hash_index() -> IndexModel {
+    let options = IndexOptions::builder()
+        .name(Some("key_hash_unique".to_string()))
+        .unique(Some(true))
+        .build();
+    IndexModel::builder()
+        .keys(doc! { "key_hash": 1 })
+        .options(options)
+        .build()
+}
+
+fn geo_json(point: &Point) -> Bson {
+    match point {
+        // GeoJSON puts the longitude first
+        Point::Geo { lat, lon } => Bson::Document(doc! {
+            "type": "Point",
+            "coordinates": [*lon, *lat],
+        }),
+        Point::Grid { .. } => Bson::Null,
+    }
+}
+
+fn point_bson(point: &Point) -> Result<Bson, FuberError> {
+    bson::to_bson(point)
+        .map_err(|e| FuberError::storage(format!("Error converting a location: {}", e)))
+}
+
+// the cab as it is stored, with the GeoJSON copy of its location
+fn cab_document(cab: &Cab) -> Result<Document, FuberError> {
+    let mut document = bson::to_document(cab)
+        .map_err(|e| FuberError::storage(format!("Error converting a cab: {}", e)))?;
+    document.insert("geo_location", geo_json(&cab.location));
+    Ok(document)
+}
+
+// mongodb hands back `Bson` ids, every document we insert gets an ObjectId
+fn bson_to_object_id(id: &Bson) -> Result<ObjectId, FuberError> {
+    match id.as_object_id() {
+        Some(obj_id) => Ok(obj_id),
+        None => Err(FuberError::storage(format!(
+            "inserted id {} is not an ObjectId",
+            id
+        ))),
+    }
+}
+
+// how often the writes of an event are tried and how long to wait before
+// the first retry, twice as long before each one after that
+const RECORD_ATTEMPTS: u32 = 4;
+const RECORD_BACKOFF: Duration = Duration::from_millis(50);
+
+fn retried<T>(write: impl Fn() -> Result<T, FuberError>) -> Result<T, FuberError> {
+    let mut backoff = RECORD_BACKOFF;
+    let mut attempt = 1;
+    loop {
+        match write() {
+            Err(_) if attempt < RECORD_ATTEMPTS => {
+                thread::sleep(backoff);
+                backoff *= 2;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+fn parse_id(id: &str) -> Result<ObjectId, FuberError> {
+    ObjectId::parse_str(id).map_err(|_| FuberError::invalid_id(id))
+}
+
+// wraps a driver error with what we were doing when it happened
+fn storage(context: &str) -> impl Fn(mongodb::error::Error) -> FuberError + '_ {
+    move |e| FuberError::storage(format!("{}: {}", context, e))
+}
+
+fn to_update_result(update: mongodb::results::UpdateResult) -> UpdateResult {
+    UpdateResult {
+        matched_count: update.matched_count,
+        modified_count: update.modified_count,
+    }
+}
+
+// `riders` and `stops` are left out of cabs while they are empty, so an
+// empty list has to match a missing field as well
+fn list_filter<T: serde::Serialize>(field: &str, list: &[T]) -> Result<Document, FuberError> {
+    if list.is_empty() {
+        Ok(doc! { format!("{}.0", field): { "$exists": false } })
+    } else {
+        let list = bson::to_bson(list)
+            .map_err(|e| FuberError::storage(format!("Error converting the {}: {}", field, e)))?;
+        Ok(doc! { field: list })
+    }
+}
+
+fn add_category(filter: &mut Document, category: Option<CabCategory>) {
+    match category {
+        // cabs stored before there were categories have no such field
+        Some(CabCategory::Standard) => {
+            filter.insert("category", doc! { "$in": ["standard", null] });
+        }
+        Some(category) => {
+            filter.insert("category", category.as_str());
+        }
+        None => (),
+    }
+}
+
+// an assigned cab always has to know where it is going
+fn assigned_destination(new_cab: &Cab) -> Result<Point, FuberError> {
+    match new_cab.destination.clone() {
+        Some(destination) => Ok(destination),
+        None => Err(FuberError::validation(
+            "cannot get the destination for this cab",
+        )),
+    }
+}
+
+impl MongoRepo {
+    fn update_cab_doc(
+        &self,
+        filter: Document,
+        new_doc: Document,
+    ) -> Result<UpdateResult, FuberError> {
+        let updated_doc = self
             .cabs
-            .insert_one(new_entry, None)
-            .ok()
-            .expect("Error creating new cab");
+            .update_one(filter, new_doc, None)
+            .map_err(storage("cannot update the cab"))?;
 
-        Ok(cab)
+        Ok(to_update_result(updated_doc))
     }
+}
 
-    pub fn create_fleet(&self, fleet: Vec<Cab>) -> Result<InsertManyResult, Error> {
-        let new_entry_vec = fleet.clone();
+impl FuberRepository for MongoRepo {
+    fn create_person(&self, new_person: Person) -> Result<InsertOneResult, FuberError> {
+        let person = self
+            .persons
+            .insert_one(&new_person, None)
+            .map_err(storage("Error creating new person"))?;
 
-        let cabs = self
+        let inserted_id = bson_to_object_id(&person.inserted_id)?;
+        self.record_committed(Event::of_person(
+            EventKind::PersonCreated,
+            inserted_id,
+            &new_person,
+        ));
+        Ok(InsertOneResult { inserted_id })
+    }
+
+    fn get_person(&self, id: &str) -> Result<Person, FuberError> {
+        let filter = doc! {"_id": parse_id(id)?};
+        match self
+            .persons
+            .find_one(filter, None)
+            .map_err(storage("Error getting person's detail"))?
+        {
+            Some(person) => Ok(person),
+            None => Err(FuberError::person_not_found(id)),
+        }
+    }
+
+    fn get_cab(&self, id: &str) -> Result<Cab, FuberError> {
+        let filter = doc! {"_id": parse_id(id)?};
+        match self
+            .cabs
+            .find_one(filter, None)
+            .map_err(storage("Error getting cab's detail"))?
+        {
+            Some(cab) => Ok(cab),
+            None => Err(FuberError::cab_not_found(id)),
+        }
+    }
+
+    fn create_cab(&self, new_cab: Cab) -> Result<InsertOneResult, FuberError> {
+        let cab = self
             .cabs
-            .insert_many(new_entry_vec, None)
-            .ok()
-            .expect("Error creating fleet");
+            .clone_with_type::<Document>()
+            .insert_one(cab_document(&new_cab)?, None)
+            .map_err(storage("Error creating new cab"))?;
 
-        Ok(cabs)
+        let inserted_id = bson_to_object_id(&cab.inserted_id)?;
+        self.record_committed(Event::of_cab(EventKind::CabCreated, inserted_id, &new_cab));
+        Ok(InsertOneResult { inserted_id })
     }
 
-    pub fn get_fleet(&self) -> Result<Vec<Cab>, Error> {
-        Ok(self
+    fn create_fleet(&self, fleet: Vec<Cab>) -> Result<InsertManyResult, FuberError> {
+        let documents = fleet
+            .iter()
+            .map(cab_document)
+            .collect::<Result<Vec<Document>, FuberError>>()?;
+        let cabs = self
             .cabs
+            .clone_with_type::<Document>()
+            .insert_many(documents, None)
+            .map_err(storage("Error creating fleet"))?;
+
+        // inserted_ids is keyed by the index of the document in `fleet`
+        let mut ids = cabs
+            .inserted_ids
+            .into_iter()
+            .collect::<Vec<(usize, Bson)>>();
+        ids.sort_by_key(|(idx, _)| *idx);
+        let inserted_ids = ids
+            .iter()
+            .map(|(_, id)| bson_to_object_id(id))
+            .collect::<Result<Vec<ObjectId>, FuberError>>()?;
+
+        for (obj_id, cab) in inserted_ids.iter().zip(fleet.iter()) {
+            self.record_committed(Event::of_cab(EventKind::CabCreated, *obj_id, cab));
+        }
+        Ok(InsertManyResult { inserted_ids })
+    }
+
+    fn get_fleet(&self) -> Result<Vec<Cab>, FuberError> {
+        self.cabs
             .find(None, None)
-            .ok()
-            .expect("Error getting the fleet")
-            .filter(|x| (*x).is_ok())
-            .map(|x| x.unwrap())
-            .collect::<Vec<Cab>>())
+            .map_err(storage("Error getting the fleet"))?
+            .map(|x| x.map_err(storage("Error reading a cab of the fleet")))
+            .collect::<Result<Vec<Cab>, FuberError>>()
+    }
+
+    fn assign_person(&self, cab_id: &str, new_cab: Cab) -> Result<UpdateResult, FuberError> {
+        let obj_id = parse_id(cab_id)?;
+        let destination = assigned_destination(&new_cab)?;
+        // `person_id: null` also matches cabs that never had the field,
+        // update_one applies filter and $set atomically on the document
+        let mut filter = doc! { "_id" : obj_id, "person_id" : null };
+        filter.extend(list_filter::<Rider>("riders", &[])?);
+        let mut new_doc = doc! {
+            "$set":
+            {
+                "id": new_cab.id,
+                "location" : point_bson(&new_cab.location)?,
+                "geo_location" : geo_json(&new_cab.location),
+                "destination" : point_bson(&destination)?,
+                "person_id" : new_cab.person_id,
+            },
+        };
+        // only a free cab matches the filter
+        let before = Cab {
+            person_id: None,
+            ..new_cab.clone()
+        };
+        let pending = PendingEvent::new(Event::of_cab_change(
+            EventKind::PersonAssigned,
+            obj_id,
+            &before,
+            &new_cab,
+        )?);
+        new_doc.insert("$push", doc! { "pending_events": pending.to_bson()? });
+        let result = self.update_cab_doc(filter, new_doc)?;
+        if result.modified_count > 0 {
+            self.drain_committed(&self.cabs.clone_with_type(), obj_id, pending);
+        }
+        Ok(result)
     }
 
-    pub fn assign_person(&self, cab_id: &String, new_cab: Cab) -> Result<UpdateResult, Error> {
-        match ObjectId::parse_str(cab_id) {
-            Ok(obj_id) => {
-                let filter = doc! { "_id" : obj_id};
+    fn unassign_person(&self, cab_id: &str, new_cab: Cab) -> Result<UpdateResult, FuberError> {
+        let obj_id = parse_id(cab_id)?;
+        // the event tells who got out, so the cab is read first and only
+        // unassigned while it still has that person
+        loop {
+            let before = match self
+                .cabs
+                .find_one(doc! { "_id" : obj_id }, None)
+                .map_err(storage("cannot get the cab to unassign"))?
+            {
+                Some(before) => before,
+                None => {
+                    return Ok(UpdateResult {
+                        matched_count: 0,
+                        modified_count: 0,
+                    })
+                }
+            };
+            let after = Cab {
+                location: new_cab.location.clone(),
+                destination: None,
+                person_id: None,
+                ..before.clone()
+            };
+            if before == after {
+                return Ok(UpdateResult {
+                    matched_count: 1,
+                    modified_count: 0,
+                });
+            }
+            let pending = PendingEvent::new(Event::of_cab_change(
+                EventKind::PersonUnassigned,
+                obj_id,
+                &before,
+                &after,
+            )?);
+            let new_doc = doc! {
+                "$set":
+                {
+                    "id": new_cab.id,
+                    "location" : point_bson(&new_cab.location)?,
+                    "geo_location" : geo_json(&new_cab.location),
+                    "destination" : null,
+                    "person_id" : null
+                },
+                "$push": { "pending_events": pending.to_bson()? },
+            };
+            let filter = doc! { "_id" : obj_id, "person_id" : before.person_id };
+            let result = self.update_cab_doc(filter, new_doc)?;
+            if result.matched_count > 0 {
+                self.drain_committed(&self.cabs.clone_with_type(), obj_id, pending);
+                return Ok(result);
+            }
+        }
+    }
+
+    fn update_cab(&self, new_cab: Cab) -> Result<UpdateResult, FuberError> {
+        let obj_id = match new_cab.id {
+            Some(obj_id) => obj_id,
+            None => {
+                return Err(FuberError::InvalidId(
+                    ErrorCode::InvalidObjectId,
+                    "Couldn't find the object id".to_string(),
+                ))
+            }
+        };
+        let filter = doc! { "_id" : obj_id };
+        let result = match new_cab.person_id {
+            Some(_) => {
+                let destination = assigned_destination(&new_cab)?;
                 let new_doc = doc! {
                     "$set":
                     {
                         "id": new_cab.id,
-                        "location" : {
-                            "x" : new_cab.location.x,
-                            "y" : new_cab.location.y
-                        },
-                        "destination" : {
-                            "x" : new_cab
-                                    .destination
-                                    .clone()
-                                    .expect("cannot get the destination for this cab")
-                                    .x,
-                            "y" : new_cab
-                                    .destination
-                                    .clone()
-                                    .expect("cannot get the destination for this cab")
-                                    .y,
-                        },
+                        "location" : point_bson(&new_cab.location)?,
+                        "geo_location" : geo_json(&new_cab.location),
+                        "destination" : point_bson(&destination)?,
                         "person_id" : new_cab.person_id,
+                        "category" : new_cab.category.as_str(),
+                        "seats" : new_cab.seats,
                     },
                 };
+                self.update_cab_doc(filter, new_doc)?
+            }
+            // a pipeline, so that a pooled cab keeps heading to its next stop
+            None => {
+                let pipeline = vec![doc! {
+                    "$set":
+                    {
+                        "id": new_cab.id,
+                        "location" : point_bson(&new_cab.location)?,
+                        "geo_location" : geo_json(&new_cab.location),
+                        "destination" : {
+                            "$cond": [
+                                { "$gt": [{ "$size": { "$ifNull": ["$riders", []] } }, 0] },
+                                "$destination",
+                                null,
+                            ]
+                        },
+                        "person_id" : null,
+                        "category" : new_cab.category.as_str(),
+                        "seats" : new_cab.seats,
+                    },
+                }];
                 let updated_doc = self
                     .cabs
-                    .update_one(filter, new_doc, None)
-                    .ok()
-                    .expect("cannot update the new cab");
-
-                Ok(updated_doc)
+                    .update_one(filter, UpdateModifications::Pipeline(pipeline), None)
+                    .map_err(storage("cannot update the cab"))?;
+                to_update_result(updated_doc)
             }
-            Err(_) => panic!("assigning person failed"),
-        }
+        };
+        self.record_update(result, || {
+            Event::of_cab(EventKind::CabUpdated, obj_id, &new_cab)
+        })
     }
 
-    pub fn unassign_person(&self, cab_id: &String, new_cab: Cab) -> Result<UpdateResult, Error> {
-        match ObjectId::parse_str(cab_id) {
-            Ok(obj_id) => {
-                let filter = doc! { "_id" : obj_id};
+    fn update_location(&self, cab_id: &str, location: Point) -> Result<UpdateResult, FuberError> {
+        let obj_id = parse_id(cab_id)?;
+        let new_doc = doc! {
+            "$set":
+            {
+                "location" : point_bson(&location)?,
+                "geo_location" : geo_json(&location),
+            },
+        };
+        // the cab from before the update tells whether it moved at all
+        let options = FindOneAndUpdateOptions::builder()
+            .return_document(Some(ReturnDocument::Before))
+            .build();
+        let before = self
+            .cabs
+            .find_one_and_update(doc! { "_id" : obj_id }, new_doc, options)
+            .map_err(storage("cannot update the cab location"))?;
+        let after = before.as_ref().map(|x| Cab {
+            location: location.clone(),
+            ..x.clone()
+        });
+        let result = UpdateResult {
+            matched_count: before.is_some() as u64,
+            modified_count: (before != after) as u64,
+        };
+        self.record_update(result, || match after {
+            Some(cab) => Event::of_cab(EventKind::LocationUpdated, obj_id, &cab),
+            None => Err(FuberError::cab_not_found(cab_id)),
+        })
+    }
+
+    fn update_pool(&self, new_cab: Cab, expected: &Cab) -> Result<UpdateResult, FuberError> {
+        let obj_id = new_cab
+            .id
+            .ok_or_else(|| FuberError::storage("cannot get the cab id"))?;
+        let mut filter = doc! { "_id" : obj_id, "person_id" : null };
+        filter.extend(list_filter("riders", &expected.riders)?);
+        filter.extend(list_filter("stops", &expected.stops)?);
+        let destination = match &new_cab.destination {
+            Some(destination) => point_bson(destination)?,
+            None => Bson::Null,
+        };
+        let pool = bson::to_document(&new_cab)
+            .map_err(|e| FuberError::storage(format!("Error converting a cab: {}", e)))?;
+        let new_doc = doc! {
+            "$set":
+            {
+                "location" : point_bson(&new_cab.location)?,
+                "geo_location" : geo_json(&new_cab.location),
+                "destination" : destination,
+                "riders" : pool.get("riders").cloned().unwrap_or(Bson::Array(Vec::new())),
+                "stops" : pool.get("stops").cloned().unwrap_or(Bson::Array(Vec::new())),
+            },
+        };
+        let result = self.update_cab_doc(filter, new_doc)?;
+        self.record_update(result, || {
+            Event::of_cab(EventKind::CabPooled, obj_id, &new_cab)
+        })
+    }
+
+    fn delete_cab(&self, cab_id: &str) -> Result<DeleteResult, FuberError> {
+        self.delete_cabs(doc! {"_id" : parse_id(cab_id)?})
+    }
+
+    fn update_person(&self, new_person: Person) -> Result<UpdateResult, FuberError> {
+        match new_person.id {
+            Some(obj_id) => {
+                let filter = doc! {"_id" : obj_id};
                 let new_doc = doc! {
                     "$set":
                     {
-                        "id": new_cab.id,
-                        "location" : {
-                            "x" : new_cab.location.x,
-                            "y" : new_cab.location.y
-                        },
-                        "destination" : null,
-                        "person_id" : null
-                    },
+                        "id" : new_person.id,
+                        "name" : new_person.name.clone(),
+                        "location" : point_bson(&new_person.location)?,
+                        "destination" : point_bson(&new_person.destination)?,
+                    }
                 };
+
                 let updated_doc = self
-                    .cabs
+                    .persons
                     .update_one(filter, new_doc, None)
-                    .ok()
-                    .expect("cannot update the new cab");
+                    .map_err(storage("Cannot update the doc"))?;
+                self.record_update(to_update_result(updated_doc), || {
+                    Event::of_person(EventKind::PersonUpdated, obj_id, &new_person)
+                })
+            }
+            None => Err(FuberError::InvalidId(
+                ErrorCode::InvalidObjectId,
+                "ObjectId for the person doesn't exist".into(),
+            )),
+        }
+    }
+
+    fn delete_person(&self, person_id: &str) -> Result<DeleteResult, FuberError> {
+        self.delete_persons(doc! {"_id" : parse_id(person_id)?})
+    }
+
+    fn delete_fleet(&self) -> Result<DeleteResult, FuberError> {
+        self.delete_cabs(doc! {})
+    }
+
+    fn delete_all_people(&self) -> Result<DeleteResult, FuberError> {
+        self.delete_persons(doc! {})
+    }
+
+    fn has_cab_of_category(&self, category: CabCategory) -> Result<bool, FuberError> {
+        let mut filter = doc! {};
+        add_category(&mut filter, Some(category));
+        let found = self
+            .cabs
+            .find_one(filter, None)
+            .map_err(storage("Error looking for a cab of the category"))?;
+        Ok(found.is_some())
+    }
+
+    fn create_ride(&self, new_ride: Ride) -> Result<InsertOneResult, FuberError> {
+        let ride = self
+            .rides
+            .insert_one(new_ride, None)
+            .map_err(storage("Error creating new ride"))?;
+
+        Ok(InsertOneResult {
+            inserted_id: bson_to_object_id(&ride.inserted_id)?,
+        })
+    }
 
-                Ok(updated_doc)
+    fn get_ride(&self, id: &str) -> Result<Ride, FuberError> {
+        let filter = doc! {"_id": parse_id(id)?};
+        match self
+            .rides
+            .find_one(filter, None)
+            .map_err(storage("Error getting ride's detail"))?
+        {
+            Some(ride) => Ok(ride),
+            None => Err(FuberError::ride_not_found(id)),
+        }
+    }
+
+    fn update_ride(&self, ride: Ride, expected: RideState) -> Result<UpdateResult, FuberError> {
+        let obj_id = match ride.id {
+            Some(obj_id) => obj_id,
+            None => {
+                return Err(FuberError::InvalidId(
+                    ErrorCode::InvalidObjectId,
+                    "ObjectId for the ride doesn't exist".into(),
+                ))
             }
-            Err(_) => panic!("unassign person failed"),
+        };
+        let filter = doc! {"_id" : obj_id, "state": expected.as_str()};
+        // a $set rather than a replace, the pending events of the ride stay
+        let mut fields = bson::to_document(&ride)
+            .map_err(|e| FuberError::storage(format!("Error converting a ride: {}", e)))?;
+        fields.remove("_id");
+        let mut new_doc = doc! { "$set": fields };
+        let pending = match Event::of_ride(expected, obj_id, &ride)? {
+            Some(event) => {
+                let pending = PendingEvent::new(event);
+                new_doc.insert("$push", doc! { "pending_events": pending.to_bson()? });
+                Some(pending)
+            }
+            None => None,
+        };
+        let updated_doc = self
+            .rides
+            .update_one(filter, new_doc, None)
+            .map_err(storage("Cannot update the ride"))?;
+        let result = to_update_result(updated_doc);
+        if let Some(pending) = pending.filter(|_| result.modified_count > 0) {
+            self.drain_committed(&self.rides.clone_with_type(), obj_id, pending);
+        }
+        Ok(result)
+    }
+
+    fn get_rides_of_person(&self, person_id: &str) -> Result<Vec<Ride>, FuberError> {
+        let filter = doc! {"person_id": parse_id(person_id)?};
+        let options = FindOptions::builder().sort(doc! {"_id": 1}).build();
+        self.rides
+            .find(filter, options)
+            .map_err(storage("Error getting the rides"))?
+            .map(|x| x.map_err(storage("Error reading a ride")))
+            .collect::<Result<Vec<Ride>, FuberError>>()
+    }
+
+    fn create_ticket(&self, new_ticket: Ticket) -> Result<InsertOneResult, FuberError> {
+        let ticket = self
+            .tickets
+            .insert_one(new_ticket, None)
+            .map_err(storage("Error queueing the ride request"))?;
+
+        Ok(InsertOneResult {
+            inserted_id: bson_to_object_id(&ticket.inserted_id)?,
+        })
+    }
+
+    fn get_ticket(&self, id: &str) -> Result<Ticket, FuberError> {
+        let filter = doc! {"_id": parse_id(id)?};
+        match self
+            .tickets
+            .find_one(filter, None)
+            .map_err(storage("Error getting ticket's detail"))?
+        {
+            Some(ticket) => Ok(ticket),
+            None => Err(FuberError::ticket_not_found(id)),
         }
     }
 
-    pub fn update_cab(&self, new_cab: Cab) -> Result<UpdateResult, Error> {
-        match new_cab.id {
+    fn update_ticket(
+        &self,
+        ticket: Ticket,
+        expected: TicketState,
+    ) -> Result<UpdateResult, FuberError> {
+        match ticket.id {
             Some(obj_id) => {
-                let filter = doc! { "_id" : obj_id };
-                match new_cab.person_id {
-                    Some(_) => {
-                        let new_doc = doc! {
-                            "$set":
-                            {
-                                "id": new_cab.id,
-                                "location" : {
-                                    "x" : new_cab.location.x,
-                                    "y" : new_cab.location.y
-                                },
-                                "destination" : {
-                                    "x" : new_cab
-                                            .destination
-                                            .clone()
-                                            .expect("cannot get the destination for this cab")
-                                            .x,
-                                    "y" : new_cab
-                                            .destination
-                                            .clone()
-                                            .expect("cannot get the destination for this cab")
-                                            .y,
-                                },
-                                "person_id" : new_cab.person_id,
-                            },
-                        };
-                        let updated_doc = self
-                            .cabs
-                            .update_one(filter, new_doc, None)
-                            .ok()
-                            .expect("cannot update the new cab");
-
-                        Ok(updated_doc)
-                    }
-                    None => {
-                        let new_doc = doc! {
-                            "$set":
-                            {
-                                "id": new_cab.id,
-                                "location" : {
-                                    "x" : new_cab.location.x,
-                                    "y" : new_cab.location.y
-                                },
-                                "destination" : null,
-                                "person_id" : null
-                            },
-                        };
-
-                        let updated_doc = self
-                            .cabs
-                            .update_one(filter, new_doc, None)
-                            .ok()
-                            .expect("cannot update the new cab");
-
-                        Ok(updated_doc)
-                    }
-                }
+                // replace_one applies the filter and the replacement
+                // atomically on the document
+                let filter = doc! {"_id" : obj_id, "state": expected.as_str()};
+                let updated_doc = self
+                    .tickets
+                    .replace_one(filter, ticket, None)
+                    .map_err(storage("Cannot update the ticket"))?;
+                Ok(to_update_result(updated_doc))
             }
-            None => Err(Error::DeserializationError {
-                message: "Couldn't find the object id".to_string(),
-            }),
+            None => Err(FuberError::InvalidId(
+                ErrorCode::InvalidObjectId,
+                "ObjectId for the ticket doesn't exist".into(),
+            )),
         }
     }
 
-    pub fn delete_cab(&self, cab_id: &String) -> Result<DeleteResult, Error> {
-        match ObjectId::parse_str(cab_id) {
-            Ok(obj_id) => {
-                let filter = doc! {"_id" : obj_id};
-                let deleted_doc = self.cabs.delete_one(filter, None);
-                match deleted_doc {
-                    Ok(d) => Ok(d),
-                    Err(_) => Err(Error::DeserializationError {
-                        message: "Cannot delete the cab".into(),
-                    }),
-                }
-            }
-            Err(_) => Err(Error::DeserializationError {
-                message: "Cannot find the object".to_string(),
-            }),
+    fn get_waiting_tickets(&self) -> Result<Vec<Ticket>, FuberError> {
+        let filter = doc! {"state": TicketState::Waiting.as_str()};
+        let options = FindOptions::builder()
+            .sort(doc! {"created_at": 1, "_id": 1})
+            .build();
+        self.tickets
+            .find(filter, options)
+            .map_err(storage("Error getting the waiting tickets"))?
+            .map(|x| x.map_err(storage("Error reading a ticket")))
+            .collect::<Result<Vec<Ticket>, FuberError>>()
+    }
+
+    fn create_booking(&self, new_booking: Booking) -> Result<InsertOneResult, FuberError> {
+        let booking = self
+            .bookings
+            .insert_one(new_booking, None)
+            .map_err(storage("Error creating new booking"))?;
+
+        Ok(InsertOneResult {
+            inserted_id: bson_to_object_id(&booking.inserted_id)?,
+        })
+    }
+
+    fn get_booking(&self, id: &str) -> Result<Booking, FuberError> {
+        let filter = doc! {"_id": parse_id(id)?};
+        match self
+            .bookings
+            .find_one(filter, None)
+            .map_err(storage("Error getting booking's detail"))?
+        {
+            Some(booking) => Ok(booking),
+            None => Err(FuberError::booking_not_found(id)),
         }
     }
 
-    pub fn update_person(&self, new_person: Person) -> Result<UpdateResult, Error> {
-        match new_person.id.clone() {
+    fn update_booking(
+        &self,
+        booking: Booking,
+        expected: BookingState,
+    ) -> Result<UpdateResult, FuberError> {
+        match booking.id {
             Some(obj_id) => {
-                let filter = doc! {"_id" : obj_id};
-                let new_doc = doc! {
-                    "$set":
-                    {
-                        "id" : new_person.id,
-                        "name" : new_person.name,
-                        "location" : {
-                            "x" : new_person.location.x.clone(),
-                            "y" : new_person.location.y.clone(),
-                        },
-                        "destination" : {
-                            "x" : new_person.destination.x.clone(),
-                            "y" : new_person.destination.y.clone(),
-                        },
-                    }
-                };
+                let filter = doc! {"_id" : obj_id, "state": expected.as_str()};
+                let updated_doc = self
+                    .bookings
+                    .replace_one(filter, booking, None)
+                    .map_err(storage("Cannot update the booking"))?;
+                Ok(to_update_result(updated_doc))
+            }
+            None => Err(FuberError::InvalidId(
+                ErrorCode::InvalidObjectId,
+                "ObjectId for the booking doesn't exist".into(),
+            )),
+        }
+    }
+
+    fn get_bookings_of_person(&self, person_id: &str) -> Result<Vec<Booking>, FuberError> {
+        self.find_bookings(doc! {"person_id": parse_id(person_id)?})
+    }
 
-                let updated_doc = self.persons.update_one(filter, new_doc, None).ok();
+    fn get_upcoming_bookings(&self, after: i64) -> Result<Vec<Booking>, FuberError> {
+        self.find_bookings(doc! {
+            "state": {"$ne": BookingState::Cancelled.as_str()},
+            "pickup_at": {"$gt": after},
+        })
+    }
+
+    fn get_scheduled_bookings(&self, until: i64) -> Result<Vec<Booking>, FuberError> {
+        self.find_bookings(doc! {
+            "state": BookingState::Scheduled.as_str(),
+            "pickup_at": {"$lte": until},
+        })
+    }
 
-                match updated_doc {
-                    Some(update) => Ok(update),
-                    None => Err(Error::DeserializationError {
-                        message: "Cannot update the doc".into(),
-                    }),
+    fn find_nearest_free_cabs(
+        &self,
+        point: &Point,
+        limit: usize,
+        max_distance: Option<f64>,
+        category: Option<CabCategory>,
+    ) -> Result<Vec<Cab>, FuberError> {
+        // `$near` already sorts by distance, nearest first
+        let mut filter = match point {
+            Point::Grid { x, y } => {
+                let mut near = doc! { "$near": [*x as f64, *y as f64] };
+                if let Some(max_distance) = max_distance {
+                    near.insert("$maxDistance", max_distance);
                 }
+                // gps cabs sit in the 2d index as well, they are never near
+                doc! { "location": near, "geo_location": null }
             }
-            None => Err(Error::DeserializationError {
-                message: "ObjectId for the person doesn't exist".into(),
-            }),
-        }
+            Point::Geo { .. } => {
+                let mut near = doc! { "$geometry": geo_json(point) };
+                if let Some(max_distance) = max_distance {
+                    // the 2dsphere index measures in metres
+                    near.insert("$maxDistance", max_distance * 1000.0);
+                }
+                doc! { "geo_location": { "$near": near } }
+            }
+        };
+        filter.insert("person_id", Bson::Null);
+        filter.extend(list_filter::<Rider>("riders", &[])?);
+        add_category(&mut filter, category);
+        // a limit of 0 is no limit at all to mongodb
+        let limit = i64::try_from(limit).unwrap_or(0);
+        let options = FindOptions::builder().limit(limit).build();
+        self.cabs
+            .find(filter, options)
+            .map_err(storage("Error finding the nearest cabs"))?
+            .map(|x| x.map_err(storage("Error reading a nearby cab")))
+            .collect::<Result<Vec<Cab>, FuberError>>()
     }
 
-    pub fn delete_person(&self, person_id: &String) -> Result<DeleteResult, Error> {
-        match ObjectId::parse_str(person_id) {
-            Ok(obj_id) => {
-                let filter = doc! {"_id" : obj_id};
-                let deleted_doc = self.persons.delete_one(filter, None);
-                match deleted_doc {
-                    Ok(d) => Ok(d),
-                    Err(_) => Err(Error::DeserializationError {
-                        message: "Cannot delete the person".into(),
-                    }),
-                }
+    fn get_pooled_cabs(&self, category: Option<CabCategory>) -> Result<Vec<Cab>, FuberError> {
+        let mut filter = doc! { "person_id": null, "riders.0": { "$exists": true } };
+        add_category(&mut filter, category);
+        self.cabs
+            .find(filter, None)
+            .map_err(storage("Error getting the pooled cabs"))?
+            .map(|x| x.map_err(storage("Error reading a pooled cab")))
+            .collect::<Result<Vec<Cab>, FuberError>>()
+    }
+
+    // Seqs are counted before their event is inserted, with several writers
+    // the event with seq 7 can show up before the one with 6. A page stops
+    // right before a missing seq so consumers moving their cursor past 7
+    // don't lose 6, unless the events after the gap are older than
+    // `SEQ_GAP_MILLIS`: then the insert of 6 failed and it never shows up.
+    fn get_events(&self, since: i64, limit: usize) -> Result<Vec<Event>, FuberError> {
+        // a limit of 0 is no limit at all to mongodb
+        let limit = match i64::try_from(limit) {
+            Ok(0) => return Ok(Vec::new()),
+            Ok(limit) => limit,
+            Err(_) => 0,
+        };
+        let options = FindOptions::builder()
+            .sort(doc! {"seq": 1})
+            .limit(limit)
+            .build();
+        let found = self
+            .events
+            .find(doc! {"seq": {"$gt": since}}, options)
+            .map_err(storage("Error getting the events"))?
+            .map(|x| x.map_err(storage("Error reading an event")))
+            .collect::<Result<Vec<Event>, FuberError>>()?;
+        let now = now_millis();
+        let mut expected = since + 1;
+        let mut events = Vec::with_capacity(found.len());
+        for event in found {
+            if event.seq != expected && now - event.at < SEQ_GAP_MILLIS {
+                break;
             }
-            Err(_) => Err(Error::DeserializationError {
-                message: "Cannot find the ObjectId for the person".to_string(),
-            }),
+            expected = event.seq + 1;
+            events.push(event);
+        }
+        Ok(events)
+    }
+
+    fn flush_pending_events(&self) -> Result<(), FuberError> {
+        self.drain_where(&self.cabs.clone_with_type(), doc! {})?;
+        self.drain_where(&self.rides.clone_with_type(), doc! {})
+    }
+
+    fn create_webhook(&self, new_webhook: Webhook) -> Result<InsertOneResult, FuberError> {
+        let webhook = self
+            .webhooks
+            .insert_one(new_webhook, None)
+            .map_err(storage("Error creating new webhook"))?;
+
+        Ok(InsertOneResult {
+            inserted_id: bson_to_object_id(&webhook.inserted_id)?,
+        })
+    }
+
+    fn get_webhooks(&self) -> Result<Vec<Webhook>, FuberError> {
+        self.webhooks
+            .find(None, None)
+            .map_err(storage("Error getting the webhooks"))?
+            .map(|x| x.map_err(storage("Error reading a webhook")))
+            .collect::<Result<Vec<Webhook>, FuberError>>()
+    }
+
+    // the deliveries go after the webhook, a delivery written in between
+    // is left behind and never found a webhook to go to
+    fn delete_webhook(&self, webhook_id: &str) -> Result<DeleteResult, FuberError> {
+        let obj_id = parse_id(webhook_id)?;
+        let deleted = self
+            .webhooks
+            .delete_one(doc! {"_id": obj_id}, None)
+            .map_err(storage("Cannot delete the webhook"))?;
+        self.outbox
+            .delete_many(doc! {"webhook_id": obj_id}, None)
+            .map_err(storage("Cannot delete the deliveries of the webhook"))?;
+        Ok(DeleteResult {
+            deleted_count: deleted.deleted_count,
+        })
+    }
+
+    fn get_delivery(&self, id: &str) -> Result<Delivery, FuberError> {
+        let filter = doc! {"_id": parse_id(id)?};
+        match self
+            .outbox
+            .find_one(filter, None)
+            .map_err(storage("Error getting the delivery"))?
+        {
+            Some(delivery) => Ok(delivery),
+            None => Err(FuberError::delivery_not_found(id)),
         }
     }
 
-    pub fn delete_fleet(&self) -> Result<DeleteResult, Error> {
-        let filter = doc! {};
-        let deleted_fleet_docs = self.cabs.delete_many(filter, None);
-        match deleted_fleet_docs {
-            Ok(d) => Ok(d),
-            Err(_) => Err(Error::DeserializationError {
-                message: "Cannot delete the complete fleet".into(),
-            }),
+    fn update_delivery(
+        &self,
+        delivery: Delivery,
+        expected: DeliveryState,
+    ) -> Result<UpdateResult, FuberError> {
+        match delivery.id {
+            Some(obj_id) => {
+                let filter = doc! {"_id" : obj_id, "state": expected.as_str()};
+                let updated_doc = self
+                    .outbox
+                    .replace_one(filter, delivery, None)
+                    .map_err(storage("Cannot update the delivery"))?;
+                Ok(to_update_result(updated_doc))
+            }
+            None => Err(FuberError::InvalidId(
+                ErrorCode::InvalidObjectId,
+                "ObjectId for the delivery doesn't exist".into(),
+            )),
         }
     }
 
-    pub fn delete_all_people(&self) -> Result<DeleteResult, Error> {
-        let filter = doc! {};
-        let deleted_people_docs = self.persons.delete_many(filter, None);
-        match deleted_people_docs {
-            Ok(d) => Ok(d),
-            Err(_) => Err(Error::DeserializationError {
-                message: "Cannot delete the complete fleet".into(),
-            }),
+    fn get_due_deliveries(&self, now: i64, limit: usize) -> Result<Vec<Delivery>, FuberError> {
+        let limit = match i64::try_from(limit) {
+            Ok(0) => return Ok(Vec::new()),
+            Ok(limit) => Some(limit),
+            Err(_) => None,
+        };
+        self.find_deliveries(
+            doc! {
+                "state": DeliveryState::Pending.as_str(),
+                "next_attempt_at": {"$lte": now},
+            },
+            doc! {"next_attempt_at": 1, "event.seq": 1},
+            limit,
+        )
+    }
+
+    fn get_dead_deliveries(&self) -> Result<Vec<Delivery>, FuberError> {
+        self.find_deliveries(
+            doc! {"state": DeliveryState::Dead.as_str()},
+            doc! {"event.seq": 1},
+            None,
+        )
+    }
+
+    fn create_api_key(&self, new_api_key: ApiKey) -> Result<InsertOneResult, FuberError> {
+        let api_key = self
+            .api_keys
+            .insert_one(new_api_key, None)
+            .map_err(storage("Error creating new api key"))?;
+
+        Ok(InsertOneResult {
+            inserted_id: bson_to_object_id(&api_key.inserted_id)?,
+        })
+    }
+
+    fn get_api_key_by_hash(&self, key_hash: &str) -> Result<ApiKey, FuberError> {
+        match self
+            .api_keys
+            .find_one(doc! {"key_hash": key_hash}, None)
+            .map_err(storage("Error getting the api key"))?
+        {
+            Some(api_key) => Ok(api_key),
+            None => Err(FuberError::api_key_not_found(key_hash)),
         }
     }
+
+    fn delete_api_key(&self, api_key_id: &str) -> Result<DeleteResult, FuberError> {
+        let obj_id = parse_id(api_key_id)?;
+        let deleted = self
+            .api_keys
+            .delete_one(doc! {"_id": obj_id}, None)
+            .map_err(storage("Cannot delete the api key"))?;
+        Ok(DeleteResult {
+            deleted_count: deleted.deleted_count,
+        })
+    }
 }
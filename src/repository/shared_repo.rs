@@ -0,0 +1,236 @@
+use std::sync::Arc;
+
+use super::fuber_repo::{
+    BoxedRepo, DeleteResult, FuberRepository, InsertManyResult, InsertOneResult, UpdateResult,
+};
+use crate::{
+    error::FuberError,
+    models::{
+        auth_model::ApiKey,
+        booking_model::{Booking, BookingState},
+        cab_model::{Cab, CabCategory},
+        event_model::Event,
+        person_model::Person,
+        point_model::Point,
+        ride_model::{Ride, RideState},
+        ticket_model::{Ticket, TicketState},
+        webhook_model::{Delivery, DeliveryState, Webhook},
+    },
+};
+
+// A backend that is used from outside of rocket as well. Rocket owns the
+// state it manages, so whatever runs next to the handlers, like the booking
+// scheduler, holds on to the same backend through one of these.
+#[derive(Clone)]
+pub struct SharedRepo(Arc<BoxedRepo>);
+
+impl SharedRepo {
+    pub fn new(inner: BoxedRepo) -> Self {
+        SharedRepo(Arc::new(inner))
+    }
+
+    pub fn repo(&self) -> &BoxedRepo {
+        &self.0
+    }
+}
+
+impl FuberRepository for SharedRepo {
+    fn create_person(&self, new_person: Person) -> Result<InsertOneResult, FuberError> {
+        self.0.create_person(new_person)
+    }
+
+    fn get_person(&self, id: &str) -> Result<Person, FuberError> {
+        self.0.get_person(id)
+    }
+
+    fn update_person(&self, new_person: Person) -> Result<UpdateResult, FuberError> {
+        self.0.update_person(new_person)
+    }
+
+    fn delete_person(&self, person_id: &str) -> Result<DeleteResult, FuberError> {
+        self.0.delete_person(person_id)
+    }
+
+    fn delete_all_people(&self) -> Result<DeleteResult, FuberError> {
+        self.0.delete_all_people()
+    }
+
+    fn create_cab(&self, new_cab: Cab) -> Result<InsertOneResult, FuberError> {
+        self.0.create_cab(new_cab)
+    }
+
+    fn get_cab(&self, id: &str) -> Result<Cab, FuberError> {
+        self.0.get_cab(id)
+    }
+
+    fn create_fleet(&self, fleet: Vec<Cab>) -> Result<InsertManyResult, FuberError> {
+        self.0.create_fleet(fleet)
+    }
+
+    fn get_fleet(&self) -> Result<Vec<Cab>, FuberError> {
+        self.0.get_fleet()
+    }
+
+    fn assign_person(&self, cab_id: &str, new_cab: Cab) -> Result<UpdateResult, FuberError> {
+        self.0.assign_person(cab_id, new_cab)
+    }
+
+    fn unassign_person(&self, cab_id: &str, new_cab: Cab) -> Result<UpdateResult, FuberError> {
+        self.0.unassign_person(cab_id, new_cab)
+    }
+
+    fn update_cab(&self, new_cab: Cab) -> Result<UpdateResult, FuberError> {
+        self.0.update_cab(new_cab)
+    }
+
+    fn update_location(&self, cab_id: &str, location: Point) -> Result<UpdateResult, FuberError> {
+        self.0.update_location(cab_id, location)
+    }
+
+    fn update_pool(&self, new_cab: Cab, expected: &Cab) -> Result<UpdateResult, FuberError> {
+        self.0.update_pool(new_cab, expected)
+    }
+
+    fn delete_cab(&self, cab_id: &str) -> Result<DeleteResult, FuberError> {
+        self.0.delete_cab(cab_id)
+    }
+
+    fn delete_fleet(&self) -> Result<DeleteResult, FuberError> {
+        self.0.delete_fleet()
+    }
+
+    fn find_nearest_free_cabs(
+        &self,
+        point: &Point,
+        limit: usize,
+        max_distance: Option<f64>,
+        category: Option<CabCategory>,
+    ) -> Result<Vec<Cab>, FuberError> {
+        self.0
+            .find_nearest_free_cabs(point, limit, max_distance, category)
+    }
+
+    fn get_pooled_cabs(&self, category: Option<CabCategory>) -> Result<Vec<Cab>, FuberError> {
+        self.0.get_pooled_cabs(category)
+    }
+
+    fn has_cab_of_category(&self, category: CabCategory) -> Result<bool, FuberError> {
+        self.0.has_cab_of_category(category)
+    }
+
+    fn create_ride(&self, new_ride: Ride) -> Result<InsertOneResult, FuberError> {
+        self.0.create_ride(new_ride)
+    }
+
+    fn get_ride(&self, id: &str) -> Result<Ride, FuberError> {
+        self.0.get_ride(id)
+    }
+
+    fn update_ride(&self, ride: Ride, expected: RideState) -> Result<UpdateResult, FuberError> {
+        self.0.update_ride(ride, expected)
+    }
+
+    fn get_rides_of_person(&self, person_id: &str) -> Result<Vec<Ride>, FuberError> {
+        self.0.get_rides_of_person(person_id)
+    }
+
+    fn create_ticket(&self, new_ticket: Ticket) -> Result<InsertOneResult, FuberError> {
+        self.0.create_ticket(new_ticket)
+    }
+
+    fn get_ticket(&self, id: &str) -> Result<Ticket, FuberError> {
+        self.0.get_ticket(id)
+    }
+
+    fn update_ticket(
+        &self,
+        ticket: Ticket,
+        expected: TicketState,
+    ) -> Result<UpdateResult, FuberError> {
+        self.0.update_ticket(ticket, expected)
+    }
+
+    fn get_waiting_tickets(&self) -> Result<Vec<Ticket>, FuberError> {
+        self.0.get_waiting_tickets()
+    }
+
+    fn create_booking(&self, new_booking: Booking) -> Result<InsertOneResult, FuberError> {
+        self.0.create_booking(new_booking)
+    }
+
+    fn get_booking(&self, id: &str) -> Result<Booking, FuberError> {
+        self.0.get_booking(id)
+    }
+
+    fn update_booking(
+        &self,
+        booking: Booking,
+        expected: BookingState,
+    ) -> Result<UpdateResult, FuberError> {
+        self.0.update_booking(booking, expected)
+    }
+
+    fn get_bookings_of_person(&self, person_id: &str) -> Result<Vec<Booking>, FuberError> {
+        self.0.get_bookings_of_person(person_id)
+    }
+
+    fn get_upcoming_bookings(&self, after: i64) -> Result<Vec<Booking>, FuberError> {
+        self.0.get_upcoming_bookings(after)
+    }
+
+    fn get_scheduled_bookings(&self, until: i64) -> Result<Vec<Booking>, FuberError> {
+        self.0.get_scheduled_bookings(until)
+    }
+
+    fn get_events(&self, since: i64, limit: usize) -> Result<Vec<Event>, FuberError> {
+        self.0.get_events(since, limit)
+    }
+
+    fn flush_pending_events(&self) -> Result<(), FuberError> {
+        self.0.flush_pending_events()
+    }
+
+    fn create_webhook(&self, new_webhook: Webhook) -> Result<InsertOneResult, FuberError> {
+        self.0.create_webhook(new_webhook)
+    }
+
+    fn get_webhooks(&self) -> Result<Vec<Webhook>, FuberError> {
+        self.0.get_webhooks()
+    }
+
+    fn delete_webhook(&self, webhook_id: &str) -> Result<DeleteResult, FuberError> {
+        self.0.delete_webhook(webhook_id)
+    }
+
+    fn get_delivery(&self, id: &str) -> Result<Delivery, FuberError> {
+        self.0.get_delivery(id)
+    }
+
+    fn update_delivery(
+        &self,
+        delivery: Delivery,
+        expected: DeliveryState,
+    ) -> Result<UpdateResult, FuberError> {
+        self.0.update_delivery(delivery, expected)
+    }
+
+    fn get_due_deliveries(&self, now: i64, limit: usize) -> Result<Vec<Delivery>, FuberError> {
+        self.0.get_due_deliveries(now, limit)
+    }
+
+    fn get_dead_deliveries(&self) -> Result<Vec<Delivery>, FuberError> {
+        self.0.get_dead_deliveries()
+    }
+
+    fn create_api_key(&self, new_api_key: ApiKey) -> Result<InsertOneResult, FuberError> {
+        self.0.create_api_key(new_api_key)
+    }
+
+    fn get_api_key_by_hash(&self, key_hash: &str) -> Result<ApiKey, FuberError> {
+        self.0.get_api_key_by_hash(key_hash)
+    }
+
+    fn delete_api_key(&self, api_key_id: &str) -> Result<DeleteResult, FuberError> {
+        self.0.delete_api_key(api_key_id)
+    }
+}
@@ -0,0 +1,1551 @@
+use std::env;
+use std::sync::{Mutex, MutexGuard};
+
+use dotenv::dotenv;
+use mongodb::bson::oid::ObjectId;
+use rusqlite::{
+    params,
+    types::{Value, ValueRef},
+    Connection, OptionalExtension, Row,
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::fuber_repo::{
+    DeleteResult, FuberRepository, InsertManyResult, InsertOneResult, UpdateResult,
+};
+use crate::{
+    error::{ErrorCode, FuberError},
+    models::{
+        auth_model::{ApiKey, Role},
+        booking_model::{Booking, BookingState},
+        cab_model::{Cab, CabCategory},
+        event_model::{Event, EventKind},
+        fare_model::Fare,
+        person_model::Person,
+        point_model::Point,
+        ride_model::{Ride, RideState, RideTransition},
+        ticket_model::{Ticket, TicketState},
+        webhook_model::{deliveries_of, Delivery, DeliveryState, Webhook, WEBHOOK_EVENTS},
+    },
+};
+
+// Every migration is applied exactly once, in order, when the repo is opened.
+// The version that was reached is kept in sqlite's `user_version` pragma so
+// new migrations only ever get appended to this list, never edited.
+const MIGRATIONS: &[&str] = &[
+    // 1: cabs, persons and which person is sitting in which cab
+    "CREATE TABLE persons (
+        id TEXT PRIMARY KEY NOT NULL,
+        name TEXT NOT NULL,
+        location_x INTEGER NOT NULL,
+        location_y INTEGER NOT NULL,
+        destination_x INTEGER NOT NULL,
+        destination_y INTEGER NOT NULL
+    );
+    CREATE TABLE cabs (
+        id TEXT PRIMARY KEY NOT NULL,
+        location_x INTEGER NOT NULL,
+        location_y INTEGER NOT NULL,
+        destination_x INTEGER,
+        destination_y INTEGER
+    );
+    CREATE TABLE assignments (
+        cab_id TEXT PRIMARY KEY NOT NULL REFERENCES cabs(id) ON DELETE CASCADE,
+        person_id TEXT NOT NULL
+    );",
+    // 2: rides and every state they went through
+    "CREATE TABLE rides (
+        id TEXT PRIMARY KEY NOT NULL,
+        person_id TEXT NOT NULL,
+        cab_id TEXT,
+        pickup_x INTEGER NOT NULL,
+        pickup_y INTEGER NOT NULL,
+        drop_x INTEGER NOT NULL,
+        drop_y INTEGER NOT NULL,
+        state TEXT NOT NULL
+    );
+    CREATE INDEX rides_person_id ON rides (person_id);
+    CREATE TABLE ride_transitions (
+        ride_id TEXT NOT NULL REFERENCES rides(id) ON DELETE CASCADE,
+        seq INTEGER NOT NULL,
+        state TEXT NOT NULL,
+        timestamp INTEGER NOT NULL,
+        PRIMARY KEY (ride_id, seq)
+    );",
+    // 3: the fare of a completed ride, kept as json
+    "ALTER TABLE rides ADD COLUMN fare TEXT;",
+    // 4: the category of every cab, the ones already stored are standard
+    "ALTER TABLE cabs ADD COLUMN category TEXT NOT NULL DEFAULT 'standard';",
+    // 5: whether every stored point is on the grid or a gps position, the
+    // points already stored are all on the grid
+    "ALTER TABLE persons ADD COLUMN location_kind TEXT NOT NULL DEFAULT 'grid';
+    ALTER TABLE persons ADD COLUMN destination_kind TEXT NOT NULL DEFAULT 'grid';
+    ALTER TABLE cabs ADD COLUMN location_kind TEXT NOT NULL DEFAULT 'grid';
+    ALTER TABLE cabs ADD COLUMN destination_kind TEXT NOT NULL DEFAULT 'grid';
+    ALTER TABLE rides ADD COLUMN pickup_kind TEXT NOT NULL DEFAULT 'grid';
+    ALTER TABLE rides ADD COLUMN drop_kind TEXT NOT NULL DEFAULT 'grid';",
+    // 6: how long the cab of a ride needed to the pickup
+    "ALTER TABLE rides ADD COLUMN eta_minutes REAL;",
+    // 7: ride requests waiting for a free cab
+    "CREATE TABLE tickets (
+        id TEXT PRIMARY KEY NOT NULL,
+        person_id TEXT NOT NULL,
+        ride_id TEXT NOT NULL,
+        category TEXT,
+        state TEXT NOT NULL,
+        created_at INTEGER NOT NULL,
+        cab_id TEXT
+    );
+    CREATE INDEX tickets_state ON tickets (state, created_at);",
+    // 8: rides booked ahead of time
+    "CREATE TABLE bookings (
+        id TEXT PRIMARY KEY NOT NULL,
+        person_id TEXT NOT NULL,
+        pickup_x INTEGER NOT NULL,
+        pickup_y INTEGER NOT NULL,
+        pickup_kind TEXT NOT NULL,
+        drop_x INTEGER NOT NULL,
+        drop_y INTEGER NOT NULL,
+        drop_kind TEXT NOT NULL,
+        pickup_at INTEGER NOT NULL,
+        category TEXT,
+        state TEXT NOT NULL,
+        attempts INTEGER NOT NULL,
+        ride_id TEXT,
+        created_at INTEGER NOT NULL
+    );
+    CREATE INDEX bookings_person_id ON bookings (person_id);
+    CREATE INDEX bookings_pickup_at ON bookings (state, pickup_at);",
+    // 9: the seats of every cab and the riders and stops of pooled cabs,
+    // kept as json
+    "ALTER TABLE cabs ADD COLUMN seats INTEGER NOT NULL DEFAULT 4;
+    ALTER TABLE cabs ADD COLUMN riders TEXT NOT NULL DEFAULT '[]';
+    ALTER TABLE cabs ADD COLUMN stops TEXT NOT NULL DEFAULT '[]';",
+    // 10: the surge multiplier a ride was priced with
+    "ALTER TABLE rides ADD COLUMN surge_multiplier REAL;",
+    // 11: the change feed of cabs and persons, the payload is kept as json
+    "CREATE TABLE events (
+        seq INTEGER PRIMARY KEY AUTOINCREMENT,
+        kind TEXT NOT NULL,
+        entity_id TEXT NOT NULL,
+        at INTEGER NOT NULL,
+        payload TEXT NOT NULL
+    );",
+    // 12: webhooks and the outbox of deliveries to them, the event types of
+    // a webhook are kept as json
+    "CREATE TABLE webhooks (
+        id TEXT PRIMARY KEY NOT NULL,
+        url TEXT NOT NULL,
+        event_types TEXT NOT NULL,
+        secret TEXT NOT NULL,
+        created_at INTEGER NOT NULL
+    );
+    CREATE TABLE outbox (
+        id TEXT PRIMARY KEY NOT NULL,
+        webhook_id TEXT NOT NULL REFERENCES webhooks(id) ON DELETE CASCADE,
+        event_seq INTEGER NOT NULL REFERENCES events(seq),
+        state TEXT NOT NULL,
+        attempts INTEGER NOT NULL,
+        next_attempt_at INTEGER NOT NULL,
+        last_error TEXT
+    );
+    CREATE INDEX outbox_state ON outbox (state, next_attempt_at);",
+    // 13: the api keys callers authenticate with, only their hash is kept
+    "CREATE TABLE api_keys (
+        id TEXT PRIMARY KEY NOT NULL,
+        key_hash TEXT NOT NULL UNIQUE,
+        role TEXT NOT NULL,
+        subject TEXT,
+        created_at INTEGER NOT NULL
+    );",
+    // 14: the person that got into or out of the cab of an event
+    "ALTER TABLE events ADD COLUMN person_id TEXT;",
+    // 15: every ride request checks the fleet has its category
+    "CREATE INDEX cabs_category ON cabs (category);",
+];
+
+// Embedded storage for deployments that can't run MongoDB.
+// ObjectIds are stored as their hex string so the ids handed out by the api
+// look exactly the same as with the other backends.
+pub struct SqliteRepo {
+    conn: Mutex<Connection>,
+}
+
+fn sql_error(e: rusqlite::Error) -> FuberError {
+    FuberError::storage(format!("sqlite error: {}", e))
+}
+
+fn parse_id(id: &str) -> Result<ObjectId, FuberError> {
+    ObjectId::parse_str(id).map_err(|_| FuberError::invalid_id(id))
+}
+
+fn migrate(conn: &mut Connection) -> Result<(), rusqlite::Error> {
+    let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    for (idx, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", idx + 1)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+// the columns of a cab are (id, location_x, location_y, destination_x,
+// destination_y, person_id, category, location_kind, destination_kind,
+// seats, riders, stops) in every query below
+const CAB_COLUMNS: &str = "SELECT cabs.id, cabs.location_x, cabs.location_y,
+        cabs.destination_x, cabs.destination_y, assignments.person_id, cabs.category,
+        cabs.location_kind, cabs.destination_kind, cabs.seats, cabs.riders, cabs.stops
+    FROM cabs LEFT JOIN assignments ON assignments.cab_id = cabs.id";
+
+const PERSON_COLUMNS: &str = "SELECT id, name, location_x, location_y,
+        destination_x, destination_y, location_kind, destination_kind
+    FROM persons";
+
+// A point takes an x, a y and a kind column. Grid points keep their integer
+// x/y, gps positions keep their longitude in x and their latitude in y.
+fn point_values(point: &Point) -> (Value, Value, &'static str) {
+    match point {
+        Point::Grid { x, y } => (Value::Integer(*x), Value::Integer(*y), "grid"),
+        Point::Geo { lat, lon } => (Value::Real(*lon), Value::Real(*lat), "geo"),
+    }
+}
+
+fn optional_point_values(point: Option<&Point>) -> (Value, Value, &'static str) {
+    match point {
+        Some(point) => point_values(point),
+        None => (Value::Null, Value::Null, "grid"),
+    }
+}
+
+fn point_column(row: &Row, x: usize, y: usize, kind: usize) -> rusqlite::Result<Point> {
+    match row.get::<_, String>(kind)?.as_str() {
+        "geo" => Point::geo(row.get(y)?, row.get(x)?).map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(y, rusqlite::types::Type::Real, Box::new(e))
+        }),
+        _ => Ok(Point::new(row.get(x)?, row.get(y)?)),
+    }
+}
+
+fn object_id_column(row: &Row, idx: usize) -> rusqlite::Result<ObjectId> {
+    let hex: String = row.get(idx)?;
+    ObjectId::parse_str(&hex).map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(idx, rusqlite::types::Type::Text, Box::new(e))
+    })
+}
+
+fn category_column(row: &Row, idx: usize) -> rusqlite::Result<CabCategory> {
+    let category: String = row.get(idx)?;
+    category.parse::<CabCategory>().map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(idx, rusqlite::types::Type::Text, Box::new(e))
+    })
+}
+
+fn json_column<T: DeserializeOwned>(row: &Row, idx: usize) -> rusqlite::Result<T> {
+    let json: String = row.get(idx)?;
+    serde_json::from_str(&json).map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(idx, rusqlite::types::Type::Text, Box::new(e))
+    })
+}
+
+fn json_value<T: Serialize>(value: &T) -> rusqlite::Result<String> {
+    serde_json::to_string(value).map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))
+}
+
+fn cab_from_row(row: &Row) -> rusqlite::Result<Cab> {
+    let destination = match row.get_ref(3)? {
+        ValueRef::Null => None,
+        _ => Some(point_column(row, 3, 4, 8)?),
+    };
+    let person_id = match row.get::<_, Option<String>>(5)? {
+        Some(_) => Some(object_id_column(row, 5)?),
+        None => None,
+    };
+    Ok(Cab {
+        id: Some(object_id_column(row, 0)?),
+        location: point_column(row, 1, 2, 7)?,
+        destination,
+        person_id,
+        category: category_column(row, 6)?,
+        seats: row.get(9)?,
+        riders: json_column(row, 10)?,
+        stops: json_column(row, 11)?,
+    })
+}
+
+fn person_from_row(row: &Row) -> rusqlite::Result<Person> {
+    Ok(Person {
+        id: Some(object_id_column(row, 0)?),
+        name: row.get(1)?,
+        location: point_column(row, 2, 3, 6)?,
+        destination: point_column(row, 4, 5, 7)?,
+    })
+}
+
+const RIDE_COLUMNS: &str = "SELECT id, person_id, cab_id, pickup_x, pickup_y,
+        drop_x, drop_y, state, fare, pickup_kind, drop_kind, eta_minutes, surge_multiplier
+    FROM rides";
+
+fn fare_column(row: &Row, idx: usize) -> rusqlite::Result<Option<Fare>> {
+    match row.get::<_, Option<String>>(idx)? {
+        Some(json) => serde_json::from_str(&json).map(Some).map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(idx, rusqlite::types::Type::Text, Box::new(e))
+        }),
+        None => Ok(None),
+    }
+}
+
+fn fare_json(ride: &Ride) -> Result<Option<String>, FuberError> {
+    ride.fare
+        .as_ref()
+        .map(serde_json::to_string)
+        .transpose()
+        .map_err(|e| FuberError::storage(format!("cannot serialize the fare: {}", e)))
+}
+
+fn state_column(row: &Row, idx: usize) -> rusqlite::Result<RideState> {
+    let state: String = row.get(idx)?;
+    state.parse::<RideState>().map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(idx, rusqlite::types::Type::Text, Box::new(e))
+    })
+}
+
+// the transitions live in their own table and get filled in by `find_rides`
+fn ride_from_row(row: &Row) -> rusqlite::Result<Ride> {
+    let cab_id = match row.get::<_, Option<String>>(2)? {
+        Some(_) => Some(object_id_column(row, 2)?),
+        None => None,
+    };
+    Ok(Ride {
+        id: Some(object_id_column(row, 0)?),
+        person_id: object_id_column(row, 1)?,
+        cab_id,
+        pickup: point_column(row, 3, 4, 9)?,
+        drop: point_column(row, 5, 6, 10)?,
+        state: state_column(row, 7)?,
+        transitions: Vec::new(),
+        fare: fare_column(row, 8)?,
+        eta_minutes: row.get(11)?,
+        surge_multiplier: row.get(12)?,
+    })
+}
+
+fn find_rides(conn: &Connection, filter: &str, param: &str) -> rusqlite::Result<Vec<Ride>> {
+    let mut stmt = conn.prepare(&format!("{} WHERE {} ORDER BY rowid", RIDE_COLUMNS, filter))?;
+    let mut rides = stmt
+        .query_map(params![param], ride_from_row)?
+        .collect::<rusqlite::Result<Vec<Ride>>>()?;
+    let mut stmt = conn
+        .prepare("SELECT state, timestamp FROM ride_transitions WHERE ride_id = ?1 ORDER BY seq")?;
+    for ride in rides.iter_mut() {
+        let ride_id = ride.id.map(|x| x.to_hex());
+        ride.transitions = stmt
+            .query_map(params![ride_id], |row| {
+                Ok(RideTransition {
+                    state: state_column(row, 0)?,
+                    timestamp: row.get(1)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<RideTransition>>>()?;
+    }
+    Ok(rides)
+}
+
+// transitions are append only, only the ones that aren't stored yet get
+// written
+fn write_transitions(conn: &Connection, ride: &Ride, obj_id: ObjectId) -> rusqlite::Result<()> {
+    let stored: usize = conn.query_row(
+        "SELECT COUNT(*) FROM ride_transitions WHERE ride_id = ?1",
+        params![obj_id.to_hex()],
+        |row| row.get(0),
+    )?;
+    for (seq, transition) in ride.transitions.iter().enumerate().skip(stored) {
+        conn.execute(
+            "INSERT INTO ride_transitions (ride_id, seq, state, timestamp)
+                VALUES (?1, ?2, ?3, ?4)",
+            params![
+                obj_id.to_hex(),
+                seq,
+                transition.state.as_str(),
+                transition.timestamp,
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+const TICKET_COLUMNS: &str = "SELECT id, person_id, ride_id, category, state,
+        created_at, cab_id
+    FROM tickets";
+
+fn ticket_from_row(row: &Row) -> rusqlite::Result<Ticket> {
+    let category = match row.get_ref(3)? {
+        ValueRef::Null => None,
+        _ => Some(category_column(row, 3)?),
+    };
+    let state: String = row.get(4)?;
+    let state = state.parse::<TicketState>().map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(4, rusqlite::types::Type::Text, Box::new(e))
+    })?;
+    let cab_id = match row.get::<_, Option<String>>(6)? {
+        Some(_) => Some(object_id_column(row, 6)?),
+        None => None,
+    };
+    Ok(Ticket {
+        id: Some(object_id_column(row, 0)?),
+        person_id: object_id_column(row, 1)?,
+        ride_id: object_id_column(row, 2)?,
+        category,
+        state,
+        created_at: row.get(5)?,
+        cab_id,
+    })
+}
+
+const BOOKING_COLUMNS: &str = "SELECT id, person_id, pickup_x, pickup_y, pickup_kind,
+        drop_x, drop_y, drop_kind, pickup_at, category, state, attempts, ride_id, created_at
+    FROM bookings";
+
+fn booking_from_row(row: &Row) -> rusqlite::Result<Booking> {
+    let category = match row.get_ref(9)? {
+        ValueRef::Null => None,
+        _ => Some(category_column(row, 9)?),
+    };
+    let state: String = row.get(10)?;
+    let state = state.parse::<BookingState>().map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(10, rusqlite::types::Type::Text, Box::new(e))
+    })?;
+    let ride_id = match row.get::<_, Option<String>>(12)? {
+        Some(_) => Some(object_id_column(row, 12)?),
+        None => None,
+    };
+    Ok(Booking {
+        id: Some(object_id_column(row, 0)?),
+        person_id: object_id_column(row, 1)?,
+        pickup: point_column(row, 2, 3, 4)?,
+        drop: point_column(row, 5, 6, 7)?,
+        pickup_at: row.get(8)?,
+        category,
+        state,
+        attempts: row.get(11)?,
+        ride_id,
+        created_at: row.get(13)?,
+    })
+}
+
+fn find_bookings(
+    conn: &Connection,
+    filter: &str,
+    params: impl rusqlite::Params,
+) -> rusqlite::Result<Vec<Booking>> {
+    let mut stmt = conn.prepare(&format!(
+        "{} WHERE {} ORDER BY pickup_at, rowid",
+        BOOKING_COLUMNS, filter
+    ))?;
+    let bookings = stmt
+        .query_map(params, booking_from_row)?
+        .collect::<rusqlite::Result<Vec<Booking>>>();
+    bookings
+}
+
+const EVENT_COLUMNS: &str = "SELECT seq, kind, entity_id, at, payload, person_id FROM events";
+
+fn event_from_row(row: &Row) -> rusqlite::Result<Event> {
+    let kind: String = row.get(1)?;
+    let kind = kind.parse::<EventKind>().map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(1, rusqlite::types::Type::Text, Box::new(e))
+    })?;
+    Ok(Event {
+        seq: row.get(0)?,
+        kind,
+        entity_id: object_id_column(row, 2)?,
+        at: row.get(3)?,
+        payload: json_column(row, 4)?,
+        person_id: match row.get::<_, Option<String>>(5)? {
+            Some(_) => Some(object_id_column(row, 5)?),
+            None => None,
+        },
+    })
+}
+
+// goes into the same transaction as the change it records, the seq comes
+// from sqlite. So do the deliveries of the event to the webhooks.
+fn insert_event(conn: &Connection, event: &Event) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO events (kind, entity_id, at, payload, person_id)
+            VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            event.kind.as_str(),
+            event.entity_id.to_hex(),
+            event.at,
+            json_value(&event.payload)?,
+            event.person_id.map(|x| x.to_hex()),
+        ],
+    )?;
+    if !WEBHOOK_EVENTS.contains(&event.kind) {
+        return Ok(());
+    }
+    let event = Event {
+        seq: conn.last_insert_rowid(),
+        ..event.clone()
+    };
+    for delivery in deliveries_of(&find_webhooks(conn)?, &event) {
+        conn.execute(
+            "INSERT INTO outbox
+                (id, webhook_id, event_seq, state, attempts, next_attempt_at, last_error)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                ObjectId::new().to_hex(),
+                delivery.webhook_id.to_hex(),
+                delivery.event.seq,
+                delivery.state.as_str(),
+                delivery.attempts,
+                delivery.next_attempt_at,
+                delivery.last_error,
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+const WEBHOOK_COLUMNS: &str = "SELECT id, url, event_types, secret, created_at FROM webhooks";
+
+fn webhook_from_row(row: &Row) -> rusqlite::Result<Webhook> {
+    Ok(Webhook {
+        id: Some(object_id_column(row, 0)?),
+        url: row.get(1)?,
+        event_types: json_column(row, 2)?,
+        secret: row.get(3)?,
+        created_at: row.get(4)?,
+    })
+}
+
+fn find_webhooks(conn: &Connection) -> rusqlite::Result<Vec<Webhook>> {
+    let mut stmt = conn.prepare(&format!("{} ORDER BY rowid", WEBHOOK_COLUMNS))?;
+    let webhooks = stmt
+        .query_map([], webhook_from_row)?
+        .collect::<rusqlite::Result<Vec<Webhook>>>();
+    webhooks
+}
+
+const API_KEY_COLUMNS: &str = "SELECT id, key_hash, role, subject, created_at FROM api_keys";
+
+fn api_key_from_row(row: &Row) -> rusqlite::Result<ApiKey> {
+    let role: String = row.get(2)?;
+    let role = role.parse::<Role>().map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(2, rusqlite::types::Type::Text, Box::new(e))
+    })?;
+    let subject = match row.get::<_, Option<String>>(3)? {
+        Some(_) => Some(object_id_column(row, 3)?),
+        None => None,
+    };
+    Ok(ApiKey {
+        id: Some(object_id_column(row, 0)?),
+        key_hash: row.get(1)?,
+        role,
+        subject,
+        created_at: row.get(4)?,
+    })
+}
+
+// the event comes first so `event_from_row` reads it as it is
+const DELIVERY_COLUMNS: &str = "SELECT events.seq, events.kind, events.entity_id, events.at,
+        events.payload, events.person_id, outbox.id, outbox.webhook_id, outbox.state,
+        outbox.attempts, outbox.next_attempt_at, outbox.last_error
+    FROM outbox JOIN events ON events.seq = outbox.event_seq";
+
+fn delivery_from_row(row: &Row) -> rusqlite::Result<Delivery> {
+    let state: String = row.get(8)?;
+    let state = state.parse::<DeliveryState>().map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(8, rusqlite::types::Type::Text, Box::new(e))
+    })?;
+    Ok(Delivery {
+        id: Some(object_id_column(row, 6)?),
+        webhook_id: object_id_column(row, 7)?,
+        event: event_from_row(row)?,
+        state,
+        attempts: row.get(9)?,
+        next_attempt_at: row.get(10)?,
+        last_error: row.get(11)?,
+    })
+}
+
+fn find_deliveries(
+    conn: &Connection,
+    filter: &str,
+    params: impl rusqlite::Params,
+) -> rusqlite::Result<Vec<Delivery>> {
+    let mut stmt = conn.prepare(&format!("{} WHERE {}", DELIVERY_COLUMNS, filter))?;
+    let deliveries = stmt
+        .query_map(params, delivery_from_row)?
+        .collect::<rusqlite::Result<Vec<Delivery>>>();
+    deliveries
+}
+
+fn insert_cab(conn: &Connection, cab: &Cab, obj_id: ObjectId) -> rusqlite::Result<()> {
+    let (location_x, location_y, location_kind) = point_values(&cab.location);
+    let (destination_x, destination_y, destination_kind) =
+        optional_point_values(cab.destination.as_ref());
+    conn.execute(
+        "INSERT INTO cabs (id, location_x, location_y, destination_x, destination_y, category,
+            location_kind, destination_kind, seats, riders, stops)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        params![
+            obj_id.to_hex(),
+            location_x,
+            location_y,
+            destination_x,
+            destination_y,
+            cab.category.as_str(),
+            location_kind,
+            destination_kind,
+            cab.seats,
+            json_value(&cab.riders)?,
+            json_value(&cab.stops)?,
+        ],
+    )?;
+    if let Some(person_id) = cab.person_id {
+        conn.execute(
+            "INSERT INTO assignments (cab_id, person_id) VALUES (?1, ?2)",
+            params![obj_id.to_hex(), person_id.to_hex()],
+        )?;
+    }
+    Ok(())
+}
+
+fn find_cab(conn: &Connection, obj_id: ObjectId) -> rusqlite::Result<Option<Cab>> {
+    conn.query_row(
+        &format!("{} WHERE cabs.id = ?1", CAB_COLUMNS),
+        params![obj_id.to_hex()],
+        cab_from_row,
+    )
+    .optional()
+}
+
+fn find_person(conn: &Connection, obj_id: ObjectId) -> rusqlite::Result<Option<Person>> {
+    conn.query_row(
+        &format!("{} WHERE id = ?1", PERSON_COLUMNS),
+        params![obj_id.to_hex()],
+        person_from_row,
+    )
+    .optional()
+}
+
+// writes every column of `cab`, including its row in assignments
+fn write_cab(conn: &Connection, cab: &Cab, obj_id: ObjectId) -> rusqlite::Result<()> {
+    let (location_x, location_y, location_kind) = point_values(&cab.location);
+    let (destination_x, destination_y, destination_kind) =
+        optional_point_values(cab.destination.as_ref());
+    conn.execute(
+        "UPDATE cabs SET location_x = ?2, location_y = ?3, destination_x = ?4, destination_y = ?5,
+            category = ?6, location_kind = ?7, destination_kind = ?8, seats = ?9, riders = ?10,
+            stops = ?11
+            WHERE id = ?1",
+        params![
+            obj_id.to_hex(),
+            location_x,
+            location_y,
+            destination_x,
+            destination_y,
+            cab.category.as_str(),
+            location_kind,
+            destination_kind,
+            cab.seats,
+            json_value(&cab.riders)?,
+            json_value(&cab.stops)?,
+        ],
+    )?;
+    match cab.person_id {
+        Some(person_id) => conn.execute(
+            "INSERT INTO assignments (cab_id, person_id) VALUES (?1, ?2)
+                ON CONFLICT (cab_id) DO UPDATE SET person_id = excluded.person_id",
+            params![obj_id.to_hex(), person_id.to_hex()],
+        )?,
+        None => conn.execute(
+            "DELETE FROM assignments WHERE cab_id = ?1",
+            params![obj_id.to_hex()],
+        )?,
+    };
+    Ok(())
+}
+
+impl SqliteRepo {
+    // reads the database file from `FUBER_SQLITE_PATH`, defaults to `fuber.db`
+    pub fn init() -> Self {
+        dotenv().ok();
+        let path = env::var("FUBER_SQLITE_PATH").unwrap_or_else(|_| "fuber.db".to_string());
+        match SqliteRepo::open(&path) {
+            Ok(repo) => repo,
+            Err(e) => panic!("unable to open the sqlite database {}: {}", path, e),
+        }
+    }
+
+    // `:memory:` gives a private database that disappears with the repo
+    pub fn open(path: &str) -> Result<Self, rusqlite::Error> {
+        let mut conn = Connection::open(path)?;
+        conn.pragma_update(None, "foreign_keys", true)?;
+        migrate(&mut conn)?;
+        Ok(SqliteRepo {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    fn conn(&self) -> Result<MutexGuard<'_, Connection>, FuberError> {
+        self.conn
+            .lock()
+            .map_err(|_| FuberError::storage("sqlite connection lock is poisoned"))
+    }
+
+    // counts follow mongodb: matched is 1 when the cab exists and passes
+    // `is_match`, modified is 1 only when the stored cab actually changed.
+    // The check and the write share one transaction behind the connection
+    // lock so nobody can sneak in between them. A change is recorded as a
+    // `kind` event with the cab as it got written.
+    fn set_cab(
+        &self,
+        obj_id: ObjectId,
+        kind: EventKind,
+        is_match: impl Fn(&Cab) -> bool,
+        update: impl Fn(&mut Cab),
+    ) -> Result<UpdateResult, FuberError> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction().map_err(sql_error)?;
+        let result = match find_cab(&tx, obj_id).map_err(sql_error)? {
+            Some(before) if is_match(&before) => {
+                let mut after = before.clone();
+                update(&mut after);
+                if before != after {
+                    write_cab(&tx, &after, obj_id).map_err(sql_error)?;
+                    let event = Event::of_cab_change(kind, obj_id, &before, &after)?;
+                    insert_event(&tx, &event).map_err(sql_error)?;
+                }
+                UpdateResult {
+                    matched_count: 1,
+                    modified_count: if before == after { 0 } else { 1 },
+                }
+            }
+            _ => UpdateResult {
+                matched_count: 0,
+                modified_count: 0,
+            },
+        };
+        tx.commit().map_err(sql_error)?;
+        Ok(result)
+    }
+
+    // Deletes the rows of `table` that pass `filter` and records each of
+    // them with what it held right before, `columns` selects them the way
+    // `from_row` reads them.
+    fn delete_rows<T>(
+        &self,
+        columns: &str,
+        table: &str,
+        filter: &str,
+        params: impl rusqlite::Params + Copy,
+        from_row: fn(&Row) -> rusqlite::Result<T>,
+        event: impl Fn(&T) -> Result<Event, FuberError>,
+    ) -> Result<DeleteResult, FuberError> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction().map_err(sql_error)?;
+        let deleted = tx
+            .prepare(&format!("{} WHERE {}", columns, filter))
+            .and_then(|mut stmt| {
+                stmt.query_map(params, from_row)?
+                    .collect::<rusqlite::Result<Vec<T>>>()
+            })
+            .map_err(sql_error)?;
+        tx.execute(&format!("DELETE FROM {} WHERE {}", table, filter), params)
+            .map_err(sql_error)?;
+        for row in &deleted {
+            insert_event(&tx, &event(row)?).map_err(sql_error)?;
+        }
+        tx.commit().map_err(sql_error)?;
+        Ok(DeleteResult {
+            deleted_count: deleted.len() as u64,
+        })
+    }
+
+    fn delete_persons(
+        &self,
+        filter: &str,
+        params: impl rusqlite::Params + Copy,
+    ) -> Result<DeleteResult, FuberError> {
+        self.delete_rows(
+            PERSON_COLUMNS,
+            "persons",
+            filter,
+            params,
+            person_from_row,
+            |x: &Person| Event::of_person(EventKind::PersonDeleted, x.id.unwrap_or_default(), x),
+        )
+    }
+
+    // the assignments of the cabs go with them
+    fn delete_cabs(
+        &self,
+        filter: &str,
+        params: impl rusqlite::Params + Copy,
+    ) -> Result<DeleteResult, FuberError> {
+        self.delete_rows(
+            CAB_COLUMNS,
+            "cabs",
+            filter,
+            params,
+            cab_from_row,
+            |x: &Cab| Event::of_cab(EventKind::CabDeleted, x.id.unwrap_or_default(), x),
+        )
+    }
+}
+
+impl FuberRepository for SqliteRepo {
+    fn create_person(&self, new_person: Person) -> Result<InsertOneResult, FuberError> {
+        let obj_id = new_person.id.unwrap_or_default();
+        let (location_x, location_y, location_kind) = point_values(&new_person.location);
+        let (destination_x, destination_y, destination_kind) =
+            point_values(&new_person.destination);
+        let mut conn = self.conn()?;
+        let tx = conn.transaction().map_err(sql_error)?;
+        tx.execute(
+            "INSERT INTO persons
+                (id, name, location_x, location_y, destination_x, destination_y,
+                location_kind, destination_kind)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                obj_id.to_hex(),
+                new_person.name,
+                location_x,
+                location_y,
+                destination_x,
+                destination_y,
+                location_kind,
+                destination_kind,
+            ],
+        )
+        .map_err(sql_error)?;
+        let event = Event::of_person(EventKind::PersonCreated, obj_id, &new_person)?;
+        insert_event(&tx, &event).map_err(sql_error)?;
+        tx.commit().map_err(sql_error)?;
+        Ok(InsertOneResult {
+            inserted_id: obj_id,
+        })
+    }
+
+    fn get_person(&self, id: &str) -> Result<Person, FuberError> {
+        let obj_id = parse_id(id)?;
+        match find_person(&*self.conn()?, obj_id).map_err(sql_error)? {
+            Some(person) => Ok(person),
+            None => Err(FuberError::person_not_found(id)),
+        }
+    }
+
+    fn update_person(&self, new_person: Person) -> Result<UpdateResult, FuberError> {
+        let obj_id = match new_person.id {
+            Some(obj_id) => obj_id,
+            None => {
+                return Err(FuberError::InvalidId(
+                    ErrorCode::InvalidObjectId,
+                    "ObjectId for the person doesn't exist".into(),
+                ))
+            }
+        };
+        let (location_x, location_y, location_kind) = point_values(&new_person.location);
+        let (destination_x, destination_y, destination_kind) =
+            point_values(&new_person.destination);
+        let mut conn = self.conn()?;
+        let tx = conn.transaction().map_err(sql_error)?;
+        let before = find_person(&tx, obj_id).map_err(sql_error)?;
+        let modified = tx
+            .execute(
+                "UPDATE persons SET name = ?2, location_x = ?3, location_y = ?4,
+                    destination_x = ?5, destination_y = ?6, location_kind = ?7,
+                    destination_kind = ?8
+                    WHERE id = ?1",
+                params![
+                    obj_id.to_hex(),
+                    new_person.name,
+                    location_x,
+                    location_y,
+                    destination_x,
+                    destination_y,
+                    location_kind,
+                    destination_kind,
+                ],
+            )
+            .map_err(sql_error)?;
+        let changed = matches!(before, Some(before) if before != new_person);
+        if changed {
+            let event = Event::of_person(EventKind::PersonUpdated, obj_id, &new_person)?;
+            insert_event(&tx, &event).map_err(sql_error)?;
+        }
+        tx.commit().map_err(sql_error)?;
+        Ok(UpdateResult {
+            matched_count: modified as u64,
+            modified_count: if changed { 1 } else { 0 },
+        })
+    }
+
+    fn delete_person(&self, person_id: &str) -> Result<DeleteResult, FuberError> {
+        let obj_id = parse_id(person_id)?;
+        self.delete_persons("id = ?1", params![obj_id.to_hex()])
+    }
+
+    fn delete_all_people(&self) -> Result<DeleteResult, FuberError> {
+        self.delete_persons("1", [])
+    }
+
+    fn create_cab(&self, new_cab: Cab) -> Result<InsertOneResult, FuberError> {
+        let obj_id = new_cab.id.unwrap_or_default();
+        let mut conn = self.conn()?;
+        let tx = conn.transaction().map_err(sql_error)?;
+        insert_cab(&tx, &new_cab, obj_id).map_err(sql_error)?;
+        let event = Event::of_cab(EventKind::CabCreated, obj_id, &new_cab)?;
+        insert_event(&tx, &event).map_err(sql_error)?;
+        tx.commit().map_err(sql_error)?;
+        Ok(InsertOneResult {
+            inserted_id: obj_id,
+        })
+    }
+
+    fn get_cab(&self, id: &str) -> Result<Cab, FuberError> {
+        let obj_id = parse_id(id)?;
+        match find_cab(&*self.conn()?, obj_id).map_err(sql_error)? {
+            Some(cab) => Ok(cab),
+            None => Err(FuberError::cab_not_found(id)),
+        }
+    }
+
+    fn create_fleet(&self, fleet: Vec<Cab>) -> Result<InsertManyResult, FuberError> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction().map_err(sql_error)?;
+        let inserted_ids = fleet
+            .iter()
+            .map(|cab| {
+                let obj_id = cab.id.unwrap_or_default();
+                insert_cab(&tx, cab, obj_id).map_err(sql_error)?;
+                let event = Event::of_cab(EventKind::CabCreated, obj_id, cab)?;
+                insert_event(&tx, &event).map_err(sql_error)?;
+                Ok(obj_id)
+            })
+            .collect::<Result<Vec<ObjectId>, FuberError>>()?;
+        tx.commit().map_err(sql_error)?;
+        Ok(InsertManyResult { inserted_ids })
+    }
+
+    fn get_fleet(&self) -> Result<Vec<Cab>, FuberError> {
+        let conn = self.conn()?;
+        let mut stmt = conn
+            .prepare(&format!("{} ORDER BY cabs.rowid", CAB_COLUMNS))
+            .map_err(sql_error)?;
+        let cabs = stmt
+            .query_map([], cab_from_row)
+            .map_err(sql_error)?
+            .collect::<Result<Vec<Cab>, rusqlite::Error>>()
+            .map_err(sql_error)?;
+        Ok(cabs)
+    }
+
+    fn assign_person(&self, cab_id: &str, new_cab: Cab) -> Result<UpdateResult, FuberError> {
+        let obj_id = parse_id(cab_id)?;
+        // only a cab that is still free can be assigned
+        self.set_cab(
+            obj_id,
+            EventKind::PersonAssigned,
+            |x| x.is_free(),
+            |x| {
+                x.location = new_cab.location.clone();
+                x.destination = new_cab.destination.clone();
+                x.person_id = new_cab.person_id;
+            },
+        )
+    }
+
+    fn unassign_person(&self, cab_id: &str, new_cab: Cab) -> Result<UpdateResult, FuberError> {
+        let obj_id = parse_id(cab_id)?;
+        self.set_cab(
+            obj_id,
+            EventKind::PersonUnassigned,
+            |_| true,
+            |x| {
+                x.location = new_cab.location.clone();
+                x.destination = None;
+                x.person_id = None;
+            },
+        )
+    }
+
+    fn update_cab(&self, new_cab: Cab) -> Result<UpdateResult, FuberError> {
+        match new_cab.id {
+            Some(obj_id) => self.set_cab(
+                obj_id,
+                EventKind::CabUpdated,
+                |_| true,
+                |x| {
+                    x.location = new_cab.location.clone();
+                    // same as MongoRepo, a free cab never keeps a destination
+                    // and a pooled one heads to its next stop
+                    x.destination = match (new_cab.person_id, x.riders.is_empty()) {
+                        (Some(_), _) => new_cab.destination.clone(),
+                        (None, false) => x.destination.clone(),
+                        (None, true) => None,
+                    };
+                    x.person_id = new_cab.person_id;
+                    x.category = new_cab.category;
+                    x.seats = new_cab.seats;
+                },
+            ),
+            None => Err(FuberError::InvalidId(
+                ErrorCode::InvalidObjectId,
+                "Couldn't find the object id".to_string(),
+            )),
+        }
+    }
+
+    fn update_location(&self, cab_id: &str, location: Point) -> Result<UpdateResult, FuberError> {
+        self.set_cab(
+            parse_id(cab_id)?,
+            EventKind::LocationUpdated,
+            |_| true,
+            |x| x.location = location.clone(),
+        )
+    }
+
+    fn update_pool(&self, new_cab: Cab, expected: &Cab) -> Result<UpdateResult, FuberError> {
+        let obj_id = new_cab
+            .id
+            .ok_or_else(|| FuberError::storage("cannot get the cab id"))?;
+        self.set_cab(
+            obj_id,
+            EventKind::CabPooled,
+            |x| x.person_id.is_none() && x.riders == expected.riders && x.stops == expected.stops,
+            |x| {
+                x.location = new_cab.location.clone();
+                x.destination = new_cab.destination.clone();
+                x.riders = new_cab.riders.clone();
+                x.stops = new_cab.stops.clone();
+            },
+        )
+    }
+
+    fn delete_cab(&self, cab_id: &str) -> Result<DeleteResult, FuberError> {
+        let obj_id = parse_id(cab_id)?;
+        self.delete_cabs("cabs.id = ?1", params![obj_id.to_hex()])
+    }
+
+    fn delete_fleet(&self) -> Result<DeleteResult, FuberError> {
+        self.delete_cabs("1", [])
+    }
+
+    fn has_cab_of_category(&self, category: CabCategory) -> Result<bool, FuberError> {
+        self.conn()?
+            .query_row(
+                "SELECT EXISTS (SELECT 1 FROM cabs WHERE category = ?1)",
+                params![category.as_str()],
+                |row| row.get(0),
+            )
+            .map_err(sql_error)
+    }
+
+    fn create_ride(&self, new_ride: Ride) -> Result<InsertOneResult, FuberError> {
+        let obj_id = new_ride.id.unwrap_or_default();
+        let fare = fare_json(&new_ride)?;
+        let (pickup_x, pickup_y, pickup_kind) = point_values(&new_ride.pickup);
+        let (drop_x, drop_y, drop_kind) = point_values(&new_ride.drop);
+        let mut conn = self.conn()?;
+        let tx = conn.transaction().map_err(sql_error)?;
+        tx.execute(
+            "INSERT INTO rides
+                (id, person_id, cab_id, pickup_x, pickup_y, drop_x, drop_y, state, fare,
+                pickup_kind, drop_kind, eta_minutes, surge_multiplier)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+            params![
+                obj_id.to_hex(),
+                new_ride.person_id.to_hex(),
+                new_ride.cab_id.map(|x| x.to_hex()),
+                pickup_x,
+                pickup_y,
+                drop_x,
+                drop_y,
+                new_ride.state.as_str(),
+                fare,
+                pickup_kind,
+                drop_kind,
+                new_ride.eta_minutes,
+                new_ride.surge_multiplier,
+            ],
+        )
+        .map_err(sql_error)?;
+        write_transitions(&tx, &new_ride, obj_id).map_err(sql_error)?;
+        tx.commit().map_err(sql_error)?;
+        Ok(InsertOneResult {
+            inserted_id: obj_id,
+        })
+    }
+
+    fn get_ride(&self, id: &str) -> Result<Ride, FuberError> {
+        let obj_id = parse_id(id)?;
+        let mut rides =
+            find_rides(&*self.conn()?, "id = ?1", &obj_id.to_hex()).map_err(sql_error)?;
+        match rides.pop() {
+            Some(ride) => Ok(ride),
+            None => Err(FuberError::ride_not_found(id)),
+        }
+    }
+
+    fn update_ride(&self, ride: Ride, expected: RideState) -> Result<UpdateResult, FuberError> {
+        let obj_id = match ride.id {
+            Some(obj_id) => obj_id,
+            None => {
+                return Err(FuberError::InvalidId(
+                    ErrorCode::InvalidObjectId,
+                    "ObjectId for the ride doesn't exist".into(),
+                ))
+            }
+        };
+        let fare = fare_json(&ride)?;
+        let (pickup_x, pickup_y, pickup_kind) = point_values(&ride.pickup);
+        let (drop_x, drop_y, drop_kind) = point_values(&ride.drop);
+        let mut conn = self.conn()?;
+        let tx = conn.transaction().map_err(sql_error)?;
+        let before = find_rides(&tx, "id = ?1", &obj_id.to_hex())
+            .map_err(sql_error)?
+            .pop();
+        let matched = tx
+            .execute(
+                "UPDATE rides SET cab_id = ?2, pickup_x = ?3, pickup_y = ?4,
+                    drop_x = ?5, drop_y = ?6, state = ?7, fare = ?8, pickup_kind = ?9,
+                    drop_kind = ?10, eta_minutes = ?11, surge_multiplier = ?12
+                    WHERE id = ?1 AND state = ?13",
+                params![
+                    obj_id.to_hex(),
+                    ride.cab_id.map(|x| x.to_hex()),
+                    pickup_x,
+                    pickup_y,
+                    drop_x,
+                    drop_y,
+                    ride.state.as_str(),
+                    fare,
+                    pickup_kind,
+                    drop_kind,
+                    ride.eta_minutes,
+                    ride.surge_multiplier,
+                    expected.as_str(),
+                ],
+            )
+            .map_err(sql_error)?;
+        if matched > 0 {
+            write_transitions(&tx, &ride, obj_id).map_err(sql_error)?;
+            if let Some(event) = Event::of_ride(expected, obj_id, &ride)? {
+                insert_event(&tx, &event).map_err(sql_error)?;
+            }
+        }
+        tx.commit().map_err(sql_error)?;
+        Ok(UpdateResult {
+            matched_count: matched as u64,
+            modified_count: match before {
+                Some(before) if before != ride => 1,
+                _ => 0,
+            },
+        })
+    }
+
+    fn get_rides_of_person(&self, person_id: &str) -> Result<Vec<Ride>, FuberError> {
+        let obj_id = parse_id(person_id)?;
+        find_rides(&*self.conn()?, "person_id = ?1", &obj_id.to_hex()).map_err(sql_error)
+    }
+
+    fn create_ticket(&self, new_ticket: Ticket) -> Result<InsertOneResult, FuberError> {
+        let obj_id = new_ticket.id.unwrap_or_default();
+        self.conn()?
+            .execute(
+                "INSERT INTO tickets
+                    (id, person_id, ride_id, category, state, created_at, cab_id)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    obj_id.to_hex(),
+                    new_ticket.person_id.to_hex(),
+                    new_ticket.ride_id.to_hex(),
+                    new_ticket.category.map(|x| x.as_str()),
+                    new_ticket.state.as_str(),
+                    new_ticket.created_at,
+                    new_ticket.cab_id.map(|x| x.to_hex()),
+                ],
+            )
+            .map_err(sql_error)?;
+        Ok(InsertOneResult {
+            inserted_id: obj_id,
+        })
+    }
+
+    fn get_ticket(&self, id: &str) -> Result<Ticket, FuberError> {
+        let obj_id = parse_id(id)?;
+        self.conn()?
+            .query_row(
+                &format!("{} WHERE id = ?1", TICKET_COLUMNS),
+                params![obj_id.to_hex()],
+                ticket_from_row,
+            )
+            .optional()
+            .map_err(sql_error)?
+            .ok_or_else(|| FuberError::ticket_not_found(id))
+    }
+
+    fn update_ticket(
+        &self,
+        ticket: Ticket,
+        expected: TicketState,
+    ) -> Result<UpdateResult, FuberError> {
+        let obj_id = match ticket.id {
+            Some(obj_id) => obj_id,
+            None => {
+                return Err(FuberError::InvalidId(
+                    ErrorCode::InvalidObjectId,
+                    "ObjectId for the ticket doesn't exist".into(),
+                ))
+            }
+        };
+        let conn = self.conn()?;
+        let before = conn
+            .query_row(
+                &format!("{} WHERE id = ?1", TICKET_COLUMNS),
+                params![obj_id.to_hex()],
+                ticket_from_row,
+            )
+            .optional()
+            .map_err(sql_error)?;
+        // the state check is part of the update, so only one caller can
+        // ever move a ticket out of `expected`
+        let matched = conn
+            .execute(
+                "UPDATE tickets SET person_id = ?2, ride_id = ?3, category = ?4, state = ?5,
+                    created_at = ?6, cab_id = ?7
+                    WHERE id = ?1 AND state = ?8",
+                params![
+                    obj_id.to_hex(),
+                    ticket.person_id.to_hex(),
+                    ticket.ride_id.to_hex(),
+                    ticket.category.map(|x| x.as_str()),
+                    ticket.state.as_str(),
+                    ticket.created_at,
+                    ticket.cab_id.map(|x| x.to_hex()),
+                    expected.as_str(),
+                ],
+            )
+            .map_err(sql_error)?;
+        Ok(UpdateResult {
+            matched_count: matched as u64,
+            modified_count: match before {
+                Some(before) if matched > 0 && before != ticket => 1,
+                _ => 0,
+            },
+        })
+    }
+
+    fn get_waiting_tickets(&self) -> Result<Vec<Ticket>, FuberError> {
+        let conn = self.conn()?;
+        let mut stmt = conn
+            .prepare(&format!(
+                "{} WHERE state = ?1 ORDER BY created_at, rowid",
+                TICKET_COLUMNS
+            ))
+            .map_err(sql_error)?;
+        let tickets = stmt
+            .query_map(params![TicketState::Waiting.as_str()], ticket_from_row)
+            .map_err(sql_error)?
+            .collect::<rusqlite::Result<Vec<Ticket>>>()
+            .map_err(sql_error);
+        tickets
+    }
+
+    fn create_booking(&self, new_booking: Booking) -> Result<InsertOneResult, FuberError> {
+        let obj_id = new_booking.id.unwrap_or_default();
+        let (pickup_x, pickup_y, pickup_kind) = point_values(&new_booking.pickup);
+        let (drop_x, drop_y, drop_kind) = point_values(&new_booking.drop);
+        self.conn()?
+            .execute(
+                "INSERT INTO bookings
+                    (id, person_id, pickup_x, pickup_y, pickup_kind, drop_x, drop_y, drop_kind,
+                    pickup_at, category, state, attempts, ride_id, created_at)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+                params![
+                    obj_id.to_hex(),
+                    new_booking.person_id.to_hex(),
+                    pickup_x,
+                    pickup_y,
+                    pickup_kind,
+                    drop_x,
+                    drop_y,
+                    drop_kind,
+                    new_booking.pickup_at,
+                    new_booking.category.map(|x| x.as_str()),
+                    new_booking.state.as_str(),
+                    new_booking.attempts,
+                    new_booking.ride_id.map(|x| x.to_hex()),
+                    new_booking.created_at,
+                ],
+            )
+            .map_err(sql_error)?;
+        Ok(InsertOneResult {
+            inserted_id: obj_id,
+        })
+    }
+
+    fn get_booking(&self, id: &str) -> Result<Booking, FuberError> {
+        let obj_id = parse_id(id)?;
+        let mut bookings = find_bookings(&*self.conn()?, "id = ?1", params![obj_id.to_hex()])
+            .map_err(sql_error)?;
+        match bookings.pop() {
+            Some(booking) => Ok(booking),
+            None => Err(FuberError::booking_not_found(id)),
+        }
+    }
+
+    fn update_booking(
+        &self,
+        booking: Booking,
+        expected: BookingState,
+    ) -> Result<UpdateResult, FuberError> {
+        let obj_id = match booking.id {
+            Some(obj_id) => obj_id,
+            None => {
+                return Err(FuberError::InvalidId(
+                    ErrorCode::InvalidObjectId,
+                    "ObjectId for the booking doesn't exist".into(),
+                ))
+            }
+        };
+        let (pickup_x, pickup_y, pickup_kind) = point_values(&booking.pickup);
+        let (drop_x, drop_y, drop_kind) = point_values(&booking.drop);
+        let conn = self.conn()?;
+        let before = find_bookings(&conn, "id = ?1", params![obj_id.to_hex()])
+            .map_err(sql_error)?
+            .pop();
+        // the state check is part of the update, the same as with tickets
+        let matched = conn
+            .execute(
+                "UPDATE bookings SET person_id = ?2, pickup_x = ?3, pickup_y = ?4,
+                    pickup_kind = ?5, drop_x = ?6, drop_y = ?7, drop_kind = ?8, pickup_at = ?9,
+                    category = ?10, state = ?11, attempts = ?12, ride_id = ?13, created_at = ?14
+                    WHERE id = ?1 AND state = ?15",
+                params![
+                    obj_id.to_hex(),
+                    booking.person_id.to_hex(),
+                    pickup_x,
+                    pickup_y,
+                    pickup_kind,
+                    drop_x,
+                    drop_y,
+                    drop_kind,
+                    booking.pickup_at,
+                    booking.category.map(|x| x.as_str()),
+                    booking.state.as_str(),
+                    booking.attempts,
+                    booking.ride_id.map(|x| x.to_hex()),
+                    booking.created_at,
+                    expected.as_str(),
+                ],
+            )
+            .map_err(sql_error)?;
+        Ok(UpdateResult {
+            matched_count: matched as u64,
+            modified_count: match before {
+                Some(before) if matched > 0 && before != booking => 1,
+                _ => 0,
+            },
+        })
+    }
+
+    fn get_bookings_of_person(&self, person_id: &str) -> Result<Vec<Booking>, FuberError> {
+        let obj_id = parse_id(person_id)?;
+        find_bookings(&*self.conn()?, "person_id = ?1", params![obj_id.to_hex()]).map_err(sql_error)
+    }
+
+    fn get_upcoming_bookings(&self, after: i64) -> Result<Vec<Booking>, FuberError> {
+        find_bookings(
+            &*self.conn()?,
+            "state != ?1 AND pickup_at > ?2",
+            params![BookingState::Cancelled.as_str(), after],
+        )
+        .map_err(sql_error)
+    }
+
+    fn get_scheduled_bookings(&self, until: i64) -> Result<Vec<Booking>, FuberError> {
+        find_bookings(
+            &*self.conn()?,
+            "state = ?1 AND pickup_at <= ?2",
+            params![BookingState::Scheduled.as_str(), until],
+        )
+        .map_err(sql_error)
+    }
+
+    fn get_events(&self, since: i64, limit: usize) -> Result<Vec<Event>, FuberError> {
+        let conn = self.conn()?;
+        let mut stmt = conn
+            .prepare(&format!(
+                "{} WHERE seq > ?1 ORDER BY seq LIMIT ?2",
+                EVENT_COLUMNS
+            ))
+            .map_err(sql_error)?;
+        let events = stmt
+            .query_map(params![since, limit as i64], event_from_row)
+            .map_err(sql_error)?
+            .collect::<rusqlite::Result<Vec<Event>>>()
+            .map_err(sql_error)?;
+        Ok(events)
+    }
+
+    fn create_webhook(&self, new_webhook: Webhook) -> Result<InsertOneResult, FuberError> {
+        let obj_id = new_webhook.id.unwrap_or_default();
+        self.conn()?
+            .execute(
+                "INSERT INTO webhooks (id, url, event_types, secret, created_at)
+                    VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    obj_id.to_hex(),
+                    new_webhook.url,
+                    json_value(&new_webhook.event_types).map_err(sql_error)?,
+                    new_webhook.secret,
+                    new_webhook.created_at,
+                ],
+            )
+            .map_err(sql_error)?;
+        Ok(InsertOneResult {
+            inserted_id: obj_id,
+        })
+    }
+
+    fn get_webhooks(&self) -> Result<Vec<Webhook>, FuberError> {
+        find_webhooks(&*self.conn()?).map_err(sql_error)
+    }
+
+    // the deliveries go with the webhook through `ON DELETE CASCADE`
+    fn delete_webhook(&self, webhook_id: &str) -> Result<DeleteResult, FuberError> {
+        let obj_id = parse_id(webhook_id)?;
+        let deleted = self
+            .conn()?
+            .execute(
+                "DELETE FROM webhooks WHERE id = ?1",
+                params![obj_id.to_hex()],
+            )
+            .map_err(sql_error)?;
+        Ok(DeleteResult {
+            deleted_count: deleted as u64,
+        })
+    }
+
+    fn get_delivery(&self, id: &str) -> Result<Delivery, FuberError> {
+        let obj_id = parse_id(id)?;
+        find_deliveries(&*self.conn()?, "outbox.id = ?1", params![obj_id.to_hex()])
+            .map_err(sql_error)?
+            .pop()
+            .ok_or_else(|| FuberError::delivery_not_found(id))
+    }
+
+    // the webhook and the event of a delivery never change, only how it
+    // went so far
+    fn update_delivery(
+        &self,
+        delivery: Delivery,
+        expected: DeliveryState,
+    ) -> Result<UpdateResult, FuberError> {
+        let obj_id = match delivery.id {
+            Some(obj_id) => obj_id,
+            None => {
+                return Err(FuberError::InvalidId(
+                    ErrorCode::InvalidObjectId,
+                    "ObjectId for the delivery doesn't exist".into(),
+                ))
+            }
+        };
+        let conn = self.conn()?;
+        let before = find_deliveries(&conn, "outbox.id = ?1", params![obj_id.to_hex()])
+            .map_err(sql_error)?
+            .pop();
+        let matched = conn
+            .execute(
+                "UPDATE outbox SET state = ?2, attempts = ?3, next_attempt_at = ?4,
+                    last_error = ?5
+                    WHERE id = ?1 AND state = ?6",
+                params![
+                    obj_id.to_hex(),
+                    delivery.state.as_str(),
+                    delivery.attempts,
+                    delivery.next_attempt_at,
+                    delivery.last_error,
+                    expected.as_str(),
+                ],
+            )
+            .map_err(sql_error)?;
+        Ok(UpdateResult {
+            matched_count: matched as u64,
+            modified_count: match before {
+                Some(before) if matched > 0 && before != delivery => 1,
+                _ => 0,
+            },
+        })
+    }
+
+    fn get_due_deliveries(&self, now: i64, limit: usize) -> Result<Vec<Delivery>, FuberError> {
+        find_deliveries(
+            &*self.conn()?,
+            "outbox.state = ?1 AND outbox.next_attempt_at <= ?2
+                ORDER BY outbox.next_attempt_at, events.seq LIMIT ?3",
+            params![DeliveryState::Pending.as_str(), now, limit as i64],
+        )
+        .map_err(sql_error)
+    }
+
+    fn get_dead_deliveries(&self) -> Result<Vec<Delivery>, FuberError> {
+        find_deliveries(
+            &*self.conn()?,
+            "outbox.state = ?1 ORDER BY events.seq",
+            params![DeliveryState::Dead.as_str()],
+        )
+        .map_err(sql_error)
+    }
+
+    fn create_api_key(&self, new_api_key: ApiKey) -> Result<InsertOneResult, FuberError> {
+        let obj_id = new_api_key.id.unwrap_or_default();
+        self.conn()?
+            .execute(
+                "INSERT INTO api_keys (id, key_hash, role, subject, created_at)
+                    VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    obj_id.to_hex(),
+                    new_api_key.key_hash,
+                    new_api_key.role.as_str(),
+                    new_api_key.subject.map(|x| x.to_hex()),
+                    new_api_key.created_at,
+                ],
+            )
+            .map_err(sql_error)?;
+        Ok(InsertOneResult {
+            inserted_id: obj_id,
+        })
+    }
+
+    fn get_api_key_by_hash(&self, key_hash: &str) -> Result<ApiKey, FuberError> {
+        self.conn()?
+            .query_row(
+                &format!("{} WHERE key_hash = ?1", API_KEY_COLUMNS),
+                params![key_hash],
+                api_key_from_row,
+            )
+            .optional()
+            .map_err(sql_error)?
+            .ok_or_else(|| FuberError::api_key_not_found(key_hash))
+    }
+
+    fn delete_api_key(&self, api_key_id: &str) -> Result<DeleteResult, FuberError> {
+        let obj_id = parse_id(api_key_id)?;
+        let deleted = self
+            .conn()?
+            .execute(
+                "DELETE FROM api_keys WHERE id = ?1",
+                params![obj_id.to_hex()],
+            )
+            .map_err(sql_error)?;
+        Ok(DeleteResult {
+            deleted_count: deleted as u64,
+        })
+    }
+}
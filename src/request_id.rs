@@ -0,0 +1,48 @@
+use std::fmt;
+
+use mongodb::bson::oid::ObjectId;
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    Request, Response,
+};
+
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+// Id that ties an error body back to the request that caused it.
+// Clients can send their own in `X-Request-Id`, otherwise we make one up,
+// either way it is echoed back in the response header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(String);
+
+impl RequestId {
+    // the id is computed once per request and cached on it
+    pub fn of<'r>(req: &'r Request<'_>) -> &'r RequestId {
+        req.local_cache(|| match req.headers().get_one(REQUEST_ID_HEADER) {
+            Some(id) if !id.is_empty() => RequestId(id.to_string()),
+            _ => RequestId(ObjectId::new().to_hex()),
+        })
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+// attach it to rocket to get the `X-Request-Id` header on every response
+pub struct RequestIdFairing;
+
+#[rocket::async_trait]
+impl Fairing for RequestIdFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request id",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        res.set_raw_header(REQUEST_ID_HEADER, RequestId::of(req).to_string());
+    }
+}
@@ -0,0 +1,170 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::graph::{EdgeRecord, GraphFile, NodeRecord, RoadGraph};
+use crate::models::point_model::Point;
+
+// The binary road graph `fuber preprocess` writes. It loads much faster than
+// an osm extract or json and is a fraction of their size:
+//
+//   magic, number of nodes, number of edges (varints)
+//   per node : id (varint), 0 and x, y (zigzag varints) for a grid point
+//              or 1 and lat, lon (i32, 1e-7 degrees, what osm stores)
+//   per edge : from, to (varints), length_km (f64, NaN without one),
+//              speed_kmh (f64)
+//
+// All fixed size numbers are little endian.
+const MAGIC: &[u8; 8] = b"FUBERRG1";
+
+pub const CACHE_EXTENSION: &str = "fgraph";
+
+const GEO_SCALE: f64 = 1e7;
+
+pub fn is_cache(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+fn put_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn put_signed(out: &mut Vec<u8>, value: i64) {
+    put_varint(out, ((value << 1) ^ (value >> 63)) as u64);
+}
+
+pub fn encode(file: &GraphFile) -> Vec<u8> {
+    let mut out = MAGIC.to_vec();
+    put_varint(&mut out, file.nodes.len() as u64);
+    put_varint(&mut out, file.edges.len() as u64);
+    for node in file.nodes.iter() {
+        put_varint(&mut out, node.id);
+        match node.location {
+            Point::Grid { x, y } => {
+                out.push(0);
+                put_signed(&mut out, x);
+                put_signed(&mut out, y);
+            }
+            Point::Geo { lat, lon } => {
+                out.push(1);
+                out.extend(((lat * GEO_SCALE).round() as i32).to_le_bytes());
+                out.extend(((lon * GEO_SCALE).round() as i32).to_le_bytes());
+            }
+        }
+    }
+    for edge in file.edges.iter() {
+        put_varint(&mut out, edge.from);
+        put_varint(&mut out, edge.to);
+        out.extend(edge.length_km.unwrap_or(f64::NAN).to_le_bytes());
+        out.extend(edge.speed_kmh.to_le_bytes());
+    }
+    out
+}
+
+// reads what `encode` wrote
+struct Cursor<'a>(&'a [u8]);
+
+impl Cursor<'_> {
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        if self.0.len() < N {
+            return Err("the road graph cache is truncated".to_string());
+        }
+        let (head, rest) = self.0.split_at(N);
+        self.0 = rest;
+        Ok(head.try_into().expect("split at N"))
+    }
+
+    fn varint(&mut self) -> Result<u64, String> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let [byte] = self.bytes::<1>()?;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err("the road graph cache has a broken number".to_string())
+    }
+
+    fn signed(&mut self) -> Result<i64, String> {
+        let value = self.varint()?;
+        Ok((value >> 1) as i64 ^ -((value & 1) as i64))
+    }
+
+    fn degrees(&mut self) -> Result<f64, String> {
+        Ok(i32::from_le_bytes(self.bytes()?) as f64 / GEO_SCALE)
+    }
+
+    fn float(&mut self) -> Result<f64, String> {
+        Ok(f64::from_le_bytes(self.bytes()?))
+    }
+}
+
+pub fn decode(bytes: &[u8]) -> Result<GraphFile, String> {
+    if !is_cache(bytes) {
+        return Err("not a road graph cache".to_string());
+    }
+    let mut cursor = Cursor(&bytes[MAGIC.len()..]);
+    let node_count = cursor.varint()? as usize;
+    let edge_count = cursor.varint()? as usize;
+    // every node and edge takes a few bytes at least, so a broken count
+    // can't make us allocate more than the file is large
+    let mut nodes = Vec::with_capacity(node_count.min(bytes.len()));
+    let mut edges = Vec::with_capacity(edge_count.min(bytes.len()));
+    for _ in 0..node_count {
+        let id = cursor.varint()?;
+        let location = match cursor.bytes::<1>()? {
+            [0] => Point::new(cursor.signed()?, cursor.signed()?),
+            [1] => {
+                let lat = cursor.degrees()?;
+                Point::geo(lat, cursor.degrees()?).map_err(|e| e.to_string())?
+            }
+            [kind] => return Err(format!("node {} has an unknown kind {}", id, kind)),
+        };
+        nodes.push(NodeRecord { id, location });
+    }
+    for _ in 0..edge_count {
+        let from = cursor.varint()?;
+        let to = cursor.varint()?;
+        let length_km = cursor.float()?;
+        edges.push(EdgeRecord {
+            from,
+            to,
+            length_km: (!length_km.is_nan()).then_some(length_km),
+            speed_kmh: cursor.float()?,
+        });
+    }
+    if !cursor.0.is_empty() {
+        return Err("the road graph cache has trailing bytes".to_string());
+    }
+    Ok(GraphFile { nodes, edges })
+}
+
+// where `fuber preprocess` puts the cache of `input` when it isn't told,
+// next to the fuber binary and named after the input
+pub fn default_cache_path(input: &str) -> Result<PathBuf, String> {
+    let exe = env::current_exe().map_err(|e| e.to_string())?;
+    let name = Path::new(input)
+        .file_name()
+        .and_then(|x| x.to_str())
+        .ok_or_else(|| format!("{} is not a file", input))?;
+    let stem = [".osm.pbf", ".osm", ".pbf", ".json"]
+        .iter()
+        .find_map(|x| name.strip_suffix(x))
+        .unwrap_or(name);
+    let dir = exe.parent().unwrap_or_else(|| Path::new("."));
+    Ok(dir.join(format!("{}.{}", stem, CACHE_EXTENSION)))
+}
+
+// Reads the road graph in `input`, checks it and writes it to `output` as
+// a cache, the graph is handed back for reporting on it.
+pub fn preprocess(input: &str, output: &Path) -> Result<GraphFile, String> {
+    let file = GraphFile::load(input)?;
+    RoadGraph::from_file(file.clone())?;
+    fs::write(output, encode(&file)).map_err(|e| e.to_string())?;
+    Ok(file)
+}
@@ -0,0 +1,363 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use std::fs;
+
+use serde::{Deserialize, Serialize};
+
+use super::{cache, osm};
+use crate::{
+    models::point_model::Point,
+    spatial::{GridIndex, Located},
+};
+
+// default speed of a road that doesn't say how fast it is
+pub const DEFAULT_ROAD_SPEED_KMH: f64 = 30.0;
+
+fn default_speed() -> f64 {
+    DEFAULT_ROAD_SPEED_KMH
+}
+
+// A road graph as it is stored on disk, e.g.
+//
+//   {
+//     "nodes": [{ "id": 1, "location": { "x": 0, "y": 0 } }, ...],
+//     "edges": [{ "from": 1, "to": 2, "length_km": 1.5, "speed_kmh": 50 }, ...]
+//   }
+//
+// Edges are one way, a street that goes both ways needs an edge each way.
+// Without a length an edge is as long as the straight line between its
+// nodes, without a speed it is driven at `DEFAULT_ROAD_SPEED_KMH`. Osm
+// extracts and the binary cache are read into the same records.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GraphFile {
+    pub nodes: Vec<NodeRecord>,
+    pub edges: Vec<EdgeRecord>,
+}
+
+impl GraphFile {
+    // reads an osm extract by its extension, a cache `fuber preprocess` wrote
+    // by its first bytes and json otherwise
+    pub fn load(path: &str) -> Result<Self, String> {
+        if osm::is_osm(path) {
+            return osm::read_osm(path);
+        }
+        let bytes = fs::read(path).map_err(|e| e.to_string())?;
+        if cache::is_cache(&bytes) {
+            cache::decode(&bytes)
+        } else {
+            serde_json::from_slice(&bytes).map_err(|e| e.to_string())
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NodeRecord {
+    pub id: u64,
+    pub location: Point,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EdgeRecord {
+    pub from: u64,
+    pub to: u64,
+    #[serde(default)]
+    pub length_km: Option<f64>,
+    #[serde(default = "default_speed")]
+    pub speed_kmh: f64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Edge {
+    to: usize,
+    length_km: f64,
+    minutes: f64,
+}
+
+// a node as the snapping index keeps it
+#[derive(Debug, Clone)]
+struct SnapNode {
+    idx: usize,
+    location: Point,
+}
+
+impl Located for SnapNode {
+    type Key = usize;
+
+    fn key(&self) -> usize {
+        self.idx
+    }
+
+    fn location(&self) -> &Point {
+        &self.location
+    }
+}
+
+// the way from one node to another along the roads
+#[derive(Debug, Clone, PartialEq)]
+pub struct Route {
+    pub path: Vec<Point>,
+    pub length_km: f64,
+    pub minutes: f64,
+}
+
+// what the heaps below order by, the smallest cost first
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Queued {
+    cost: f64,
+    node: usize,
+}
+
+impl Eq for Queued {}
+
+impl Ord for Queued {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .cost
+            .total_cmp(&self.cost)
+            .then_with(|| other.node.cmp(&self.node))
+    }
+}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// The roads cabs drive on, a directed graph weighted by travel time.
+// Nodes are kept by their position in `nodes`, the ids of the file are only
+// needed while loading it.
+pub struct RoadGraph {
+    nodes: Vec<Point>,
+    edges: Vec<Vec<Edge>>,
+    // the same edges the other way round, for searching backwards from
+    // where everyone is going
+    reversed: Vec<Vec<Edge>>,
+    snap: GridIndex<SnapNode>,
+    // the fastest road and the least any road is longer than the straight
+    // line between its ends, together they make the A* estimate a lower
+    // bound on the time that is left
+    max_speed_kmh: f64,
+    stretch: f64,
+}
+
+impl RoadGraph {
+    pub fn load(path: &str) -> Result<Self, String> {
+        RoadGraph::from_file(GraphFile::load(path)?)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        let file: GraphFile = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        RoadGraph::from_file(file)
+    }
+
+    pub fn from_file(file: GraphFile) -> Result<Self, String> {
+        let mut ids = HashMap::with_capacity(file.nodes.len());
+        let mut nodes = Vec::with_capacity(file.nodes.len());
+        for node in file.nodes {
+            if ids.insert(node.id, nodes.len()).is_some() {
+                return Err(format!("node {} is there more than once", node.id));
+            }
+            nodes.push(node.location);
+        }
+
+        let mut edges = vec![Vec::new(); nodes.len()];
+        let mut reversed = vec![Vec::new(); nodes.len()];
+        let mut max_speed_kmh: f64 = 0.0;
+        let mut stretch: f64 = 1.0;
+        for edge in file.edges {
+            let (from, to) = match (ids.get(&edge.from), ids.get(&edge.to)) {
+                (Some(from), Some(to)) => (*from, *to),
+                _ => {
+                    return Err(format!(
+                        "edge {} -> {} has a node that doesn't exist",
+                        edge.from, edge.to
+                    ))
+                }
+            };
+            let straight = nodes[from].dist(&nodes[to]);
+            let length_km = edge.length_km.unwrap_or(straight);
+            if !length_km.is_finite() || length_km < 0.0 {
+                return Err(format!(
+                    "edge {} -> {} cannot be {} km long",
+                    edge.from, edge.to, length_km
+                ));
+            }
+            if !edge.speed_kmh.is_finite() || edge.speed_kmh <= 0.0 {
+                return Err(format!(
+                    "edge {} -> {} cannot be driven at {} km/h",
+                    edge.from, edge.to, edge.speed_kmh
+                ));
+            }
+            if straight > 0.0 {
+                stretch = stretch.min(length_km / straight);
+            }
+            max_speed_kmh = max_speed_kmh.max(edge.speed_kmh);
+            let minutes = length_km / edge.speed_kmh * 60.0;
+            edges[from].push(Edge {
+                to,
+                length_km,
+                minutes,
+            });
+            reversed[to].push(Edge {
+                to: from,
+                length_km,
+                minutes,
+            });
+        }
+
+        let mut snap = GridIndex::default();
+        for (idx, location) in nodes.iter().enumerate() {
+            snap.insert(SnapNode {
+                idx,
+                location: location.clone(),
+            });
+        }
+        Ok(RoadGraph {
+            nodes,
+            edges,
+            reversed,
+            snap,
+            max_speed_kmh,
+            stretch,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn max_speed_kmh(&self) -> f64 {
+        self.max_speed_kmh
+    }
+
+    // how much shorter than its road the straight line between two nodes
+    // can be, at most 1
+    pub fn stretch(&self) -> f64 {
+        self.stretch
+    }
+
+    // the node nearest to `point` in a straight line, `None` when the graph
+    // has no node of the same kind as the point
+    pub fn snap(&self, point: &Point) -> Option<(usize, Point)> {
+        self.snap
+            .nearest(point, 1, None, |_| true)
+            .pop()
+            .map(|x| (x.idx, x.location))
+    }
+
+    // A* from the node nearest to `from` to the node nearest to `to`, the
+    // way onto and off the graph is up to the caller
+    pub fn route(&self, from: &Point, to: &Point) -> Option<Route> {
+        let (start, _) = self.snap(from)?;
+        let (goal, goal_location) = self.snap(to)?;
+        // minutes it takes at least to cover the straight line to the goal,
+        // it never drops by more than an edge takes so a node is done the
+        // first time it comes off the heap
+        let estimate = |node: usize| {
+            if self.max_speed_kmh > 0.0 {
+                self.nodes[node].dist(&goal_location) * self.stretch / self.max_speed_kmh * 60.0
+            } else {
+                0.0
+            }
+        };
+
+        let mut minutes = vec![f64::INFINITY; self.nodes.len()];
+        let mut previous: Vec<Option<(usize, f64)>> = vec![None; self.nodes.len()];
+        let mut settled = vec![false; self.nodes.len()];
+        let mut heap = BinaryHeap::new();
+        minutes[start] = 0.0;
+        heap.push(Queued {
+            cost: estimate(start),
+            node: start,
+        });
+        while let Some(Queued { node, .. }) = heap.pop() {
+            if node == goal {
+                break;
+            }
+            if settled[node] {
+                continue;
+            }
+            settled[node] = true;
+            for edge in self.edges[node].iter() {
+                let next = minutes[node] + edge.minutes;
+                if next < minutes[edge.to] {
+                    minutes[edge.to] = next;
+                    previous[edge.to] = Some((node, edge.length_km));
+                    heap.push(Queued {
+                        cost: next + estimate(edge.to),
+                        node: edge.to,
+                    });
+                }
+            }
+        }
+        if !minutes[goal].is_finite() {
+            return None;
+        }
+
+        let mut path = vec![self.nodes[goal].clone()];
+        let mut length_km = 0.0;
+        let mut node = goal;
+        while let Some((before, length)) = previous[node] {
+            path.push(self.nodes[before].clone());
+            length_km += length;
+            node = before;
+        }
+        path.reverse();
+        Some(Route {
+            path,
+            length_km,
+            minutes: minutes[goal],
+        })
+    }
+
+    // Minutes from every node in `sources` to `target` along the roads, one
+    // Dijkstra backwards from the target instead of a search per source.
+    // Sources that can't get there are infinitely far.
+    pub fn minutes_to(&self, sources: &[usize], target: usize) -> Vec<f64> {
+        let mut minutes = vec![f64::INFINITY; self.nodes.len()];
+        let mut settled = vec![false; self.nodes.len()];
+        let mut wanted = vec![false; self.nodes.len()];
+        let mut missing = 0;
+        for source in sources {
+            if !wanted[*source] {
+                wanted[*source] = true;
+                missing += 1;
+            }
+        }
+        let mut heap = BinaryHeap::new();
+        minutes[target] = 0.0;
+        heap.push(Queued {
+            cost: 0.0,
+            node: target,
+        });
+        while let Some(Queued { cost, node }) = heap.pop() {
+            if settled[node] {
+                continue;
+            }
+            settled[node] = true;
+            if wanted[node] {
+                wanted[node] = false;
+                missing -= 1;
+                if missing == 0 {
+                    break;
+                }
+            }
+            for edge in self.reversed[node].iter() {
+                let next = cost + edge.minutes;
+                if next < minutes[edge.to] {
+                    minutes[edge.to] = next;
+                    heap.push(Queued {
+                        cost: next,
+                        node: edge.to,
+                    });
+                }
+            }
+        }
+        sources.iter().map(|x| minutes[*x]).collect()
+    }
+}
@@ -0,0 +1,594 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read};
+
+use flate2::read::ZlibDecoder;
+use quick_xml::events::{BytesStart, Event};
+
+use super::graph::{EdgeRecord, GraphFile, NodeRecord};
+use crate::models::point_model::Point;
+
+// How fast a road is driven when it has no `maxspeed`, by its `highway`
+// tag. Ways with any other `highway` aren't for cars and are left out.
+pub const HIGHWAY_SPEEDS_KMH: [(&str, f64); 15] = [
+    ("motorway", 100.0),
+    ("motorway_link", 60.0),
+    ("trunk", 80.0),
+    ("trunk_link", 50.0),
+    ("primary", 60.0),
+    ("primary_link", 40.0),
+    ("secondary", 50.0),
+    ("secondary_link", 40.0),
+    ("tertiary", 40.0),
+    ("tertiary_link", 30.0),
+    ("unclassified", 30.0),
+    ("road", 30.0),
+    ("residential", 25.0),
+    ("service", 15.0),
+    ("living_street", 10.0),
+];
+
+const KM_PER_MILE: f64 = 1.609344;
+
+// the ways osm files come in, by their extension
+pub fn is_osm(path: &str) -> bool {
+    path.ends_with(".osm") || path.ends_with(".pbf")
+}
+
+// Reads the roads of an `.osm` or `.osm.pbf` extract. Every node of a
+// drivable way becomes a node of the graph, numbered from 0 in the order
+// they are first met, and every pair of nodes next to each other on the way
+// an edge as long as the straight line between them. Ways that leave the
+// extract are cut where their nodes are missing.
+pub fn read_osm(path: &str) -> Result<GraphFile, String> {
+    let file = BufReader::new(File::open(path).map_err(|e| e.to_string())?);
+    let extract = if path.ends_with(".pbf") {
+        read_pbf(file)?
+    } else {
+        read_xml(file)?
+    };
+    extract.into_graph()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Oneway {
+    No,
+    Forward,
+    Backward,
+}
+
+// a way cabs can drive on
+struct Road {
+    refs: Vec<i64>,
+    speed_kmh: f64,
+    oneway: Oneway,
+}
+
+// The speed and direction of a way with `tags`, `None` when cabs can't
+// drive it.
+fn road(tags: &HashMap<String, String>) -> Option<(f64, Oneway)> {
+    let tag = |key: &str| tags.get(key).map(String::as_str);
+    let highway = tag("highway")?;
+    let default_speed = HIGHWAY_SPEEDS_KMH
+        .iter()
+        .find(|(name, _)| *name == highway)
+        .map(|(_, speed)| *speed)?;
+    let denied = |key: &str| matches!(tag(key), Some("no") | Some("private"));
+    if denied("access")
+        || denied("motor_vehicle")
+        || denied("motorcar")
+        || tag("area") == Some("yes")
+    {
+        return None;
+    }
+
+    let oneway = match tag("oneway") {
+        Some("yes") | Some("true") | Some("1") => Oneway::Forward,
+        Some("-1") | Some("reverse") => Oneway::Backward,
+        Some("no") | Some("false") | Some("0") => Oneway::No,
+        // the direction changes during the day, we can't know which it is
+        Some("reversible") | Some("alternating") => return None,
+        _ if matches!(highway, "motorway" | "motorway_link")
+            || matches!(tag("junction"), Some("roundabout") | Some("circular")) =>
+        {
+            Oneway::Forward
+        }
+        _ => Oneway::No,
+    };
+    let speed_kmh = tag("maxspeed")
+        .and_then(parse_maxspeed)
+        .unwrap_or(default_speed);
+    Some((speed_kmh, oneway))
+}
+
+// `50`, `50 km/h` or `30 mph`, anything else like `none` or `DE:urban`
+// gets the default speed of the road
+fn parse_maxspeed(maxspeed: &str) -> Option<f64> {
+    let number = maxspeed
+        .trim()
+        .split(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .next()?;
+    let speed = number.parse::<f64>().ok()?;
+    let speed = if maxspeed.contains("mph") {
+        speed * KM_PER_MILE
+    } else {
+        speed
+    };
+    (speed.is_finite() && speed > 0.0).then_some(speed)
+}
+
+// what is kept of an extract while reading it, the nodes are only known to
+// be on a road once all the ways are read
+#[derive(Default)]
+struct Extract {
+    nodes: HashMap<i64, (f64, f64)>,
+    roads: Vec<Road>,
+}
+
+impl Extract {
+    fn add_way(&mut self, refs: Vec<i64>, tags: &HashMap<String, String>) {
+        if let Some((speed_kmh, oneway)) = road(tags) {
+            self.roads.push(Road {
+                refs,
+                speed_kmh,
+                oneway,
+            });
+        }
+    }
+
+    fn into_graph(self) -> Result<GraphFile, String> {
+        let mut ids: HashMap<i64, u64> = HashMap::new();
+        let mut nodes = Vec::new();
+        let mut edges = Vec::new();
+        for road in self.roads {
+            let mut previous: Option<u64> = None;
+            for osm_id in road.refs {
+                let (lat, lon) = match self.nodes.get(&osm_id) {
+                    Some(position) => *position,
+                    None => {
+                        previous = None;
+                        continue;
+                    }
+                };
+                let id = match ids.get(&osm_id) {
+                    Some(id) => *id,
+                    None => {
+                        let id = nodes.len() as u64;
+                        let location =
+                            Point::geo(lat, lon).map_err(|e| format!("node {}: {}", osm_id, e))?;
+                        nodes.push(NodeRecord { id, location });
+                        ids.insert(osm_id, id);
+                        id
+                    }
+                };
+                if let Some(from) = previous {
+                    let mut add = |from: u64, to: u64| {
+                        edges.push(EdgeRecord {
+                            from,
+                            to,
+                            length_km: None,
+                            speed_kmh: road.speed_kmh,
+                        })
+                    };
+                    if road.oneway != Oneway::Backward {
+                        add(from, id);
+                    }
+                    if road.oneway != Oneway::Forward {
+                        add(id, from);
+                    }
+                }
+                previous = Some(id);
+            }
+        }
+        Ok(GraphFile { nodes, edges })
+    }
+}
+
+fn xml_attributes(element: &BytesStart) -> Result<HashMap<String, String>, String> {
+    let mut attributes = HashMap::new();
+    for attribute in element.attributes() {
+        let attribute = attribute.map_err(|e| e.to_string())?;
+        let key = String::from_utf8_lossy(attribute.key.as_ref()).into_owned();
+        let value = attribute.unescape_value().map_err(|e| e.to_string())?;
+        attributes.insert(key, value.into_owned());
+    }
+    Ok(attributes)
+}
+
+fn xml_number<T: std::str::FromStr>(
+    attributes: &HashMap<String, String>,
+    name: &str,
+) -> Result<T, String> {
+    attributes
+        .get(name)
+        .and_then(|x| x.parse::<T>().ok())
+        .ok_or_else(|| format!("missing or invalid {} attribute", name))
+}
+
+fn read_xml<R: std::io::BufRead>(input: R) -> Result<Extract, String> {
+    let mut reader = quick_xml::Reader::from_reader(input);
+    let mut extract = Extract::default();
+    let mut buf = Vec::new();
+    // the refs and tags of the way that is being read
+    let mut way: Option<(Vec<i64>, HashMap<String, String>)> = None;
+    loop {
+        let event = reader
+            .read_event_into(&mut buf)
+            .map_err(|e| format!("at byte {}: {}", reader.buffer_position(), e))?;
+        match event {
+            Event::Start(ref element) | Event::Empty(ref element) => {
+                match element.name().as_ref() {
+                    b"node" => {
+                        let attributes = xml_attributes(element)?;
+                        extract.nodes.insert(
+                            xml_number(&attributes, "id")?,
+                            (
+                                xml_number(&attributes, "lat")?,
+                                xml_number(&attributes, "lon")?,
+                            ),
+                        );
+                    }
+                    b"way" if matches!(event, Event::Start(_)) => {
+                        way = Some((Vec::new(), HashMap::new()));
+                    }
+                    b"nd" => {
+                        if let Some((refs, _)) = way.as_mut() {
+                            refs.push(xml_number(&xml_attributes(element)?, "ref")?);
+                        }
+                    }
+                    b"tag" => {
+                        if let Some((_, tags)) = way.as_mut() {
+                            let mut attributes = xml_attributes(element)?;
+                            if let (Some(k), Some(v)) =
+                                (attributes.remove("k"), attributes.remove("v"))
+                            {
+                                tags.insert(k, v);
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Event::End(ref element) if element.name().as_ref() == b"way" => {
+                if let Some((refs, tags)) = way.take() {
+                    extract.add_way(refs, &tags);
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    Ok(extract)
+}
+
+// The pbf format is a sequence of blobs, each a protocol buffer message
+// that is usually zlib compressed, see
+// https://wiki.openstreetmap.org/wiki/PBF_Format. Only the few messages
+// and fields the roads need are decoded here.
+
+// the largest blob header and blob the format allows
+const MAX_HEADER_SIZE: usize = 64 * 1024;
+const MAX_BLOB_SIZE: usize = 32 * 1024 * 1024;
+
+const SUPPORTED_FEATURES: [&str; 2] = ["OsmSchema-V0.6", "DenseNodes"];
+
+enum Field<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    // fixed size numbers, nothing we read uses them
+    Fixed,
+}
+
+fn varint(buf: &mut &[u8]) -> Result<u64, String> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (byte, rest) = buf.split_first().ok_or("truncated varint")?;
+        *buf = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err("varint is too long".to_string())
+}
+
+fn zigzag(value: u64) -> i64 {
+    (value >> 1) as i64 ^ -((value & 1) as i64)
+}
+
+fn take<'a>(buf: &mut &'a [u8], len: usize) -> Result<&'a [u8], String> {
+    if buf.len() < len {
+        return Err("truncated message".to_string());
+    }
+    let (head, rest) = buf.split_at(len);
+    *buf = rest;
+    Ok(head)
+}
+
+// the fields of a protocol buffer message, in the order they are stored
+struct Message<'a>(&'a [u8]);
+
+impl<'a> Iterator for Message<'a> {
+    type Item = Result<(u64, Field<'a>), String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.0.is_empty() {
+            return None;
+        }
+        let mut field = || {
+            let key = varint(&mut self.0)?;
+            let value = match key & 7 {
+                0 => Field::Varint(varint(&mut self.0)?),
+                1 => {
+                    take(&mut self.0, 8)?;
+                    Field::Fixed
+                }
+                2 => {
+                    let len = varint(&mut self.0)? as usize;
+                    Field::Bytes(take(&mut self.0, len)?)
+                }
+                5 => {
+                    take(&mut self.0, 4)?;
+                    Field::Fixed
+                }
+                wire_type => return Err(format!("unknown wire type {}", wire_type)),
+            };
+            Ok((key >> 3, value))
+        };
+        let result = field();
+        if result.is_err() {
+            // nothing after a broken field can be read
+            self.0 = &[];
+        }
+        Some(result)
+    }
+}
+
+// repeated numbers, which are usually packed but don't have to be
+fn packed(field: Field) -> Result<Vec<u64>, String> {
+    match field {
+        Field::Varint(value) => Ok(vec![value]),
+        Field::Bytes(mut buf) => {
+            let mut values = Vec::new();
+            while !buf.is_empty() {
+                values.push(varint(&mut buf)?);
+            }
+            Ok(values)
+        }
+        Field::Fixed => Err("expected packed varints".to_string()),
+    }
+}
+
+// numbers that are each stored as the difference to the one before
+fn deltas(values: Vec<u64>) -> Vec<i64> {
+    let mut last = 0i64;
+    values
+        .into_iter()
+        .map(|x| {
+            last = last.wrapping_add(zigzag(x));
+            last
+        })
+        .collect()
+}
+
+fn bytes(field: Field<'_>) -> Result<&[u8], String> {
+    match field {
+        Field::Bytes(buf) => Ok(buf),
+        _ => Err("expected a length delimited field".to_string()),
+    }
+}
+
+fn number(field: Field) -> Result<u64, String> {
+    match field {
+        Field::Varint(value) => Ok(value),
+        _ => Err("expected a varint".to_string()),
+    }
+}
+
+// reads the next blob, `None` at the end of the file
+fn read_blob<R: Read>(input: &mut R) -> Result<Option<(String, Vec<u8>)>, String> {
+    let mut len = [0u8; 4];
+    match input.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.to_string()),
+    }
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_HEADER_SIZE {
+        return Err(format!("blob header of {} bytes is too large", len));
+    }
+    let mut header = vec![0u8; len];
+    input.read_exact(&mut header).map_err(|e| e.to_string())?;
+
+    let mut kind = String::new();
+    let mut size = 0;
+    for field in Message(&header) {
+        match field? {
+            (1, value) => kind = String::from_utf8_lossy(bytes(value)?).into_owned(),
+            (3, value) => size = number(value)? as usize,
+            _ => {}
+        }
+    }
+    if size > MAX_BLOB_SIZE {
+        return Err(format!("blob of {} bytes is too large", size));
+    }
+    let mut blob = vec![0u8; size];
+    input.read_exact(&mut blob).map_err(|e| e.to_string())?;
+
+    let mut raw_size = 0;
+    let mut data = None;
+    for field in Message(&blob) {
+        match field? {
+            (1, value) => data = Some(bytes(value)?.to_vec()),
+            (2, value) => raw_size = number(value)? as usize,
+            (3, value) => {
+                let mut inflated = Vec::with_capacity(raw_size.min(MAX_BLOB_SIZE));
+                ZlibDecoder::new(bytes(value)?)
+                    .take(MAX_BLOB_SIZE as u64)
+                    .read_to_end(&mut inflated)
+                    .map_err(|e| e.to_string())?;
+                data = Some(inflated);
+            }
+            (4..=7, _) => {
+                return Err("only raw and zlib compressed blobs are supported".to_string())
+            }
+            _ => {}
+        }
+    }
+    match data {
+        Some(data) => Ok(Some((kind, data))),
+        None => Err(format!("{} blob has no data", kind)),
+    }
+}
+
+fn read_pbf<R: Read>(mut input: R) -> Result<Extract, String> {
+    let mut extract = Extract::default();
+    while let Some((kind, data)) = read_blob(&mut input)? {
+        match kind.as_str() {
+            "OSMHeader" => {
+                for field in Message(&data) {
+                    if let (4, value) = field? {
+                        let feature = String::from_utf8_lossy(bytes(value)?).into_owned();
+                        if !SUPPORTED_FEATURES.contains(&feature.as_str()) {
+                            return Err(format!("the extract needs {}", feature));
+                        }
+                    }
+                }
+            }
+            "OSMData" => read_primitive_block(&data, &mut extract)?,
+            // blobs of unknown kinds are meant to be skipped
+            _ => {}
+        }
+    }
+    Ok(extract)
+}
+
+// where the nodes of a block are, in nanodegrees
+struct Positions {
+    granularity: i64,
+    lat_offset: i64,
+    lon_offset: i64,
+}
+
+impl Positions {
+    // a block can put its nodes anywhere, those that don't fit are an error
+    // rather than somewhere else
+    fn degrees(&self, lat: i64, lon: i64) -> Result<(f64, f64), String> {
+        let degrees = |offset: i64, units: i64| {
+            self.granularity
+                .checked_mul(units)
+                .and_then(|x| x.checked_add(offset))
+                .map(|x| x as f64 / 1e9)
+                .ok_or_else(|| format!("position {} is out of range", units))
+        };
+        Ok((
+            degrees(self.lat_offset, lat)?,
+            degrees(self.lon_offset, lon)?,
+        ))
+    }
+}
+
+fn read_primitive_block(data: &[u8], extract: &mut Extract) -> Result<(), String> {
+    let mut strings = Vec::new();
+    let mut groups = Vec::new();
+    let mut positions = Positions {
+        granularity: 100,
+        lat_offset: 0,
+        lon_offset: 0,
+    };
+    for field in Message(data) {
+        match field? {
+            (1, value) => {
+                for string in Message(bytes(value)?) {
+                    if let (1, value) = string? {
+                        strings.push(String::from_utf8_lossy(bytes(value)?).into_owned());
+                    }
+                }
+            }
+            (2, value) => groups.push(bytes(value)?),
+            (17, value) => positions.granularity = number(value)? as i64,
+            (19, value) => positions.lat_offset = number(value)? as i64,
+            (20, value) => positions.lon_offset = number(value)? as i64,
+            _ => {}
+        }
+    }
+    if positions.granularity <= 0 {
+        return Err(format!(
+            "granularity {} is not positive",
+            positions.granularity
+        ));
+    }
+
+    let string = |idx: u64| {
+        strings
+            .get(idx as usize)
+            .cloned()
+            .ok_or_else(|| format!("string {} is not in the block", idx))
+    };
+    for group in groups {
+        for field in Message(group) {
+            match field? {
+                (1, value) => read_node(bytes(value)?, &positions, extract)?,
+                (2, value) => read_dense_nodes(bytes(value)?, &positions, extract)?,
+                (3, value) => {
+                    let mut keys = Vec::new();
+                    let mut values = Vec::new();
+                    let mut refs = Vec::new();
+                    for field in Message(bytes(value)?) {
+                        match field? {
+                            (2, value) => keys.extend(packed(value)?),
+                            (3, value) => values.extend(packed(value)?),
+                            (8, value) => refs.extend(packed(value)?),
+                            _ => {}
+                        }
+                    }
+                    let mut tags = HashMap::with_capacity(keys.len());
+                    for (key, value) in keys.into_iter().zip(values) {
+                        tags.insert(string(key)?, string(value)?);
+                    }
+                    extract.add_way(deltas(refs), &tags);
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_node(data: &[u8], positions: &Positions, extract: &mut Extract) -> Result<(), String> {
+    let (mut id, mut lat, mut lon) = (0, 0, 0);
+    for field in Message(data) {
+        match field? {
+            (1, value) => id = zigzag(number(value)?),
+            (8, value) => lat = zigzag(number(value)?),
+            (9, value) => lon = zigzag(number(value)?),
+            _ => {}
+        }
+    }
+    extract.nodes.insert(id, positions.degrees(lat, lon)?);
+    Ok(())
+}
+
+fn read_dense_nodes(
+    data: &[u8],
+    positions: &Positions,
+    extract: &mut Extract,
+) -> Result<(), String> {
+    let (mut ids, mut lats, mut lons) = (Vec::new(), Vec::new(), Vec::new());
+    for field in Message(data) {
+        match field? {
+            (1, value) => ids.extend(packed(value)?),
+            (8, value) => lats.extend(packed(value)?),
+            (9, value) => lons.extend(packed(value)?),
+            _ => {}
+        }
+    }
+    if ids.len() != lats.len() || ids.len() != lons.len() {
+        return Err("dense nodes don't have a position for every id".to_string());
+    }
+    for ((id, lat), lon) in deltas(ids).into_iter().zip(deltas(lats)).zip(deltas(lons)) {
+        extract.nodes.insert(id, positions.degrees(lat, lon)?);
+    }
+    Ok(())
+}
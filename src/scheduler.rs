@@ -0,0 +1,158 @@
+use std::env;
+use std::time::Duration;
+
+use dotenv::dotenv;
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    tokio::{select, task, time},
+    Orbit, Rocket,
+};
+
+use crate::{
+    api::booking_api::dispatch_due_bookings, metric::Travel, models::ride_model::now_millis,
+    repository::shared_repo::SharedRepo,
+};
+
+pub const DEFAULT_LEAD_MINUTES: u64 = 15;
+pub const DEFAULT_TICK_MS: u64 = 10_000;
+pub const DEFAULT_RADIUS: f64 = 5.0;
+pub const DEFAULT_MAX_RADIUS: f64 = 50.0;
+
+// When booked rides get their cab. `lead` before the pickup the scheduler
+// starts looking for a free cab within `radius` of it, and every `tick`
+// that it doesn't find one it looks twice as far, up to `max_radius`. A
+// booking still without a cab at its pickup time is served like any ride
+// request, by the cheapest free cab there is or the queue.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schedule {
+    lead: Duration,
+    tick: Duration,
+    radius: f64,
+    max_radius: f64,
+}
+
+impl Default for Schedule {
+    fn default() -> Self {
+        Schedule::new(
+            Duration::from_secs(DEFAULT_LEAD_MINUTES * 60),
+            Duration::from_millis(DEFAULT_TICK_MS),
+            DEFAULT_RADIUS,
+            DEFAULT_MAX_RADIUS,
+        )
+    }
+}
+
+pub(crate) fn env_or<T: std::str::FromStr>(
+    name: &str,
+    default: T,
+    valid: impl Fn(&T) -> bool,
+) -> T {
+    match env::var(name) {
+        Ok(value) => match value.parse::<T>() {
+            Ok(parsed) if valid(&parsed) => parsed,
+            _ => panic!("{} cannot be {}", name, value),
+        },
+        Err(_) => default,
+    }
+}
+
+impl Schedule {
+    pub fn new(lead: Duration, tick: Duration, radius: f64, max_radius: f64) -> Self {
+        Schedule {
+            lead,
+            tick,
+            radius,
+            max_radius: max_radius.max(radius),
+        }
+    }
+
+    // reads `FUBER_BOOKING_LEAD_MINUTES`, `FUBER_SCHEDULER_TICK_MS`,
+    // `FUBER_BOOKING_RADIUS` and `FUBER_BOOKING_MAX_RADIUS`, the defaults
+    // above for the ones that aren't set
+    pub fn init() -> Self {
+        dotenv().ok();
+        let lead = env_or("FUBER_BOOKING_LEAD_MINUTES", DEFAULT_LEAD_MINUTES, |_| true);
+        let tick = env_or("FUBER_SCHEDULER_TICK_MS", DEFAULT_TICK_MS, |x| *x > 0);
+        let positive = |x: &f64| x.is_finite() && *x > 0.0;
+        let radius = env_or("FUBER_BOOKING_RADIUS", DEFAULT_RADIUS, positive);
+        let max_radius = env_or("FUBER_BOOKING_MAX_RADIUS", DEFAULT_MAX_RADIUS, positive);
+        Schedule::new(
+            Duration::from_secs(lead * 60),
+            Duration::from_millis(tick),
+            radius,
+            max_radius,
+        )
+    }
+
+    pub fn lead(&self) -> Duration {
+        self.lead
+    }
+
+    pub fn tick(&self) -> Duration {
+        self.tick
+    }
+
+    // how far from the pickup a cab can be after `attempts` searches that
+    // came up empty
+    pub fn radius(&self, attempts: u32) -> f64 {
+        let radius = self.radius * 2f64.powi(attempts.min(64) as i32);
+        radius.min(self.max_radius)
+    }
+}
+
+// Attach it to rocket to dispatch the booked rides in the background. It
+// needs the same repo the handlers use, which rocket can't hand out once it
+// manages it, so it gets its own handle to it.
+pub struct BookingScheduler {
+    db: SharedRepo,
+    travel: Travel,
+    schedule: Schedule,
+}
+
+impl BookingScheduler {
+    pub fn new(db: SharedRepo, travel: Travel, schedule: Schedule) -> Self {
+        BookingScheduler {
+            db,
+            travel,
+            schedule,
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for BookingScheduler {
+    fn info(&self) -> Info {
+        Info {
+            name: "Booking scheduler",
+            kind: Kind::Liftoff,
+        }
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let db = self.db.clone();
+        let travel = self.travel.clone();
+        let schedule = self.schedule.clone();
+        let mut shutdown = rocket.shutdown();
+        rocket::tokio::spawn(async move {
+            let mut ticks = time::interval(schedule.tick());
+            loop {
+                select! {
+                    _ = ticks.tick() => {}
+                    _ = &mut shutdown => break,
+                }
+                let (db, travel, schedule) = (db.clone(), travel.clone(), schedule.clone());
+                // the repos block, so the bookings are dispatched off the
+                // async workers
+                let dispatched = task::spawn_blocking(move || {
+                    dispatch_due_bookings(db.repo(), &travel, &schedule, now_millis())
+                })
+                .await;
+                match dispatched {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => eprintln!("unable to dispatch the bookings: {:?}", e),
+                    Err(e) => eprintln!("the booking scheduler failed: {}", e),
+                }
+            }
+        });
+    }
+}
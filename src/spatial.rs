@@ -0,0 +1,326 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use mongodb::bson::oid::ObjectId;
+
+use crate::models::{
+    cab_model::CabCategory,
+    point_model::{Point, EARTH_RADIUS_KM},
+};
+
+// Anything the index can hold, it only needs to know where the thing is
+// and a key to find it by. Keys also break ties between things at the same
+// distance.
+pub trait Located: Clone {
+    type Key: Copy + Eq + Hash + Ord;
+
+    fn key(&self) -> Self::Key;
+
+    fn location(&self) -> &Point;
+}
+
+// what the index keeps for every free cab, enough to answer a query
+// without going to the repository
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexedCab {
+    pub id: ObjectId,
+    pub location: Point,
+    pub category: CabCategory,
+}
+
+impl Located for IndexedCab {
+    type Key = ObjectId;
+
+    fn key(&self) -> ObjectId {
+        self.id
+    }
+
+    fn location(&self) -> &Point {
+        &self.location
+    }
+}
+
+type Cell = (i64, i64);
+
+// the occupied cells of one kind of point
+struct Layer<K> {
+    cells: HashMap<Cell, Vec<K>>,
+    // bounding box of every cell that was ever occupied, rings past it
+    // can't have anything in them
+    bounds: Option<(Cell, Cell)>,
+}
+
+impl<K> Default for Layer<K> {
+    fn default() -> Self {
+        Layer {
+            cells: HashMap::new(),
+            bounds: None,
+        }
+    }
+}
+
+impl<K: Copy + Eq> Layer<K> {
+    fn add(&mut self, cell: Cell, id: K) {
+        self.cells.entry(cell).or_default().push(id);
+        self.bounds = match self.bounds {
+            Some((lo, hi)) => Some((
+                (lo.0.min(cell.0), lo.1.min(cell.1)),
+                (hi.0.max(cell.0), hi.1.max(cell.1)),
+            )),
+            None => Some((cell, cell)),
+        };
+    }
+
+    fn remove(&mut self, cell: Cell, id: &K) {
+        if let Some(ids) = self.cells.get_mut(&cell) {
+            ids.retain(|x| x != id);
+            if ids.is_empty() {
+                self.cells.remove(&cell);
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        self.cells.clear();
+        self.bounds = None;
+    }
+
+    // how many rings it takes from `center` to cover every occupied cell
+    fn last_ring(&self, center: Cell) -> i64 {
+        match self.bounds {
+            Some((lo, hi)) => [
+                center.0 - lo.0,
+                hi.0 - center.0,
+                center.1 - lo.1,
+                hi.1 - center.1,
+            ]
+            .into_iter()
+            .max()
+            .unwrap_or(0)
+            .max(0),
+            None => -1,
+        }
+    }
+}
+
+// Uniform grid over the locations of free cabs, or of anything else that is
+// `Located`. Every cab sits in the square cell its location falls in, a
+// query starts in the cell of the point and walks outwards ring by ring until
+// nothing in a further ring can be closer than what it already found.
+// Grid points and gps positions are never near each other, they live in
+// separate layers and a query only walks the layer of its own kind. Cells of
+// the grid layer are `cell_size` units wide, the ones of the gps layer
+// `GEO_CELL_DEGREES` of latitude by as many of longitude.
+pub struct GridIndex<T: Located = IndexedCab> {
+    cell_size: i64,
+    grid: Layer<T::Key>,
+    geo: Layer<T::Key>,
+    cabs: HashMap<T::Key, T>,
+}
+
+pub const DEFAULT_CELL_SIZE: i64 = 16;
+
+// about 1.1 km north to south, a little less east to west
+pub const GEO_CELL_DEGREES: f64 = 0.01;
+
+impl<T: Located> Default for GridIndex<T> {
+    fn default() -> Self {
+        GridIndex::new(DEFAULT_CELL_SIZE)
+    }
+}
+
+impl<T: Located> GridIndex<T> {
+    pub fn new(cell_size: i64) -> Self {
+        GridIndex {
+            cell_size: cell_size.max(1),
+            grid: Layer::default(),
+            geo: Layer::default(),
+            cabs: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.cabs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cabs.is_empty()
+    }
+
+    pub fn contains(&self, id: &T::Key) -> bool {
+        self.cabs.contains_key(id)
+    }
+
+    pub fn clear(&mut self) {
+        self.grid.clear();
+        self.geo.clear();
+        self.cabs.clear();
+    }
+
+    fn layer(&self, p: &Point) -> &Layer<T::Key> {
+        match p {
+            Point::Grid { .. } => &self.grid,
+            Point::Geo { .. } => &self.geo,
+        }
+    }
+
+    fn layer_mut(&mut self, p: &Point) -> &mut Layer<T::Key> {
+        match p {
+            Point::Grid { .. } => &mut self.grid,
+            Point::Geo { .. } => &mut self.geo,
+        }
+    }
+
+    fn cell_of(&self, p: &Point) -> Cell {
+        match p {
+            Point::Grid { x, y } => (x.div_euclid(self.cell_size), y.div_euclid(self.cell_size)),
+            Point::Geo { lat, lon } => (
+                (lon / GEO_CELL_DEGREES).floor() as i64,
+                (lat / GEO_CELL_DEGREES).floor() as i64,
+            ),
+        }
+    }
+
+    // Nothing outside the first `ring` rings around the cell of `point` is
+    // closer than this. On the grid that is `ring` whole cells. For gps
+    // positions it is `ring` cells of latitude, or as many of longitude
+    // which shrink towards the poles, so the latitude furthest from the
+    // equator the next ring reaches is what counts. The walk doesn't wrap
+    // around the antimeridian, cabs on the other side of it are only found
+    // once the rings have reached them.
+    fn beyond_ring(&self, point: &Point, ring: i64) -> f64 {
+        match point {
+            Point::Grid { .. } => (ring * self.cell_size) as f64,
+            Point::Geo { lat, .. } => {
+                let degrees = ring as f64 * GEO_CELL_DEGREES;
+                let by_lat = EARTH_RADIUS_KM * degrees.to_radians();
+                let max_lat = (lat.abs() + (ring + 2) as f64 * GEO_CELL_DEGREES).min(90.0);
+                let half_lon = (degrees.min(180.0) / 2.0).to_radians();
+                let by_lon = 2.0
+                    * EARTH_RADIUS_KM
+                    * (max_lat.to_radians().cos() * half_lon.sin())
+                        .clamp(0.0, 1.0)
+                        .asin();
+                by_lat.min(by_lon)
+            }
+        }
+    }
+
+    // adds the cab or moves it if it is already indexed
+    pub fn insert(&mut self, cab: T) {
+        let id = cab.key();
+        self.remove(&id);
+        let cell = self.cell_of(cab.location());
+        self.layer_mut(cab.location()).add(cell, id);
+        self.cabs.insert(id, cab);
+    }
+
+    pub fn remove(&mut self, id: &T::Key) -> Option<T> {
+        let cab = self.cabs.remove(id)?;
+        let cell = self.cell_of(cab.location());
+        self.layer_mut(cab.location()).remove(cell, id);
+        Some(cab)
+    }
+
+    // the cells exactly `ring` steps away from `center`
+    fn ring(center: Cell, ring: i64) -> Vec<Cell> {
+        if ring == 0 {
+            return vec![center];
+        }
+        let (cx, cy) = center;
+        let mut cells = Vec::with_capacity(8 * ring as usize);
+        for dx in -ring..=ring {
+            cells.push((cx + dx, cy - ring));
+            cells.push((cx + dx, cy + ring));
+        }
+        for dy in (-ring + 1)..ring {
+            cells.push((cx - ring, cy + dy));
+            cells.push((cx + ring, cy + dy));
+        }
+        cells
+    }
+
+    fn visit<'a>(
+        &'a self,
+        layer: &Layer<T::Key>,
+        cells: &[Cell],
+        point: &Point,
+        max_distance: f64,
+        filter: &impl Fn(&T) -> bool,
+        found: &mut Vec<(f64, &'a T)>,
+    ) {
+        for cell in cells {
+            for id in layer.cells.get(cell).into_iter().flatten() {
+                let cab = &self.cabs[id];
+                let dist = point.dist(cab.location());
+                if dist <= max_distance && filter(cab) {
+                    found.push((dist, cab));
+                }
+            }
+        }
+    }
+
+    // Up to `k` cabs that pass `filter`, nearest to `point` first and none
+    // further away than `max_distance`. Ties are broken by id so the same
+    // index always answers the same way.
+    pub fn nearest(
+        &self,
+        point: &Point,
+        k: usize,
+        max_distance: Option<f64>,
+        filter: impl Fn(&T) -> bool,
+    ) -> Vec<T> {
+        if k == 0 {
+            return Vec::new();
+        }
+        let layer = self.layer(point);
+        let center = self.cell_of(point);
+        let last_ring = layer.last_ring(center);
+        let max_distance = max_distance.unwrap_or(f64::INFINITY);
+        let mut found: Vec<(f64, &T)> = Vec::new();
+
+        let mut ring = 0;
+        while ring <= last_ring {
+            // a ring has 8 * ring cells, once that is more than the cells
+            // that have anything in them it is cheaper to look at those
+            if 8 * ring as usize > layer.cells.len() {
+                let rest = layer
+                    .cells
+                    .keys()
+                    .filter(|(x, y)| (x - center.0).abs().max((y - center.1).abs()) >= ring)
+                    .copied()
+                    .collect::<Vec<Cell>>();
+                self.visit(layer, &rest, point, max_distance, &filter, &mut found);
+                break;
+            }
+            let cells = GridIndex::<T>::ring(center, ring);
+            self.visit(layer, &cells, point, max_distance, &filter, &mut found);
+
+            // the point is inside the center cell, so everything in the
+            // next ring is at least `ring` cells away from it
+            let next_ring_min = self.beyond_ring(point, ring);
+            if next_ring_min > max_distance {
+                break;
+            }
+            if found.len() >= k {
+                found.sort_by(|a, b| a.0.total_cmp(&b.0));
+                if found[k - 1].0 < next_ring_min {
+                    break;
+                }
+            }
+            ring += 1;
+        }
+
+        found.sort_by(|a, b| a.0.total_cmp(&b.0).then_with(|| a.1.key().cmp(&b.1.key())));
+        found
+            .into_iter()
+            .take(k)
+            .map(|(_, cab)| cab.clone())
+            .collect()
+    }
+
+    // every indexed cab no further than `radius` from `point`, nearest first
+    pub fn within_radius(&self, point: &Point, radius: f64) -> Vec<T> {
+        self.nearest(point, self.len(), Some(radius), |_| true)
+    }
+}
@@ -0,0 +1,241 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use dotenv::dotenv;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::FuberError,
+    models::point_model::{Point, GRID_BOUND},
+    repository::fuber_repo::BoxedRepo,
+    scheduler::env_or,
+    spatial::{DEFAULT_CELL_SIZE, GEO_CELL_DEGREES},
+};
+
+pub const DEFAULT_WINDOW_SECS: u64 = 300;
+pub const DEFAULT_MAX_MULTIPLIER: f64 = 3.0;
+pub const DEFAULT_SMOOTHING_SECS: u64 = 60;
+
+// A square of the map prices surge together, cut like the cells of the
+// spatial index. Grid zones are `cell_size` units wide, gps zones
+// `GEO_CELL_DEGREES` of latitude by as many of longitude.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Zone {
+    Grid { x: i64, y: i64 },
+    Geo { x: i64, y: i64 },
+}
+
+// the surge of a zone right now and what it came from
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SurgeReading {
+    pub zone: Zone,
+    pub multiplier: f64,
+    // requests from the zone within the window
+    pub requests: usize,
+    pub free_cabs: usize,
+}
+
+// the requests of a zone within the window and where its multiplier was
+// the last time it was looked at
+struct ZoneState {
+    requests: VecDeque<i64>,
+    multiplier: f64,
+    updated_at: i64,
+}
+
+// How fares react to demand, managed by rocket next to the tariff. Every
+// zone compares the ride requests from it within `window` to the free cabs
+// in it, twice as many requests as cabs make rides twice as expensive, up
+// to `max_multiplier`. The multiplier doesn't jump to that right away, it
+// closes in on it with `smoothing` as time constant, so a short burst of
+// requests or a cab passing by barely moves it. Its clones share the
+// zones, so a request can take it along off the async workers.
+#[derive(Clone)]
+pub struct Surge {
+    cell_size: i64,
+    window: Duration,
+    max_multiplier: f64,
+    smoothing: Duration,
+    zones: Arc<Mutex<HashMap<Zone, ZoneState>>>,
+}
+
+impl Default for Surge {
+    fn default() -> Self {
+        Surge::new(
+            DEFAULT_CELL_SIZE,
+            Duration::from_secs(DEFAULT_WINDOW_SECS),
+            DEFAULT_MAX_MULTIPLIER,
+            Duration::from_secs(DEFAULT_SMOOTHING_SECS),
+        )
+    }
+}
+
+fn two_decimals(multiplier: f64) -> f64 {
+    (multiplier * 100.0).round() / 100.0
+}
+
+impl Surge {
+    pub fn new(cell_size: i64, window: Duration, max_multiplier: f64, smoothing: Duration) -> Self {
+        Surge {
+            cell_size: cell_size.max(1),
+            window,
+            max_multiplier: max_multiplier.max(1.0),
+            smoothing,
+            zones: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    // reads `FUBER_SURGE_CELL_SIZE`, `FUBER_SURGE_WINDOW_SECS`,
+    // `FUBER_SURGE_MAX_MULTIPLIER` and `FUBER_SURGE_SMOOTHING_SECS`, the
+    // defaults above for the ones that aren't set. A max multiplier of 1
+    // turns surge pricing off.
+    pub fn init() -> Self {
+        dotenv().ok();
+        let cell_size = env_or("FUBER_SURGE_CELL_SIZE", DEFAULT_CELL_SIZE, |x| *x > 0);
+        let window = env_or("FUBER_SURGE_WINDOW_SECS", DEFAULT_WINDOW_SECS, |x| *x > 0);
+        let max_multiplier = env_or(
+            "FUBER_SURGE_MAX_MULTIPLIER",
+            DEFAULT_MAX_MULTIPLIER,
+            |x: &f64| x.is_finite() && *x >= 1.0,
+        );
+        let smoothing = env_or("FUBER_SURGE_SMOOTHING_SECS", DEFAULT_SMOOTHING_SECS, |_| {
+            true
+        });
+        Surge::new(
+            cell_size,
+            Duration::from_secs(window),
+            max_multiplier,
+            Duration::from_secs(smoothing),
+        )
+    }
+
+    pub fn zone_of(&self, p: &Point) -> Zone {
+        match p {
+            Point::Grid { x, y } => Zone::Grid {
+                x: x.div_euclid(self.cell_size),
+                y: y.div_euclid(self.cell_size),
+            },
+            Point::Geo { lat, lon } => Zone::Geo {
+                x: (lon / GEO_CELL_DEGREES).floor() as i64,
+                y: (lat / GEO_CELL_DEGREES).floor() as i64,
+            },
+        }
+    }
+
+    // Counts a ride request from `point` and tells the surge it is priced
+    // with, the request itself included.
+    pub fn record_request(
+        &self,
+        db: &BoxedRepo,
+        point: &Point,
+        now: i64,
+    ) -> Result<SurgeReading, FuberError> {
+        self.reading(db, point, now, true)
+    }
+
+    // the surge at `point` without counting a request
+    pub fn surge_at(
+        &self,
+        db: &BoxedRepo,
+        point: &Point,
+        now: i64,
+    ) -> Result<SurgeReading, FuberError> {
+        self.reading(db, point, now, false)
+    }
+
+    // The free cabs of `zone`. They are asked for within the circle around
+    // the zone, which the spatial index of the repo answers without going
+    // through the whole fleet, and the ones of the zone counted.
+    fn free_cabs_in(&self, db: &BoxedRepo, zone: Zone) -> Result<usize, FuberError> {
+        let (center, corners) = match zone {
+            Zone::Grid { x, y } => {
+                // mongodb refuses points outside of the bounds of its index
+                let bound = GRID_BOUND - 1;
+                let grid =
+                    |x: i64, y: i64| Point::new(x.clamp(-bound, bound), y.clamp(-bound, bound));
+                let (x0, y0) = (x * self.cell_size, y * self.cell_size);
+                let (half, last) = (self.cell_size / 2, self.cell_size - 1);
+                (
+                    grid(x0.saturating_add(half), y0.saturating_add(half)),
+                    [
+                        grid(x0, y0),
+                        grid(x0.saturating_add(last), y0),
+                        grid(x0, y0.saturating_add(last)),
+                        grid(x0.saturating_add(last), y0.saturating_add(last)),
+                    ],
+                )
+            }
+            Zone::Geo { x, y } => {
+                let geo = |lat: f64, lon: f64| {
+                    Point::geo(lat.clamp(-90.0, 90.0), lon.clamp(-180.0, 180.0))
+                };
+                let (lon0, lat0) = (x as f64 * GEO_CELL_DEGREES, y as f64 * GEO_CELL_DEGREES);
+                let (half, width) = (GEO_CELL_DEGREES / 2.0, GEO_CELL_DEGREES);
+                (
+                    geo(lat0 + half, lon0 + half)?,
+                    [
+                        geo(lat0, lon0)?,
+                        geo(lat0, lon0 + width)?,
+                        geo(lat0 + width, lon0)?,
+                        geo(lat0 + width, lon0 + width)?,
+                    ],
+                )
+            }
+        };
+        // a little more, mongodb measures the earth a tad larger
+        let radius = corners.iter().map(|x| center.dist(x)).fold(0.0, f64::max) * 1.01;
+        Ok(db
+            .find_nearest_free_cabs(&center, usize::MAX, Some(radius), None)?
+            .iter()
+            .filter(|x| self.zone_of(&x.location) == zone)
+            .count())
+    }
+
+    fn reading(
+        &self,
+        db: &BoxedRepo,
+        point: &Point,
+        now: i64,
+        request: bool,
+    ) -> Result<SurgeReading, FuberError> {
+        let zone = self.zone_of(point);
+        let free_cabs = self.free_cabs_in(db, zone)?;
+        let window = self.window.as_millis() as i64;
+        let mut zones = self.zones.lock().unwrap_or_else(|e| e.into_inner());
+        // zones nobody looked at since their requests left the window and
+        // the multiplier settled back are forgotten
+        let settled = window + 5 * self.smoothing.as_millis() as i64;
+        zones.retain(|_, x| now - x.updated_at <= settled);
+
+        let state = zones.entry(zone).or_insert_with(|| ZoneState {
+            requests: VecDeque::new(),
+            multiplier: 1.0,
+            updated_at: now,
+        });
+        while state.requests.front().is_some_and(|x| now - x >= window) {
+            state.requests.pop_front();
+        }
+        if request {
+            state.requests.push_back(now);
+        }
+        let target =
+            (state.requests.len() as f64 / free_cabs.max(1) as f64).clamp(1.0, self.max_multiplier);
+        let elapsed = (now - state.updated_at).max(0) as f64;
+        let smoothing = self.smoothing.as_millis() as f64;
+        let keep = if smoothing > 0.0 {
+            (-elapsed / smoothing).exp()
+        } else {
+            0.0
+        };
+        state.multiplier = target + (state.multiplier - target) * keep;
+        state.updated_at = now.max(state.updated_at);
+        Ok(SurgeReading {
+            zone,
+            multiplier: two_decimals(state.multiplier),
+            requests: state.requests.len(),
+            free_cabs,
+        })
+    }
+}
@@ -0,0 +1,56 @@
+use mongodb::bson::oid::ObjectId;
+use rocket::tokio::sync::broadcast;
+use serde::{Deserialize, Serialize};
+
+use crate::models::{cab_model::Cab, point_model::Point};
+
+// how many updates a subscriber can fall behind before it misses some
+pub const CHANNEL_CAPACITY: usize = 1024;
+
+// where a cab is and where it is heading, what riders get to see of it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CabPosition {
+    pub cab_id: ObjectId,
+    pub location: Point,
+    pub destination: Option<Point>,
+}
+
+impl CabPosition {
+    pub fn of(cab: &Cab) -> Self {
+        CabPosition {
+            cab_id: cab.id.unwrap_or_default(),
+            location: cab.location.clone(),
+            destination: cab.destination.clone(),
+        }
+    }
+}
+
+// The cabs as they move, managed by rocket. The handlers that move a cab
+// publish it here and every stream that is open gets it, the ones that fell
+// too far behind skip what they missed. Nothing is kept for streams that
+// aren't open yet, they start from what the repo has.
+pub struct Tracker {
+    updates: broadcast::Sender<Cab>,
+}
+
+impl Default for Tracker {
+    fn default() -> Self {
+        Tracker::new(CHANNEL_CAPACITY)
+    }
+}
+
+impl Tracker {
+    pub fn new(capacity: usize) -> Self {
+        let (updates, _) = broadcast::channel(capacity.max(1));
+        Tracker { updates }
+    }
+
+    pub fn publish(&self, cab: &Cab) {
+        // nobody listening is fine
+        let _ = self.updates.send(cab.clone());
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Cab> {
+        self.updates.subscribe()
+    }
+}
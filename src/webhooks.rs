@@ -0,0 +1,297 @@
+use std::collections::HashMap;
+use std::thread;
+use std::time::Duration;
+
+use dotenv::dotenv;
+use hmac::{Hmac, Mac};
+use mongodb::bson::oid::ObjectId;
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    tokio::{select, task, time},
+    Orbit, Rocket,
+};
+use sha2::Sha256;
+
+use crate::{
+    error::FuberError,
+    models::{
+        ride_model::now_millis,
+        webhook_model::{Delivery, DeliveryState, Webhook},
+    },
+    repository::{fuber_repo::BoxedRepo, shared_repo::SharedRepo},
+    scheduler::env_or,
+};
+
+pub const DEFAULT_TICK_MS: u64 = 1_000;
+pub const DEFAULT_TIMEOUT_MS: u64 = 5_000;
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 8;
+pub const DEFAULT_BACKOFF_MS: u64 = 1_000;
+pub const DEFAULT_MAX_BACKOFF_MS: u64 = 600_000;
+
+// how many deliveries one tick sends at most, the rest wait for the next
+const BATCH_SIZE: usize = 100;
+
+pub const EVENT_HEADER: &str = "X-Fuber-Event";
+pub const DELIVERY_HEADER: &str = "X-Fuber-Delivery";
+pub const SIGNATURE_HEADER: &str = "X-Fuber-Signature";
+
+// How the outbox is worked off. Every `tick` the due deliveries are posted
+// to their webhooks, one that doesn't answer with a 2xx within `timeout` is
+// tried again `backoff` later, twice as long after every failed attempt up
+// to `max_backoff`. After `max_attempts` it is a dead letter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WebhookPolicy {
+    tick: Duration,
+    timeout: Duration,
+    max_attempts: u32,
+    backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl Default for WebhookPolicy {
+    fn default() -> Self {
+        WebhookPolicy::new(
+            Duration::from_millis(DEFAULT_TICK_MS),
+            Duration::from_millis(DEFAULT_TIMEOUT_MS),
+            DEFAULT_MAX_ATTEMPTS,
+            Duration::from_millis(DEFAULT_BACKOFF_MS),
+            Duration::from_millis(DEFAULT_MAX_BACKOFF_MS),
+        )
+    }
+}
+
+impl WebhookPolicy {
+    pub fn new(
+        tick: Duration,
+        timeout: Duration,
+        max_attempts: u32,
+        backoff: Duration,
+        max_backoff: Duration,
+    ) -> Self {
+        WebhookPolicy {
+            tick,
+            timeout,
+            max_attempts: max_attempts.max(1),
+            backoff,
+            max_backoff: max_backoff.max(backoff),
+        }
+    }
+
+    // reads `FUBER_WEBHOOK_TICK_MS`, `FUBER_WEBHOOK_TIMEOUT_MS`,
+    // `FUBER_WEBHOOK_MAX_ATTEMPTS`, `FUBER_WEBHOOK_BACKOFF_MS` and
+    // `FUBER_WEBHOOK_MAX_BACKOFF_MS`, the defaults above for the ones that
+    // aren't set
+    pub fn init() -> Self {
+        dotenv().ok();
+        let positive = |x: &u64| *x > 0;
+        let tick = env_or("FUBER_WEBHOOK_TICK_MS", DEFAULT_TICK_MS, positive);
+        let timeout = env_or("FUBER_WEBHOOK_TIMEOUT_MS", DEFAULT_TIMEOUT_MS, positive);
+        let max_attempts = env_or("FUBER_WEBHOOK_MAX_ATTEMPTS", DEFAULT_MAX_ATTEMPTS, |x| {
+            *x > 0
+        });
+        let backoff = env_or("FUBER_WEBHOOK_BACKOFF_MS", DEFAULT_BACKOFF_MS, |_| true);
+        let max_backoff = env_or(
+            "FUBER_WEBHOOK_MAX_BACKOFF_MS",
+            DEFAULT_MAX_BACKOFF_MS,
+            |_| true,
+        );
+        WebhookPolicy::new(
+            Duration::from_millis(tick),
+            Duration::from_millis(timeout),
+            max_attempts,
+            Duration::from_millis(backoff),
+            Duration::from_millis(max_backoff),
+        )
+    }
+
+    pub fn tick(&self) -> Duration {
+        self.tick
+    }
+
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    // how long a delivery waits after `attempts` failed ones
+    pub fn backoff(&self, attempts: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempts.saturating_sub(1));
+        self.backoff.saturating_mul(factor).min(self.max_backoff)
+    }
+}
+
+// What the receiver checks a delivery with, the hex of the hmac-sha256 of
+// the body with the secret of the webhook as the key.
+pub fn sign(secret: &str, body: &str) -> String {
+    // hmac takes keys of any length
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac cannot take the secret");
+    mac.update(body.as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+// posts the event of `delivery` to the webhook, anything but a 2xx is a
+// failed attempt
+fn post(
+    agent: &ureq::Agent,
+    webhook: &Webhook,
+    delivery_id: ObjectId,
+    delivery: &Delivery,
+) -> Result<(), String> {
+    let body = serde_json::to_string(&delivery.event).map_err(|e| e.to_string())?;
+    let response = agent
+        .post(&webhook.url)
+        .set("Content-Type", "application/json")
+        .set(EVENT_HEADER, delivery.event.kind.as_str())
+        .set(DELIVERY_HEADER, &delivery_id.to_hex())
+        .set(SIGNATURE_HEADER, &sign(&webhook.secret, &body))
+        .send_string(&body);
+    match response {
+        Ok(response) if (200..300).contains(&response.status()) => Ok(()),
+        Ok(response) => Err(format!("the webhook answered {}", response.status())),
+        Err(ureq::Error::Status(status, _)) => Err(format!("the webhook answered {}", status)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// Sends every delivery due at `now` once, after the events the backend
+// still kept with their changes made it into the outbox. A delivery is only
+// marked as delivered after its webhook answered, so one whose result
+// couldn't be stored goes out again: webhooks get every event at least once
+// and have to tell repeats apart by the delivery id. Every webhook gets its
+// deliveries on its own thread, so one that doesn't answer only holds up
+// its own.
+pub fn deliver_due(db: &BoxedRepo, policy: &WebhookPolicy, now: i64) -> Result<(), FuberError> {
+    db.flush_pending_events()?;
+    let due = db.get_due_deliveries(now, BATCH_SIZE)?;
+    if due.is_empty() {
+        return Ok(());
+    }
+    let webhooks = db
+        .get_webhooks()?
+        .into_iter()
+        .filter_map(|x| x.id.map(|id| (id, x)))
+        .collect::<HashMap<ObjectId, Webhook>>();
+    // in the order they were due for every webhook
+    let mut by_webhook: HashMap<ObjectId, Vec<Delivery>> = HashMap::new();
+    for delivery in due {
+        by_webhook
+            .entry(delivery.webhook_id)
+            .or_default()
+            .push(delivery);
+    }
+    let agent = ureq::AgentBuilder::new().timeout(policy.timeout).build();
+    thread::scope(|scope| {
+        let sending = by_webhook
+            .into_iter()
+            .map(|(webhook_id, deliveries)| {
+                let (agent, webhook) = (&agent, webhooks.get(&webhook_id));
+                scope.spawn(move || deliver_to(db, policy, now, agent, webhook, deliveries))
+            })
+            .collect::<Vec<_>>();
+        sending.into_iter().try_for_each(|x| {
+            x.join()
+                .map_err(|_| FuberError::storage("a webhook delivery panicked"))?
+        })
+    })
+}
+
+// Sends the deliveries of one webhook in order. After the first one that
+// fails the rest wait for the next tick, a webhook that is down costs a
+// tick at most one timeout.
+fn deliver_to(
+    db: &BoxedRepo,
+    policy: &WebhookPolicy,
+    now: i64,
+    agent: &ureq::Agent,
+    webhook: Option<&Webhook>,
+    deliveries: Vec<Delivery>,
+) -> Result<(), FuberError> {
+    for delivery in deliveries {
+        let delivery_id = match delivery.id {
+            Some(delivery_id) => delivery_id,
+            None => continue,
+        };
+        let sent = match webhook {
+            Some(webhook) => post(agent, webhook, delivery_id, &delivery),
+            None => Err("the webhook is gone".to_string()),
+        };
+        let failed = sent.is_err() && webhook.is_some();
+        let attempts = delivery.attempts + 1;
+        let updated = match sent {
+            Ok(()) => Delivery {
+                state: DeliveryState::Delivered,
+                attempts,
+                last_error: None,
+                ..delivery
+            },
+            // nothing to try again without a webhook
+            Err(e) if webhook.is_none() || attempts >= policy.max_attempts => Delivery {
+                state: DeliveryState::Dead,
+                attempts,
+                last_error: Some(e),
+                ..delivery
+            },
+            Err(e) => Delivery {
+                attempts,
+                next_attempt_at: now + policy.backoff(attempts).as_millis() as i64,
+                last_error: Some(e),
+                ..delivery
+            },
+        };
+        // a delivery sent off again in the meantime is left alone
+        db.update_delivery(updated, DeliveryState::Pending)?;
+        if failed {
+            break;
+        }
+    }
+    Ok(())
+}
+
+// Attach it to rocket to work off the outbox in the background, it gets
+// its own handle to the repo the same way as the `BookingScheduler`.
+pub struct WebhookDispatcher {
+    db: SharedRepo,
+    policy: WebhookPolicy,
+}
+
+impl WebhookDispatcher {
+    pub fn new(db: SharedRepo, policy: WebhookPolicy) -> Self {
+        WebhookDispatcher { db, policy }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for WebhookDispatcher {
+    fn info(&self) -> Info {
+        Info {
+            name: "Webhook dispatcher",
+            kind: Kind::Liftoff,
+        }
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let db = self.db.clone();
+        let policy = self.policy.clone();
+        let mut shutdown = rocket.shutdown();
+        rocket::tokio::spawn(async move {
+            let mut ticks = time::interval(policy.tick());
+            loop {
+                select! {
+                    _ = ticks.tick() => {}
+                    _ = &mut shutdown => break,
+                }
+                let (db, policy) = (db.clone(), policy.clone());
+                // the repos and the http calls block, so the deliveries are
+                // sent off the async workers
+                let delivered =
+                    task::spawn_blocking(move || deliver_due(db.repo(), &policy, now_millis()))
+                        .await;
+                match delivered {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => rocket::error!("unable to deliver the webhooks: {:?}", e),
+                    Err(e) => rocket::error!("the webhook dispatcher failed: {}", e),
+                }
+            }
+        });
+    }
+}
@@ -1,36 +1,75 @@
 use fuber::api::cab_api;
+use fuber::api::catcher_api;
 use fuber::api::person_api;
+use fuber::api::queue_api;
+use fuber::api::ride_api;
+use fuber::auth::{Admin, Auth, Caller};
+use fuber::error::{ErrorBody, ErrorCode, FuberError};
 use fuber::generate_random_string;
+use fuber::metric::{DistanceMetric, Euclidean, Manhattan, Travel};
+use fuber::models::auth_model::ApiKey;
+use fuber::models::booking_model::{Booking, BookingState};
+use fuber::models::cab_model::{Cab, CabCategory};
+use fuber::models::event_model::Event;
 use fuber::models::person_model::Person;
 use fuber::models::point_model::Point;
-use fuber::repository::mongodb_repos::MongoRepo;
+use fuber::models::ride_model::{Ride, RideState};
+use fuber::models::ticket_model::{Ticket, TicketState};
+use fuber::models::webhook_model::{Delivery, DeliveryState, Webhook};
+use fuber::pooling::Pooling;
+use fuber::pricing::Tariff;
+use fuber::repository::fuber_repo::{
+    BoxedRepo, DeleteResult, FuberRepository, InsertManyResult, InsertOneResult, UpdateResult,
+};
+use fuber::repository::indexed_repo::IndexedRepo;
+use fuber::repository::memory_repos::MemoryRepo;
+use fuber::request_id::{RequestIdFairing, REQUEST_ID_HEADER};
+use fuber::surge::Surge;
+use fuber::tracking::Tracker;
+use mongodb::bson::oid::ObjectId;
+use rocket::http::{ContentType, Header, Status};
+use rocket::local::blocking::Client;
 use rocket::serde::json::Json;
 use rocket::State;
+use std::collections::HashSet;
+use std::sync::Barrier;
+use std::thread;
+use std::time::Duration;
+
+mod common;
+
+use common::{admin, JWT_SECRET};
 
 #[test]
 fn test_get_nearest_cab() {
-    // create a db client
-    let db = MongoRepo::init();
-    let rocket = rocket::build().manage(db);
+    // create an in-memory repo so the tests run without MongoDB
+    let db: BoxedRepo = Box::new(MemoryRepo::init());
+    let rocket = rocket::build()
+        .manage(db)
+        .manage(Travel::default())
+        .manage(Surge::default());
     let state = State::get(&rocket).expect("cannot get the state");
+    let travel = State::get(&rocket).expect("cannot get the travel config");
+    let surge = State::get(&rocket).expect("cannot get the surge config");
 
     // check if fleet is empty or not
     match cab_api::get_fleet(state) {
         Ok(Json(v)) => {
             if !v.is_empty() {
                 // delete all the stuff from the db
-                let _ = cab_api::delete_fleet(state).expect("cannot delete fleet");
-                let _ = person_api::delete_all_people(state).expect("cannot delete all people");
+                let _ = cab_api::delete_fleet(Admin, state).expect("cannot delete fleet");
+                let _ =
+                    person_api::delete_all_people(Admin, state).expect("cannot delete all people");
             }
         }
         Err(_) => panic!("Cannot get a fleet"),
     }
 
     // generate a fleet
-    let fleet = cab_api::generate_fleet(state, 3);
+    let fleet = cab_api::generate_fleet(Admin, state, 3);
     // insert fleet to db
-    let Json(fleet_id_vec) =
-        cab_api::create_fleet(state, fleet).expect("cannot insert fleet into the db");
+    let Json(_fleet_id_vec) = cab_api::create_fleet(Admin, state, travel, fleet)
+        .expect("cannot insert fleet into the db");
     let fleet = cab_api::get_fleet(state).expect("cannot get fleet");
 
     // generate a person
@@ -47,14 +86,23 @@ fn test_get_nearest_cab() {
         .expect("cannot get the person data after insertion");
 
     // use the api to get a cab nearest to the person
-    let Json((_, api_cab)) = person_api::request_cab(state, person_id.clone())
-        .expect("cannot find the nearest cab to the person requesting the cab");
+    let (_, api_cab) = person_api::request_cab(
+        Caller::admin(),
+        state,
+        travel,
+        surge,
+        person_id.clone(),
+        None,
+    )
+    .expect("cannot find the nearest cab to the person requesting the cab")
+    .assigned()
+    .expect("no cab was assigned");
 
     // manually find out the nearest cab to the person
     let mut manual_cab = fleet
         .into_inner()
         .into_iter()
-        .reduce(|c1, c2| person.nearest_cab(&c1, &c2))
+        .reduce(|c1, c2| person.nearest_cab(&c1, &c2, &Euclidean))
         .expect("cannot find the nearest cab to the person, manually");
     manual_cab.update_destination(Some(person.location.clone()));
     manual_cab.update_person_id(person.id);
@@ -66,29 +114,35 @@ fn test_get_nearest_cab() {
 #[test]
 #[should_panic]
 fn test_assign_cab_panic() {
-    // create a db client
-    let db = MongoRepo::init();
-    let rocket = rocket::build().manage(db);
+    // create an in-memory repo so the tests run without MongoDB
+    let db: BoxedRepo = Box::new(MemoryRepo::init());
+    let rocket = rocket::build()
+        .manage(db)
+        .manage(Travel::default())
+        .manage(Surge::default());
     let state = State::get(&rocket).expect("cannot get the state");
+    let travel = State::get(&rocket).expect("cannot get the travel config");
+    let surge = State::get(&rocket).expect("cannot get the surge config");
 
     // check if fleet is empty or not
     match cab_api::get_fleet(state) {
         Ok(Json(v)) => {
             if !v.is_empty() {
                 // delete all the stuff from the db
-                let _ = cab_api::delete_fleet(state).expect("cannot delete fleet");
-                let _ = person_api::delete_all_people(state).expect("cannot delete all people");
+                let _ = cab_api::delete_fleet(Admin, state).expect("cannot delete fleet");
+                let _ =
+                    person_api::delete_all_people(Admin, state).expect("cannot delete all people");
             }
         }
         Err(_) => panic!("Cannot get a fleet"),
     }
 
     // generate a fleet
-    let fleet = cab_api::generate_fleet(state, 3);
+    let fleet = cab_api::generate_fleet(Admin, state, 3);
     // insert fleet to db
-    let Json(fleet_id_vec) =
-        cab_api::create_fleet(state, fleet).expect("cannot insert fleet into the db");
-    let fleet = cab_api::get_fleet(state).expect("cannot get fleet");
+    let Json(_fleet_id_vec) = cab_api::create_fleet(Admin, state, travel, fleet)
+        .expect("cannot insert fleet into the db");
+    let _fleet = cab_api::get_fleet(state).expect("cannot get fleet");
 
     // generate a person1
     let person1 = Person::new(
@@ -102,8 +156,17 @@ fn test_assign_cab_panic() {
         .expect("cannot insert the person1 into db");
 
     // use the api to get a cab nearest to the person
-    let Json((_, api_cab)) = person_api::request_cab(state, person_id_1.clone())
-        .expect("cannot find the nearest cab to the person requesting the cab");
+    let (_, api_cab) = person_api::request_cab(
+        Caller::admin(),
+        state,
+        travel,
+        surge,
+        person_id_1.clone(),
+        None,
+    )
+    .expect("cannot find the nearest cab to the person requesting the cab")
+    .assigned()
+    .expect("no cab was assigned");
 
     // generate a person2
     let person2 = Person::new(
@@ -117,38 +180,43 @@ fn test_assign_cab_panic() {
         .expect("cannot insert the person2 into db");
 
     // try to assign the cab with another person
-    let status = cab_api::assign_person(state, person_id_2, Json(api_cab.clone()));
+    let status = cab_api::assign_person(Admin, state, person_id_2, Json(api_cab.clone()));
 
     // this assertion should panic
     assert!(status.is_ok())
 }
 
 #[test]
-#[should_panic]
-fn test_request_cab_panic_when_fleet_occupied() {
-    // create a db client
-    let db = MongoRepo::init();
-    let rocket = rocket::build().manage(db);
+fn test_request_cab_is_queued_when_fleet_occupied() {
+    // create an in-memory repo so the tests run without MongoDB
+    let db: BoxedRepo = Box::new(MemoryRepo::init());
+    let rocket = rocket::build()
+        .manage(db)
+        .manage(Travel::default())
+        .manage(Surge::default());
     let state = State::get(&rocket).expect("cannot get the state");
+    let travel = State::get(&rocket).expect("cannot get the travel config");
+    let surge = State::get(&rocket).expect("cannot get the surge config");
 
     // check if fleet is empty or not
     match cab_api::get_fleet(state) {
         Ok(Json(v)) => {
             if !v.is_empty() {
                 // delete all the stuff from the db
-                let _ = cab_api::delete_fleet(state).expect("cannot delete fleet");
-                let _ = person_api::delete_all_people(state).expect("cannot delete all people");
+                let _ = cab_api::delete_fleet(Admin, state).expect("cannot delete fleet");
+                let _ =
+                    person_api::delete_all_people(Admin, state).expect("cannot delete all people");
             }
         }
         Err(_) => panic!("Cannot get a fleet"),
     }
 
     // generate a fleet
-    let fleet = cab_api::generate_fleet(state, 3);
+    let fleet = cab_api::generate_fleet(Admin, state, 3);
     // insert fleet to db
-    let Json(fleet_id_vec) =
-        cab_api::create_fleet(state, fleet).expect("cannot insert fleet into the db");
-    let fleet = cab_api::get_fleet(state).expect("cannot get fleet");
+    let Json(_fleet_id_vec) = cab_api::create_fleet(Admin, state, travel, fleet)
+        .expect("cannot insert fleet into the db");
+    let _fleet = cab_api::get_fleet(state).expect("cannot get fleet");
 
     // generate person1, person2 and person3 to occupy a fleet of 3
     let person1 = Person::new(
@@ -179,14 +247,23 @@ fn test_request_cab_panic_when_fleet_occupied() {
         .expect("cannot insert the person3 into db");
 
     // all persons request cab
-    let Json((person_1, cab_1)) =
-        person_api::request_cab(state, person_id_1).expect("person1 cab request failed");
-    let Json((person_2, cab_2)) =
-        person_api::request_cab(state, person_id_2).expect("person1 cab request failed");
-    let Json((person_3, cab_3)) =
-        person_api::request_cab(state, person_id_3).expect("person1 cab request failed");
-
-    // create the person4 which will be rejected when requested for a cab
+    let (_person_1, _cab_1) =
+        person_api::request_cab(Caller::admin(), state, travel, surge, person_id_1, None)
+            .expect("person1 cab request failed")
+            .assigned()
+            .expect("no cab was assigned");
+    let (_person_2, _cab_2) =
+        person_api::request_cab(Caller::admin(), state, travel, surge, person_id_2, None)
+            .expect("person1 cab request failed")
+            .assigned()
+            .expect("no cab was assigned");
+    let (_person_3, _cab_3) =
+        person_api::request_cab(Caller::admin(), state, travel, surge, person_id_3, None)
+            .expect("person1 cab request failed")
+            .assigned()
+            .expect("no cab was assigned");
+
+    // create the person4 which will be queued when requested for a cab
     let person4 = Person::new(
         None,
         generate_random_string(),
@@ -197,7 +274,900 @@ fn test_request_cab_panic_when_fleet_occupied() {
     let Json(person_id_4) =
         person_api::create_person(state, Json(person4)).expect("cannot insert person4 into db");
 
-    let res = person_api::request_cab(state, person_id_4);
+    let status = person_api::request_cab(
+        Caller::admin(),
+        state,
+        travel,
+        surge,
+        person_id_4.clone(),
+        None,
+    )
+    .expect("person4 cab request failed")
+    .queued()
+    .expect("person4 got a cab");
+    assert_eq!(status.ticket.state, TicketState::Waiting);
+    assert_eq!(status.position, Some(1));
+    assert_eq!(status.ticket.cab_id, None);
+
+    // the ride waits along with the ticket
+    let ride = person_api::active_ride(state, &person_id_4)
+        .expect("cannot get the ride")
+        .expect("person4 has no ride");
+    assert_eq!(ride.state, RideState::Requested);
+    assert_eq!(ride.id, Some(status.ticket.ride_id));
+}
+
+// in-memory repo that holds on to the fleet it read for a bit before
+// returning it, so every concurrent ride request works off a stale fleet
+struct SlowFleetRepo(MemoryRepo);
+
+impl FuberRepository for SlowFleetRepo {
+    fn create_person(&self, new_person: Person) -> Result<InsertOneResult, FuberError> {
+        self.0.create_person(new_person)
+    }
+    fn get_person(&self, id: &str) -> Result<Person, FuberError> {
+        self.0.get_person(id)
+    }
+    fn update_person(&self, new_person: Person) -> Result<UpdateResult, FuberError> {
+        self.0.update_person(new_person)
+    }
+    fn delete_person(&self, person_id: &str) -> Result<DeleteResult, FuberError> {
+        self.0.delete_person(person_id)
+    }
+    fn delete_all_people(&self) -> Result<DeleteResult, FuberError> {
+        self.0.delete_all_people()
+    }
+    fn create_cab(&self, new_cab: Cab) -> Result<InsertOneResult, FuberError> {
+        self.0.create_cab(new_cab)
+    }
+    fn get_cab(&self, id: &str) -> Result<Cab, FuberError> {
+        self.0.get_cab(id)
+    }
+    fn create_fleet(&self, fleet: Vec<Cab>) -> Result<InsertManyResult, FuberError> {
+        self.0.create_fleet(fleet)
+    }
+    fn get_fleet(&self) -> Result<Vec<Cab>, FuberError> {
+        let fleet = self.0.get_fleet();
+        thread::sleep(Duration::from_millis(50));
+        fleet
+    }
+    fn assign_person(&self, cab_id: &str, new_cab: Cab) -> Result<UpdateResult, FuberError> {
+        self.0.assign_person(cab_id, new_cab)
+    }
+    fn unassign_person(&self, cab_id: &str, new_cab: Cab) -> Result<UpdateResult, FuberError> {
+        self.0.unassign_person(cab_id, new_cab)
+    }
+    fn update_cab(&self, new_cab: Cab) -> Result<UpdateResult, FuberError> {
+        self.0.update_cab(new_cab)
+    }
+    fn update_location(&self, cab_id: &str, location: Point) -> Result<UpdateResult, FuberError> {
+        self.0.update_location(cab_id, location)
+    }
+    fn update_pool(&self, new_cab: Cab, expected: &Cab) -> Result<UpdateResult, FuberError> {
+        self.0.update_pool(new_cab, expected)
+    }
+    fn delete_cab(&self, cab_id: &str) -> Result<DeleteResult, FuberError> {
+        self.0.delete_cab(cab_id)
+    }
+    fn delete_fleet(&self) -> Result<DeleteResult, FuberError> {
+        self.0.delete_fleet()
+    }
+    fn create_ride(&self, new_ride: Ride) -> Result<InsertOneResult, FuberError> {
+        self.0.create_ride(new_ride)
+    }
+    fn get_ride(&self, id: &str) -> Result<Ride, FuberError> {
+        self.0.get_ride(id)
+    }
+    fn update_ride(&self, ride: Ride, expected: RideState) -> Result<UpdateResult, FuberError> {
+        self.0.update_ride(ride, expected)
+    }
+    fn get_rides_of_person(&self, person_id: &str) -> Result<Vec<Ride>, FuberError> {
+        self.0.get_rides_of_person(person_id)
+    }
+    fn create_ticket(&self, new_ticket: Ticket) -> Result<InsertOneResult, FuberError> {
+        self.0.create_ticket(new_ticket)
+    }
+    fn get_ticket(&self, id: &str) -> Result<Ticket, FuberError> {
+        self.0.get_ticket(id)
+    }
+    fn update_ticket(
+        &self,
+        ticket: Ticket,
+        expected: TicketState,
+    ) -> Result<UpdateResult, FuberError> {
+        self.0.update_ticket(ticket, expected)
+    }
+    fn get_waiting_tickets(&self) -> Result<Vec<Ticket>, FuberError> {
+        self.0.get_waiting_tickets()
+    }
+    fn create_booking(&self, new_booking: Booking) -> Result<InsertOneResult, FuberError> {
+        self.0.create_booking(new_booking)
+    }
+    fn get_booking(&self, id: &str) -> Result<Booking, FuberError> {
+        self.0.get_booking(id)
+    }
+    fn update_booking(
+        &self,
+        booking: Booking,
+        expected: BookingState,
+    ) -> Result<UpdateResult, FuberError> {
+        self.0.update_booking(booking, expected)
+    }
+    fn get_bookings_of_person(&self, person_id: &str) -> Result<Vec<Booking>, FuberError> {
+        self.0.get_bookings_of_person(person_id)
+    }
+    fn get_upcoming_bookings(&self, after: i64) -> Result<Vec<Booking>, FuberError> {
+        self.0.get_upcoming_bookings(after)
+    }
+    fn get_scheduled_bookings(&self, until: i64) -> Result<Vec<Booking>, FuberError> {
+        self.0.get_scheduled_bookings(until)
+    }
+
+    fn get_events(&self, since: i64, limit: usize) -> Result<Vec<Event>, FuberError> {
+        self.0.get_events(since, limit)
+    }
+
+    fn flush_pending_events(&self) -> Result<(), FuberError> {
+        self.0.flush_pending_events()
+    }
+
+    fn create_webhook(&self, new_webhook: Webhook) -> Result<InsertOneResult, FuberError> {
+        self.0.create_webhook(new_webhook)
+    }
+
+    fn get_webhooks(&self) -> Result<Vec<Webhook>, FuberError> {
+        self.0.get_webhooks()
+    }
+
+    fn delete_webhook(&self, webhook_id: &str) -> Result<DeleteResult, FuberError> {
+        self.0.delete_webhook(webhook_id)
+    }
+
+    fn get_delivery(&self, id: &str) -> Result<Delivery, FuberError> {
+        self.0.get_delivery(id)
+    }
+
+    fn update_delivery(
+        &self,
+        delivery: Delivery,
+        expected: DeliveryState,
+    ) -> Result<UpdateResult, FuberError> {
+        self.0.update_delivery(delivery, expected)
+    }
+
+    fn get_due_deliveries(&self, now: i64, limit: usize) -> Result<Vec<Delivery>, FuberError> {
+        self.0.get_due_deliveries(now, limit)
+    }
+
+    fn get_dead_deliveries(&self) -> Result<Vec<Delivery>, FuberError> {
+        self.0.get_dead_deliveries()
+    }
+
+    fn create_api_key(&self, new_api_key: ApiKey) -> Result<InsertOneResult, FuberError> {
+        self.0.create_api_key(new_api_key)
+    }
+
+    fn get_api_key_by_hash(&self, key_hash: &str) -> Result<ApiKey, FuberError> {
+        self.0.get_api_key_by_hash(key_hash)
+    }
+
+    fn delete_api_key(&self, api_key_id: &str) -> Result<DeleteResult, FuberError> {
+        self.0.delete_api_key(api_key_id)
+    }
+}
+
+#[test]
+fn test_parallel_requests_never_double_book_a_cab() {
+    let db: BoxedRepo = Box::new(SlowFleetRepo(MemoryRepo::init()));
+    let rocket = rocket::build()
+        .manage(db)
+        .manage(Travel::default())
+        .manage(Surge::default());
+    let state = State::get(&rocket).expect("cannot get the state");
+    let travel = State::get(&rocket).expect("cannot get the travel config");
+    let surge = State::get(&rocket).expect("cannot get the surge config");
+
+    // as many people as there are cabs, so everyone has to get one even
+    // though they all race for the same nearest cabs
+    let size = 20;
+    let fleet = cab_api::generate_fleet(Admin, state, size);
+    cab_api::create_fleet(Admin, state, travel, fleet).expect("cannot insert fleet into the db");
+    let person_ids = (0..size)
+        .map(|_| {
+            let person = Person::new(
+                None,
+                generate_random_string(),
+                Point::new(0, 0),
+                Point::create_random_point(),
+            );
+            let Json(person_id) = person_api::create_person(state, Json(person))
+                .expect("cannot insert the person into db");
+            person_id
+        })
+        .collect::<Vec<String>>();
+
+    // fire all the ride requests at the same time
+    let barrier = Barrier::new(size);
+    let cabs = thread::scope(|s| {
+        let handles = person_ids
+            .iter()
+            .map(|person_id| {
+                let barrier = &barrier;
+                s.spawn(move || {
+                    barrier.wait();
+                    person_api::request_cab(
+                        Caller::admin(),
+                        state,
+                        travel,
+                        surge,
+                        person_id.clone(),
+                        None,
+                    )
+                })
+            })
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .map(|h| {
+                let (_, cab) = h
+                    .join()
+                    .expect("ride request panicked")
+                    .expect("ride request failed")
+                    .assigned()
+                    .expect("no cab was assigned");
+                cab
+            })
+            .collect::<Vec<Cab>>()
+    });
+
+    // every request got its own cab
+    let cab_ids = cabs.iter().map(|c| c.id).collect::<HashSet<_>>();
+    assert_eq!(cab_ids.len(), size);
+
+    // and the stored fleet agrees, every cab has a different person
+    let Json(fleet) = cab_api::get_fleet(state).expect("cannot get fleet");
+    let assigned = fleet
+        .iter()
+        .filter_map(|c| c.person_id)
+        .collect::<HashSet<_>>();
+    assert_eq!(assigned.len(), size);
+}
+
+// same setup main.rs uses for errors, on top of an in-memory repo
+fn client_with_routes(routes: Vec<rocket::Route>) -> Client {
+    let db: BoxedRepo = Box::new(MemoryRepo::init());
+    let rocket = rocket::build()
+        .manage(db)
+        .manage(Travel::default())
+        .manage(Surge::default())
+        .manage(Auth::new(Some(JWT_SECRET)))
+        .attach(RequestIdFairing)
+        .register(
+            "/",
+            rocket::catchers![
+                catcher_api::unauthorized,
+                catcher_api::forbidden,
+                catcher_api::not_found,
+                catcher_api::unprocessable_entity,
+                catcher_api::internal_error
+            ],
+        )
+        .mount("/person", routes);
+    Client::tracked(rocket).expect("cannot build a rocket client")
+}
+
+#[test]
+fn test_missing_person_is_not_found() {
+    let client = client_with_routes(rocket::routes![
+        person_api::get_person,
+        person_api::request_cab
+    ]);
+
+    // a well formed id that nobody has
+    let missing_id = ObjectId::new().to_hex();
+    let response = client.get(format!("/person/{}", missing_id)).dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+    let response = client
+        .get(format!("/person/request_cab/{}", missing_id))
+        .header(admin())
+        .dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+    let body: ErrorBody = response.into_json().expect("error body is not json");
+    assert_eq!(body.code, ErrorCode::PersonNotFound);
+
+    // and an id that can't even be parsed
+    let response = client.get("/person/not-an-object-id").dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+    let body: ErrorBody = response.into_json().expect("error body is not json");
+    assert_eq!(body.code, ErrorCode::InvalidObjectId);
+}
+
+#[test]
+fn test_error_bodies_carry_the_request_id() {
+    let client = client_with_routes(rocket::routes![
+        person_api::get_person,
+        person_api::create_person
+    ]);
+
+    // the id the client sent is echoed in the header and in the body
+    let response = client
+        .get("/person/not-an-object-id")
+        .header(Header::new(REQUEST_ID_HEADER, "my-request-1"))
+        .dispatch();
+    assert_eq!(
+        response.headers().get_one(REQUEST_ID_HEADER),
+        Some("my-request-1")
+    );
+    let body: ErrorBody = response.into_json().expect("error body is not json");
+    assert_eq!(body.request_id, "my-request-1");
+
+    // without one we still get an id back that matches the header
+    let response = client.get("/person/not-an-object-id").dispatch();
+    let header = response
+        .headers()
+        .get_one(REQUEST_ID_HEADER)
+        .expect("no request id header")
+        .to_string();
+    let body: ErrorBody = response.into_json().expect("error body is not json");
+    assert_eq!(body.request_id, header);
 
-    assert!(res.is_ok())
+    // catchers answer in the same shape
+    let response = client.get("/no/such/route").dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+    let body: ErrorBody = response.into_json().expect("error body is not json");
+    assert_eq!(body.code, ErrorCode::RouteNotFound);
+
+    let response = client
+        .post("/person/create")
+        .header(ContentType::JSON)
+        .body(r#"{ "name": 1 }"#)
+        .dispatch();
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+    let body: ErrorBody = response.into_json().expect("error body is not json");
+    assert_eq!(body.code, ErrorCode::InvalidRequestBody);
+}
+
+#[test]
+fn test_rides_follow_request_and_unassign() {
+    let db: BoxedRepo = Box::new(MemoryRepo::init());
+    let rocket = rocket::build()
+        .manage(db)
+        .manage(Travel::default())
+        .manage(Surge::default())
+        .manage(Tariff::default())
+        .manage(Tracker::default());
+    let state = State::get(&rocket).expect("cannot get the state");
+    let travel = State::get(&rocket).expect("cannot get the travel config");
+    let surge = State::get(&rocket).expect("cannot get the surge config");
+    let tariff = State::get(&rocket).expect("cannot get the tariff");
+    let tracker = State::get(&rocket).expect("cannot get the tracker");
+
+    let person = Person::new(
+        None,
+        generate_random_string(),
+        Point::new(0, 0),
+        Point::new(5, 5),
+    );
+    let Json(person_id) =
+        person_api::create_person(state, Json(person)).expect("cannot insert the person");
+
+    // nothing in the fleet, the request waits in the queue
+    let status = person_api::request_cab(
+        Caller::admin(),
+        state,
+        travel,
+        surge,
+        person_id.clone(),
+        None,
+    )
+    .expect("cannot request a cab")
+    .queued()
+    .expect("fleet is empty");
+    assert_eq!(status.position, Some(1));
+
+    // and gets one of the new cabs right away
+    cab_api::create_fleet(
+        Admin,
+        state,
+        travel,
+        cab_api::generate_fleet(Admin, state, 2),
+    )
+    .expect("cannot create fleet");
+    let ticket_id = status.ticket.id.expect("ticket has no id").to_hex();
+    let Json(status) =
+        queue_api::get_ticket(Caller::admin(), state, ticket_id).expect("cannot get the ticket");
+    assert_eq!(status.ticket.state, TicketState::Matched);
+    assert_eq!(status.position, None);
+    let Json(rides) = ride_api::get_rides_of_person(Caller::admin(), state, person_id.clone())
+        .expect("cannot get the rides");
+    assert_eq!(rides.len(), 1);
+    assert_eq!(rides[0].state, RideState::Assigned);
+    assert_eq!(rides[0].cab_id, status.ticket.cab_id);
+
+    let ride_id = rides[0].id.expect("ride has no id").to_hex();
+    let Json(ride) = ride_api::driver_arriving(Caller::admin(), state, ride_id.clone())
+        .expect("cannot mark driver arriving");
+    assert_eq!(ride.state, RideState::DriverArriving);
+
+    // dropping the person off fills in the pickup and completes the ride
+    let Json((_, _, fare)) =
+        person_api::unassign_cab(Caller::admin(), state, tariff, travel, tracker, person_id)
+            .expect("cannot unassign the cab");
+    let Json(ride) =
+        ride_api::get_ride(Caller::admin(), state, ride_id.clone()).expect("cannot get the ride");
+    assert_eq!(ride.state, RideState::Completed);
+    // the fare handed back is the one stored on the ride
+    assert_eq!(ride.fare, Some(fare.clone()));
+    assert_eq!(fare.distance_km, 7.07);
+    assert!(fare.total >= Tariff::default().minimum_fare);
+    let states = ride
+        .transitions
+        .iter()
+        .map(|t| t.state)
+        .collect::<Vec<RideState>>();
+    assert_eq!(
+        states,
+        vec![
+            RideState::Requested,
+            RideState::Assigned,
+            RideState::DriverArriving,
+            RideState::PickedUp,
+            RideState::Completed
+        ]
+    );
+    assert!(ride
+        .transitions
+        .windows(2)
+        .all(|w| w[0].timestamp <= w[1].timestamp));
+
+    // a completed ride can't be cancelled anymore
+    let err = ride_api::cancel_ride(Caller::admin(), state, travel, tracker, ride_id)
+        .expect_err("ride is already completed");
+    assert_eq!(err.status(), Status::Conflict);
+    assert_eq!(err.code(), ErrorCode::InvalidRideTransition);
+}
+
+#[test]
+fn test_cancelling_a_ride_frees_the_cab() {
+    let db: BoxedRepo = Box::new(MemoryRepo::init());
+    let rocket = rocket::build()
+        .manage(db)
+        .manage(Travel::default())
+        .manage(Surge::default())
+        .manage(Tracker::default());
+    let state = State::get(&rocket).expect("cannot get the state");
+    let travel = State::get(&rocket).expect("cannot get the travel config");
+    let surge = State::get(&rocket).expect("cannot get the surge config");
+    let tracker = State::get(&rocket).expect("cannot get the tracker");
+
+    cab_api::create_fleet(
+        Admin,
+        state,
+        travel,
+        cab_api::generate_fleet(Admin, state, 1),
+    )
+    .expect("cannot create fleet");
+    let person = Person::new(
+        None,
+        generate_random_string(),
+        Point::new(1, 1),
+        Point::new(5, 5),
+    );
+    let Json(person_id) =
+        person_api::create_person(state, Json(person)).expect("cannot insert the person");
+    let (_, cab) = person_api::request_cab(
+        Caller::admin(),
+        state,
+        travel,
+        surge,
+        person_id.clone(),
+        None,
+    )
+    .expect("cannot request a cab")
+    .assigned()
+    .expect("no cab was assigned");
+
+    let Json(rides) = ride_api::get_rides_of_person(Caller::admin(), state, person_id)
+        .expect("cannot get the rides");
+    let ride_id = rides[0].id.expect("ride has no id").to_hex();
+    let Json(ride) = ride_api::cancel_ride(Caller::admin(), state, travel, tracker, ride_id)
+        .expect("cannot cancel the ride");
+    assert_eq!(ride.state, RideState::Cancelled);
+
+    // the cab is free again and stays where it was
+    let cab_id = cab.id.expect("cab has no id").to_hex();
+    let Json(freed_cab) = cab_api::get_cab(state, cab_id).expect("cannot get the cab");
+    assert_eq!(freed_cab.person_id, None);
+    assert_eq!(freed_cab.destination, None);
+    assert_eq!(freed_cab.location, cab.location);
+}
+
+#[test]
+fn test_one_ride_at_a_time() {
+    let db: BoxedRepo = Box::new(MemoryRepo::init());
+    let rocket = rocket::build()
+        .manage(db)
+        .manage(Travel::default())
+        .manage(Surge::default())
+        .manage(Pooling::default())
+        .manage(Tariff::default())
+        .manage(Tracker::default());
+    let state = State::get(&rocket).expect("cannot get the state");
+    let travel = State::get(&rocket).expect("cannot get the travel config");
+    let surge = State::get(&rocket).expect("cannot get the surge config");
+    let pooling = State::get(&rocket).expect("cannot get the pooling config");
+    let tariff = State::get(&rocket).expect("cannot get the tariff");
+    let tracker = State::get(&rocket).expect("cannot get the tracker");
+
+    let person = Person::new(
+        None,
+        generate_random_string(),
+        Point::new(0, 0),
+        Point::new(5, 5),
+    );
+    let Json(person_id) =
+        person_api::create_person(state, Json(person)).expect("cannot insert the person");
+    let request = || {
+        person_api::request_cab(
+            Caller::admin(),
+            state,
+            travel,
+            surge,
+            person_id.clone(),
+            None,
+        )
+    };
+
+    // a queued request is a ride already
+    request()
+        .expect("cannot request a cab")
+        .queued()
+        .expect("fleet is empty");
+    let err = request().expect_err("the person is queued");
+    assert_eq!(err.status(), Status::Conflict);
+    assert_eq!(err.code(), ErrorCode::RideAlreadyActive);
+
+    // a cab was handed to the person through the test routes, the queued
+    // ride gets the other one. That one comes second in the fleet.
+    let mut by_hand = Cab::new(Point::new(0, 0));
+    by_hand.update_person_id(ObjectId::parse_str(&person_id).ok());
+    by_hand.update_destination(Some(Point::new(5, 5)));
+    let by_hand_id = state
+        .create_cab(by_hand)
+        .expect("cannot create the cab")
+        .inserted_id
+        .to_hex();
+    let Json(cab_ids) =
+        cab_api::create_fleet(Admin, state, travel, Json(vec![Cab::new(Point::new(9, 9))]))
+            .expect("cannot create fleet");
+    let ride_cab_id = cab_ids[0].clone().expect("cab has no id");
+    for err in [
+        request().expect_err("the person has a cab"),
+        person_api::request_pool(
+            Caller::admin(),
+            state,
+            travel,
+            pooling,
+            surge,
+            person_id.clone(),
+            None,
+        )
+        .expect_err("the person has a cab"),
+    ] {
+        assert_eq!(err.code(), ErrorCode::RideAlreadyActive);
+    }
+
+    // dropping them off frees the cab of the ride, not the first one of
+    // the fleet that has them
+    let Json((_, cab, _)) = person_api::unassign_cab(
+        Caller::admin(),
+        state,
+        tariff,
+        travel,
+        tracker,
+        person_id.clone(),
+    )
+    .expect("cannot unassign the cab");
+    assert_eq!(cab.id.map(|x| x.to_hex()), Some(ride_cab_id.clone()));
+    assert!(cab.is_free());
+    let Json(rides) = ride_api::get_rides_of_person(Caller::admin(), state, person_id.clone())
+        .expect("cannot get the rides");
+    assert_eq!(rides.len(), 1);
+    assert_eq!(rides[0].state, RideState::Completed);
+    let Json(by_hand) = cab_api::get_cab(state, by_hand_id).expect("cannot get the cab");
+    assert_eq!(by_hand.person_id.map(|x| x.to_hex()), Some(person_id));
+}
+
+#[test]
+fn test_request_cab_by_category() {
+    let db: BoxedRepo = Box::new(MemoryRepo::init());
+    let tariff = Tariff {
+        category_surcharges: std::collections::HashMap::from([("pink".to_string(), 2.0)]),
+        ..Tariff::default()
+    };
+    let rocket = rocket::build()
+        .manage(db)
+        .manage(Travel::default())
+        .manage(Surge::default())
+        .manage(tariff)
+        .manage(Tracker::default());
+    let state = State::get(&rocket).expect("cannot get the state");
+    let travel = State::get(&rocket).expect("cannot get the travel config");
+    let surge = State::get(&rocket).expect("cannot get the surge config");
+    let tariff = State::get(&rocket).expect("cannot get the tariff");
+    let tracker = State::get(&rocket).expect("cannot get the tracker");
+
+    // the standard cab is the nearest one, the pink one is further away
+    let fleet = vec![
+        Cab::new(Point::new(1, 0)),
+        Cab::with_category(Point::new(50, 50), CabCategory::Pink),
+    ];
+    cab_api::create_fleet(Admin, state, travel, Json(fleet)).expect("cannot create fleet");
+    let person = Person::new(
+        None,
+        generate_random_string(),
+        Point::new(0, 0),
+        Point::new(30, 40),
+    );
+    let Json(person_id) =
+        person_api::create_person(state, Json(person)).expect("cannot insert the person");
+
+    // without any xl cab in the fleet the request can't ever be served
+    let err = person_api::request_cab(
+        Caller::admin(),
+        state,
+        travel,
+        surge,
+        person_id.clone(),
+        Some("xl".into()),
+    )
+    .expect_err("there is no xl cab");
+    assert_eq!(err.status(), Status::Conflict);
+    assert_eq!(err.code(), ErrorCode::NoCabsInCategory);
+
+    // the standard cab doesn't do for somebody waiting on a busy xl one
+    let mut busy = Cab::with_category(Point::new(5, 5), CabCategory::Xl);
+    busy.update_person_id(Some(ObjectId::new()));
+    busy.update_destination(Some(Point::new(6, 6)));
+    state.create_cab(busy).expect("cannot create the cab");
+    let status = person_api::request_cab(
+        Caller::admin(),
+        state,
+        travel,
+        surge,
+        person_id.clone(),
+        Some("xl".into()),
+    )
+    .expect("cannot request an xl cab")
+    .queued()
+    .expect("the xl cab is busy");
+    assert_eq!(status.ticket.category, Some(CabCategory::Xl));
+    let ticket_id = status.ticket.id.expect("ticket has no id").to_hex();
+    let Json(status) = queue_api::cancel_ticket(Caller::admin(), state, ticket_id.clone())
+        .expect("cannot cancel the ticket");
+    assert_eq!(status.ticket.state, TicketState::Cancelled);
+    let err = queue_api::cancel_ticket(Caller::admin(), state, ticket_id)
+        .expect_err("ticket is cancelled");
+    assert_eq!(err.code(), ErrorCode::TicketNotWaiting);
+
+    let err = person_api::request_cab(
+        Caller::admin(),
+        state,
+        travel,
+        surge,
+        person_id.clone(),
+        Some("limo".into()),
+    )
+    .expect_err("limo is not a category");
+    assert_eq!(err.status(), Status::UnprocessableEntity);
+
+    let (_, cab) = person_api::request_cab(
+        Caller::admin(),
+        state,
+        travel,
+        surge,
+        person_id.clone(),
+        Some("pink".into()),
+    )
+    .expect("cannot request a pink cab")
+    .assigned()
+    .expect("no cab was assigned");
+    assert_eq!(cab.category, CabCategory::Pink);
+    assert_eq!(cab.location, Point::new(50, 50));
+
+    // the only pink cab is taken now
+    let other = Person::new(
+        None,
+        generate_random_string(),
+        Point::new(0, 0),
+        Point::new(1, 1),
+    );
+    let Json(other_id) =
+        person_api::create_person(state, Json(other)).expect("cannot insert the person");
+    let status = person_api::request_cab(
+        Caller::admin(),
+        state,
+        travel,
+        surge,
+        other_id.clone(),
+        Some("pink".into()),
+    )
+    .expect("cannot request a pink cab")
+    .queued()
+    .expect("the pink cab is taken");
+    assert_eq!(status.position, Some(1));
+
+    // pink cabs pay the surcharge of the tariff
+    let Json((_, _, fare)) =
+        person_api::unassign_cab(Caller::admin(), state, tariff, travel, tracker, person_id)
+            .expect("cannot unassign the cab");
+    assert_eq!(fare.distance_km, 50.0);
+    assert_eq!(
+        fare.surcharge,
+        fare.base_fare + fare.distance_charge + fare.time_charge
+    );
+
+    // and the freed pink cab goes on to the other person, the standard
+    // one right next to them doesn't
+    let Json(cab) = cab_api::get_cab(state, cab.id.expect("cab has no id").to_hex())
+        .expect("cannot get the cab");
+    assert_eq!(cab.person_id, Some(status.ticket.person_id));
+}
+
+#[test]
+fn test_request_cab_with_gps_positions() {
+    let db: BoxedRepo =
+        Box::new(IndexedRepo::new(Box::new(MemoryRepo::init())).expect("cannot build the index"));
+    let rocket = rocket::build()
+        .manage(db)
+        .manage(Travel::default())
+        .manage(Surge::default())
+        .manage(Tariff::default())
+        .manage(Tracker::default());
+    let state = State::get(&rocket).expect("cannot get the state");
+    let travel = State::get(&rocket).expect("cannot get the travel config");
+    let surge = State::get(&rocket).expect("cannot get the surge config");
+    let tariff = State::get(&rocket).expect("cannot get the tariff");
+    let tracker = State::get(&rocket).expect("cannot get the tracker");
+
+    let alexanderplatz = Point::geo(52.5219, 13.4132).expect("not a valid gps position");
+    let fleet = vec![
+        // a grid cab sitting at the numeric coordinates of the person
+        Cab::new(Point::new(52, 13)),
+        Cab::new(Point::geo(52.5163, 13.3777).expect("not a valid gps position")),
+        Cab::new(Point::geo(52.5200, 13.4050).expect("not a valid gps position")),
+    ];
+    cab_api::create_fleet(Admin, state, travel, Json(fleet)).expect("cannot create fleet");
+    let person = Person::new(
+        None,
+        generate_random_string(),
+        alexanderplatz.clone(),
+        Point::geo(52.5075, 13.3903).expect("not a valid gps position"),
+    );
+    let Json(person_id) =
+        person_api::create_person(state, Json(person)).expect("cannot insert the person");
+
+    let (_, cab) = person_api::request_cab(
+        Caller::admin(),
+        state,
+        travel,
+        surge,
+        person_id.clone(),
+        None,
+    )
+    .expect("cannot request a cab")
+    .assigned()
+    .expect("no cab was assigned");
+    assert_eq!(
+        cab.location,
+        Point::geo(52.5200, 13.4050).expect("not a valid gps position")
+    );
+
+    // the fare is charged for the great-circle distance in km
+    let Json((_, _, fare)) =
+        person_api::unassign_cab(Caller::admin(), state, tariff, travel, tracker, person_id)
+            .expect("cannot unassign the cab");
+    assert!(
+        (fare.distance_km - 2.23).abs() < 0.01,
+        "distance is {}",
+        fare.distance_km
+    );
+}
+
+#[test]
+fn test_invalid_gps_positions_are_rejected() {
+    let client = client_with_routes(rocket::routes![person_api::create_person]);
+    let bodies = [
+        r#"{ "name": "a", "location": { "lat": 91.0, "lon": 0.0 }, "destination": { "lat": 0.0, "lon": 0.0 } }"#,
+        r#"{ "name": "a", "location": { "lat": 0.0, "lon": 181.0 }, "destination": { "lat": 0.0, "lon": 0.0 } }"#,
+        // a trip from the grid to a gps position
+        r#"{ "name": "a", "location": { "x": 0, "y": 0 }, "destination": { "lat": 0.0, "lon": 0.0 } }"#,
+    ];
+    for body in bodies {
+        let response = client
+            .post("/person/create")
+            .header(ContentType::JSON)
+            .body(body)
+            .dispatch();
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let body: ErrorBody = response.into_json().expect("error body is not json");
+        assert_eq!(body.code, ErrorCode::InvalidRequestBody);
+    }
+
+    let response = client
+        .post("/person/create")
+        .header(ContentType::JSON)
+        .body(r#"{ "name": "a", "location": { "lat": 52.52, "lon": 13.4 }, "destination": { "lat": 48.85, "lon": 2.35 } }"#)
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+}
+
+#[test]
+fn test_request_cab_uses_the_configured_metric() {
+    // eight cabs 5 away in a straight line but 7 along the streets, and one
+    // 6 away either way
+    let mut fleet = [
+        (3, 4),
+        (4, 3),
+        (-3, 4),
+        (-4, 3),
+        (3, -4),
+        (4, -3),
+        (-3, -4),
+        (-4, -3),
+    ]
+    .into_iter()
+    .map(|x| Cab::new(Point::from_tuple(x)))
+    .collect::<Vec<Cab>>();
+    fleet.push(Cab::new(Point::new(0, 6)));
+
+    for (metric, expected) in [
+        (
+            Box::new(Euclidean) as Box<dyn DistanceMetric>,
+            Point::new(3, 4),
+        ),
+        (Box::new(Manhattan), Point::new(0, 6)),
+    ] {
+        let db: BoxedRepo = Box::new(
+            IndexedRepo::new(Box::new(MemoryRepo::init())).expect("cannot build the index"),
+        );
+        let rocket = rocket::build()
+            .manage(db)
+            .manage(Travel::new(metric, 30.0))
+            .manage(Surge::default());
+        let state = State::get(&rocket).expect("cannot get the state");
+        let travel = State::get(&rocket).expect("cannot get the travel config");
+        let surge = State::get(&rocket).expect("cannot get the surge config");
+
+        cab_api::create_fleet(Admin, state, travel, Json(fleet.clone()))
+            .expect("cannot create fleet");
+        let person = Person::new(
+            None,
+            generate_random_string(),
+            Point::new(0, 0),
+            Point::new(10, 10),
+        );
+        let Json(person_id) =
+            person_api::create_person(state, Json(person)).expect("cannot insert the person");
+        let (_, cab) = person_api::request_cab(
+            Caller::admin(),
+            state,
+            travel,
+            surge,
+            person_id.clone(),
+            None,
+        )
+        .expect("cannot request a cab")
+        .assigned()
+        .expect("no cab was assigned");
+        assert_eq!(cab.location, expected);
+
+        // 5 or 6 km at 30 km/h
+        let ride = person_api::active_ride(state, &person_id)
+            .expect("cannot get the ride")
+            .expect("the person has no ride");
+        let eta = ride.eta_minutes.expect("the ride has no eta");
+        let expected_eta = travel.dist(&expected, &Point::new(0, 0)) * 2.0;
+        assert!((eta - expected_eta).abs() < 1e-9, "eta is {}", eta);
+    }
 }
@@ -0,0 +1,374 @@
+use fuber::api::{auth_api, cab_api, catcher_api, person_api, ride_api};
+use fuber::auth::{Auth, Caller};
+use fuber::error::{ErrorBody, ErrorCode};
+use fuber::generate_random_string;
+use fuber::metric::Travel;
+use fuber::models::auth_model::{Claims, CreatedApiKey, Role};
+use fuber::models::person_model::Person;
+use fuber::models::point_model::Point;
+use fuber::models::ride_model::{Ride, RideState};
+use fuber::repository::fuber_repo::BoxedRepo;
+use fuber::repository::memory_repos::MemoryRepo;
+use fuber::request_id::RequestIdFairing;
+use fuber::surge::Surge;
+use fuber::tracking::Tracker;
+use jsonwebtoken::{EncodingKey, Header as JwtHeader};
+use mongodb::bson::oid::ObjectId;
+use rocket::http::{ContentType, Header, Status};
+use rocket::local::blocking::{Client, LocalResponse};
+use serde_json::json;
+use std::time::Duration;
+
+const JWT_SECRET: &str = "4uth";
+
+fn client() -> Client {
+    let db: BoxedRepo = Box::new(MemoryRepo::init());
+    let rocket = rocket::build()
+        .manage(db)
+        .manage(Travel::default())
+        .manage(Surge::default())
+        .manage(Tracker::default())
+        .manage(Auth::new(Some(JWT_SECRET)))
+        .attach(RequestIdFairing)
+        .register(
+            "/",
+            rocket::catchers![
+                catcher_api::unauthorized,
+                catcher_api::forbidden,
+                catcher_api::not_found,
+                catcher_api::unprocessable_entity,
+                catcher_api::internal_error
+            ],
+        )
+        .mount(
+            "/person",
+            rocket::routes![
+                person_api::create_person,
+                person_api::request_cab,
+                person_api::update_person,
+                person_api::delete_person
+            ],
+        )
+        .mount(
+            "/ride",
+            rocket::routes![
+                ride_api::get_ride,
+                ride_api::get_rides_of_person,
+                ride_api::driver_arriving
+            ],
+        )
+        .mount(
+            "/cab",
+            rocket::routes![
+                cab_api::create_cab,
+                cab_api::update_location,
+                cab_api::delete_fleet
+            ],
+        )
+        .mount(
+            "/auth",
+            rocket::routes![auth_api::create_api_key, auth_api::delete_api_key],
+        );
+    Client::tracked(rocket).expect("cannot build a rocket client")
+}
+
+fn bearer(token: &str) -> Header<'static> {
+    Header::new("Authorization", format!("Bearer {}", token))
+}
+
+fn token(caller: Caller) -> String {
+    Auth::new(Some(JWT_SECRET))
+        .issue(caller.role, caller.subject, Duration::from_secs(60))
+        .expect("cannot issue a token")
+}
+
+fn error_code(response: LocalResponse) -> ErrorCode {
+    response
+        .into_json::<ErrorBody>()
+        .expect("error body is not json")
+        .code
+}
+
+fn create_person(client: &Client) -> ObjectId {
+    let person = Person::new(
+        None,
+        generate_random_string(),
+        Point::new(0, 0),
+        Point::new(9, 9),
+    );
+    let response = client
+        .post("/person/create")
+        .header(ContentType::JSON)
+        .body(serde_json::to_string(&person).expect("cannot serialize the person"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let person_id = response.into_json::<String>().expect("not a person id");
+    ObjectId::parse_str(person_id).expect("not a person id")
+}
+
+fn create_cab(client: &Client, x: i64) -> ObjectId {
+    let response = client
+        .post("/cab/create")
+        .header(ContentType::JSON)
+        .header(bearer(&token(Caller::admin())))
+        .body(json!({ "location": { "x": x, "y": 0 }, "destination": null }).to_string())
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let cab_id = response.into_json::<String>().expect("not a cab id");
+    ObjectId::parse_str(cab_id).expect("not a cab id")
+}
+
+fn move_cab(client: &Client, cab_id: ObjectId, token: &str) -> Status {
+    client
+        .put(format!("/cab/update_location/{}", cab_id.to_hex()))
+        .header(ContentType::JSON)
+        .header(bearer(token))
+        .body(json!({ "x": 2, "y": 2 }).to_string())
+        .dispatch()
+        .status()
+}
+
+fn request_cab(client: &Client, person_id: ObjectId, token: &str) -> Status {
+    client
+        .get(format!("/person/request_cab/{}", person_id.to_hex()))
+        .header(bearer(token))
+        .dispatch()
+        .status()
+}
+
+#[test]
+fn test_callers_need_a_valid_token() {
+    let client = client();
+
+    let response = client.delete("/cab/delete_fleet").dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+    assert_eq!(error_code(response), ErrorCode::Unauthorized);
+    let response = client
+        .delete("/cab/delete_fleet")
+        .header(Header::new("Authorization", "Basic YWRtaW46YWRtaW4="))
+        .dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+
+    // signed with another secret, expired long ago or no api key we know
+    let forged = Auth::new(Some("another secret"))
+        .issue(Role::Admin, None, Duration::from_secs(60))
+        .expect("cannot issue a token");
+    let expired = jsonwebtoken::encode(
+        &JwtHeader::default(),
+        &Claims {
+            sub: None,
+            role: Role::Admin,
+            exp: 1_000,
+        },
+        &EncodingKey::from_secret(JWT_SECRET.as_bytes()),
+    )
+    .expect("cannot sign the token");
+    for token in [forged.as_str(), expired.as_str(), "not-a-key"] {
+        let response = client
+            .delete("/cab/delete_fleet")
+            .header(bearer(token))
+            .dispatch();
+        assert_eq!(response.status(), Status::Unauthorized, "{}", token);
+        assert_eq!(error_code(response), ErrorCode::Unauthorized);
+    }
+}
+
+#[test]
+fn test_admin_routes_need_an_admin() {
+    let client = client();
+    let person_id = create_person(&client);
+    let cab_id = create_cab(&client, 1);
+
+    for caller in [Caller::rider(person_id), Caller::driver(cab_id)] {
+        let response = client
+            .delete("/cab/delete_fleet")
+            .header(bearer(&token(caller)))
+            .dispatch();
+        assert_eq!(response.status(), Status::Forbidden);
+        assert_eq!(error_code(response), ErrorCode::Forbidden);
+    }
+    let response = client
+        .delete("/cab/delete_fleet")
+        .header(bearer(&token(Caller::admin())))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+}
+
+#[test]
+fn test_drivers_only_move_their_own_cab() {
+    let client = client();
+    let cab_id = create_cab(&client, 1);
+    let other_id = create_cab(&client, 2);
+
+    let driver = token(Caller::driver(cab_id));
+    assert_eq!(move_cab(&client, cab_id, &driver), Status::Ok);
+    assert_eq!(move_cab(&client, other_id, &driver), Status::Forbidden);
+    // a rider isn't a driver, even with the id of the cab
+    let rider = token(Caller {
+        role: Role::Rider,
+        subject: Some(cab_id),
+    });
+    assert_eq!(move_cab(&client, cab_id, &rider), Status::Forbidden);
+    assert_eq!(
+        move_cab(&client, other_id, &token(Caller::admin())),
+        Status::Ok
+    );
+}
+
+#[test]
+fn test_riders_only_request_their_own_cab() {
+    let client = client();
+    create_cab(&client, 1);
+    create_cab(&client, 2);
+    let person_id = create_person(&client);
+    let other_id = create_person(&client);
+
+    let rider = token(Caller::rider(person_id));
+    assert_eq!(request_cab(&client, other_id, &rider), Status::Forbidden);
+    assert_eq!(request_cab(&client, person_id, &rider), Status::Ok);
+    assert_eq!(
+        request_cab(&client, other_id, &token(Caller::admin())),
+        Status::Ok
+    );
+}
+
+#[test]
+fn test_api_keys_stand_in_for_tokens() {
+    let client = client();
+    create_cab(&client, 1);
+    let person_id = create_person(&client);
+    let other_id = create_person(&client);
+    let admin = token(Caller::admin());
+    let create_api_key = |body: serde_json::Value, token: &str| {
+        client
+            .post("/auth/api_keys")
+            .header(ContentType::JSON)
+            .header(bearer(token))
+            .body(body.to_string())
+            .dispatch()
+    };
+
+    let response = create_api_key(
+        json!({ "role": "rider", "subject": { "$oid": person_id.to_hex() } }),
+        &admin,
+    );
+    assert_eq!(response.status(), Status::Ok);
+    let api_key: CreatedApiKey = response.into_json().expect("api key is not json");
+    assert_eq!(api_key.role, Role::Rider);
+    assert_eq!(api_key.subject, Some(person_id));
+    assert_eq!(
+        request_cab(&client, other_id, &api_key.key),
+        Status::Forbidden
+    );
+    assert_eq!(request_cab(&client, person_id, &api_key.key), Status::Ok);
+    // riders don't hand out keys
+    let response = create_api_key(json!({ "role": "admin" }), &api_key.key);
+    assert_eq!(response.status(), Status::Forbidden);
+
+    // riders act for a person that is there
+    let response = create_api_key(json!({ "role": "rider" }), &admin);
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+    let response = create_api_key(
+        json!({ "role": "rider", "subject": { "$oid": ObjectId::new().to_hex() } }),
+        &admin,
+    );
+    assert_eq!(response.status(), Status::NotFound);
+
+    // a deleted key doesn't get anybody in anymore
+    let delete = format!("/auth/api_keys/{}", api_key.id.to_hex());
+    let response = client.delete(&delete).header(bearer(&admin)).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(
+        request_cab(&client, person_id, &api_key.key),
+        Status::Unauthorized
+    );
+    let response = client.delete(&delete).header(bearer(&admin)).dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+    assert_eq!(error_code(response), ErrorCode::ApiKeyNotFound);
+}
+
+#[test]
+fn test_riders_and_drivers_only_handle_their_own_rides() {
+    let client = client();
+    let cab_id = create_cab(&client, 1);
+    let other_cab_id = create_cab(&client, 2);
+    let person_id = create_person(&client);
+    let other_id = create_person(&client);
+    let rider = token(Caller::rider(person_id));
+    let other_rider = token(Caller::rider(other_id));
+    assert_eq!(request_cab(&client, person_id, &rider), Status::Ok);
+
+    let rides = format!("/ride/person/{}", person_id.to_hex());
+    let response = client.get(&rides).dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+    let response = client.get(&rides).header(bearer(&other_rider)).dispatch();
+    assert_eq!(response.status(), Status::Forbidden);
+    let response = client.get(&rides).header(bearer(&rider)).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let rides: Vec<Ride> = response.into_json().expect("rides are not json");
+    let ride_id = rides[0].id.expect("ride has no id").to_hex();
+    let ride = format!("/ride/{}", ride_id);
+    let response = client.get(&ride).header(bearer(&other_rider)).dispatch();
+    assert_eq!(response.status(), Status::Forbidden);
+    let response = client.get(&ride).header(bearer(&rider)).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    // only the driver of the cab moves the ride along
+    let arriving = format!("/ride/{}/driver_arriving", ride_id);
+    for token in [rider, token(Caller::driver(other_cab_id))] {
+        let response = client.put(&arriving).header(bearer(&token)).dispatch();
+        assert_eq!(response.status(), Status::Forbidden);
+    }
+    let response = client
+        .put(&arriving)
+        .header(bearer(&token(Caller::driver(cab_id))))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let ride: Ride = response.into_json().expect("ride is not json");
+    assert_eq!(ride.state, RideState::DriverArriving);
+}
+
+#[test]
+fn test_riders_only_change_their_own_person() {
+    let client = client();
+    let person_id = create_person(&client);
+    let other_id = create_person(&client);
+    let update = format!("/person/update_person/{}", person_id.to_hex());
+    let delete = format!("/person/delete_person/{}", person_id.to_hex());
+    let body = json!({
+        "name": "somebody",
+        "location": { "x": 1, "y": 1 },
+        "destination": { "x": 2, "y": 2 }
+    })
+    .to_string();
+
+    let response = client
+        .put(&update)
+        .header(ContentType::JSON)
+        .body(&body)
+        .dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+    let response = client
+        .put(&update)
+        .header(ContentType::JSON)
+        .header(bearer(&token(Caller::rider(other_id))))
+        .body(&body)
+        .dispatch();
+    assert_eq!(response.status(), Status::Forbidden);
+    let response = client
+        .delete(&delete)
+        .header(bearer(&token(Caller::rider(other_id))))
+        .dispatch();
+    assert_eq!(response.status(), Status::Forbidden);
+
+    let rider = token(Caller::rider(person_id));
+    let response = client
+        .put(&update)
+        .header(ContentType::JSON)
+        .header(bearer(&rider))
+        .body(&body)
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let response = client.delete(&delete).header(bearer(&rider)).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+}
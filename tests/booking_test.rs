@@ -0,0 +1,379 @@
+use fuber::api::{booking_api, cab_api, person_api, ride_api};
+use fuber::auth::{Admin, Auth, Caller};
+use fuber::error::ErrorCode;
+use fuber::generate_random_string;
+use fuber::metric::Travel;
+use fuber::models::booking_model::{Booking, BookingState, NewBooking};
+use fuber::models::cab_model::Cab;
+use fuber::models::person_model::Person;
+use fuber::models::point_model::Point;
+use fuber::models::ride_model::{now_millis, Ride, RideState};
+use fuber::repository::fuber_repo::BoxedRepo;
+use fuber::repository::memory_repos::MemoryRepo;
+use fuber::scheduler::Schedule;
+use fuber::tracking::Tracker;
+use rocket::http::{ContentType, Status};
+use rocket::local::blocking::Client;
+use rocket::serde::json::Json;
+use rocket::State;
+use std::time::Duration;
+
+mod common;
+
+use common::{admin, rider, JWT_SECRET};
+
+const MINUTE: i64 = 60 * 1000;
+
+// 15 minutes ahead, 5 units around the pickup and up to 40 units
+fn schedule() -> Schedule {
+    Schedule::new(
+        Duration::from_secs(15 * 60),
+        Duration::from_secs(10),
+        5.0,
+        40.0,
+    )
+}
+
+fn create_person(db: &State<BoxedRepo>) -> String {
+    let person = Person::new(
+        None,
+        generate_random_string(),
+        Point::new(0, 0),
+        Point::new(9, 9),
+    );
+    let Json(person_id) =
+        person_api::create_person(db, Json(person)).expect("cannot insert the person");
+    person_id
+}
+
+fn book(db: &State<BoxedRepo>, person_id: &str, pickup_at: i64, origin: Point) -> Booking {
+    let booking = NewBooking {
+        pickup_at,
+        origin,
+        destination: Point::new(20, 20),
+        category: None,
+    };
+    let Json(booking) =
+        booking_api::schedule_ride(Caller::admin(), db, person_id.to_string(), Json(booking))
+            .expect("cannot book the ride");
+    booking
+}
+
+fn booking(db: &State<BoxedRepo>, booking: &Booking) -> Booking {
+    let booking_id = booking.id.expect("booking has no id").to_hex();
+    let Json(booking) =
+        booking_api::get_booking(Caller::admin(), db, booking_id).expect("cannot get the booking");
+    booking
+}
+
+fn ride(db: &State<BoxedRepo>, booking: &Booking) -> Ride {
+    let ride_id = booking.ride_id.expect("booking has no ride").to_hex();
+    let Json(ride) = ride_api::get_ride(Caller::admin(), db, ride_id).expect("cannot get the ride");
+    ride
+}
+
+#[test]
+fn test_bookings_get_a_cab_lead_time_before_pickup() {
+    let db: BoxedRepo = Box::new(MemoryRepo::init());
+    let rocket = rocket::build().manage(db).manage(Travel::default());
+    let state = State::get(&rocket).expect("cannot get the state");
+    let travel = State::get(&rocket).expect("cannot get the travel config");
+    let schedule = schedule();
+
+    let person_id = create_person(state);
+    let now = now_millis();
+    let pickup_at = now + 60 * MINUTE;
+    let booked = book(state, &person_id, pickup_at, Point::new(50, 50));
+    assert_eq!(booked.state, BookingState::Scheduled);
+    let Json(cab_id) =
+        cab_api::create_cab(Admin, state, travel, Json(Cab::new(Point::new(52, 50))))
+            .expect("cannot create the cab");
+
+    // an hour ahead it isn't due yet, the cab stays free for others
+    booking_api::dispatch_due_bookings(state, travel, &schedule, now).expect("cannot dispatch");
+    let scheduled = booking(state, &booked);
+    assert_eq!(scheduled.state, BookingState::Scheduled);
+    assert_eq!(scheduled.attempts, 0);
+
+    booking_api::dispatch_due_bookings(state, travel, &schedule, pickup_at - 15 * MINUTE)
+        .expect("cannot dispatch");
+    let dispatched = booking(state, &booked);
+    assert_eq!(dispatched.state, BookingState::Dispatched);
+    let ride = ride(state, &dispatched);
+    assert_eq!(ride.state, RideState::Assigned);
+    assert_eq!(ride.cab_id.map(|x| x.to_hex()), Some(cab_id));
+    // the ride goes from the booked pickup, not where the person is now
+    assert_eq!(ride.pickup, Point::new(50, 50));
+    assert_eq!(ride.drop, Point::new(20, 20));
+
+    // a dispatched booking is done for the scheduler
+    booking_api::dispatch_due_bookings(state, travel, &schedule, pickup_at)
+        .expect("cannot dispatch");
+    let Json(rides) = ride_api::get_rides_of_person(Caller::admin(), state, person_id)
+        .expect("cannot get the rides");
+    assert_eq!(rides.len(), 1);
+}
+
+#[test]
+fn test_bookings_look_farther_for_a_cab_every_tick() {
+    let db: BoxedRepo = Box::new(MemoryRepo::init());
+    let rocket = rocket::build().manage(db).manage(Travel::default());
+    let state = State::get(&rocket).expect("cannot get the state");
+    let travel = State::get(&rocket).expect("cannot get the travel config");
+    let schedule = schedule();
+    assert_eq!(schedule.radius(0), 5.0);
+    assert_eq!(schedule.radius(2), 20.0);
+    assert_eq!(schedule.radius(10), 40.0);
+
+    let person_id = create_person(state);
+    let now = now_millis();
+    let pickup_at = now + 10 * MINUTE;
+    let booked = book(state, &person_id, pickup_at, Point::new(0, 0));
+    let Json(cab_id) = cab_api::create_cab(Admin, state, travel, Json(Cab::new(Point::new(12, 0))))
+        .expect("cannot create the cab");
+
+    // 5 and 10 units around the pickup there is no cab
+    for attempts in 1..=2 {
+        booking_api::dispatch_due_bookings(state, travel, &schedule, now).expect("cannot dispatch");
+        let scheduled = booking(state, &booked);
+        assert_eq!(scheduled.state, BookingState::Scheduled);
+        assert_eq!(scheduled.attempts, attempts);
+    }
+    let Json(cab) = cab_api::get_cab(state, cab_id.clone()).expect("cannot get the cab");
+    assert_eq!(cab.person_id, None);
+
+    booking_api::dispatch_due_bookings(state, travel, &schedule, now).expect("cannot dispatch");
+    let dispatched = booking(state, &booked);
+    assert_eq!(dispatched.state, BookingState::Dispatched);
+    assert_eq!(dispatched.attempts, 2);
+    assert_eq!(
+        ride(state, &dispatched).cab_id.map(|x| x.to_hex()),
+        Some(cab_id)
+    );
+}
+
+#[test]
+fn test_bookings_without_a_cab_at_pickup_are_queued() {
+    let db: BoxedRepo = Box::new(MemoryRepo::init());
+    let rocket = rocket::build().manage(db).manage(Travel::default());
+    let state = State::get(&rocket).expect("cannot get the state");
+    let travel = State::get(&rocket).expect("cannot get the travel config");
+    let schedule = schedule();
+
+    let person_id = create_person(state);
+    let now = now_millis();
+    let pickup_at = now + 10 * MINUTE;
+    let booked = book(state, &person_id, pickup_at, Point::new(0, 0));
+    // farther than the booking ever looks
+    let Json(cab_id) =
+        cab_api::create_cab(Admin, state, travel, Json(Cab::new(Point::new(100, 0))))
+            .expect("cannot create the cab");
+
+    booking_api::dispatch_due_bookings(state, travel, &schedule, now).expect("cannot dispatch");
+    assert_eq!(booking(state, &booked).state, BookingState::Scheduled);
+
+    // at the pickup any cab will do
+    booking_api::dispatch_due_bookings(state, travel, &schedule, pickup_at)
+        .expect("cannot dispatch");
+    let dispatched = booking(state, &booked);
+    assert_eq!(dispatched.state, BookingState::Dispatched);
+    assert_eq!(
+        ride(state, &dispatched).cab_id.map(|x| x.to_hex()),
+        Some(cab_id)
+    );
+
+    // and without any the ride waits in the queue for the next one
+    let booked = book(state, &person_id, pickup_at, Point::new(0, 0));
+    booking_api::dispatch_due_bookings(state, travel, &schedule, pickup_at)
+        .expect("cannot dispatch");
+    let dispatched = booking(state, &booked);
+    assert_eq!(dispatched.state, BookingState::Dispatched);
+    assert_eq!(ride(state, &dispatched).state, RideState::Requested);
+    let Json(cab_id) = cab_api::create_cab(Admin, state, travel, Json(Cab::new(Point::new(1, 0))))
+        .expect("cannot create the cab");
+    assert_eq!(
+        ride(state, &dispatched).cab_id.map(|x| x.to_hex()),
+        Some(cab_id)
+    );
+}
+
+#[test]
+fn test_cancelled_bookings_are_not_dispatched() {
+    let db: BoxedRepo = Box::new(MemoryRepo::init());
+    let rocket = rocket::build()
+        .manage(db)
+        .manage(Travel::default())
+        .manage(Tracker::default());
+    let state = State::get(&rocket).expect("cannot get the state");
+    let travel = State::get(&rocket).expect("cannot get the travel config");
+    let tracker = State::get(&rocket).expect("cannot get the tracker");
+    let schedule = schedule();
+
+    let person_id = create_person(state);
+    let now = now_millis();
+    let pickup_at = now + 10 * MINUTE;
+    let scheduled = book(state, &person_id, pickup_at, Point::new(0, 0));
+    let dispatched = book(state, &person_id, pickup_at, Point::new(0, 0));
+    let Json(cab_id) = cab_api::create_cab(Admin, state, travel, Json(Cab::new(Point::new(1, 0))))
+        .expect("cannot create the cab");
+
+    let booking_id = scheduled.id.expect("booking has no id").to_hex();
+    let Json(cancelled) =
+        booking_api::cancel_booking(Caller::admin(), state, travel, tracker, booking_id.clone())
+            .expect("cannot cancel the booking");
+    assert_eq!(cancelled.state, BookingState::Cancelled);
+    booking_api::dispatch_due_bookings(state, travel, &schedule, now).expect("cannot dispatch");
+    assert_eq!(booking(state, &scheduled).ride_id, None);
+    let dispatched = booking(state, &dispatched);
+    assert_eq!(dispatched.state, BookingState::Dispatched);
+
+    // cancelling a dispatched booking cancels its ride and frees the cab
+    let Json(cancelled) = booking_api::cancel_booking(
+        Caller::admin(),
+        state,
+        travel,
+        tracker,
+        dispatched.id.expect("booking has no id").to_hex(),
+    )
+    .expect("cannot cancel the booking");
+    assert_eq!(cancelled.state, BookingState::Cancelled);
+    assert_eq!(ride(state, &cancelled).state, RideState::Cancelled);
+    let Json(cab) = cab_api::get_cab(state, cab_id).expect("cannot get the cab");
+    assert_eq!(cab.person_id, None);
+
+    let err = booking_api::cancel_booking(Caller::admin(), state, travel, tracker, booking_id)
+        .expect_err("the booking is cancelled");
+    assert_eq!(err.status(), Status::Conflict);
+    assert_eq!(err.code(), ErrorCode::BookingAlreadyCancelled);
+
+    // nobody to pick up, nothing to dispatch
+    let booked = book(state, &person_id, pickup_at, Point::new(0, 0));
+    person_api::delete_person(Caller::admin(), state, person_id).expect("cannot delete the person");
+    booking_api::dispatch_due_bookings(state, travel, &schedule, now).expect("cannot dispatch");
+    let cancelled = booking(state, &booked);
+    assert_eq!(cancelled.state, BookingState::Cancelled);
+    assert_eq!(cancelled.ride_id, None);
+}
+
+#[test]
+fn test_upcoming_bookings_are_listed() {
+    let db: BoxedRepo = Box::new(MemoryRepo::init());
+    let rocket = rocket::build()
+        .manage(db)
+        .manage(Travel::default())
+        .manage(Tracker::default())
+        .manage(Auth::new(Some(JWT_SECRET)))
+        .mount("/person", rocket::routes![booking_api::schedule_ride])
+        .mount(
+            "/booking",
+            rocket::routes![
+                booking_api::get_upcoming_bookings,
+                booking_api::get_bookings_of_person,
+                booking_api::get_booking,
+                booking_api::cancel_booking
+            ],
+        );
+    let (person_id, other_id) = {
+        let state = State::get(&rocket).expect("cannot get the state");
+        (create_person(state), create_person(state))
+    };
+    let client = Client::tracked(rocket).expect("cannot build a rocket client");
+    let schedule_ride = |person_id: &str, body: String| {
+        client
+            .post(format!("/person/{}/schedule_ride", person_id))
+            .header(ContentType::JSON)
+            .header(rider(person_id))
+            .body(body)
+            .dispatch()
+    };
+
+    let pickup_at = now_millis() + 60 * MINUTE;
+    let response = schedule_ride(
+        &person_id,
+        format!(
+            r#"{{"pickup_at": {}, "origin": {{"x": 1, "y": 2}}, "destination": {{"x": 3, "y": 4}}}}"#,
+            pickup_at
+        ),
+    );
+    assert_eq!(response.status(), Status::Ok);
+    let booked: Booking = response.into_json().expect("booking is not json");
+    let response = schedule_ride(
+        &other_id,
+        format!(
+            r#"{{"pickup_at": {}, "origin": {{"x": 1, "y": 2}}, "destination": {{"x": 3, "y": 4}}, "category": "pink"}}"#,
+            pickup_at + MINUTE
+        ),
+    );
+    assert_eq!(response.status(), Status::Ok);
+    let other: Booking = response.into_json().expect("booking is not json");
+
+    // a pickup in the past or a trip from the grid to a gps position can't
+    // be booked
+    let response = schedule_ride(
+        &person_id,
+        r#"{"pickup_at": 1000, "origin": {"x": 1, "y": 2}, "destination": {"x": 3, "y": 4}}"#
+            .to_string(),
+    );
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+    let response = schedule_ride(
+        &person_id,
+        format!(
+            r#"{{"pickup_at": {}, "origin": {{"x": 1, "y": 2}}, "destination": {{"lat": 52.52, "lon": 13.4}}}}"#,
+            pickup_at
+        ),
+    );
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+
+    // the bookings of a person are theirs to see, all of them the admins'
+    let booking_id = booked.id.expect("booking has no id").to_hex();
+    for url in [
+        format!("/booking/person/{}", person_id),
+        format!("/booking/{}", booking_id),
+        "/booking/upcoming".to_string(),
+    ] {
+        let response = client.get(&url).dispatch();
+        assert_eq!(response.status(), Status::Unauthorized, "{}", url);
+        let response = client.get(&url).header(rider(&other_id)).dispatch();
+        assert_eq!(response.status(), Status::Forbidden, "{}", url);
+    }
+    let response = client
+        .get(format!("/booking/person/{}", person_id))
+        .header(rider(&person_id))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let bookings: Vec<Booking> = response.into_json().expect("bookings are not json");
+    assert_eq!(bookings, vec![booked.clone()]);
+    let response = client
+        .get(format!("/booking/{}", booking_id))
+        .header(rider(&person_id))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let response = client.get("/booking/upcoming").header(admin()).dispatch();
+    let bookings: Vec<Booking> = response.into_json().expect("bookings are not json");
+    assert_eq!(bookings, vec![booked.clone(), other]);
+
+    // only the rider who booked it can cancel it
+    let response = client
+        .put(format!("/booking/{}/cancel", booking_id))
+        .header(rider(&other_id))
+        .dispatch();
+    assert_eq!(response.status(), Status::Forbidden);
+    let response = client
+        .put(format!("/booking/{}/cancel", booking_id))
+        .header(rider(&person_id))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let response = client
+        .get(format!("/booking/person/{}", person_id))
+        .header(rider(&person_id))
+        .dispatch();
+    let bookings: Vec<Booking> = response.into_json().expect("bookings are not json");
+    assert!(bookings.is_empty());
+
+    let missing = mongodb::bson::oid::ObjectId::new().to_hex();
+    let response = client
+        .get(format!("/booking/{}", missing))
+        .header(admin())
+        .dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+}
@@ -0,0 +1,30 @@
+// What the test crates share. Every one of them builds its own copy and
+// uses only some of it.
+#![allow(dead_code)]
+
+use fuber::auth::Auth;
+use fuber::models::auth_model::Role;
+use mongodb::bson::oid::ObjectId;
+use rocket::http::Header;
+use std::time::Duration;
+
+// the secret the rockets of the tests check their tokens with
+pub const JWT_SECRET: &str = "t3st";
+
+fn bearer(role: Role, subject: Option<ObjectId>) -> Header<'static> {
+    let token = Auth::new(Some(JWT_SECRET))
+        .issue(role, subject, Duration::from_secs(60))
+        .expect("cannot issue a token");
+    Header::new("Authorization", format!("Bearer {}", token))
+}
+
+// the authorization header of an admin
+pub fn admin() -> Header<'static> {
+    bearer(Role::Admin, None)
+}
+
+// the authorization header of the rider of the person
+pub fn rider(person_id: &str) -> Header<'static> {
+    let person_id = ObjectId::parse_str(person_id).expect("not a person id");
+    bearer(Role::Rider, Some(person_id))
+}
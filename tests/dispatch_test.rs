@@ -0,0 +1,192 @@
+use fuber::api::{cab_api, person_api};
+use fuber::auth::{Admin, Caller};
+use fuber::dispatch::{min_cost_assignment, CabRequest, Dispatch};
+use fuber::generate_random_string;
+use fuber::metric::Travel;
+use fuber::models::cab_model::Cab;
+use fuber::models::person_model::Person;
+use fuber::models::point_model::Point;
+use fuber::models::ride_model::RideState;
+use fuber::repository::fuber_repo::BoxedRepo;
+use fuber::repository::memory_repos::MemoryRepo;
+use fuber::repository::shared_repo::SharedRepo;
+use fuber::surge::Surge;
+use rand::Rng;
+use rocket::serde::json::Json;
+use rocket::State;
+use std::collections::HashSet;
+use std::time::Duration;
+
+// (rows that got a column, their total cost) of the best assignment, by
+// trying every one of them
+fn brute_force(costs: &[Vec<f64>], row: usize, taken: &mut Vec<bool>) -> (usize, f64) {
+    if row == costs.len() {
+        return (0, 0.0);
+    }
+    // the row can always go without a column
+    let mut best = brute_force(costs, row + 1, taken);
+    for col in 0..taken.len() {
+        let cost = costs[row].get(col).copied().unwrap_or(f64::INFINITY);
+        if taken[col] || !cost.is_finite() {
+            continue;
+        }
+        taken[col] = true;
+        let (count, total) = brute_force(costs, row + 1, taken);
+        taken[col] = false;
+        let (count, total) = (count + 1, total + cost);
+        if count > best.0 || (count == best.0 && total < best.1) {
+            best = (count, total);
+        }
+    }
+    best
+}
+
+#[test]
+fn test_min_cost_assignment() {
+    // the greedy pick for the first row leaves the second one the far column
+    let costs = vec![vec![1.0, 3.0], vec![1.0, 5.0]];
+    assert_eq!(min_cost_assignment(&costs), vec![Some(1), Some(0)]);
+
+    // columns that can't be assigned are left alone, even when that leaves
+    // a row without one
+    let inf = f64::INFINITY;
+    let costs = vec![vec![1.0, inf], vec![2.0, inf]];
+    assert_eq!(min_cost_assignment(&costs), vec![Some(0), None]);
+    assert_eq!(min_cost_assignment(&[]), Vec::<Option<usize>>::new());
+    assert_eq!(min_cost_assignment(&[vec![], vec![]]), vec![None, None]);
+
+    let mut rng = rand::thread_rng();
+    for _ in 0..300 {
+        let rows = rng.gen_range(1..6);
+        let cols = rng.gen_range(1..6);
+        let costs = (0..rows)
+            .map(|_| {
+                (0..cols)
+                    .map(|_| {
+                        if rng.gen_bool(0.2) {
+                            f64::INFINITY
+                        } else {
+                            rng.gen_range(0.0..100.0)
+                        }
+                    })
+                    .collect::<Vec<f64>>()
+            })
+            .collect::<Vec<Vec<f64>>>();
+        let assigned = min_cost_assignment(&costs);
+
+        // every column at most once and only where it can go
+        let count = assigned.iter().flatten().count();
+        let unique = assigned.iter().flatten().collect::<HashSet<_>>();
+        assert_eq!(unique.len(), count, "{:?}", costs);
+        let total = assigned
+            .iter()
+            .enumerate()
+            .filter_map(|(row, col)| col.map(|col| costs[row][col]))
+            .sum::<f64>();
+        assert!(total.is_finite(), "{:?}", costs);
+
+        let (best_count, best_total) = brute_force(&costs, 0, &mut vec![false; cols]);
+        assert_eq!(count, best_count, "{:?}", costs);
+        assert!((total - best_total).abs() < 1e-9, "{:?}", costs);
+    }
+}
+
+#[rocket::async_test]
+async fn test_batched_dispatch_matches_the_whole_batch() {
+    for batched in [false, true] {
+        let db = SharedRepo::new(Box::new(MemoryRepo::init()));
+        let rocket = rocket::build()
+            .manage(Box::new(db.clone()) as BoxedRepo)
+            .manage(db)
+            .manage(Travel::default())
+            .manage(Surge::default())
+            .manage(Dispatch::batched(Duration::from_millis(50)));
+        let state = State::get(&rocket).expect("cannot get the state");
+        let shared = State::get(&rocket).expect("cannot get the shared repo");
+        let travel = State::get(&rocket).expect("cannot get the travel config");
+        let surge = State::get(&rocket).expect("cannot get the surge config");
+        let dispatch = State::get(&rocket).expect("cannot get the dispatch config");
+
+        // the cab at (1, 0) is the nearest for both a and b, but only b has
+        // no other cab nearby
+        let fleet = vec![Cab::new(Point::new(1, 0)), Cab::new(Point::new(-3, 0))];
+        cab_api::create_fleet(Admin, state, travel, Json(fleet)).expect("cannot create fleet");
+        let person_ids = [Point::new(0, 0), Point::new(2, 0), Point::new(100, 0)]
+            .into_iter()
+            .map(|location| {
+                let person =
+                    Person::new(None, generate_random_string(), location, Point::new(0, 0));
+                let Json(person_id) = person_api::create_person(state, Json(person))
+                    .expect("cannot insert the person");
+                person_id
+            })
+            .collect::<Vec<String>>();
+
+        let results = if batched {
+            let (a, b, c) = rocket::tokio::join!(
+                person_api::request_cab_batched(
+                    Caller::admin(),
+                    shared,
+                    travel,
+                    dispatch,
+                    surge,
+                    person_ids[0].clone(),
+                    None
+                ),
+                person_api::request_cab_batched(
+                    Caller::admin(),
+                    shared,
+                    travel,
+                    dispatch,
+                    surge,
+                    person_ids[1].clone(),
+                    None
+                ),
+                person_api::request_cab_batched(
+                    Caller::admin(),
+                    shared,
+                    travel,
+                    dispatch,
+                    surge,
+                    person_ids[2].clone(),
+                    None
+                ),
+            );
+            vec![a, b, c]
+        } else {
+            person_ids
+                .iter()
+                .map(|x| {
+                    person_api::request_cab(Caller::admin(), state, travel, surge, x.clone(), None)
+                })
+                .collect()
+        };
+        let locations = results
+            .into_iter()
+            .map(|x| match x.expect("cannot request a cab") {
+                CabRequest::Assigned(Json((_, cab))) => Some(cab.location),
+                CabRequest::Queued(_) => None,
+            })
+            .collect::<Vec<Option<Point>>>();
+
+        // one at a time a takes the cab b needed, 1 + 5 away, together
+        // they are 3 + 1 away and the far away c is queued either way
+        let expected = if batched {
+            vec![Some(Point::new(-3, 0)), Some(Point::new(1, 0)), None]
+        } else {
+            vec![Some(Point::new(1, 0)), Some(Point::new(-3, 0)), None]
+        };
+        assert_eq!(locations, expected);
+
+        // c's ride waits for a cab and a's knows how far its cab is
+        let ride = person_api::active_ride(state, &person_ids[2])
+            .expect("cannot get the ride")
+            .expect("c has no ride");
+        assert_eq!(ride.state, RideState::Requested);
+        let ride = person_api::active_ride(state, &person_ids[0])
+            .expect("cannot get the ride")
+            .expect("a has no ride");
+        let eta = if batched { 6.0 } else { 2.0 };
+        assert_eq!(ride.eta_minutes, Some(eta));
+    }
+}
@@ -0,0 +1,102 @@
+use fuber::api::{cab_api, catcher_api, event_api};
+use fuber::auth::Auth;
+use fuber::metric::Travel;
+use fuber::models::event_model::{EventKind, EventPage};
+use fuber::models::point_model::Point;
+use fuber::repository::fuber_repo::BoxedRepo;
+use fuber::repository::memory_repos::MemoryRepo;
+use fuber::tracking::Tracker;
+use rocket::http::{ContentType, Status};
+use rocket::local::blocking::Client;
+use serde_json::json;
+
+mod common;
+
+use common::{admin, JWT_SECRET};
+
+fn client() -> Client {
+    let db: BoxedRepo = Box::new(MemoryRepo::init());
+    let rocket = rocket::build()
+        .manage(db)
+        .manage(Auth::new(Some(JWT_SECRET)))
+        .manage(Travel::default())
+        .manage(Tracker::default())
+        .register("/", rocket::catchers![catcher_api::unprocessable_entity])
+        .mount(
+            "/cab",
+            rocket::routes![cab_api::create_cab, cab_api::update_location],
+        )
+        .mount("/events", rocket::routes![event_api::get_events]);
+    Client::tracked(rocket).expect("cannot build a rocket client")
+}
+
+fn page(client: &Client, query: &str) -> EventPage {
+    let response = client
+        .get(format!("/events{}", query))
+        .header(admin())
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    response
+        .into_json::<EventPage>()
+        .expect("not a page of events")
+}
+
+// consumers follow the feed by passing `next` on as `since`
+#[test]
+fn test_events_are_paged_with_a_cursor() {
+    let client = client();
+    let response = client
+        .post("/cab/create")
+        .header(admin())
+        .header(ContentType::JSON)
+        .body(json!({ "location": { "x": 0, "y": 0 }, "destination": null }).to_string())
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let cab_id = response.into_json::<String>().expect("not a cab id");
+    for x in 1..=4 {
+        let response = client
+            .put(format!("/cab/update_location/{}", cab_id))
+            .header(admin())
+            .header(ContentType::JSON)
+            .body(json!({ "x": x, "y": 0 }).to_string())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+    }
+
+    let first = page(&client, "?limit=2");
+    assert_eq!(
+        first.events.iter().map(|x| x.kind).collect::<Vec<_>>(),
+        vec![EventKind::CabCreated, EventKind::LocationUpdated]
+    );
+    assert_eq!(first.events[0].entity_id.to_hex(), cab_id);
+    assert_eq!(first.next, 2);
+    let second = page(&client, &format!("?since={}", first.next));
+    assert_eq!(second.events.len(), 3);
+    assert_eq!(second.next, 5);
+    let location = second.events[2]
+        .payload
+        .get("location")
+        .cloned()
+        .expect("the cab has no location");
+    assert_eq!(
+        mongodb::bson::from_bson::<Point>(location).expect("not a point"),
+        Point::new(4, 0)
+    );
+    // caught up, the cursor stays
+    let third = page(&client, &format!("?since={}", second.next));
+    assert!(third.events.is_empty());
+    assert_eq!(third.next, 5);
+    assert_eq!(page(&client, "").events.len(), 5);
+}
+
+#[test]
+fn test_events_reject_bad_cursors() {
+    let client = client();
+    for query in ["?since=-1", "?limit=0", "?limit=1001"] {
+        let response = client
+            .get(format!("/events{}", query))
+            .header(admin())
+            .dispatch();
+        assert_eq!(response.status(), Status::UnprocessableEntity, "{}", query);
+    }
+}
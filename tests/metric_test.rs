@@ -0,0 +1,79 @@
+use fuber::metric::{metric_by_name, Chebyshev, DistanceMetric, Euclidean, Haversine, Manhattan};
+use fuber::models::point_model::Point;
+
+fn metrics() -> Vec<Box<dyn DistanceMetric>> {
+    vec![
+        Box::new(Euclidean),
+        Box::new(Manhattan),
+        Box::new(Chebyshev),
+        Box::new(Haversine),
+    ]
+}
+
+fn random_geo_point() -> Point {
+    Point::geo(
+        52.52 + rand::random::<f64>() * 0.4 - 0.2,
+        13.40 + rand::random::<f64>() * 0.4 - 0.2,
+    )
+    .expect("not a valid gps position")
+}
+
+#[test]
+fn test_metrics_on_the_grid() {
+    let a = Point::new(0, 0);
+    let b = Point::new(3, -4);
+    assert_eq!(Euclidean.dist(&a, &b), 5.0);
+    assert_eq!(Manhattan.dist(&a, &b), 7.0);
+    assert_eq!(Chebyshev.dist(&a, &b), 4.0);
+    assert_eq!(Haversine.dist(&a, &b), 5.0);
+
+    let berlin = Point::geo(52.52, 13.405).expect("not a valid gps position");
+    for metric in metrics() {
+        assert_eq!(metric.dist(&a, &a), 0.0);
+        assert_eq!(metric.dist(&a, &berlin), f64::INFINITY);
+        // configured by the name it reports
+        let named = metric_by_name(metric.name()).expect("unknown metric");
+        assert_eq!(named.dist(&a, &b), metric.dist(&a, &b));
+    }
+    assert!(metric_by_name("road").is_err());
+}
+
+#[test]
+fn test_metrics_on_gps_positions() {
+    let berlin = Point::geo(52.5200, 13.4050).expect("not a valid gps position");
+    let potsdam = Point::geo(52.3906, 13.0645).expect("not a valid gps position");
+    let great_circle = Haversine.dist(&berlin, &potsdam);
+    assert!((great_circle - 27.0).abs() < 0.5, "{}", great_circle);
+    // the flat projection is close to the great circle over a city
+    let straight = Euclidean.dist(&berlin, &potsdam);
+    assert!((straight - great_circle).abs() / great_circle < 0.001);
+    assert!(Manhattan.dist(&berlin, &potsdam) > straight);
+    assert!(Chebyshev.dist(&berlin, &potsdam) < straight);
+
+    // the short way round the antimeridian
+    let east = Point::geo(0.0, 179.9).expect("not a valid gps position");
+    let west = Point::geo(0.0, -179.9).expect("not a valid gps position");
+    assert!(Euclidean.dist(&east, &west) < 25.0);
+}
+
+#[test]
+fn test_reach_covers_every_metric() {
+    // whatever a metric measures, `Point::dist` is never further than its
+    // reach, dispatch relies on it to see every candidate
+    let pairs = Point::create_random_points(200)
+        .into_iter()
+        .zip(Point::create_random_points(200))
+        .chain((0..200).map(|_| (random_geo_point(), random_geo_point())));
+    for (a, b) in pairs {
+        for metric in metrics() {
+            let dist = metric.dist(&a, &b);
+            assert!(
+                a.dist(&b) <= metric.reach(dist) + 1e-9,
+                "{} reach {} is short of {}",
+                metric.name(),
+                metric.reach(dist),
+                a.dist(&b)
+            );
+        }
+    }
+}
@@ -0,0 +1,347 @@
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use fuber::generate_random_string;
+use fuber::models::point_model::Point;
+use fuber::routing::cache;
+use fuber::routing::graph::{EdgeRecord, GraphFile, NodeRecord, RoadGraph};
+
+// (id, lat, lon) of the nodes in the extracts below
+const NODES: [(i64, f64, f64); 6] = [
+    (1, 52.52, 13.4),
+    (2, 52.521, 13.4),
+    (3, 52.522, 13.4),
+    (4, 52.522, 13.401),
+    (5, 52.522, 13.402),
+    (6, 52.523, 13.402),
+];
+
+// id, refs and tags of a way
+type Way = (i64, Vec<i64>, Vec<(&'static str, &'static str)>);
+
+// the ways of the extracts below, node 99 isn't in them
+fn ways() -> Vec<Way> {
+    vec![
+        (10, vec![1, 2, 3], vec![("highway", "residential")]),
+        (
+            11,
+            vec![3, 4],
+            vec![
+                ("highway", "primary"),
+                ("oneway", "yes"),
+                ("maxspeed", "50"),
+            ],
+        ),
+        (12, vec![4, 5], vec![("highway", "footway")]),
+        (
+            13,
+            vec![4, 99, 5, 6],
+            vec![
+                ("highway", "tertiary"),
+                ("oneway", "-1"),
+                ("maxspeed", "20 mph"),
+            ],
+        ),
+        (
+            14,
+            vec![1, 6],
+            vec![("highway", "service"), ("access", "private")],
+        ),
+    ]
+}
+
+// what the extract turns into, nodes are numbered in the order the roads
+// reach them and the footway, the private road and node 99 are left out
+fn expected() -> GraphFile {
+    let node = |id: u64, osm_id: usize| NodeRecord {
+        id,
+        location: Point::geo(NODES[osm_id - 1].1, NODES[osm_id - 1].2)
+            .expect("not a valid gps position"),
+    };
+    let edge = |from: u64, to: u64, speed_kmh: f64| EdgeRecord {
+        from,
+        to,
+        length_km: None,
+        speed_kmh,
+    };
+    GraphFile {
+        nodes: vec![
+            node(0, 1),
+            node(1, 2),
+            node(2, 3),
+            node(3, 4),
+            node(4, 5),
+            node(5, 6),
+        ],
+        edges: vec![
+            edge(0, 1, 25.0),
+            edge(1, 0, 25.0),
+            edge(1, 2, 25.0),
+            edge(2, 1, 25.0),
+            edge(2, 3, 50.0),
+            edge(5, 4, 20.0 * 1.609344),
+        ],
+    }
+}
+
+fn temp_path(extension: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("fuber-{}.{}", generate_random_string(), extension))
+}
+
+fn osm_xml() -> String {
+    let mut xml = String::from("<?xml version='1.0' encoding='UTF-8'?>\n<osm version=\"0.6\">\n");
+    for (id, lat, lon) in NODES {
+        xml += &format!("  <node id=\"{}\" lat=\"{}\" lon=\"{}\"/>\n", id, lat, lon);
+    }
+    for (id, refs, tags) in ways() {
+        xml += &format!("  <way id=\"{}\">\n", id);
+        for x in refs {
+            xml += &format!("    <nd ref=\"{}\"/>\n", x);
+        }
+        for (k, v) in tags {
+            xml += &format!("    <tag k=\"{}\" v=\"{}\"/>\n", k, v);
+        }
+        xml += "  </way>\n";
+    }
+    xml + "</osm>\n"
+}
+
+// just enough of a protocol buffer writer to build a pbf extract
+
+fn varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn number_field(out: &mut Vec<u8>, field: u64, value: u64) {
+    varint(out, field << 3);
+    varint(out, value);
+}
+
+fn bytes_field(out: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    varint(out, field << 3 | 2);
+    varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+fn packed_field(out: &mut Vec<u8>, field: u64, values: impl IntoIterator<Item = u64>) {
+    let mut packed = Vec::new();
+    values.into_iter().for_each(|x| varint(&mut packed, x));
+    bytes_field(out, field, &packed);
+}
+
+fn delta_field(out: &mut Vec<u8>, field: u64, values: impl IntoIterator<Item = i64>) {
+    let mut last = 0;
+    let deltas = values.into_iter().map(|x| {
+        let delta = x - last;
+        last = x;
+        zigzag(delta)
+    });
+    packed_field(out, field, deltas);
+}
+
+// 1e-7 degrees, what a block with the default granularity of 100 stores
+fn units(degrees: f64) -> i64 {
+    (degrees * 1e7).round() as i64
+}
+
+fn blob(kind: &str, data: &[u8], compress: bool) -> Vec<u8> {
+    let mut blob = Vec::new();
+    if compress {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).expect("cannot compress");
+        number_field(&mut blob, 2, data.len() as u64);
+        bytes_field(&mut blob, 3, &encoder.finish().expect("cannot compress"));
+    } else {
+        bytes_field(&mut blob, 1, data);
+    }
+    let mut header = Vec::new();
+    bytes_field(&mut header, 1, kind.as_bytes());
+    number_field(&mut header, 3, blob.len() as u64);
+
+    let mut out = (header.len() as u32).to_be_bytes().to_vec();
+    out.extend(header);
+    out.extend(blob);
+    out
+}
+
+// `positions` are the granularity and offset fields of the data block
+fn osm_pbf(features: &[&str], positions: &[(u64, u64)]) -> Vec<u8> {
+    let mut header = Vec::new();
+    for feature in features {
+        bytes_field(&mut header, 4, feature.as_bytes());
+    }
+
+    let mut strings = vec![""];
+    let mut string = |x: &'static str| match strings.iter().position(|s| *s == x) {
+        Some(idx) => idx as u64,
+        None => {
+            strings.push(x);
+            strings.len() as u64 - 1
+        }
+    };
+
+    // all but the last node are dense, the last one is a plain node
+    let (dense, plain) = NODES.split_at(NODES.len() - 1);
+    let mut dense_nodes = Vec::new();
+    delta_field(&mut dense_nodes, 1, dense.iter().map(|x| x.0));
+    delta_field(&mut dense_nodes, 8, dense.iter().map(|x| units(x.1)));
+    delta_field(&mut dense_nodes, 9, dense.iter().map(|x| units(x.2)));
+    let mut node = Vec::new();
+    number_field(&mut node, 1, zigzag(plain[0].0));
+    number_field(&mut node, 8, zigzag(units(plain[0].1)));
+    number_field(&mut node, 9, zigzag(units(plain[0].2)));
+    let mut nodes = Vec::new();
+    bytes_field(&mut nodes, 2, &dense_nodes);
+    bytes_field(&mut nodes, 1, &node);
+
+    let mut ways_group = Vec::new();
+    for (id, refs, tags) in ways() {
+        let mut way = Vec::new();
+        number_field(&mut way, 1, id as u64);
+        let keys = tags.iter().map(|x| string(x.0)).collect::<Vec<u64>>();
+        packed_field(&mut way, 2, keys);
+        let values = tags.iter().map(|x| string(x.1)).collect::<Vec<u64>>();
+        packed_field(&mut way, 3, values);
+        delta_field(&mut way, 8, refs);
+        bytes_field(&mut ways_group, 3, &way);
+    }
+
+    let mut string_table = Vec::new();
+    for x in strings {
+        bytes_field(&mut string_table, 1, x.as_bytes());
+    }
+    let mut block = Vec::new();
+    bytes_field(&mut block, 1, &string_table);
+    bytes_field(&mut block, 2, &nodes);
+    bytes_field(&mut block, 2, &ways_group);
+    for (field, value) in positions {
+        number_field(&mut block, *field, *value);
+    }
+
+    let mut pbf = blob("OSMHeader", &header, false);
+    pbf.extend(blob("OSMData", &block, true));
+    pbf
+}
+
+#[test]
+fn test_osm_extracts_are_imported() {
+    let xml = temp_path("osm");
+    fs::write(&xml, osm_xml()).expect("cannot write the extract");
+    let pbf = temp_path("osm.pbf");
+    fs::write(&pbf, osm_pbf(&["OsmSchema-V0.6", "DenseNodes"], &[]))
+        .expect("cannot write the extract");
+
+    for path in [&xml, &pbf] {
+        let file = GraphFile::load(path.to_str().expect("not a valid path"))
+            .expect("cannot read the extract");
+        assert_eq!(file, expected(), "{}", path.display());
+    }
+
+    // the one way streets only go one way
+    let roads =
+        RoadGraph::load(pbf.to_str().expect("not a valid path")).expect("cannot load the graph");
+    let at =
+        |x: usize| Point::geo(NODES[x - 1].1, NODES[x - 1].2).expect("not a valid gps position");
+    assert!(roads.route(&at(1), &at(4)).is_some());
+    assert!(roads.route(&at(4), &at(1)).is_none());
+    assert!(roads.route(&at(6), &at(5)).is_some());
+    assert!(roads.route(&at(5), &at(6)).is_none());
+
+    // extracts that need more than we can read are refused
+    fs::write(
+        &pbf,
+        osm_pbf(&["OsmSchema-V0.6", "HistoricalInformation"], &[]),
+    )
+    .expect("cannot write the extract");
+    assert!(GraphFile::load(pbf.to_str().expect("not a valid path")).is_err());
+
+    fs::remove_file(xml).ok();
+    fs::remove_file(pbf).ok();
+}
+
+// blocks whose nodes can't be placed are refused instead of wrapping around
+#[test]
+fn test_osm_extracts_with_broken_positions_are_refused() {
+    let pbf = temp_path("osm.pbf");
+    let features = ["OsmSchema-V0.6", "DenseNodes"];
+    // no granularity, a negative one and an offset that overflows
+    for positions in [
+        vec![(17, 0)],
+        vec![(17, -100i64 as u64)],
+        vec![(19, i64::MAX as u64)],
+    ] {
+        fs::write(&pbf, osm_pbf(&features, &positions)).expect("cannot write the extract");
+        assert!(GraphFile::load(pbf.to_str().expect("not a valid path")).is_err());
+    }
+    // the default granularity spelled out is fine
+    fs::write(&pbf, osm_pbf(&features, &[(17, 100)])).expect("cannot write the extract");
+    let file =
+        GraphFile::load(pbf.to_str().expect("not a valid path")).expect("cannot read the extract");
+    assert_eq!(file, expected());
+
+    fs::remove_file(pbf).ok();
+}
+
+#[test]
+fn test_preprocessed_graphs_load_the_same() {
+    let xml = temp_path("osm");
+    fs::write(&xml, osm_xml()).expect("cannot write the extract");
+    let cached = temp_path(cache::CACHE_EXTENSION);
+    let graph = cache::preprocess(xml.to_str().expect("not a valid path"), &cached)
+        .expect("cannot preprocess the extract");
+    assert_eq!(graph, expected());
+
+    let bytes = fs::read(&cached).expect("cannot read the cache");
+    assert!(cache::is_cache(&bytes));
+    assert!(bytes.len() < osm_xml().len() / 4);
+    let file =
+        GraphFile::load(cached.to_str().expect("not a valid path")).expect("cannot read the cache");
+    assert_eq!(file, expected());
+
+    // grid graphs and lengths make it through as well
+    let mut grid = GraphFile {
+        nodes: vec![
+            NodeRecord {
+                id: 7,
+                location: Point::new(-3, 4),
+            },
+            NodeRecord {
+                id: 300,
+                location: Point::new(i64::MAX, i64::MIN),
+            },
+        ],
+        edges: vec![EdgeRecord {
+            from: 300,
+            to: 7,
+            length_km: Some(2.5),
+            speed_kmh: 30.0,
+        }],
+    };
+    assert_eq!(cache::decode(&cache::encode(&grid)), Ok(grid.clone()));
+
+    // and broken caches are refused
+    grid.nodes.pop();
+    let bytes = cache::encode(&grid);
+    assert!(cache::decode(&bytes[..bytes.len() - 1]).is_err());
+    assert!(cache::decode(&bytes[1..]).is_err());
+
+    let name = cache::default_cache_path("/data/berlin.osm.pbf").expect("no path for the cache");
+    assert_eq!(
+        name.file_name().and_then(|x| x.to_str()),
+        Some("berlin.fgraph")
+    );
+
+    fs::remove_file(xml).ok();
+    fs::remove_file(cached).ok();
+}
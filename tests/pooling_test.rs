@@ -0,0 +1,337 @@
+use fuber::api::{cab_api, person_api, ride_api};
+use fuber::auth::{Admin, Caller};
+use fuber::dispatch::CabRequest;
+use fuber::generate_random_string;
+use fuber::metric::Travel;
+use fuber::models::cab_model::{Cab, StopKind};
+use fuber::models::event_model::EventKind;
+use fuber::models::person_model::Person;
+use fuber::models::point_model::Point;
+use fuber::models::ride_model::{Ride, RideState};
+use fuber::pooling::Pooling;
+use fuber::pricing::Tariff;
+use fuber::repository::fuber_repo::BoxedRepo;
+use fuber::repository::memory_repos::MemoryRepo;
+use fuber::surge::Surge;
+use fuber::tracking::Tracker;
+use mongodb::bson::oid::ObjectId;
+use rocket::serde::json::Json;
+use rocket::{Build, Rocket, State};
+
+// cabs drive 30 km/h by default, two minutes for every unit of the grid,
+// so a 10 minute detour is 5 units out of the way
+fn rocket() -> Rocket<Build> {
+    let db: BoxedRepo = Box::new(MemoryRepo::init());
+    rocket::build()
+        .manage(db)
+        .manage(Travel::default())
+        .manage(Surge::default())
+        .manage(Tariff::default())
+        .manage(Pooling::new(10.0))
+        .manage(Tracker::default())
+}
+
+fn create_cab(rocket: &Rocket<Build>, cab: Cab) -> String {
+    let db = State::get(rocket).expect("cannot get the state");
+    let travel = State::get(rocket).expect("cannot get the travel config");
+    let Json(cab_id) =
+        cab_api::create_cab(Admin, db, travel, Json(cab)).expect("cannot create the cab");
+    cab_id
+}
+
+fn create_person(rocket: &Rocket<Build>, location: Point, destination: Point) -> String {
+    let db = State::get(rocket).expect("cannot get the state");
+    let person = Person::new(None, generate_random_string(), location, destination);
+    let Json(person_id) =
+        person_api::create_person(db, Json(person)).expect("cannot insert the person");
+    person_id
+}
+
+fn request_pool(rocket: &Rocket<Build>, person_id: &str) -> CabRequest {
+    person_api::request_pool(
+        Caller::admin(),
+        State::get(rocket).expect("cannot get the state"),
+        State::get(rocket).expect("cannot get the travel config"),
+        State::get(rocket).expect("cannot get the pooling config"),
+        State::get(rocket).expect("cannot get the surge config"),
+        person_id.to_string(),
+        None,
+    )
+    .expect("cannot request a pooled cab")
+}
+
+fn get_cab(rocket: &Rocket<Build>, cab_id: &str) -> Cab {
+    let db = State::get(rocket).expect("cannot get the state");
+    let Json(cab) = cab_api::get_cab(db, cab_id.to_string()).expect("cannot get the cab");
+    cab
+}
+
+fn ride_of(rocket: &Rocket<Build>, person_id: &str) -> Ride {
+    let db = State::get(rocket).expect("cannot get the state");
+    let Json(rides) = ride_api::get_rides_of_person(Caller::admin(), db, person_id.to_string())
+        .expect("cannot get the rides");
+    rides.last().cloned().expect("person has no ride")
+}
+
+fn pick_up(rocket: &Rocket<Build>, ride: &Ride) {
+    let db = State::get(rocket).expect("cannot get the state");
+    let ride_id = ride.id.expect("ride has no id").to_hex();
+    ride_api::driver_arriving(Caller::admin(), db, ride_id.clone()).expect("cannot move the ride");
+    ride_api::picked_up(Caller::admin(), db, ride_id).expect("cannot move the ride");
+}
+
+// the new rider goes wherever the cab drives the least longer for them
+#[test]
+fn test_pooling_inserts_riders_on_the_way() {
+    let travel = Travel::default();
+    let pooling = Pooling::new(10.0);
+    let mut cab = Cab::new(Point::new(0, 0));
+    let first = ObjectId::new();
+    let insertion = pooling
+        .insert(&travel, &cab, first, &Point::new(1, 0), &Point::new(10, 0))
+        .expect("a free cab takes anybody");
+    assert_eq!(insertion.pickup_minutes, 2.0);
+    assert_eq!(insertion.added_minutes, 20.0);
+    cab.stops = insertion.stops;
+
+    // on the way, it costs nothing
+    let second = ObjectId::new();
+    let insertion = pooling
+        .insert(&travel, &cab, second, &Point::new(3, 0), &Point::new(8, 0))
+        .expect("the rider is on the way");
+    assert_eq!(insertion.added_minutes, 0.0);
+    assert_eq!(insertion.pickup_minutes, 6.0);
+    let stops = insertion
+        .stops
+        .iter()
+        .map(|x| (x.ride_id, x.kind))
+        .collect::<Vec<(ObjectId, StopKind)>>();
+    assert_eq!(
+        stops,
+        vec![
+            (first, StopKind::Pickup),
+            (second, StopKind::Pickup),
+            (second, StopKind::Drop),
+            (first, StopKind::Drop),
+        ]
+    );
+
+    // 6 units off the road the first rider gets to their drop 12 minutes
+    // late
+    assert_eq!(
+        pooling.insert(&travel, &cab, second, &Point::new(5, 6), &Point::new(8, 0)),
+        None
+    );
+    // which is fine for a more patient pool
+    assert!(Pooling::new(15.0)
+        .insert(&travel, &cab, second, &Point::new(5, 6), &Point::new(8, 0))
+        .is_some());
+}
+
+#[test]
+fn test_pooling_respects_seats() {
+    let travel = Travel::default();
+    let pooling = Pooling::new(10.0);
+    let mut cab = Cab::new(Point::new(0, 0)).with_seats(1);
+    let first = ObjectId::new();
+    cab.stops = pooling
+        .insert(&travel, &cab, first, &Point::new(1, 0), &Point::new(10, 0))
+        .expect("a free cab takes anybody")
+        .stops;
+
+    // nobody joins the first rider in a cab with one seat
+    assert_eq!(
+        pooling.insert(
+            &travel,
+            &cab,
+            ObjectId::new(),
+            &Point::new(3, 0),
+            &Point::new(8, 0)
+        ),
+        None
+    );
+    // or after the trip, the cab is on its way to the first drop
+    assert_eq!(
+        pooling.insert(
+            &travel,
+            &cab,
+            ObjectId::new(),
+            &Point::new(10, 1),
+            &Point::new(10, 5)
+        ),
+        None
+    );
+    cab.seats = 2;
+    assert!(pooling
+        .insert(
+            &travel,
+            &cab,
+            ObjectId::new(),
+            &Point::new(3, 0),
+            &Point::new(8, 0)
+        )
+        .is_some());
+}
+
+// riders share the cab while it suits everybody on board, the cab is free
+// again once the last one got dropped off
+#[test]
+fn test_request_pool_shares_cabs() {
+    let rocket = rocket();
+    let db: &State<BoxedRepo> = State::get(&rocket).expect("cannot get the state");
+    let tariff = State::get(&rocket).expect("cannot get the tariff");
+    let travel = State::get(&rocket).expect("cannot get the travel config");
+    let tracker = State::get(&rocket).expect("cannot get the tracker");
+    let cab_id = create_cab(&rocket, Cab::new(Point::new(0, 0)).with_seats(2));
+
+    let first = create_person(&rocket, Point::new(1, 0), Point::new(10, 0));
+    let (_, cab) = request_pool(&rocket, &first)
+        .assigned()
+        .expect("the cab is free");
+    assert_eq!(cab.id.map(|x| x.to_hex()), Some(cab_id.clone()));
+    assert_eq!(cab.destination, Some(Point::new(1, 0)));
+    let first_ride = ride_of(&rocket, &first);
+    assert_eq!(first_ride.state, RideState::Assigned);
+    assert_eq!(first_ride.eta_minutes, Some(2.0));
+
+    let second = create_person(&rocket, Point::new(3, 0), Point::new(8, 0));
+    let (_, cab) = request_pool(&rocket, &second)
+        .assigned()
+        .expect("the cab goes that way");
+    assert_eq!(cab.id.map(|x| x.to_hex()), Some(cab_id.clone()));
+    assert_eq!(cab.riders.len(), 2);
+    assert_eq!(cab.stops.len(), 4);
+    let second_ride = ride_of(&rocket, &second);
+    assert_eq!(second_ride.eta_minutes, Some(6.0));
+
+    // the cab is full, the third rider waits for it
+    let third = create_person(&rocket, Point::new(4, 0), Point::new(6, 0));
+    assert!(matches!(
+        request_pool(&rocket, &third),
+        CabRequest::Queued(_)
+    ));
+
+    pick_up(&rocket, &first_ride);
+    let cab = get_cab(&rocket, &cab_id);
+    assert_eq!(cab.location, Point::new(1, 0));
+    assert_eq!(cab.destination, Some(Point::new(3, 0)));
+    assert!(cab.riders.iter().any(|x| x.onboard));
+    pick_up(&rocket, &second_ride);
+
+    let Json((_, cab, fare)) =
+        person_api::unassign_cab(Caller::admin(), db, tariff, travel, tracker, second.clone())
+            .expect("cannot drop the rider off");
+    assert_eq!(cab.location, Point::new(8, 0));
+    assert_eq!(cab.destination, Some(Point::new(10, 0)));
+    assert_eq!(cab.riders.len(), 1);
+    assert!(fare.total > 0.0);
+    assert_eq!(ride_of(&rocket, &second).state, RideState::Completed);
+    assert!(matches!(
+        ride_of(&rocket, &third).state,
+        RideState::Requested
+    ));
+
+    let Json((_, cab, _)) =
+        person_api::unassign_cab(Caller::admin(), db, tariff, travel, tracker, first.clone())
+            .expect("cannot drop the rider off");
+    assert_eq!(cab.location, Point::new(10, 0));
+    assert!(cab.is_free());
+    // and it went straight to the queued request
+    let cab = get_cab(&rocket, &cab_id);
+    assert_eq!(cab.person_id.map(|x| x.to_hex()), Some(third.clone()));
+    assert_eq!(ride_of(&rocket, &third).state, RideState::Assigned);
+
+    // pooled rides are told about like any other
+    let rides = db
+        .get_events(0, 1000)
+        .expect("cannot get the events")
+        .into_iter()
+        .filter(|x| {
+            matches!(
+                x.kind,
+                EventKind::RideAssigned | EventKind::RideCompleted | EventKind::RideCancelled
+            )
+        })
+        .map(|x| (x.kind, x.person_id.map(|x| x.to_hex()).unwrap_or_default()))
+        .collect::<Vec<(EventKind, String)>>();
+    assert_eq!(
+        rides,
+        vec![
+            (EventKind::RideAssigned, first.clone()),
+            (EventKind::RideAssigned, second.clone()),
+            (EventKind::RideCompleted, second),
+            (EventKind::RideCompleted, first),
+            (EventKind::RideAssigned, third),
+        ]
+    );
+}
+
+// a rider too far out of the way gets a cab of their own
+#[test]
+fn test_request_pool_detours_take_another_cab() {
+    let rocket = rocket();
+    let pooled_id = create_cab(&rocket, Cab::new(Point::new(0, 0)));
+    let first = create_person(&rocket, Point::new(1, 0), Point::new(10, 0));
+    request_pool(&rocket, &first)
+        .assigned()
+        .expect("the cab is free");
+
+    let other_id = create_cab(&rocket, Cab::new(Point::new(0, 30)));
+    let second = create_person(&rocket, Point::new(0, 20), Point::new(0, 25));
+    let (_, cab) = request_pool(&rocket, &second)
+        .assigned()
+        .expect("the other cab is free");
+    assert_eq!(cab.id.map(|x| x.to_hex()), Some(other_id));
+    assert_eq!(get_cab(&rocket, &pooled_id).riders.len(), 1);
+}
+
+// cancelling takes the rider's stops off the cab
+#[test]
+fn test_cancelled_pooled_rides_leave_the_cab() {
+    let rocket = rocket();
+    let db: &State<BoxedRepo> = State::get(&rocket).expect("cannot get the state");
+    let travel = State::get(&rocket).expect("cannot get the travel config");
+    let tracker = State::get(&rocket).expect("cannot get the tracker");
+    let cab_id = create_cab(&rocket, Cab::new(Point::new(0, 0)));
+    let first = create_person(&rocket, Point::new(1, 0), Point::new(10, 0));
+    let second = create_person(&rocket, Point::new(3, 0), Point::new(8, 0));
+    request_pool(&rocket, &first)
+        .assigned()
+        .expect("the cab is free");
+    request_pool(&rocket, &second)
+        .assigned()
+        .expect("the cab goes that way");
+
+    let second_ride = ride_of(&rocket, &second);
+    let ride_id = second_ride.id.expect("ride has no id");
+    ride_api::cancel_ride(Caller::admin(), db, travel, tracker, ride_id.to_hex())
+        .expect("cannot cancel the ride");
+    let cab = get_cab(&rocket, &cab_id);
+    assert!(cab.rider(ride_id).is_none());
+    assert!(cab.stops.iter().all(|x| x.ride_id != ride_id));
+    assert_eq!(cab.stops.len(), 2);
+
+    let first_ride = ride_of(&rocket, &first);
+    ride_api::cancel_ride(
+        Caller::admin(),
+        db,
+        travel,
+        tracker,
+        first_ride.id.expect("ride has no id").to_hex(),
+    )
+    .expect("cannot cancel the ride");
+    let cab = get_cab(&rocket, &cab_id);
+    assert!(cab.is_free());
+    assert_eq!(cab.destination, None);
+    let cancelled = db
+        .get_events(0, 1000)
+        .expect("cannot get the events")
+        .into_iter()
+        .filter(|x| x.kind == EventKind::RideCancelled)
+        .map(|x| x.entity_id)
+        .collect::<Vec<ObjectId>>();
+    assert_eq!(
+        cancelled,
+        vec![ride_id, first_ride.id.expect("ride has no id")]
+    );
+}
@@ -0,0 +1,118 @@
+use fuber::metric::{Euclidean, Manhattan};
+use fuber::models::point_model::Point;
+use fuber::models::ride_model::{Ride, RideState, RideTransition};
+use fuber::pricing::Tariff;
+use mongodb::bson::oid::ObjectId;
+use std::collections::HashMap;
+
+fn tariff() -> Tariff {
+    Tariff {
+        base_fare: 3.0,
+        per_km: 1.5,
+        per_minute: 0.5,
+        minimum_fare: 8.0,
+        category_surcharges: HashMap::from([("pink".to_string(), 1.5)]),
+    }
+}
+
+#[test]
+fn test_fare_adds_up_the_rates() {
+    let fare = tariff().fare(10.0, 20.0, None);
+    assert_eq!(fare.base_fare, 3.0);
+    assert_eq!(fare.distance_charge, 15.0);
+    assert_eq!(fare.time_charge, 10.0);
+    assert_eq!(fare.surcharge, 0.0);
+    assert_eq!(fare.total, 28.0);
+
+    // categories without a surcharge pay the plain fare
+    assert_eq!(tariff().fare(10.0, 20.0, Some("suv")).total, 28.0);
+
+    let fare = tariff().fare(10.0, 20.0, Some("pink"));
+    assert_eq!(fare.surcharge, 14.0);
+    assert_eq!(fare.total, 42.0);
+}
+
+#[test]
+fn test_fare_never_goes_below_the_minimum() {
+    let fare = tariff().fare(0.5, 1.0, None);
+    assert_eq!(fare.total, 8.0);
+    assert_eq!(tariff().fare(0.0, 0.0, Some("pink")).total, 8.0);
+}
+
+// the surge multiplies the fare with its surcharge, but not the minimum
+#[test]
+fn test_surged_fare() {
+    let fare = tariff().surged_fare(10.0, 20.0, Some("pink"), 1.5);
+    assert_eq!(fare.surcharge, 14.0);
+    assert_eq!(fare.surge, 21.0);
+    assert_eq!(fare.total, 63.0);
+    assert_eq!(tariff().surged_fare(0.0, 0.0, None, 2.0).total, 8.0);
+    assert_eq!(
+        tariff().surged_fare(10.0, 20.0, None, 1.0),
+        tariff().fare(10.0, 20.0, None)
+    );
+
+    let mut ride = Ride::new(ObjectId::new(), Point::new(0, 0), Point::new(3, 4));
+    ride.surge_multiplier = Some(2.0);
+    ride.assign(ObjectId::new())
+        .expect("cannot assign the ride");
+    ride.complete().expect("cannot complete the ride");
+    let fare = tariff().fare_for_ride(&ride, None, &Euclidean);
+    assert_eq!(fare.surge, 10.5);
+    assert_eq!(fare.total, 21.0);
+}
+
+#[test]
+fn test_fare_for_ride_uses_distance_and_time_in_the_cab() {
+    let mut ride = Ride::new(ObjectId::new(), Point::new(0, 0), Point::new(3, 4));
+    ride.assign(ObjectId::new())
+        .expect("cannot assign the ride");
+    ride.move_to(RideState::DriverArriving)
+        .expect("cannot mark driver arriving");
+    // pin the timestamps so the ride took exactly 6 minutes
+    ride.transitions = vec![
+        RideTransition {
+            state: RideState::Requested,
+            timestamp: 0,
+        },
+        RideTransition {
+            state: RideState::Assigned,
+            timestamp: 1_000,
+        },
+        RideTransition {
+            state: RideState::DriverArriving,
+            timestamp: 2_000,
+        },
+        RideTransition {
+            state: RideState::PickedUp,
+            timestamp: 60_000,
+        },
+        RideTransition {
+            state: RideState::Completed,
+            timestamp: 420_000,
+        },
+    ];
+    let fare = tariff().fare_for_ride(&ride, None, &Euclidean);
+    assert_eq!(fare.distance_km, 5.0);
+    assert_eq!(fare.duration_minutes, 6.0);
+    assert_eq!(fare.total, 3.0 + 7.5 + 3.0);
+
+    // the distance is the one of the configured metric
+    let fare = tariff().fare_for_ride(&ride, None, &Manhattan);
+    assert_eq!(fare.distance_km, 7.0);
+}
+
+#[test]
+fn test_tariff_config() {
+    // anything left out falls back to the default
+    let tariff = Tariff::from_json(r#"{"per_km": 2.0, "category_surcharges": {"pink": 1.2}}"#)
+        .expect("cannot parse the tariff");
+    assert_eq!(tariff.per_km, 2.0);
+    assert_eq!(tariff.base_fare, Tariff::default().base_fare);
+    assert_eq!(tariff.surcharge_for(Some("pink")), 1.2);
+    assert_eq!(tariff.surcharge_for(None), 1.0);
+
+    assert!(Tariff::from_json(r#"{"per_km": -1.0}"#).is_err());
+    assert!(Tariff::from_json(r#"{"category_surcharges": {"pink": -2.0}}"#).is_err());
+    assert!(Tariff::from_json("not json").is_err());
+}
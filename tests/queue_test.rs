@@ -0,0 +1,232 @@
+use fuber::api::{cab_api, person_api, queue_api, ride_api};
+use fuber::auth::{Admin, Auth, Caller};
+use fuber::error::ErrorCode;
+use fuber::generate_random_string;
+use fuber::metric::Travel;
+use fuber::models::cab_model::Cab;
+use fuber::models::person_model::Person;
+use fuber::models::point_model::Point;
+use fuber::models::ride_model::RideState;
+use fuber::models::ticket_model::{TicketState, TicketStatus};
+use fuber::pricing::Tariff;
+use fuber::repository::fuber_repo::BoxedRepo;
+use fuber::repository::memory_repos::MemoryRepo;
+use fuber::surge::Surge;
+use fuber::tracking::Tracker;
+use mongodb::bson::oid::ObjectId;
+use rocket::http::Status;
+use rocket::local::blocking::Client;
+use rocket::serde::json::Json;
+use rocket::State;
+
+mod common;
+
+use common::{admin, rider, JWT_SECRET};
+
+fn create_person(db: &State<BoxedRepo>, location: Point) -> String {
+    let person = Person::new(None, generate_random_string(), location, Point::new(9, 9));
+    let Json(person_id) =
+        person_api::create_person(db, Json(person)).expect("cannot insert the person");
+    person_id
+}
+
+fn queue(
+    db: &State<BoxedRepo>,
+    travel: &State<Travel>,
+    surge: &State<Surge>,
+    person_id: &str,
+) -> TicketStatus {
+    person_api::request_cab(
+        Caller::admin(),
+        db,
+        travel,
+        surge,
+        person_id.to_string(),
+        None,
+    )
+    .expect("cannot request a cab")
+    .queued()
+    .expect("the request got a cab")
+}
+
+fn ticket(db: &State<BoxedRepo>, status: &TicketStatus) -> TicketStatus {
+    let ticket_id = status.ticket.id.expect("ticket has no id").to_hex();
+    let Json(status) =
+        queue_api::get_ticket(Caller::admin(), db, ticket_id).expect("cannot get the ticket");
+    status
+}
+
+#[test]
+fn test_queued_requests_are_served_first_come_first_served() {
+    let db: BoxedRepo = Box::new(MemoryRepo::init());
+    let rocket = rocket::build()
+        .manage(db)
+        .manage(Travel::default())
+        .manage(Surge::default())
+        .manage(Tariff::default())
+        .manage(Tracker::default());
+    let state = State::get(&rocket).expect("cannot get the state");
+    let travel = State::get(&rocket).expect("cannot get the travel config");
+    let surge = State::get(&rocket).expect("cannot get the surge config");
+    let tariff = State::get(&rocket).expect("cannot get the tariff");
+    let tracker = State::get(&rocket).expect("cannot get the tracker");
+
+    // the second one in line is closer to where the cab shows up, that
+    // doesn't get them ahead
+    let first = create_person(state, Point::new(10, 0));
+    let second = create_person(state, Point::new(1, 0));
+    let first_status = queue(state, travel, surge, &first);
+    let second_status = queue(state, travel, surge, &second);
+    assert_eq!(first_status.position, Some(1));
+    assert_eq!(second_status.position, Some(2));
+
+    let Json(cab_id) = cab_api::create_cab(Admin, state, travel, Json(Cab::new(Point::new(0, 0))))
+        .expect("cannot create the cab");
+    let first_status = ticket(state, &first_status);
+    assert_eq!(first_status.ticket.state, TicketState::Matched);
+    assert_eq!(
+        first_status.ticket.cab_id.map(|x| x.to_hex()),
+        Some(cab_id.clone())
+    );
+    let ride = person_api::active_ride(state, &first)
+        .expect("cannot get the ride")
+        .expect("the first person has no ride");
+    assert_eq!(ride.state, RideState::Assigned);
+    assert!(ride.eta_minutes.is_some());
+    assert_eq!(ticket(state, &second_status).position, Some(1));
+
+    // the cab coming back frees it for the next one in line
+    person_api::unassign_cab(Caller::admin(), state, tariff, travel, tracker, first)
+        .expect("cannot unassign the cab");
+    let second_status = ticket(state, &second_status);
+    assert_eq!(second_status.ticket.state, TicketState::Matched);
+    assert_eq!(
+        second_status.ticket.cab_id.map(|x| x.to_hex()),
+        Some(cab_id)
+    );
+}
+
+#[test]
+fn test_cancelled_requests_leave_the_queue() {
+    let db: BoxedRepo = Box::new(MemoryRepo::init());
+    let rocket = rocket::build()
+        .manage(db)
+        .manage(Travel::default())
+        .manage(Surge::default())
+        .manage(Tracker::default());
+    let state = State::get(&rocket).expect("cannot get the state");
+    let travel = State::get(&rocket).expect("cannot get the travel config");
+    let surge = State::get(&rocket).expect("cannot get the surge config");
+    let tracker = State::get(&rocket).expect("cannot get the tracker");
+
+    let by_ticket = create_person(state, Point::new(0, 0));
+    let by_ride = create_person(state, Point::new(0, 0));
+    let waiting = create_person(state, Point::new(0, 0));
+    let by_ticket_status = queue(state, travel, surge, &by_ticket);
+    let by_ride_status = queue(state, travel, surge, &by_ride);
+    let waiting_status = queue(state, travel, surge, &waiting);
+
+    // cancelling the ticket cancels its ride
+    let ticket_id = by_ticket_status
+        .ticket
+        .id
+        .expect("ticket has no id")
+        .to_hex();
+    let Json(status) = queue_api::cancel_ticket(Caller::admin(), state, ticket_id)
+        .expect("cannot cancel the ticket");
+    assert_eq!(status.ticket.state, TicketState::Cancelled);
+    assert_eq!(status.position, None);
+    let ride_id = status.ticket.ride_id.to_hex();
+    let Json(ride) =
+        ride_api::get_ride(Caller::admin(), state, ride_id).expect("cannot get the ride");
+    assert_eq!(ride.state, RideState::Cancelled);
+
+    // and cancelling the ride cancels its ticket
+    let ride_id = by_ride_status.ticket.ride_id.to_hex();
+    ride_api::cancel_ride(Caller::admin(), state, travel, tracker, ride_id)
+        .expect("cannot cancel the ride");
+    assert_eq!(
+        ticket(state, &by_ride_status).ticket.state,
+        TicketState::Cancelled
+    );
+    assert_eq!(ticket(state, &waiting_status).position, Some(1));
+
+    // neither of them gets the next cab
+    cab_api::create_fleet(Admin, state, travel, Json(vec![Cab::new(Point::new(0, 0))]))
+        .expect("cannot create the fleet");
+    let waiting_status = ticket(state, &waiting_status);
+    assert_eq!(waiting_status.ticket.state, TicketState::Matched);
+
+    // a matched ticket is cancelled through its ride
+    let ticket_id = waiting_status.ticket.id.expect("ticket has no id").to_hex();
+    let err = queue_api::cancel_ticket(Caller::admin(), state, ticket_id)
+        .expect_err("the ticket is matched");
+    assert_eq!(err.status(), Status::Conflict);
+    assert_eq!(err.code(), ErrorCode::TicketNotWaiting);
+}
+
+#[test]
+fn test_queued_requests_are_accepted() {
+    let db: BoxedRepo = Box::new(MemoryRepo::init());
+    let rocket = rocket::build()
+        .manage(db)
+        .manage(Travel::default())
+        .manage(Surge::default())
+        .manage(Auth::new(Some(JWT_SECRET)))
+        .mount("/person", rocket::routes![person_api::request_cab])
+        .mount(
+            "/queue",
+            rocket::routes![queue_api::get_ticket, queue_api::cancel_ticket],
+        );
+    let (person_id, other_id) = {
+        let state = State::get(&rocket).expect("cannot get the state");
+        (
+            create_person(state, Point::new(0, 0)),
+            create_person(state, Point::new(0, 0)),
+        )
+    };
+    let client = Client::tracked(rocket).expect("cannot build a rocket client");
+
+    let response = client
+        .get(format!("/person/request_cab/{}", person_id))
+        .header(rider(&person_id))
+        .dispatch();
+    assert_eq!(response.status(), Status::Accepted);
+    let status: TicketStatus = response.into_json().expect("ticket is not json");
+    assert_eq!(status.ticket.state, TicketState::Waiting);
+    assert_eq!(status.position, Some(1));
+
+    // nobody but the rider sees or cancels the ticket
+    let ticket_id = status.ticket.id.expect("ticket has no id").to_hex();
+    let response = client.get(format!("/queue/{}", ticket_id)).dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+    let response = client
+        .get(format!("/queue/{}", ticket_id))
+        .header(rider(&other_id))
+        .dispatch();
+    assert_eq!(response.status(), Status::Forbidden);
+    let response = client
+        .put(format!("/queue/{}/cancel", ticket_id))
+        .header(rider(&other_id))
+        .dispatch();
+    assert_eq!(response.status(), Status::Forbidden);
+    let response = client
+        .get(format!("/queue/{}", ticket_id))
+        .header(rider(&person_id))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let response = client
+        .put(format!("/queue/{}/cancel", ticket_id))
+        .header(rider(&person_id))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let status: TicketStatus = response.into_json().expect("ticket is not json");
+    assert_eq!(status.ticket.state, TicketState::Cancelled);
+
+    let missing = ObjectId::new().to_hex();
+    let response = client
+        .get(format!("/queue/{}", missing))
+        .header(admin())
+        .dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+}
@@ -0,0 +1,1047 @@
+use fuber::auth::hash_key;
+use fuber::error::{ErrorCode, FuberError};
+use fuber::generate_random_string;
+use fuber::metric::Euclidean;
+use fuber::models::auth_model::{ApiKey, Role};
+use fuber::models::booking_model::{Booking, BookingState, NewBooking};
+use fuber::models::cab_model::{Cab, CabCategory, Rider, Stop, StopKind};
+use fuber::models::event_model::{Event, EventKind};
+use fuber::models::person_model::Person;
+use fuber::models::point_model::{Point, GRID_BOUND};
+use fuber::models::ride_model::{Ride, RideState};
+use fuber::models::ticket_model::{Ticket, TicketState};
+use fuber::models::webhook_model::{DeliveryState, NewWebhook, Webhook};
+use fuber::pricing::Tariff;
+use fuber::repository::fuber_repo::FuberRepository;
+use fuber::repository::indexed_repo::IndexedRepo;
+use fuber::repository::memory_repos::MemoryRepo;
+use fuber::repository::sqlite_repos::SqliteRepo;
+use mongodb::bson::{self, oid::ObjectId};
+use serde_json::json;
+
+// inserting generates an id and the stored document can be read back by it
+#[test]
+fn test_memory_repo_generates_object_ids() {
+    let repo = MemoryRepo::init();
+
+    let cab_id = repo
+        .create_cab(Cab::new(Point::new(1, 2)))
+        .expect("cannot create a cab")
+        .inserted_id;
+    let cab = repo.get_cab(&cab_id.to_hex()).expect("cannot get the cab");
+    assert_eq!(cab.id, Some(cab_id));
+    assert_eq!(cab.location, Point::new(1, 2));
+
+    let fleet_ids = repo
+        .create_fleet(vec![Cab::new(Point::new(0, 0)), Cab::new(Point::new(3, 3))])
+        .expect("cannot create a fleet")
+        .inserted_ids;
+    assert_eq!(fleet_ids.len(), 2);
+    assert_ne!(fleet_ids[0], fleet_ids[1]);
+    assert_eq!(repo.get_fleet().expect("cannot get the fleet").len(), 3);
+}
+
+// updates and deletes only count the documents that matched the id
+#[test]
+fn test_memory_repo_matched_and_deleted_counts() {
+    let repo = MemoryRepo::init();
+    let person = Person::new(
+        None,
+        "shubham".to_string(),
+        Point::new(0, 0),
+        Point::new(10, 10),
+    );
+    let person_id = repo
+        .create_person(person.clone())
+        .expect("cannot create a person")
+        .inserted_id;
+
+    let update = repo
+        .update_person(Person::new(
+            Some(person_id),
+            "kumar".to_string(),
+            Point::new(1, 1),
+            Point::new(10, 10),
+        ))
+        .expect("cannot update the person");
+    assert_eq!(update.matched_count, 1);
+    assert_eq!(update.modified_count, 1);
+
+    let missing = repo
+        .update_person(Person::new(
+            Some(Default::default()),
+            "nobody".to_string(),
+            Point::new(0, 0),
+            Point::new(0, 0),
+        ))
+        .expect("cannot update the person");
+    assert_eq!(missing.matched_count, 0);
+
+    let deleted = repo
+        .delete_person(&person_id.to_hex())
+        .expect("cannot delete the person");
+    assert_eq!(deleted.deleted_count, 1);
+    let deleted_again = repo
+        .delete_person(&person_id.to_hex())
+        .expect("cannot delete the person");
+    assert_eq!(deleted_again.deleted_count, 0);
+}
+
+// a malformed id is an error instead of a silent miss
+#[test]
+fn test_memory_repo_rejects_invalid_ids() {
+    let repo = MemoryRepo::init();
+    assert_eq!(
+        repo.get_cab("not-an-object-id").map(|_| ()),
+        Err(FuberError::invalid_id("not-an-object-id"))
+    );
+    assert_eq!(
+        repo.get_person("not-an-object-id").map(|_| ()),
+        Err(FuberError::invalid_id("not-an-object-id"))
+    );
+    assert_eq!(
+        repo.delete_cab("not-an-object-id").map(|_| ()),
+        Err(FuberError::invalid_id("not-an-object-id"))
+    );
+}
+
+// every backend reports a missing document as NotFound instead of panicking
+#[test]
+fn test_repos_report_missing_documents() {
+    let repos: Vec<Box<dyn FuberRepository>> = vec![
+        Box::new(MemoryRepo::init()),
+        Box::new(SqliteRepo::open(":memory:").expect("cannot open sqlite")),
+    ];
+    let missing_id = ObjectId::new().to_hex();
+    for repo in repos {
+        assert_eq!(
+            repo.get_person(&missing_id).map(|_| ()),
+            Err(FuberError::person_not_found(&missing_id))
+        );
+        assert_eq!(
+            repo.get_cab(&missing_id).map(|_| ()),
+            Err(FuberError::cab_not_found(&missing_id))
+        );
+    }
+}
+
+// the sqlite backend hands out the same json as the other backends, with
+// `destination` as null and `person_id` left out while the cab is free
+#[test]
+fn test_sqlite_repo_json_shapes() {
+    let repo = SqliteRepo::open(":memory:").expect("cannot open sqlite");
+
+    let cab_id = repo
+        .create_cab(Cab::new(Point::new(4, 5)))
+        .expect("cannot create a cab")
+        .inserted_id;
+    let free_cab = repo.get_cab(&cab_id.to_hex()).expect("cannot get the cab");
+    assert_eq!(
+        serde_json::to_value(&free_cab).expect("cannot serialize the cab"),
+        json!({
+            "_id": { "$oid": cab_id.to_hex() },
+            "location": { "x": 4, "y": 5 },
+            "destination": null,
+            "category": "standard",
+            "seats": 4,
+        })
+    );
+
+    let person_id = repo
+        .create_person(Person::new(
+            None,
+            "shubham".to_string(),
+            Point::new(1, 1),
+            Point::new(9, 9),
+        ))
+        .expect("cannot create a person")
+        .inserted_id;
+    let mut assigned = free_cab.clone();
+    assigned.update_destination(Some(Point::new(1, 1)));
+    assigned.update_person_id(Some(person_id));
+    let update = repo
+        .assign_person(&cab_id.to_hex(), assigned.clone())
+        .expect("cannot assign the person");
+    assert_eq!(update.matched_count, 1);
+    assert_eq!(update.modified_count, 1);
+    assert_eq!(
+        repo.get_cab(&cab_id.to_hex()).expect("cannot get the cab"),
+        assigned
+    );
+
+    let update = repo
+        .unassign_person(&cab_id.to_hex(), Cab::new(Point::new(9, 9)))
+        .expect("cannot unassign the person");
+    assert_eq!(update.matched_count, 1);
+    let cab = repo.get_cab(&cab_id.to_hex()).expect("cannot get the cab");
+    assert_eq!(cab.location, Point::new(9, 9));
+    assert_eq!(cab.destination, None);
+    assert_eq!(cab.person_id, None);
+}
+
+// reopening an existing database doesn't re-run the migrations
+#[test]
+fn test_sqlite_repo_migrations_run_once() {
+    let path = std::env::temp_dir().join(format!("fuber-{}.db", generate_random_string()));
+    let path = path.to_str().expect("temp path is not utf-8").to_string();
+
+    let repo = SqliteRepo::open(&path).expect("cannot open sqlite");
+    repo.create_fleet(vec![Cab::new(Point::new(0, 0)), Cab::new(Point::new(1, 1))])
+        .expect("cannot create a fleet");
+    drop(repo);
+
+    let repo = SqliteRepo::open(&path).expect("cannot reopen sqlite");
+    assert_eq!(repo.get_fleet().expect("cannot get the fleet").len(), 2);
+    assert_eq!(repo.delete_fleet().expect("cannot delete").deleted_count, 2);
+
+    drop(repo);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_ride_state_machine() {
+    let mut ride = Ride::new(ObjectId::new(), Point::new(0, 0), Point::new(3, 4));
+    assert_eq!(ride.state, RideState::Requested);
+
+    // a ride can't skip the assignment
+    let err = ride
+        .move_to(RideState::PickedUp)
+        .expect_err("requested ride cannot be picked up");
+    assert_eq!(err.code(), ErrorCode::InvalidRideTransition);
+    assert_eq!(ride.state, RideState::Requested);
+    assert_eq!(ride.transitions.len(), 1);
+
+    let cab_id = ObjectId::new();
+    ride.assign(cab_id).expect("cannot assign the ride");
+    assert_eq!(ride.cab_id, Some(cab_id));
+    ride.move_to(RideState::DriverArriving)
+        .expect("cannot mark driver arriving");
+    ride.move_to(RideState::PickedUp)
+        .expect("cannot pick the person up");
+    // once the person sits in the cab the ride can only be completed
+    assert!(ride.move_to(RideState::Cancelled).is_err());
+    ride.complete().expect("cannot complete the ride");
+    assert!(!ride.state.is_active());
+    assert!(ride.move_to(RideState::Requested).is_err());
+    assert!(ride.entered_at(RideState::PickedUp).is_some());
+    assert_eq!(ride.transitions.len(), 5);
+}
+
+#[test]
+fn test_repos_store_rides() {
+    let repos: Vec<Box<dyn FuberRepository>> = vec![
+        Box::new(MemoryRepo::init()),
+        Box::new(SqliteRepo::open(":memory:").expect("cannot open sqlite")),
+    ];
+    for repo in repos {
+        let person_id = ObjectId::new();
+        let mut ride = Ride::new(person_id, Point::new(0, 0), Point::new(3, 4));
+        ride.surge_multiplier = Some(1.25);
+        ride.id = Some(
+            repo.create_ride(ride.clone())
+                .expect("cannot create the ride")
+                .inserted_id,
+        );
+        let ride_id = ride.id.expect("ride has no id").to_hex();
+        assert_eq!(repo.get_ride(&ride_id).expect("cannot get the ride"), ride);
+
+        ride.assign(ObjectId::new())
+            .expect("cannot assign the ride");
+        ride.complete().expect("cannot complete the ride");
+        ride.fare = Some(Tariff::default().fare_for_ride(&ride, None, &Euclidean));
+        let update = repo
+            .update_ride(ride.clone(), RideState::Requested)
+            .expect("cannot update");
+        assert_eq!(update.matched_count, 1);
+        assert_eq!(update.modified_count, 1);
+        assert_eq!(repo.get_ride(&ride_id).expect("cannot get the ride"), ride);
+        // a compare-and-set on the state, the ride isn't requested anymore
+        let mut cancelled = ride.clone();
+        cancelled.state = RideState::Cancelled;
+        let update = repo
+            .update_ride(cancelled, RideState::Requested)
+            .expect("cannot update");
+        assert_eq!(update.matched_count, 0);
+        assert_eq!(repo.get_ride(&ride_id).expect("cannot get the ride"), ride);
+
+        let other = Ride::new(ObjectId::new(), Point::new(1, 1), Point::new(2, 2));
+        repo.create_ride(other).expect("cannot create the ride");
+        assert_eq!(
+            repo.get_rides_of_person(&person_id.to_hex())
+                .expect("cannot get the rides"),
+            vec![ride]
+        );
+        let missing = ObjectId::new().to_hex();
+        assert_eq!(
+            repo.get_ride(&missing).map(|_| ()),
+            Err(FuberError::ride_not_found(&missing))
+        );
+    }
+}
+
+// rides getting a cab, ending and getting cancelled go into the feed and
+// the outbox, with the person and the fare to bill
+#[test]
+fn test_repos_record_ride_events() {
+    let repos: Vec<Box<dyn FuberRepository>> = vec![
+        Box::new(MemoryRepo::init()),
+        Box::new(SqliteRepo::open(":memory:").expect("cannot open sqlite")),
+    ];
+    for repo in repos {
+        let webhook_id = repo
+            .create_webhook(
+                Webhook::new(NewWebhook {
+                    url: "http://localhost/hook".to_string(),
+                    event_types: vec![EventKind::RideCompleted],
+                    secret: generate_random_string(),
+                })
+                .expect("not a valid webhook"),
+            )
+            .expect("cannot create the webhook")
+            .inserted_id;
+        let person_id = ObjectId::new();
+        let mut ride = Ride::new(person_id, Point::new(0, 0), Point::new(3, 4));
+        let ride_id = repo
+            .create_ride(ride.clone())
+            .expect("cannot create the ride")
+            .inserted_id;
+        ride.id = Some(ride_id);
+        ride.assign(ObjectId::new())
+            .expect("cannot assign the ride");
+        repo.update_ride(ride.clone(), RideState::Requested)
+            .expect("cannot update");
+        // nobody hears about the steps in between
+        ride.move_to(RideState::DriverArriving)
+            .expect("cannot move the ride");
+        repo.update_ride(ride.clone(), RideState::Assigned)
+            .expect("cannot update");
+        ride.complete().expect("cannot complete the ride");
+        let fare = Tariff::default().fare_for_ride(&ride, None, &Euclidean);
+        ride.fare = Some(fare.clone());
+        repo.update_ride(ride.clone(), RideState::DriverArriving)
+            .expect("cannot update");
+        // moved on already, nothing happens
+        repo.update_ride(ride.clone(), RideState::DriverArriving)
+            .expect("cannot update");
+
+        let mut other = Ride::new(person_id, Point::new(1, 1), Point::new(2, 2));
+        other.id = Some(
+            repo.create_ride(other.clone())
+                .expect("cannot create the ride")
+                .inserted_id,
+        );
+        other
+            .move_to(RideState::Cancelled)
+            .expect("cannot cancel the ride");
+        repo.update_ride(other, RideState::Requested)
+            .expect("cannot update");
+
+        let events = repo.get_events(0, 100).expect("cannot get the events");
+        assert_eq!(
+            events.iter().map(|x| x.kind).collect::<Vec<EventKind>>(),
+            vec![
+                EventKind::RideAssigned,
+                EventKind::RideCompleted,
+                EventKind::RideCancelled,
+            ]
+        );
+        assert!(events.iter().all(|x| x.person_id == Some(person_id)));
+        assert_eq!(events[1].entity_id, ride_id);
+        let completed: Ride =
+            bson::from_document(events[1].payload.clone()).expect("the payload is not a ride");
+        assert_eq!(completed.id, Some(ride_id));
+        assert_eq!(completed.fare, Some(fare));
+
+        let due = repo
+            .get_due_deliveries(i64::MAX, 100)
+            .expect("cannot get the deliveries");
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].webhook_id, webhook_id);
+        assert_eq!(due[0].event, events[1]);
+    }
+}
+
+#[test]
+fn test_repos_store_cab_categories() {
+    let repos: Vec<Box<dyn FuberRepository>> = vec![
+        Box::new(MemoryRepo::init()),
+        Box::new(SqliteRepo::open(":memory:").expect("cannot open sqlite")),
+    ];
+    for repo in repos {
+        let cab_id = repo
+            .create_cab(Cab::with_category(
+                Point::new(0, 0),
+                CabCategory::Accessible,
+            ))
+            .expect("cannot create a cab")
+            .inserted_id;
+        let mut cab = repo.get_cab(&cab_id.to_hex()).expect("cannot get the cab");
+        assert_eq!(cab.category, CabCategory::Accessible);
+
+        cab.category = CabCategory::Xl;
+        repo.update_cab(cab).expect("cannot update the cab");
+        let cab = repo.get_cab(&cab_id.to_hex()).expect("cannot get the cab");
+        assert_eq!(cab.category, CabCategory::Xl);
+        let has = |category| {
+            repo.has_cab_of_category(category)
+                .expect("cannot look for the category")
+        };
+        assert!(has(CabCategory::Xl));
+        assert!(!has(CabCategory::Accessible));
+        assert!(!has(CabCategory::Standard));
+    }
+
+    // cabs stored before there were categories are standard ones
+    let cab: Cab = serde_json::from_value(json!({
+        "location": { "x": 1, "y": 2 },
+        "destination": null,
+    }))
+    .expect("cannot deserialize the cab");
+    assert_eq!(cab.category, CabCategory::Standard);
+}
+
+// a driver keeps reporting where the cab is while it gets assigned, none of
+// those pings may hand the cab back to the fleet
+#[test]
+fn test_repos_keep_assignments_across_location_updates() {
+    let repos: Vec<Box<dyn FuberRepository>> = vec![
+        Box::new(MemoryRepo::init()),
+        Box::new(SqliteRepo::open(":memory:").expect("cannot open sqlite")),
+        Box::new(IndexedRepo::new(Box::new(MemoryRepo::init())).expect("cannot index")),
+    ];
+    for repo in repos {
+        let cab_id = repo
+            .create_cab(Cab::new(Point::new(0, 0)))
+            .expect("cannot create a cab")
+            .inserted_id
+            .to_hex();
+        let person_id = ObjectId::new();
+        let mut assigned = repo.get_cab(&cab_id).expect("cannot get the cab");
+        assigned.update_person_id(Some(person_id));
+        assigned.update_destination(Some(Point::new(9, 9)));
+
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                for x in 1..=50 {
+                    repo.update_location(&cab_id, Point::new(x, 0))
+                        .expect("cannot update the location");
+                }
+            });
+            s.spawn(|| {
+                let update = repo
+                    .assign_person(&cab_id, assigned.clone())
+                    .expect("cannot assign the cab");
+                assert_eq!(update.matched_count, 1);
+            });
+        });
+
+        let cab = repo.get_cab(&cab_id).expect("cannot get the cab");
+        assert_eq!(cab.person_id, Some(person_id));
+        assert_eq!(cab.destination, Some(Point::new(9, 9)));
+
+        // and the next ping still only moves the cab
+        let update = repo
+            .update_location(&cab_id, Point::new(3, 4))
+            .expect("cannot update the location");
+        assert_eq!(update.matched_count, 1);
+        let cab = repo.get_cab(&cab_id).expect("cannot get the cab");
+        assert_eq!(cab.location, Point::new(3, 4));
+        assert_eq!(cab.person_id, Some(person_id));
+        assert!(repo
+            .find_nearest_free_cabs(&Point::new(3, 4), 1, None, None)
+            .expect("cannot find the nearest cabs")
+            .is_empty());
+
+        let missing = ObjectId::new().to_hex();
+        let update = repo
+            .update_location(&missing, Point::new(0, 0))
+            .expect("cannot update the location");
+        assert_eq!(update.matched_count, 0);
+    }
+}
+
+#[test]
+fn test_gps_points() {
+    // berlin to paris is about 878 km as the crow flies
+    let berlin = Point::geo(52.5200, 13.4050).expect("berlin is a valid position");
+    let paris = Point::geo(48.8566, 2.3522).expect("paris is a valid position");
+    let dist = berlin.dist(&paris);
+    assert!((dist - 878.0).abs() < 2.0, "berlin to paris is {} km", dist);
+    assert_eq!(berlin.dist(&berlin), 0.0);
+    // grid points and gps positions don't mix
+    assert_eq!(berlin.dist(&Point::new(0, 0)), f64::INFINITY);
+
+    for (lat, lon) in [(91.0, 0.0), (-90.5, 0.0), (0.0, 181.0), (f64::NAN, 0.0)] {
+        let err = Point::geo(lat, lon).expect_err("not a valid gps position");
+        assert_eq!(err.code(), ErrorCode::InvalidRequestBody);
+    }
+
+    let json = serde_json::to_value(&berlin).expect("cannot serialize the point");
+    assert_eq!(json, json!({ "lat": 52.52, "lon": 13.405 }));
+    let point: Point = serde_json::from_value(json).expect("cannot deserialize the point");
+    assert_eq!(point, berlin);
+    let point: Point =
+        serde_json::from_value(json!({ "x": 1, "y": 2 })).expect("cannot deserialize the point");
+    assert_eq!(point, Point::new(1, 2));
+    assert!(serde_json::from_value::<Point>(json!({ "lat": 91.0, "lon": 0.0 })).is_err());
+    assert!(serde_json::from_value::<Point>(json!({ "x": 1.5, "y": 2 })).is_err());
+
+    // grid points have to fit into the 2d index of mongodb
+    assert_eq!(
+        Point::grid(-999_999, 999_999),
+        Ok(Point::new(-999_999, 999_999))
+    );
+    for (x, y) in [(GRID_BOUND, 0), (0, -GRID_BOUND), (i64::MIN, 0)] {
+        let err = Point::grid(x, y).expect_err("not within the grid");
+        assert_eq!(err.code(), ErrorCode::InvalidRequestBody);
+    }
+    assert!(serde_json::from_value::<Point>(json!({ "x": 1_000_000, "y": 2 })).is_err());
+}
+
+#[test]
+fn test_repos_store_gps_points() {
+    let repos: Vec<Box<dyn FuberRepository>> = vec![
+        Box::new(MemoryRepo::init()),
+        Box::new(SqliteRepo::open(":memory:").expect("cannot open sqlite")),
+    ];
+    let home = Point::geo(52.5200, 13.4050).expect("not a valid gps position");
+    let office = Point::geo(52.5163, 13.3777).expect("not a valid gps position");
+    for repo in repos {
+        let person = Person::new(None, generate_random_string(), home.clone(), office.clone());
+        let person_id = repo
+            .create_person(person)
+            .expect("cannot create the person")
+            .inserted_id;
+        let person = repo
+            .get_person(&person_id.to_hex())
+            .expect("cannot get the person");
+        assert_eq!(person.location, home);
+        assert_eq!(person.destination, office);
+
+        let fleet = vec![
+            Cab::new(Point::new(0, 0)),
+            Cab::new(office.clone()),
+            Cab::new(Point::geo(52.5201, 13.4051).expect("not a valid gps position")),
+        ];
+        let ids = repo
+            .create_fleet(fleet)
+            .expect("cannot create the fleet")
+            .inserted_ids;
+        let cab = repo.get_cab(&ids[1].to_hex()).expect("cannot get the cab");
+        assert_eq!(cab.location, office);
+
+        // nearest first, the grid cab is never near a gps position
+        let nearest = repo
+            .find_nearest_free_cabs(&home, 5, None, None)
+            .expect("cannot find the nearest cabs")
+            .into_iter()
+            .map(|x| x.id.expect("cab has no id"))
+            .collect::<Vec<ObjectId>>();
+        assert_eq!(nearest, vec![ids[2], ids[1]]);
+        let nearest = repo
+            .find_nearest_free_cabs(&home, 5, Some(1.0), None)
+            .expect("cannot find the nearest cabs");
+        assert_eq!(nearest.len(), 1);
+    }
+}
+
+#[test]
+fn test_repos_store_tickets() {
+    let repos: Vec<Box<dyn FuberRepository>> = vec![
+        Box::new(MemoryRepo::init()),
+        Box::new(SqliteRepo::open(":memory:").expect("cannot open sqlite")),
+    ];
+    for repo in repos {
+        // queued in the same millisecond, they still come back in order
+        let mut tickets = [None, Some(CabCategory::Pink), None]
+            .into_iter()
+            .map(|category| {
+                let mut ticket = Ticket::new(ObjectId::new(), ObjectId::new(), category);
+                ticket.created_at = 1000;
+                ticket.id = Some(
+                    repo.create_ticket(ticket.clone())
+                        .expect("cannot create the ticket")
+                        .inserted_id,
+                );
+                ticket
+            })
+            .collect::<Vec<Ticket>>();
+        let ticket_id = tickets[1].id.expect("ticket has no id").to_hex();
+        assert_eq!(
+            repo.get_ticket(&ticket_id).expect("cannot get the ticket"),
+            tickets[1]
+        );
+        assert_eq!(
+            repo.get_waiting_tickets()
+                .expect("cannot get the waiting tickets"),
+            tickets
+        );
+
+        // only a waiting ticket can be matched, and only once
+        let mut matched = tickets.remove(1);
+        matched.state = TicketState::Matched;
+        matched.cab_id = Some(ObjectId::new());
+        let update = repo
+            .update_ticket(matched.clone(), TicketState::Waiting)
+            .expect("cannot update");
+        assert_eq!(update.matched_count, 1);
+        assert_eq!(update.modified_count, 1);
+        let update = repo
+            .update_ticket(matched.clone(), TicketState::Waiting)
+            .expect("cannot update");
+        assert_eq!(update.matched_count, 0);
+        assert_eq!(
+            repo.get_ticket(&ticket_id).expect("cannot get the ticket"),
+            matched
+        );
+        assert_eq!(
+            repo.get_waiting_tickets()
+                .expect("cannot get the waiting tickets"),
+            tickets
+        );
+
+        let missing = ObjectId::new().to_hex();
+        assert_eq!(
+            repo.get_ticket(&missing).map(|_| ()),
+            Err(FuberError::ticket_not_found(&missing))
+        );
+    }
+}
+
+#[test]
+fn test_repos_store_bookings() {
+    let repos: Vec<Box<dyn FuberRepository>> = vec![
+        Box::new(MemoryRepo::init()),
+        Box::new(SqliteRepo::open(":memory:").expect("cannot open sqlite")),
+    ];
+    for repo in repos {
+        let person_id = ObjectId::new();
+        // booked out of order, they come back by pickup time
+        let mut bookings = [(3000, None), (1000, Some(CabCategory::Pink)), (2000, None)]
+            .into_iter()
+            .map(|(pickup_at, category)| {
+                let mut booking = Booking::new(
+                    person_id,
+                    NewBooking {
+                        pickup_at,
+                        origin: Point::new(0, 0),
+                        destination: Point::geo(52.5163, 13.3777)
+                            .expect("not a valid gps position"),
+                        category,
+                    },
+                );
+                booking.id = Some(
+                    repo.create_booking(booking.clone())
+                        .expect("cannot create the booking")
+                        .inserted_id,
+                );
+                booking
+            })
+            .collect::<Vec<Booking>>();
+        bookings.sort_by_key(|x| x.pickup_at);
+        let booking_id = bookings[0].id.expect("booking has no id").to_hex();
+        assert_eq!(
+            repo.get_booking(&booking_id)
+                .expect("cannot get the booking"),
+            bookings[0]
+        );
+        assert_eq!(
+            repo.get_bookings_of_person(&person_id.to_hex())
+                .expect("cannot get the bookings"),
+            bookings
+        );
+        assert_eq!(
+            repo.get_upcoming_bookings(1000)
+                .expect("cannot get the upcoming bookings"),
+            bookings[1..]
+        );
+        assert_eq!(
+            repo.get_scheduled_bookings(2000)
+                .expect("cannot get the scheduled bookings"),
+            bookings[..2]
+        );
+
+        // only a scheduled booking can be dispatched, and only once
+        let mut dispatched = bookings[0].clone();
+        dispatched.state = BookingState::Dispatched;
+        dispatched.attempts = 2;
+        dispatched.ride_id = Some(ObjectId::new());
+        let update = repo
+            .update_booking(dispatched.clone(), BookingState::Scheduled)
+            .expect("cannot update");
+        assert_eq!(update.matched_count, 1);
+        let update = repo
+            .update_booking(dispatched.clone(), BookingState::Scheduled)
+            .expect("cannot update");
+        assert_eq!(update.matched_count, 0);
+        assert_eq!(
+            repo.get_booking(&booking_id)
+                .expect("cannot get the booking"),
+            dispatched
+        );
+        assert_eq!(
+            repo.get_scheduled_bookings(2000)
+                .expect("cannot get the scheduled bookings"),
+            bookings[1..2]
+        );
+
+        // cancelled ones aren't upcoming anymore
+        let mut cancelled = bookings[2].clone();
+        cancelled.state = BookingState::Cancelled;
+        repo.update_booking(cancelled, BookingState::Scheduled)
+            .expect("cannot update");
+        assert_eq!(
+            repo.get_upcoming_bookings(0)
+                .expect("cannot get the upcoming bookings"),
+            vec![dispatched, bookings[1].clone()]
+        );
+
+        let missing = ObjectId::new().to_hex();
+        assert_eq!(
+            repo.get_booking(&missing).map(|_| ()),
+            Err(FuberError::booking_not_found(&missing))
+        );
+    }
+}
+
+// a shared cab is claimed by the riders and stops it had, only one of two
+// riders that want the same seat gets it
+#[test]
+fn test_repos_store_pooled_cabs() {
+    let repos: Vec<Box<dyn FuberRepository>> = vec![
+        Box::new(MemoryRepo::init()),
+        Box::new(SqliteRepo::open(":memory:").expect("cannot open sqlite")),
+        Box::new(IndexedRepo::new(Box::new(MemoryRepo::init())).expect("cannot build the index")),
+    ];
+    for repo in repos {
+        let cab_id = repo
+            .create_cab(Cab::with_category(Point::new(0, 0), CabCategory::Pink).with_seats(2))
+            .expect("cannot create a cab")
+            .inserted_id;
+        let free = repo.get_cab(&cab_id.to_hex()).expect("cannot get the cab");
+        assert_eq!(free.seats, 2);
+        assert!(repo
+            .get_pooled_cabs(None)
+            .expect("cannot get the pooled cabs")
+            .is_empty());
+
+        let ride_id = ObjectId::new();
+        let mut pooled = free.clone();
+        pooled.riders.push(Rider {
+            person_id: ObjectId::new(),
+            ride_id,
+            onboard: false,
+        });
+        pooled.stops = vec![
+            Stop {
+                ride_id,
+                kind: StopKind::Pickup,
+                location: Point::new(1, 1),
+            },
+            Stop {
+                ride_id,
+                kind: StopKind::Drop,
+                location: Point::new(5, 5),
+            },
+        ];
+        pooled.update_destination(Some(Point::new(1, 1)));
+        let update = repo
+            .update_pool(pooled.clone(), &free)
+            .expect("cannot update the pool");
+        assert_eq!(update.matched_count, 1);
+        let update = repo
+            .update_pool(pooled.clone(), &free)
+            .expect("cannot update the pool");
+        assert_eq!(update.matched_count, 0);
+        assert_eq!(
+            repo.get_cab(&cab_id.to_hex()).expect("cannot get the cab"),
+            pooled
+        );
+
+        // shared cabs aren't free, not even for a person of their own
+        assert!(repo
+            .find_nearest_free_cabs(&Point::new(0, 0), 5, None, None)
+            .expect("cannot find the free cabs")
+            .is_empty());
+        let mut assigned = pooled.clone();
+        assigned.update_person_id(Some(ObjectId::new()));
+        let update = repo
+            .assign_person(&cab_id.to_hex(), assigned)
+            .expect("cannot assign the person");
+        assert_eq!(update.matched_count, 0);
+        assert_eq!(
+            repo.get_pooled_cabs(Some(CabCategory::Pink))
+                .expect("cannot get the pooled cabs"),
+            vec![pooled.clone()]
+        );
+        assert!(repo
+            .get_pooled_cabs(Some(CabCategory::Standard))
+            .expect("cannot get the pooled cabs")
+            .is_empty());
+
+        // the last rider out frees the cab again
+        let mut emptied = pooled.clone();
+        emptied.leave(ride_id);
+        repo.update_pool(emptied.clone(), &pooled)
+            .expect("cannot update the pool");
+        assert_eq!(
+            repo.find_nearest_free_cabs(&Point::new(0, 0), 5, None, None)
+                .expect("cannot find the free cabs"),
+            vec![emptied]
+        );
+    }
+}
+
+// every change to cabs and persons lands in the feed once, in order, and
+// what didn't change anything doesn't
+#[test]
+fn test_repos_record_events() {
+    let repos: Vec<Box<dyn FuberRepository>> = vec![
+        Box::new(MemoryRepo::init()),
+        Box::new(SqliteRepo::open(":memory:").expect("cannot open sqlite")),
+        Box::new(IndexedRepo::new(Box::new(MemoryRepo::init())).expect("cannot build the index")),
+    ];
+    for repo in repos {
+        let person = Person::new(
+            None,
+            generate_random_string(),
+            Point::new(1, 1),
+            Point::new(4, 4),
+        );
+        let person_id = repo
+            .create_person(person.clone())
+            .expect("cannot create a person")
+            .inserted_id;
+        let cab_id = repo
+            .create_cab(Cab::new(Point::new(0, 0)))
+            .expect("cannot create a cab")
+            .inserted_id;
+
+        let mut cab = repo.get_cab(&cab_id.to_hex()).expect("cannot get the cab");
+        cab.update_person_id(Some(person_id));
+        cab.update_destination(Some(Point::new(1, 1)));
+        repo.assign_person(&cab_id.to_hex(), cab.clone())
+            .expect("cannot assign the person");
+        // taken already, nothing happens
+        repo.assign_person(&cab_id.to_hex(), cab.clone())
+            .expect("cannot assign the person");
+        cab.update_location(Point::new(1, 1));
+        repo.update_cab(cab.clone()).expect("cannot update the cab");
+        repo.update_cab(cab.clone()).expect("cannot update the cab");
+        cab.update_location(Point::new(4, 4));
+        repo.unassign_person(&cab_id.to_hex(), cab.clone())
+            .expect("cannot unassign the person");
+        repo.delete_cab(&cab_id.to_hex())
+            .expect("cannot delete the cab");
+        repo.delete_cab(&cab_id.to_hex())
+            .expect("cannot delete the cab");
+        repo.delete_all_people().expect("cannot delete the people");
+
+        let events = repo.get_events(0, 100).expect("cannot get the events");
+        assert_eq!(
+            events.iter().map(|x| x.kind).collect::<Vec<EventKind>>(),
+            vec![
+                EventKind::PersonCreated,
+                EventKind::CabCreated,
+                EventKind::PersonAssigned,
+                EventKind::CabUpdated,
+                EventKind::PersonUnassigned,
+                EventKind::CabDeleted,
+                EventKind::PersonDeleted,
+            ]
+        );
+        assert_eq!(
+            events.iter().map(|x| x.seq).collect::<Vec<i64>>(),
+            (1..=7).collect::<Vec<i64>>()
+        );
+        assert_eq!(events[0].entity_id, person_id);
+        assert!(events[1..6].iter().all(|x| x.entity_id == cab_id));
+
+        let payload = |event: &Event| -> Cab {
+            bson::from_document(event.payload.clone()).expect("the payload is not a cab")
+        };
+        assert_eq!(payload(&events[2]).person_id, Some(person_id));
+        assert_eq!(events[2].person_id, Some(person_id));
+        assert_eq!(payload(&events[3]).location, Point::new(1, 1));
+        assert_eq!(events[3].person_id, None);
+        let unassigned = payload(&events[4]);
+        assert_eq!(unassigned.location, Point::new(4, 4));
+        assert_eq!(unassigned.person_id, None);
+        // the payload lost the person, the event still tells who got out
+        assert_eq!(events[4].person_id, Some(person_id));
+        // deletes keep what was there
+        assert_eq!(payload(&events[5]).id, Some(cab_id));
+        assert_eq!(payload(&events[5]).location, Point::new(4, 4));
+        let deleted: Person =
+            bson::from_document(events[6].payload.clone()).expect("the payload is not a person");
+        assert_eq!(deleted.name, person.name);
+
+        // paging
+        let page = repo.get_events(2, 2).expect("cannot get the events");
+        assert_eq!(page, events[2..4].to_vec());
+        assert!(repo
+            .get_events(7, 100)
+            .expect("cannot get the events")
+            .is_empty());
+    }
+}
+
+// assignments go into the outbox for every webhook that wants them, the
+// rest of the feed doesn't
+#[test]
+fn test_repos_keep_an_outbox() {
+    let repos: Vec<Box<dyn FuberRepository>> = vec![
+        Box::new(MemoryRepo::init()),
+        Box::new(SqliteRepo::open(":memory:").expect("cannot open sqlite")),
+        Box::new(IndexedRepo::new(Box::new(MemoryRepo::init())).expect("cannot build the index")),
+    ];
+    for repo in repos {
+        let webhook = |event_types: Vec<EventKind>| {
+            Webhook::new(NewWebhook {
+                url: "http://localhost/hook".to_string(),
+                event_types,
+                secret: generate_random_string(),
+            })
+            .expect("not a valid webhook")
+        };
+        let everything = repo
+            .create_webhook(webhook(vec![]))
+            .expect("cannot create the webhook")
+            .inserted_id;
+        let drops = repo
+            .create_webhook(webhook(vec![EventKind::PersonUnassigned]))
+            .expect("cannot create the webhook")
+            .inserted_id;
+        assert_eq!(
+            repo.get_webhooks().expect("cannot get the webhooks").len(),
+            2
+        );
+
+        let cab_id = repo
+            .create_cab(Cab::new(Point::new(0, 0)))
+            .expect("cannot create a cab")
+            .inserted_id;
+        let mut cab = repo.get_cab(&cab_id.to_hex()).expect("cannot get the cab");
+        cab.update_person_id(Some(ObjectId::new()));
+        cab.update_destination(Some(Point::new(1, 1)));
+        repo.assign_person(&cab_id.to_hex(), cab.clone())
+            .expect("cannot assign the person");
+        cab.update_location(Point::new(4, 4));
+        repo.unassign_person(&cab_id.to_hex(), cab)
+            .expect("cannot unassign the person");
+
+        let due = repo
+            .get_due_deliveries(i64::MAX, 100)
+            .expect("cannot get the deliveries");
+        assert_eq!(
+            due.iter()
+                .map(|x| (x.webhook_id, x.event.kind))
+                .collect::<Vec<(ObjectId, EventKind)>>(),
+            vec![
+                (everything, EventKind::PersonAssigned),
+                (everything, EventKind::PersonUnassigned),
+                (drops, EventKind::PersonUnassigned),
+            ]
+        );
+        assert!(due.iter().all(|x| x.state == DeliveryState::Pending));
+        assert_eq!(due[0].event.seq, 2);
+        assert_eq!(due[1].event, due[2].event);
+        assert!(repo
+            .get_due_deliveries(due[0].next_attempt_at - 1, 100)
+            .expect("cannot get the deliveries")
+            .is_empty());
+
+        // a compare-and-set on the state
+        let mut dead = due[0].clone();
+        dead.state = DeliveryState::Dead;
+        dead.attempts = 3;
+        dead.last_error = Some("the webhook answered 500".to_string());
+        let update = repo
+            .update_delivery(dead.clone(), DeliveryState::Pending)
+            .expect("cannot update the delivery");
+        assert_eq!((update.matched_count, update.modified_count), (1, 1));
+        let update = repo
+            .update_delivery(dead.clone(), DeliveryState::Pending)
+            .expect("cannot update the delivery");
+        assert_eq!(update.matched_count, 0);
+        let dead_id = dead.id.expect("delivery has no id").to_hex();
+        assert_eq!(
+            repo.get_delivery(&dead_id)
+                .expect("cannot get the delivery"),
+            dead
+        );
+        assert_eq!(
+            repo.get_dead_deliveries()
+                .expect("cannot get the dead letters"),
+            vec![dead]
+        );
+        assert_eq!(
+            repo.get_due_deliveries(i64::MAX, 1)
+                .expect("cannot get the deliveries")
+                .len(),
+            1
+        );
+
+        // the deliveries go with their webhook
+        let deleted = repo
+            .delete_webhook(&everything.to_hex())
+            .expect("cannot delete the webhook");
+        assert_eq!(deleted.deleted_count, 1);
+        let err = repo
+            .get_delivery(&dead_id)
+            .expect_err("the delivery is still there");
+        assert_eq!(err.code(), ErrorCode::DeliveryNotFound);
+        let due = repo
+            .get_due_deliveries(i64::MAX, 100)
+            .expect("cannot get the deliveries");
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].webhook_id, drops);
+    }
+}
+
+// api keys are found by their hash and nothing else
+#[test]
+fn test_repos_store_api_keys() {
+    let repos: Vec<Box<dyn FuberRepository>> = vec![
+        Box::new(MemoryRepo::init()),
+        Box::new(SqliteRepo::open(":memory:").expect("cannot open sqlite")),
+        Box::new(IndexedRepo::new(Box::new(MemoryRepo::init())).expect("cannot build the index")),
+    ];
+    for repo in repos {
+        let api_key = ApiKey {
+            id: None,
+            key_hash: hash_key("my-key"),
+            role: Role::Driver,
+            subject: Some(ObjectId::new()),
+            created_at: 1_000,
+        };
+        let api_key_id = repo
+            .create_api_key(api_key.clone())
+            .expect("cannot create the api key")
+            .inserted_id;
+        let stored = repo
+            .get_api_key_by_hash(&hash_key("my-key"))
+            .expect("cannot get the api key");
+        assert_eq!(
+            stored,
+            ApiKey {
+                id: Some(api_key_id),
+                ..api_key
+            }
+        );
+        let err = repo
+            .get_api_key_by_hash(&hash_key("another-key"))
+            .expect_err("found a key that isn't there");
+        assert_eq!(err.code(), ErrorCode::ApiKeyNotFound);
+
+        let deleted = repo
+            .delete_api_key(&api_key_id.to_hex())
+            .expect("cannot delete the api key");
+        assert_eq!(deleted.deleted_count, 1);
+        assert!(repo.get_api_key_by_hash(&hash_key("my-key")).is_err());
+        let deleted = repo
+            .delete_api_key(&api_key_id.to_hex())
+            .expect("cannot delete the api key");
+        assert_eq!(deleted.deleted_count, 0);
+    }
+}
@@ -0,0 +1,224 @@
+use fuber::api::{cab_api, person_api};
+use fuber::auth::{Admin, Caller};
+use fuber::generate_random_string;
+use fuber::metric::Travel;
+use fuber::models::cab_model::Cab;
+use fuber::models::person_model::Person;
+use fuber::models::point_model::Point;
+use fuber::repository::fuber_repo::BoxedRepo;
+use fuber::repository::memory_repos::MemoryRepo;
+use fuber::routing::graph::{EdgeRecord, GraphFile, NodeRecord, RoadGraph};
+use fuber::surge::Surge;
+use rand::Rng;
+use rocket::serde::json::Json;
+use rocket::State;
+
+fn node(id: u64, x: i64, y: i64) -> NodeRecord {
+    NodeRecord {
+        id,
+        location: Point::new(x, y),
+    }
+}
+
+fn road(from: u64, to: u64) -> [EdgeRecord; 2] {
+    [
+        EdgeRecord {
+            from,
+            to,
+            length_km: None,
+            speed_kmh: 30.0,
+        },
+        EdgeRecord {
+            from: to,
+            to: from,
+            length_km: None,
+            speed_kmh: 30.0,
+        },
+    ]
+}
+
+// Two banks of a river, y = 0 and y = 2, with nodes every km from x = 0 to
+// x = 10 and the only bridge at x = 10. Node `x` is on the south bank and
+// node `100 + x` on the north bank.
+fn river() -> GraphFile {
+    let mut nodes = Vec::new();
+    let mut edges = Vec::new();
+    for x in 0..=10 {
+        nodes.push(node(x as u64, x, 0));
+        nodes.push(node(100 + x as u64, x, 2));
+        if x > 0 {
+            edges.extend(road(x as u64 - 1, x as u64));
+            edges.extend(road(100 + x as u64 - 1, 100 + x as u64));
+        }
+    }
+    edges.extend(road(10, 110));
+    GraphFile { nodes, edges }
+}
+
+#[test]
+fn test_routes_follow_the_roads() {
+    let roads = RoadGraph::from_file(river()).expect("cannot build the graph");
+    assert_eq!(roads.len(), 22);
+
+    // across the river is 2 km as the crow flies but 22 km over the bridge
+    let route = roads
+        .route(&Point::new(0, 2), &Point::new(0, 0))
+        .expect("there is a way over the bridge");
+    assert_eq!(route.length_km, 22.0);
+    assert_eq!(route.minutes, 44.0);
+    assert_eq!(route.path.len(), 22);
+    assert_eq!(route.path[10], Point::new(10, 2));
+    assert_eq!(route.path[11], Point::new(10, 0));
+
+    // points snap to the nearest node
+    let route = roads
+        .route(&Point::new(3, -1), &Point::new(6, 1))
+        .expect("both are on the south bank");
+    assert_eq!(route.length_km, 3.0);
+
+    // a one way street only goes one way
+    let mut file = river();
+    file.edges.retain(|x| !(x.from == 110 && x.to == 10));
+    let roads = RoadGraph::from_file(file).expect("cannot build the graph");
+    assert!(roads.route(&Point::new(0, 2), &Point::new(0, 0)).is_none());
+    assert!(roads.route(&Point::new(0, 0), &Point::new(0, 2)).is_some());
+    // and there are no roads between grid points and gps positions
+    let berlin = Point::geo(52.52, 13.405).expect("not a valid gps position");
+    assert!(roads.route(&berlin, &Point::new(0, 0)).is_none());
+}
+
+#[test]
+fn test_a_star_agrees_with_dijkstra() {
+    // a lattice of roads, every one of them with its own speed and some
+    // longer than the straight line between their ends
+    let mut rng = rand::thread_rng();
+    let size = 15;
+    let mut file = GraphFile {
+        nodes: Vec::new(),
+        edges: Vec::new(),
+    };
+    for x in 0..size {
+        for y in 0..size {
+            let id = (x * size + y) as u64;
+            file.nodes.push(node(id, x * 3, y * 3));
+            for (dx, dy) in [(1, 0), (0, 1)] {
+                if x + dx < size && y + dy < size && rng.gen_bool(0.8) {
+                    let to = ((x + dx) * size + y + dy) as u64;
+                    for (from, to) in [(id, to), (to, id)] {
+                        file.edges.push(EdgeRecord {
+                            from,
+                            to,
+                            length_km: Some(3.0 * rng.gen_range(1.0..2.0)),
+                            speed_kmh: rng.gen_range(10.0..80.0),
+                        });
+                    }
+                }
+            }
+        }
+    }
+    let locations = file
+        .nodes
+        .iter()
+        .map(|x| x.location.clone())
+        .collect::<Vec<Point>>();
+    let roads = RoadGraph::from_file(file).expect("cannot build the graph");
+
+    for _ in 0..100 {
+        let from = &locations[rng.gen_range(0..locations.len())];
+        let to = &locations[rng.gen_range(0..locations.len())];
+        let (source, _) = roads.snap(from).expect("cannot snap");
+        let (target, _) = roads.snap(to).expect("cannot snap");
+        let dijkstra = roads.minutes_to(&[source], target)[0];
+        match roads.route(from, to) {
+            Some(route) => assert!((route.minutes - dijkstra).abs() < 1e-9),
+            None => assert_eq!(dijkstra, f64::INFINITY),
+        }
+    }
+}
+
+#[test]
+fn test_road_graph_files_are_checked() {
+    let graph = RoadGraph::from_json(
+        r#"{
+            "nodes": [
+                { "id": 1, "location": { "lat": 52.52, "lon": 13.40 } },
+                { "id": 2, "location": { "lat": 52.53, "lon": 13.40 } }
+            ],
+            "edges": [{ "from": 1, "to": 2 }]
+        }"#,
+    )
+    .expect("cannot read the graph");
+    let route = graph
+        .route(
+            &Point::geo(52.52, 13.40).expect("not a valid gps position"),
+            &Point::geo(52.53, 13.40).expect("not a valid gps position"),
+        )
+        .expect("there is a road");
+    // 1.11 km at the default 30 km/h
+    assert!((route.minutes - 2.22).abs() < 0.01, "{}", route.minutes);
+
+    let broken = [
+        r#"{ "nodes": [{ "id": 1, "location": { "x": 0, "y": 0 } }], "edges": [{ "from": 1, "to": 2 }] }"#,
+        r#"{ "nodes": [{ "id": 1, "location": { "x": 0, "y": 0 } }, { "id": 1, "location": { "x": 1, "y": 0 } }], "edges": [] }"#,
+        r#"{ "nodes": [{ "id": 1, "location": { "x": 0, "y": 0 } }], "edges": [{ "from": 1, "to": 1, "length_km": -1 }] }"#,
+        r#"{ "nodes": [{ "id": 1, "location": { "x": 0, "y": 0 } }], "edges": [{ "from": 1, "to": 1, "speed_kmh": 0 }] }"#,
+    ];
+    for json in broken {
+        assert!(RoadGraph::from_json(json).is_err(), "{}", json);
+    }
+}
+
+#[test]
+fn test_dispatch_drives_around_the_river() {
+    for (roads, expected) in [
+        (None, Point::new(0, 2)),
+        (
+            Some(RoadGraph::from_file(river()).expect("cannot build the graph")),
+            Point::new(5, 0),
+        ),
+    ] {
+        let travel = match roads {
+            Some(roads) => Travel::default().with_roads(roads),
+            None => Travel::default(),
+        };
+        let db: BoxedRepo = Box::new(MemoryRepo::init());
+        let rocket = rocket::build()
+            .manage(db)
+            .manage(travel)
+            .manage(Surge::default());
+        let state = State::get(&rocket).expect("cannot get the state");
+        let travel = State::get(&rocket).expect("cannot get the travel config");
+        let surge = State::get(&rocket).expect("cannot get the surge config");
+
+        // right across the river, and further down the same bank
+        let fleet = vec![Cab::new(Point::new(0, 2)), Cab::new(Point::new(5, 0))];
+        cab_api::create_fleet(Admin, state, travel, Json(fleet)).expect("cannot create fleet");
+        let person = Person::new(
+            None,
+            generate_random_string(),
+            Point::new(0, 0),
+            Point::new(10, 0),
+        );
+        let Json(person_id) =
+            person_api::create_person(state, Json(person)).expect("cannot insert the person");
+        let (_, cab) = person_api::request_cab(
+            Caller::admin(),
+            state,
+            travel,
+            surge,
+            person_id.clone(),
+            None,
+        )
+        .expect("cannot request a cab")
+        .assigned()
+        .expect("no cab was assigned");
+        assert_eq!(cab.location, expected);
+
+        // 5 km along the bank at 30 km/h, or 2 km in a straight line
+        let ride = person_api::active_ride(state, &person_id)
+            .expect("cannot get the ride")
+            .expect("the person has no ride");
+        let eta = ride.eta_minutes.expect("the ride has no eta");
+        assert_eq!(eta, if travel.roads().is_some() { 10.0 } else { 4.0 });
+    }
+}
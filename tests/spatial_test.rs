@@ -0,0 +1,216 @@
+use fuber::models::cab_model::{Cab, CabCategory};
+use fuber::models::point_model::Point;
+use fuber::repository::fuber_repo::{BoxedRepo, FuberRepository};
+use fuber::repository::indexed_repo::IndexedRepo;
+use fuber::repository::memory_repos::MemoryRepo;
+use fuber::spatial::{GridIndex, IndexedCab};
+use mongodb::bson::oid::ObjectId;
+
+fn random_cabs(n: usize) -> Vec<IndexedCab> {
+    Point::create_random_points(n)
+        .into_iter()
+        .enumerate()
+        .map(|(idx, location)| IndexedCab {
+            id: ObjectId::new(),
+            location,
+            category: if idx % 3 == 0 {
+                CabCategory::Pink
+            } else {
+                CabCategory::Standard
+            },
+        })
+        .collect()
+}
+
+// what the index has to agree with, sorted the same way
+fn linear_scan(
+    cabs: &[IndexedCab],
+    point: &Point,
+    k: usize,
+    max_distance: Option<f64>,
+    category: Option<CabCategory>,
+) -> Vec<IndexedCab> {
+    let mut found = cabs
+        .iter()
+        .filter(|x| category.is_none_or(|c| x.category == c))
+        .map(|x| (point.dist(&x.location), x.clone()))
+        .filter(|(dist, _)| dist.is_finite() && max_distance.is_none_or(|max| *dist <= max))
+        .collect::<Vec<(f64, IndexedCab)>>();
+    found.sort_by(|a, b| a.0.total_cmp(&b.0).then_with(|| a.1.id.cmp(&b.1.id)));
+    found.into_iter().take(k).map(|(_, x)| x).collect()
+}
+
+#[test]
+fn test_grid_index_agrees_with_a_linear_scan() {
+    let cabs = random_cabs(500);
+    // small cells so queries have to walk many rings, and big ones
+    for cell_size in [1, 7, 16, 300] {
+        let mut index = GridIndex::new(cell_size);
+        for cab in cabs.iter() {
+            index.insert(cab.clone());
+        }
+        assert_eq!(index.len(), cabs.len());
+
+        for point in Point::create_random_points(50) {
+            for k in [1, 5, 600] {
+                assert_eq!(
+                    index.nearest(&point, k, None, |_| true),
+                    linear_scan(&cabs, &point, k, None, None)
+                );
+            }
+            assert_eq!(
+                index.nearest(&point, 5, None, |x| x.category == CabCategory::Pink),
+                linear_scan(&cabs, &point, 5, None, Some(CabCategory::Pink))
+            );
+            assert_eq!(
+                index.within_radius(&point, 40.0),
+                linear_scan(&cabs, &point, cabs.len(), Some(40.0), None)
+            );
+        }
+    }
+}
+
+// gps positions scattered up to `spread` degrees around (lat, lon)
+fn random_geo_cabs(n: usize, lat: f64, lon: f64, spread: f64) -> Vec<IndexedCab> {
+    (0..n)
+        .map(|_| IndexedCab {
+            id: ObjectId::new(),
+            location: Point::geo(
+                (lat + spread * (rand::random::<f64>() * 2.0 - 1.0)).clamp(-90.0, 90.0),
+                lon + spread * (rand::random::<f64>() * 2.0 - 1.0),
+            )
+            .expect("not a valid gps position"),
+            category: CabCategory::Standard,
+        })
+        .collect()
+}
+
+#[test]
+fn test_grid_index_agrees_with_a_linear_scan_on_gps_positions() {
+    // a city, a spot close to the pole where longitudes get narrow and
+    // some grid cabs that must never show up for a gps query
+    let mut cabs = random_geo_cabs(300, 52.52, 13.40, 0.2);
+    cabs.extend(random_geo_cabs(100, 89.9, 0.0, 0.09));
+    cabs.extend(random_cabs(100));
+    let mut index = GridIndex::default();
+    for cab in cabs.iter() {
+        index.insert(cab.clone());
+    }
+
+    let queries = random_geo_cabs(30, 52.52, 13.40, 0.3)
+        .into_iter()
+        .chain(random_geo_cabs(10, 89.9, 0.0, 0.09))
+        .map(|x| x.location);
+    for point in queries {
+        for k in [1, 5, 500] {
+            assert_eq!(
+                index.nearest(&point, k, None, |_| true),
+                linear_scan(&cabs, &point, k, None, None)
+            );
+        }
+        assert_eq!(
+            index.within_radius(&point, 5.0),
+            linear_scan(&cabs, &point, cabs.len(), Some(5.0), None)
+        );
+    }
+}
+
+#[test]
+fn test_grid_index_moves_and_removes() {
+    let mut index = GridIndex::new(4);
+    let id = ObjectId::new();
+    index.insert(IndexedCab {
+        id,
+        location: Point::new(-10, -10),
+        category: CabCategory::Standard,
+    });
+    // inserting the same id again moves the cab
+    index.insert(IndexedCab {
+        id,
+        location: Point::new(10, 10),
+        category: CabCategory::Standard,
+    });
+    assert_eq!(index.len(), 1);
+    assert!(index.within_radius(&Point::new(-10, -10), 5.0).is_empty());
+    assert_eq!(index.within_radius(&Point::new(10, 10), 0.0).len(), 1);
+
+    assert!(index.remove(&id).is_some());
+    assert!(index.remove(&id).is_none());
+    assert!(index.is_empty());
+    assert!(index
+        .nearest(&Point::new(0, 0), 3, None, |_| true)
+        .is_empty());
+}
+
+#[test]
+fn test_indexed_repo_follows_every_cab_write() {
+    let inner: BoxedRepo = Box::new(MemoryRepo::init());
+    // cabs that were stored before the index existed are picked up
+    let old_id = inner
+        .create_cab(Cab::new(Point::new(100, 100)))
+        .expect("cannot create a cab")
+        .inserted_id;
+    let repo = IndexedRepo::new(inner).expect("cannot build the index");
+    let origin = Point::new(0, 0);
+    let nearest_ids = |repo: &IndexedRepo| {
+        repo.find_nearest_free_cabs(&origin, 10, None, None)
+            .expect("cannot find cabs")
+            .into_iter()
+            .map(|x| x.id.expect("cab has no id"))
+            .collect::<Vec<ObjectId>>()
+    };
+    assert_eq!(nearest_ids(&repo), vec![old_id]);
+
+    let near_id = repo
+        .create_cab(Cab::new(Point::new(1, 1)))
+        .expect("cannot create a cab")
+        .inserted_id;
+    let fleet_ids = repo
+        .create_fleet(vec![
+            Cab::new(Point::new(2, 2)),
+            Cab::with_category(Point::new(3, 3), CabCategory::Xl),
+        ])
+        .expect("cannot create a fleet")
+        .inserted_ids;
+    assert_eq!(
+        nearest_ids(&repo),
+        vec![near_id, fleet_ids[0], fleet_ids[1], old_id]
+    );
+    let xl = repo
+        .find_nearest_free_cabs(&origin, 10, None, Some(CabCategory::Xl))
+        .expect("cannot find cabs");
+    assert_eq!(xl.len(), 1);
+    assert_eq!(xl[0].id, Some(fleet_ids[1]));
+    let close = repo
+        .find_nearest_free_cabs(&origin, 10, Some(3.0), None)
+        .expect("cannot find cabs");
+    assert_eq!(close.len(), 2);
+
+    // an assigned cab is not free anymore
+    let mut cab = repo.get_cab(&near_id.to_hex()).expect("cannot get the cab");
+    cab.update_destination(Some(origin.clone()));
+    cab.update_person_id(Some(ObjectId::new()));
+    repo.assign_person(&near_id.to_hex(), cab)
+        .expect("cannot assign the cab");
+    assert_eq!(nearest_ids(&repo), vec![fleet_ids[0], fleet_ids[1], old_id]);
+
+    // dropping the person off frees it where the ride ended
+    repo.unassign_person(&near_id.to_hex(), Cab::new(Point::new(50, 50)))
+        .expect("cannot unassign the cab");
+    assert_eq!(
+        nearest_ids(&repo),
+        vec![fleet_ids[0], fleet_ids[1], near_id, old_id]
+    );
+
+    // moving a cab moves it in the index too
+    let mut cab = repo.get_cab(&old_id.to_hex()).expect("cannot get the cab");
+    cab.update_location(Point::new(0, 0));
+    repo.update_cab(cab).expect("cannot update the cab");
+    assert_eq!(nearest_ids(&repo)[0], old_id);
+
+    repo.delete_cab(&old_id.to_hex())
+        .expect("cannot delete the cab");
+    assert_eq!(nearest_ids(&repo).len(), 3);
+    repo.delete_fleet().expect("cannot delete the fleet");
+    assert!(nearest_ids(&repo).is_empty());
+}
@@ -0,0 +1,245 @@
+use fuber::api::{cab_api, catcher_api, person_api, pricing_api, ride_api};
+use fuber::auth::{Admin, Caller};
+use fuber::generate_random_string;
+use fuber::metric::Travel;
+use fuber::models::cab_model::Cab;
+use fuber::models::person_model::Person;
+use fuber::models::point_model::Point;
+use fuber::pricing::Tariff;
+use fuber::repository::fuber_repo::BoxedRepo;
+use fuber::repository::indexed_repo::IndexedRepo;
+use fuber::repository::memory_repos::MemoryRepo;
+use fuber::surge::{Surge, SurgeReading, Zone};
+use fuber::tracking::Tracker;
+use mongodb::bson::oid::ObjectId;
+use rocket::http::Status;
+use rocket::local::blocking::Client;
+use rocket::serde::json::Json;
+use rocket::State;
+use std::time::Duration;
+
+const SECOND: i64 = 1000;
+
+// zones of 10 units, a window of a minute and no smoothing
+fn surge() -> Surge {
+    Surge::new(10, Duration::from_secs(60), 2.5, Duration::ZERO)
+}
+
+fn create_cab(db: &BoxedRepo, location: Point) {
+    db.create_cab(Cab::new(location))
+        .expect("cannot create the cab");
+}
+
+#[test]
+fn test_surge_follows_requests_per_free_cab() {
+    let db: BoxedRepo = Box::new(MemoryRepo::init());
+    let surge = surge();
+    create_cab(&db, Point::new(1, 1));
+    create_cab(&db, Point::new(9, 9));
+    // the cab next door is in another zone
+    create_cab(&db, Point::new(10, 9));
+
+    let reading = |x: SurgeReading| (x.multiplier, x.requests, x.free_cabs);
+    let at = Point::new(5, 5);
+    let now = 100 * SECOND;
+    let first = surge
+        .record_request(&db, &at, now)
+        .expect("cannot record the request");
+    assert_eq!(first.zone, Zone::Grid { x: 0, y: 0 });
+    assert_eq!(reading(first), (1.0, 1, 2));
+    surge
+        .record_request(&db, &at, now)
+        .expect("cannot record the request");
+    let third = surge
+        .record_request(&db, &Point::new(0, 0), now)
+        .expect("cannot record the request");
+    assert_eq!(reading(third), (1.5, 3, 2));
+    // looking doesn't count
+    let looked = surge.surge_at(&db, &at, now).expect("cannot get the surge");
+    assert_eq!(reading(looked), (1.5, 3, 2));
+    assert_eq!(
+        surge
+            .surge_at(&db, &Point::new(15, 5), now)
+            .expect("cannot get the surge")
+            .multiplier,
+        1.0
+    );
+
+    // capped
+    for _ in 0..10 {
+        surge
+            .record_request(&db, &at, now)
+            .expect("cannot record the request");
+    }
+    let capped = surge.surge_at(&db, &at, now).expect("cannot get the surge");
+    assert_eq!(capped.multiplier, 2.5);
+
+    // the requests leave the window a minute later
+    let later = surge
+        .surge_at(&db, &at, now + 60 * SECOND)
+        .expect("cannot get the surge");
+    assert_eq!(reading(later), (1.0, 0, 2));
+}
+
+// the free cabs are looked up through the spatial index, every corner of
+// the zone counts and nothing next to it does
+#[test]
+fn test_surge_counts_the_free_cabs_of_its_zone() {
+    let db: BoxedRepo =
+        Box::new(IndexedRepo::new(Box::new(MemoryRepo::init())).expect("cannot build the index"));
+    let surge = surge();
+    for (x, y) in [(10, 10), (19, 10), (10, 19), (19, 19), (15, 15)] {
+        create_cab(&db, Point::new(x, y));
+    }
+    for (x, y) in [(9, 15), (20, 15), (15, 9), (15, 20), (20, 20)] {
+        create_cab(&db, Point::new(x, y));
+    }
+    let mut busy = Cab::new(Point::new(12, 12));
+    busy.update_person_id(Some(ObjectId::new()));
+    db.create_cab(busy).expect("cannot create the cab");
+    let reading = surge
+        .surge_at(&db, &Point::new(11, 18), 0)
+        .expect("cannot get the surge");
+    assert_eq!(reading.zone, Zone::Grid { x: 1, y: 1 });
+    assert_eq!(reading.free_cabs, 5);
+
+    let gps = |lat: f64, lon: f64| Point::geo(lat, lon).expect("not a gps position");
+    create_cab(&db, gps(52.5201, 13.4049));
+    create_cab(&db, gps(52.5299, 13.4001));
+    create_cab(&db, gps(52.5301, 13.4049));
+    let reading = surge
+        .surge_at(&db, &gps(52.525, 13.405), 0)
+        .expect("cannot get the surge");
+    assert_eq!(reading.free_cabs, 2);
+}
+
+#[test]
+fn test_surge_is_smoothed() {
+    let db: BoxedRepo = Box::new(MemoryRepo::init());
+    let surge = Surge::new(10, Duration::from_secs(600), 3.0, Duration::from_secs(60));
+    let at = Point::geo(52.5163, 13.3777).expect("not a valid gps position");
+
+    // without a free cab every request counts fully, but the multiplier
+    // only gets there over time
+    let now = 100 * SECOND;
+    for _ in 0..3 {
+        surge
+            .record_request(&db, &at, now)
+            .expect("cannot record the request");
+    }
+    let reading = surge.surge_at(&db, &at, now).expect("cannot get the surge");
+    assert!(matches!(reading.zone, Zone::Geo { .. }));
+    assert_eq!(reading.multiplier, 1.0);
+    // 1 + 2 * (1 - 1/e)
+    let reading = surge
+        .surge_at(&db, &at, now + 60 * SECOND)
+        .expect("cannot get the surge");
+    assert_eq!(reading.multiplier, 2.26);
+    // almost all the way while the requests are still in the window
+    let reading = surge
+        .surge_at(&db, &at, now + 540 * SECOND)
+        .expect("cannot get the surge");
+    assert!(reading.multiplier > 2.9);
+}
+
+// every ride keeps the surge it was requested with and pays for it
+#[test]
+fn test_rides_are_priced_with_their_surge() {
+    let db: BoxedRepo = Box::new(MemoryRepo::init());
+    let rocket = rocket::build()
+        .manage(db)
+        .manage(Travel::default())
+        .manage(surge())
+        .manage(Tariff::default())
+        .manage(Tracker::default());
+    let state = State::get(&rocket).expect("cannot get the state");
+    let travel = State::get(&rocket).expect("cannot get the travel config");
+    let surge = State::get(&rocket).expect("cannot get the surge config");
+    let tariff = State::get(&rocket).expect("cannot get the tariff");
+    let tracker = State::get(&rocket).expect("cannot get the tracker");
+
+    cab_api::create_cab(Admin, state, travel, Json(Cab::new(Point::new(2, 2))))
+        .expect("cannot create the cab");
+    let person_ids = (0..2)
+        .map(|_| {
+            let person = Person::new(
+                None,
+                generate_random_string(),
+                Point::new(1, 1),
+                Point::new(4, 5),
+            );
+            let Json(person_id) =
+                person_api::create_person(state, Json(person)).expect("cannot insert the person");
+            person_id
+        })
+        .collect::<Vec<String>>();
+    for person_id in &person_ids {
+        person_api::request_cab(
+            Caller::admin(),
+            state,
+            travel,
+            surge,
+            person_id.clone(),
+            None,
+        )
+        .expect("cannot request a cab");
+    }
+    let Json(rides) = ride_api::get_rides_of_person(Caller::admin(), state, person_ids[0].clone())
+        .expect("cannot get the rides");
+    assert_eq!(rides[0].surge_multiplier, Some(1.0));
+    // the only cab is taken, the second request is one too many
+    let Json(rides) = ride_api::get_rides_of_person(Caller::admin(), state, person_ids[1].clone())
+        .expect("cannot get the rides");
+    assert_eq!(rides[0].surge_multiplier, Some(2.0));
+
+    person_api::unassign_cab(
+        Caller::admin(),
+        state,
+        tariff,
+        travel,
+        tracker,
+        person_ids[0].clone(),
+    )
+    .expect("cannot drop the person off");
+    let Json((_, _, fare)) = person_api::unassign_cab(
+        Caller::admin(),
+        state,
+        tariff,
+        travel,
+        tracker,
+        person_ids[1].clone(),
+    )
+    .expect("cannot drop the person off");
+    assert_eq!(fare.surge, 7.0);
+    assert_eq!(fare.total, 14.0);
+}
+
+#[test]
+fn test_get_surge() {
+    let db: BoxedRepo = Box::new(MemoryRepo::init());
+    create_cab(&db, Point::new(3, 3));
+    let rocket = rocket::build()
+        .manage(db)
+        .manage(surge())
+        .register("/", rocket::catchers![catcher_api::unprocessable_entity])
+        .mount("/pricing", rocket::routes![pricing_api::get_surge]);
+    let client = Client::tracked(rocket).expect("cannot build a rocket client");
+
+    let response = client.get("/pricing/surge?x=4&y=-2").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let reading = response
+        .into_json::<SurgeReading>()
+        .expect("not a surge reading");
+    assert_eq!(reading.zone, Zone::Grid { x: 0, y: -1 });
+    assert_eq!(reading.multiplier, 1.0);
+    assert_eq!(reading.free_cabs, 0);
+
+    let response = client
+        .get("/pricing/surge?lat=52.5163&lon=13.3777")
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    for query in ["", "?x=4", "?x=4&y=2&lat=52.5", "?lat=95&lon=0"] {
+        let response = client.get(format!("/pricing/surge{}", query)).dispatch();
+        assert_eq!(response.status(), Status::UnprocessableEntity, "{}", query);
+    }
+}
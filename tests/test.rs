@@ -55,7 +55,7 @@ fn test_remove_person_after_reaching_destination() {
         Point::new(100, 100),
     );
 
-    let cab = match person1.request_cab(&mut fleet) {
+    let _cab = match person1.request_cab(&mut fleet) {
         Ok(cab) => cab,
         Err(s) => panic!("{}", s),
     };
@@ -83,7 +83,7 @@ fn test_failure_when_all_cabs_occupied() {
 
         loc_points
             .into_iter()
-            .zip(dest_points.into_iter())
+            .zip(dest_points)
             .enumerate()
             .map(|x| Person::new(x.0, generate_random_string(), x.1 .0, x.1 .1))
             .collect()
@@ -93,10 +93,7 @@ fn test_failure_when_all_cabs_occupied() {
     let _ = {
         person_vec
             .iter()
-            .filter_map(|p| match (*p).request_cab(&mut fleet) {
-                Ok(cab) => Some(cab),
-                Err(_) => None,
-            })
+            .filter_map(|p| (*p).request_cab(&mut fleet).ok())
             .collect::<Vec<Cab>>()
     };
 
@@ -0,0 +1,58 @@
+use fuber::api::mount_test_routes;
+use fuber::auth::Auth;
+use fuber::generate_random_string;
+use fuber::metric::Travel;
+use fuber::models::person_model::Person;
+use fuber::models::point_model::Point;
+use fuber::repository::fuber_repo::BoxedRepo;
+use fuber::repository::memory_repos::MemoryRepo;
+use rocket::http::Status;
+use rocket::local::blocking::Client;
+use std::env;
+
+mod common;
+
+use common::{admin, JWT_SECRET};
+
+// the status codes of the test routes for an admin
+fn test_route_statuses() -> (Status, Status) {
+    // somebody to delete, the route answers 404 without anybody too
+    let db: BoxedRepo = Box::new(MemoryRepo::init());
+    db.create_person(Person::new(
+        None,
+        generate_random_string(),
+        Point::new(0, 0),
+        Point::new(1, 1),
+    ))
+    .expect("cannot create the person");
+    let rocket = rocket::build()
+        .manage(db)
+        .manage(Travel::default())
+        .manage(Auth::new(Some(JWT_SECRET)));
+    let client = Client::tracked(mount_test_routes(rocket)).expect("cannot build a rocket client");
+    let cab = client.get("/cab/test/fleet/3").header(admin()).dispatch();
+    let person = client
+        .delete("/person/test/delete_all_people")
+        .header(admin())
+        .dispatch();
+    (cab.status(), person.status())
+}
+
+// one test for both settings, the env var is shared by the whole process
+#[test]
+fn test_test_routes_are_only_mounted_when_asked_for() {
+    env::remove_var("FUBER_TEST_ROUTES");
+    assert_eq!(test_route_statuses(), (Status::NotFound, Status::NotFound));
+    env::set_var("FUBER_TEST_ROUTES", "false");
+    assert_eq!(test_route_statuses(), (Status::NotFound, Status::NotFound));
+
+    env::set_var("FUBER_TEST_ROUTES", "true");
+    // a build without the feature never has them
+    let expected = if cfg!(feature = "test-routes") {
+        (Status::Ok, Status::Ok)
+    } else {
+        (Status::NotFound, Status::NotFound)
+    };
+    assert_eq!(test_route_statuses(), expected);
+    env::remove_var("FUBER_TEST_ROUTES");
+}
@@ -0,0 +1,281 @@
+use fuber::api::{cab_api, catcher_api, person_api, ride_api, tracking_api};
+use fuber::auth::{Auth, Caller};
+use fuber::generate_random_string;
+use fuber::metric::Travel;
+use fuber::models::cab_model::Cab;
+use fuber::models::person_model::Person;
+use fuber::models::point_model::Point;
+use fuber::pricing::Tariff;
+use fuber::repository::fuber_repo::BoxedRepo;
+use fuber::repository::memory_repos::MemoryRepo;
+use fuber::surge::Surge;
+use fuber::tracking::{CabPosition, Tracker};
+use mongodb::bson::oid::ObjectId;
+use rocket::http::{ContentType, Header, Status};
+use rocket::local::asynchronous::{Client, LocalResponse};
+use rocket::tokio::time;
+use serde_json::json;
+use std::time::Duration;
+
+const JWT_SECRET: &str = "tr4ck1ng";
+
+// the authorization header of a token for the caller
+fn bearer(caller: Caller) -> Header<'static> {
+    let token = Auth::new(Some(JWT_SECRET))
+        .issue(caller.role, caller.subject, Duration::from_secs(60))
+        .expect("cannot issue a token");
+    Header::new("Authorization", format!("Bearer {}", token))
+}
+
+async fn client(db: BoxedRepo) -> Client {
+    let rocket = rocket::build()
+        .manage(db)
+        .manage(Travel::default())
+        .manage(Surge::default())
+        .manage(Tariff::default())
+        .manage(Tracker::default())
+        .manage(Auth::new(Some(JWT_SECRET)))
+        .register(
+            "/",
+            rocket::catchers![
+                catcher_api::unauthorized,
+                catcher_api::forbidden,
+                catcher_api::not_found,
+                catcher_api::internal_error
+            ],
+        )
+        .mount(
+            "/cab",
+            rocket::routes![cab_api::update_location, cab_api::update_cab],
+        )
+        .mount(
+            "/person",
+            rocket::routes![person_api::request_cab, person_api::unassign_cab],
+        )
+        .mount("/ride", rocket::routes![ride_api::cancel_ride])
+        .mount(
+            "/track",
+            rocket::routes![tracking_api::track_cab_of_person, tracking_api::track_fleet],
+        );
+    Client::tracked(rocket)
+        .await
+        .expect("cannot build a rocket client")
+}
+
+// the data of every event of the stream with that name
+fn events<T: serde::de::DeserializeOwned>(stream: &str, name: &str) -> Vec<T> {
+    stream
+        .split("\n\n")
+        .filter(|x| x.lines().any(|l| l == format!("event:{}", name)))
+        .filter_map(|x| x.lines().find_map(|l| l.strip_prefix("data:")))
+        .map(|x| serde_json::from_str(x).expect("not the json of the event"))
+        .collect()
+}
+
+async fn move_cab(client: &Client, cab_id: ObjectId, x: i64, y: i64) {
+    let response = client
+        .put(format!("/cab/update_location/{}", cab_id.to_hex()))
+        .header(ContentType::JSON)
+        .header(bearer(Caller::driver(cab_id)))
+        .body(json!({ "x": x, "y": y }).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+}
+
+async fn track(client: &Client, person_id: ObjectId) -> LocalResponse<'_> {
+    let response = client
+        .get(format!("/track/person/{}", person_id.to_hex()))
+        .header(bearer(Caller::rider(person_id)))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    response
+}
+
+// the whole stream, it has to end on its own
+async fn until_closed(response: LocalResponse<'_>) -> String {
+    time::timeout(Duration::from_secs(5), response.into_string())
+        .await
+        .expect("the stream is still open")
+        .expect("no stream")
+}
+
+fn setup() -> (BoxedRepo, ObjectId, ObjectId, ObjectId) {
+    let db: BoxedRepo = Box::new(MemoryRepo::init());
+    let person_id = db
+        .create_person(Person::new(
+            None,
+            generate_random_string(),
+            Point::new(1, 1),
+            Point::new(9, 9),
+        ))
+        .expect("cannot create the person")
+        .inserted_id;
+    let mut cab = Cab::new(Point::new(0, 0));
+    cab.update_person_id(Some(person_id));
+    cab.update_destination(Some(Point::new(1, 1)));
+    let cab_id = db
+        .create_cab(cab)
+        .expect("cannot create the cab")
+        .inserted_id;
+    let other_id = db
+        .create_cab(Cab::new(Point::new(5, 5)))
+        .expect("cannot create the cab")
+        .inserted_id;
+    (db, person_id, cab_id, other_id)
+}
+
+// a rider only sees their own cab, until they aren't in it anymore
+#[rocket::async_test]
+async fn test_riders_track_their_cab() {
+    let (db, person_id, cab_id, other_id) = setup();
+    let client = client(db).await;
+
+    let response = client
+        .get(format!("/track/person/{}", person_id.to_hex()))
+        .header(bearer(Caller::rider(person_id)))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.content_type(), Some(ContentType::EventStream));
+
+    move_cab(&client, cab_id, 1, 0).await;
+    move_cab(&client, other_id, 6, 6).await;
+    move_cab(&client, cab_id, 1, 1).await;
+    // dropped off, the stream is done
+    let dropped = client
+        .put(format!("/cab/update_cab/{}", cab_id.to_hex()))
+        .header(ContentType::JSON)
+        .header(bearer(Caller::admin()))
+        .body(json!({ "location": { "x": 9, "y": 9 }, "destination": null }).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(dropped.status(), Status::Ok);
+    move_cab(&client, cab_id, 10, 10).await;
+
+    let stream = response.into_string().await.expect("no stream");
+    let positions = events::<CabPosition>(&stream, "location");
+    assert!(positions.iter().all(|x| x.cab_id == cab_id));
+    assert_eq!(
+        positions
+            .iter()
+            .map(|x| x.location.clone())
+            .collect::<Vec<Point>>(),
+        vec![Point::new(0, 0), Point::new(1, 0), Point::new(1, 1)]
+    );
+    assert_eq!(positions[0].destination, Some(Point::new(1, 1)));
+}
+
+// dropping the rider off or cancelling their ride ends the stream right
+// away, not only once the cab moves again
+#[rocket::async_test]
+async fn test_rider_streams_end_with_the_ride() {
+    let (db, person_id, cab_id, _) = setup();
+    let client = client(db).await;
+
+    let response = track(&client, person_id).await;
+    let dropped = client
+        .get(format!("/person/unassign_cab/{}", person_id.to_hex()))
+        .header(bearer(Caller::rider(person_id)))
+        .dispatch()
+        .await;
+    assert_eq!(dropped.status(), Status::Ok);
+    let positions = events::<CabPosition>(&until_closed(response).await, "location");
+    assert_eq!(
+        positions
+            .iter()
+            .map(|x| x.cab_id)
+            .collect::<Vec<ObjectId>>(),
+        vec![cab_id]
+    );
+
+    let requested = client
+        .get(format!("/person/request_cab/{}", person_id.to_hex()))
+        .header(bearer(Caller::rider(person_id)))
+        .dispatch()
+        .await;
+    assert_eq!(requested.status(), Status::Ok);
+    let (_, cab): (Person, Cab) = requested.into_json().await.expect("no cab");
+    let response = track(&client, person_id).await;
+    let ride = person_api::active_ride(
+        client.rocket().state::<BoxedRepo>().expect("no repo"),
+        &person_id.to_hex(),
+    )
+    .expect("cannot get the ride")
+    .expect("the person has no ride");
+    let cancelled = client
+        .put(format!(
+            "/ride/{}/cancel",
+            ride.id.expect("ride has no id").to_hex()
+        ))
+        .header(bearer(Caller::rider(person_id)))
+        .dispatch()
+        .await;
+    assert_eq!(cancelled.status(), Status::Ok);
+    let positions = events::<CabPosition>(&until_closed(response).await, "location");
+    assert_eq!(
+        positions
+            .iter()
+            .map(|x| x.cab_id)
+            .collect::<Vec<ObjectId>>(),
+        vec![cab.id.expect("cab has no id")]
+    );
+}
+
+#[rocket::async_test]
+async fn test_only_riders_with_a_cab_can_track_it() {
+    let (db, _, _, _) = setup();
+    let nobody = db
+        .create_person(Person::new(
+            None,
+            generate_random_string(),
+            Point::new(1, 1),
+            Point::new(9, 9),
+        ))
+        .expect("cannot create the person")
+        .inserted_id;
+    let client = client(db).await;
+
+    let response = client
+        .get(format!("/track/person/{}", nobody.to_hex()))
+        .header(bearer(Caller::rider(nobody)))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Conflict);
+    let response = client
+        .get(format!("/track/person/{}", ObjectId::new().to_hex()))
+        .header(bearer(Caller::admin()))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+// admins see every cab move, until the server shuts down
+#[rocket::async_test]
+async fn test_admins_track_the_fleet() {
+    let (db, _, cab_id, other_id) = setup();
+    let client = client(db).await;
+
+    let response = client
+        .get("/track/fleet")
+        .header(bearer(Caller::admin()))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    move_cab(&client, cab_id, 1, 0).await;
+    move_cab(&client, other_id, 6, 6).await;
+    client.rocket().shutdown().notify();
+
+    let stream = response.into_string().await.expect("no stream");
+    let cabs = events::<Cab>(&stream, "cab");
+    assert_eq!(
+        cabs.iter()
+            .map(|x| (x.id, x.location.clone()))
+            .collect::<Vec<_>>(),
+        vec![
+            (Some(cab_id), Point::new(1, 0)),
+            (Some(other_id), Point::new(6, 6))
+        ]
+    );
+}
@@ -0,0 +1,350 @@
+use fuber::api::{catcher_api, webhook_api};
+use fuber::auth::Auth;
+use fuber::models::cab_model::Cab;
+use fuber::models::event_model::{Event, EventKind};
+use fuber::models::point_model::Point;
+use fuber::models::ride_model::now_millis;
+use fuber::models::webhook_model::{Delivery, DeliveryState, WebhookInfo};
+use fuber::repository::fuber_repo::BoxedRepo;
+use fuber::repository::memory_repos::MemoryRepo;
+use fuber::webhooks::{deliver_due, sign, WebhookPolicy, DELIVERY_HEADER, SIGNATURE_HEADER};
+use mongodb::bson::oid::ObjectId;
+use rocket::http::{ContentType, Status};
+use rocket::local::blocking::Client;
+use serde_json::json;
+use std::collections::{HashMap, VecDeque};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+mod common;
+
+use common::{admin, JWT_SECRET};
+
+const SECRET: &str = "s3cr3t";
+
+// what the stand-in got, the header names in lower case
+#[derive(Debug, Clone)]
+struct Received {
+    headers: HashMap<String, String>,
+    body: String,
+}
+
+// A webhook on a local port that answers with the statuses it is given, in
+// order, and with 200 once they ran out.
+struct StandIn {
+    url: String,
+    received: Arc<Mutex<Vec<Received>>>,
+}
+
+impl StandIn {
+    fn start(statuses: Vec<u16>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("cannot bind the stand-in");
+        let url = format!(
+            "http://{}/hook",
+            listener.local_addr().expect("no local address")
+        );
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let statuses = Arc::new(Mutex::new(VecDeque::from(statuses)));
+        let log = received.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { continue };
+                let mut reader = BufReader::new(stream.try_clone().expect("cannot clone"));
+                let mut headers = HashMap::new();
+                let mut line = String::new();
+                reader.read_line(&mut line).expect("no request line");
+                loop {
+                    line.clear();
+                    reader.read_line(&mut line).expect("no header");
+                    match line.trim_end().split_once(": ") {
+                        Some((name, value)) => {
+                            headers.insert(name.to_lowercase(), value.to_string());
+                        }
+                        None => break,
+                    }
+                }
+                let length = headers
+                    .get("content-length")
+                    .and_then(|x| x.parse::<usize>().ok())
+                    .unwrap_or(0);
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).expect("no body");
+                log.lock().expect("poisoned").push(Received {
+                    headers,
+                    body: String::from_utf8(body).expect("body is not utf-8"),
+                });
+                let status = statuses
+                    .lock()
+                    .expect("poisoned")
+                    .pop_front()
+                    .unwrap_or(200);
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 {} Whatever\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    status
+                );
+            }
+        });
+        StandIn { url, received }
+    }
+
+    fn received(&self) -> Vec<Received> {
+        self.received.lock().expect("poisoned").clone()
+    }
+}
+
+fn client() -> Client {
+    let db: BoxedRepo = Box::new(MemoryRepo::init());
+    let rocket = rocket::build()
+        .manage(db)
+        .manage(Auth::new(Some(JWT_SECRET)))
+        .register(
+            "/",
+            rocket::catchers![
+                catcher_api::not_found,
+                catcher_api::unprocessable_entity,
+                catcher_api::internal_error
+            ],
+        )
+        .mount(
+            "/webhooks",
+            rocket::routes![
+                webhook_api::create_webhook,
+                webhook_api::get_webhooks,
+                webhook_api::delete_webhook,
+                webhook_api::get_dead_letters,
+                webhook_api::retry_dead_letter
+            ],
+        );
+    Client::tracked(rocket).expect("cannot build a rocket client")
+}
+
+fn db(client: &Client) -> &BoxedRepo {
+    client.rocket().state::<BoxedRepo>().expect("no repo")
+}
+
+fn create_webhook(client: &Client, url: &str, event_types: Vec<&str>) -> String {
+    let response = client
+        .post("/webhooks/create")
+        .header(admin())
+        .header(ContentType::JSON)
+        .body(json!({ "url": url, "event_types": event_types, "secret": SECRET }).to_string())
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    response.into_json::<String>().expect("not an id")
+}
+
+// a cab that takes somebody and drops them off again
+fn ride(db: &BoxedRepo) -> ObjectId {
+    let cab_id = db
+        .create_cab(Cab::new(Point::new(0, 0)))
+        .expect("cannot create the cab")
+        .inserted_id;
+    let mut cab = db.get_cab(&cab_id.to_hex()).expect("cannot get the cab");
+    cab.update_person_id(Some(ObjectId::new()));
+    cab.update_destination(Some(Point::new(1, 1)));
+    db.assign_person(&cab_id.to_hex(), cab.clone())
+        .expect("cannot assign the person");
+    db.unassign_person(&cab_id.to_hex(), cab)
+        .expect("cannot unassign the person");
+    cab_id
+}
+
+// a second between attempts, twice as long after every one and three of
+// them
+fn policy() -> WebhookPolicy {
+    WebhookPolicy::new(
+        Duration::from_secs(1),
+        Duration::from_secs(5),
+        3,
+        Duration::from_secs(1),
+        Duration::from_secs(60),
+    )
+}
+
+#[test]
+fn test_webhooks_get_signed_assignments() {
+    let stand_in = StandIn::start(vec![]);
+    let client = client();
+    create_webhook(&client, &stand_in.url, vec!["person_assigned"]);
+
+    // the secret is never handed out
+    let response = client.get("/webhooks").header(admin()).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.into_string().expect("no body");
+    assert!(!body.contains(SECRET));
+    let webhooks: Vec<WebhookInfo> = serde_json::from_str(&body).expect("not the webhooks");
+    assert_eq!(webhooks[0].url, stand_in.url);
+
+    let cab_id = ride(db(&client));
+    deliver_due(db(&client), &policy(), now_millis()).expect("cannot deliver");
+    let received = stand_in.received();
+    assert_eq!(received.len(), 1);
+    let event: Event = serde_json::from_str(&received[0].body).expect("not an event");
+    assert_eq!(event.kind, EventKind::PersonAssigned);
+    assert_eq!(event.entity_id, cab_id);
+    assert_eq!(received[0].headers["x-fuber-event"], "person_assigned");
+    assert_eq!(
+        received[0].headers[&SIGNATURE_HEADER.to_lowercase()],
+        sign(SECRET, &received[0].body)
+    );
+    // the well known hmac-sha256 example
+    assert_eq!(
+        sign("key", "The quick brown fox jumps over the lazy dog"),
+        "sha256=f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+    );
+    let delivery_id = &received[0].headers[&DELIVERY_HEADER.to_lowercase()];
+    let delivery = db(&client)
+        .get_delivery(delivery_id)
+        .expect("cannot get the delivery");
+    assert_eq!(delivery.state, DeliveryState::Delivered);
+    assert_eq!(delivery.attempts, 1);
+
+    // delivered once
+    deliver_due(db(&client), &policy(), now_millis() + 60_000).expect("cannot deliver");
+    assert_eq!(stand_in.received().len(), 1);
+}
+
+#[test]
+fn test_failed_deliveries_back_off_until_they_are_dead() {
+    let stand_in = StandIn::start(vec![500, 503, 500]);
+    let client = client();
+    create_webhook(&client, &stand_in.url, vec!["person_unassigned"]);
+    ride(db(&client));
+    let now = now_millis();
+
+    let sent = |at: i64| {
+        deliver_due(db(&client), &policy(), at).expect("cannot deliver");
+        stand_in.received().len()
+    };
+    assert_eq!(sent(now), 1);
+    // 1 second after the first attempt, 2 after the second
+    assert_eq!(sent(now + 999), 1);
+    assert_eq!(sent(now + 1_000), 2);
+    assert_eq!(sent(now + 2_999), 2);
+    assert_eq!(sent(now + 3_000), 3);
+    // out of attempts
+    assert_eq!(sent(now + 60_000), 3);
+
+    let response = client
+        .get("/webhooks/dead_letters")
+        .header(admin())
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let dead = response
+        .into_json::<Vec<Delivery>>()
+        .expect("not the dead letters");
+    assert_eq!(dead.len(), 1);
+    assert_eq!(dead[0].attempts, 3);
+    assert_eq!(
+        dead[0].last_error.as_deref(),
+        Some("the webhook answered 500")
+    );
+    assert_eq!(dead[0].event.kind, EventKind::PersonUnassigned);
+
+    // sent off again, and this time the stand-in takes it
+    let delivery_id = dead[0].id.expect("delivery has no id").to_hex();
+    let retry = format!("/webhooks/dead_letters/{}/retry", delivery_id);
+    let response = client.put(&retry).header(admin()).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(
+        client.put(&retry).header(admin()).dispatch().status(),
+        Status::Conflict
+    );
+    assert_eq!(sent(now_millis()), 4);
+    let delivery = db(&client)
+        .get_delivery(&delivery_id)
+        .expect("cannot get the delivery");
+    assert_eq!(delivery.state, DeliveryState::Delivered);
+    let response = client
+        .get("/webhooks/dead_letters")
+        .header(admin())
+        .dispatch();
+    assert_eq!(
+        response
+            .into_json::<Vec<Delivery>>()
+            .expect("not the dead letters"),
+        vec![]
+    );
+}
+
+// a webhook that never answers holds up nobody but itself
+#[test]
+fn test_dead_webhooks_dont_hold_up_the_others() {
+    // takes the connections and never reads from them
+    let silent = TcpListener::bind("127.0.0.1:0").expect("cannot bind the silent webhook");
+    let silent_url = format!(
+        "http://{}/hook",
+        silent.local_addr().expect("no local address")
+    );
+    let stand_in = StandIn::start(vec![]);
+    let client = client();
+    create_webhook(&client, &silent_url, vec!["person_assigned"]);
+    create_webhook(&client, &stand_in.url, vec!["person_assigned"]);
+    for _ in 0..3 {
+        ride(db(&client));
+    }
+
+    let policy = WebhookPolicy::new(
+        Duration::from_secs(1),
+        Duration::from_millis(500),
+        3,
+        Duration::from_secs(1),
+        Duration::from_secs(60),
+    );
+    let started = Instant::now();
+    deliver_due(db(&client), &policy, now_millis()).expect("cannot deliver");
+    // one timeout, not one for every delivery to the silent webhook
+    assert!(started.elapsed() < Duration::from_millis(1_400));
+    assert_eq!(stand_in.received().len(), 3);
+    // the silent one got tried once, the rest wait for the next tick
+    let attempts = db(&client)
+        .get_due_deliveries(i64::MAX, 100)
+        .expect("cannot get the deliveries")
+        .into_iter()
+        .map(|x| x.attempts)
+        .collect::<Vec<u32>>();
+    assert_eq!(attempts, vec![0, 0, 1]);
+    drop(silent);
+}
+
+#[test]
+fn test_webhooks_are_validated() {
+    let client = client();
+    for body in [
+        json!({ "url": "ftp://localhost/hook", "secret": SECRET }),
+        json!({ "url": "http://localhost/hook", "secret": "" }),
+        json!({ "url": "http://localhost/hook", "event_types": ["cab_created"], "secret": SECRET }),
+    ] {
+        let response = client
+            .post("/webhooks/create")
+            .header(admin())
+            .header(ContentType::JSON)
+            .body(body.to_string())
+            .dispatch();
+        assert_eq!(response.status(), Status::UnprocessableEntity, "{}", body);
+    }
+
+    let webhook_id = create_webhook(&client, "http://localhost/hook", vec![]);
+    let response = client
+        .delete(format!("/webhooks/{}", webhook_id))
+        .header(admin())
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let response = client
+        .delete(format!("/webhooks/{}", webhook_id))
+        .header(admin())
+        .dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+    let response = client
+        .put(format!(
+            "/webhooks/dead_letters/{}/retry",
+            ObjectId::new().to_hex()
+        ))
+        .header(admin())
+        .dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+}